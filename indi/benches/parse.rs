@@ -0,0 +1,57 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use indi::{CommandIter, SetBlobIter};
+use quick_xml::Reader;
+
+const FIXTURES: &[(&str, &[u8])] = &[
+    (
+        "image_capture",
+        include_bytes!("../tests/image_capture.log"),
+    ),
+    (
+        "image_capture_blob_vector",
+        include_bytes!("../tests/image_capture_blob_vector.log"),
+    ),
+];
+
+fn parse(xml: &[u8]) -> usize {
+    let mut reader = Reader::from_reader(xml);
+    reader.trim_text(true);
+    reader.expand_empty_elements(true);
+
+    let mut count = 0;
+    for command in CommandIter::new(reader) {
+        black_box(command.expect("fixture should parse"));
+        count += 1;
+    }
+    count
+}
+
+fn bench_command_iter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("command_iter");
+    for (name, xml) in FIXTURES {
+        group.throughput(Throughput::Bytes(xml.len() as u64));
+        group.bench_function(*name, |b| b.iter(|| parse(black_box(xml))));
+    }
+    group.finish();
+}
+
+fn bench_one_blob(c: &mut Criterion) {
+    let xml = include_bytes!("../tests/image_capture_one_blob.log");
+
+    let mut group = c.benchmark_group("blob");
+    group.throughput(Throughput::Bytes(xml.len() as u64));
+    group.bench_function("image_capture_one_blob", |b| {
+        b.iter(|| {
+            let mut reader = Reader::from_reader(black_box(&xml[..]));
+            reader.trim_text(true);
+            reader.expand_empty_elements(true);
+            let mut command_iter = CommandIter::new(reader);
+            let blob = SetBlobIter::new(&mut command_iter).next();
+            black_box(blob.expect("fixture should contain a blob").unwrap())
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_command_iter, bench_one_blob);
+criterion_main!(benches);
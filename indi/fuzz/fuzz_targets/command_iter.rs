@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use quick_xml::Reader;
+
+// Parsing arbitrary input must never panic or hang, whatever errors it produces.
+fuzz_target!(|data: &[u8]| {
+    let mut reader = Reader::from_reader(data);
+    reader.trim_text(true);
+    reader.expand_empty_elements(true);
+
+    for _ in indi::CommandIter::new(reader).take(data.len() + 1) {}
+});
@@ -0,0 +1,498 @@
+use super::*;
+
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+/// Identifies a device by the server it is attached to as well as its INDI device name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DeviceId {
+    pub server: String,
+    pub device: String,
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.server, self.device)
+    }
+}
+
+/// Events delivered by an `Aggregator` for each of its servers.  Each
+/// connection made to a server gets its own `connection` id, so that events
+/// still in flight from a connection that has since been replaced can be told
+/// apart from those of the current one.
+#[derive(Debug)]
+pub enum ServerEvent {
+    /// A command, or the error encountered while reading it, received from `server`.
+    Command {
+        server: String,
+        connection: u64,
+        command: Result<Command, DeError>,
+    },
+    /// The connection to `server` has been closed.
+    Disconnected { server: String, connection: u64 },
+}
+
+/// The writing half of a connection, whatever its transport.  Unlike
+/// `Transport` it can be boxed, so servers on different transports can share
+/// an aggregator.
+trait Link: Write + Send {
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl<T: Transport + Send> Link for T {
+    fn shutdown(&self) -> io::Result<()> {
+        Transport::shutdown(self)
+    }
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct Server {
+    id: u64,
+    #[derivative(Debug = "ignore")]
+    xml_writer: Writer<BufWriter<Box<dyn Link>>>,
+    client: Client,
+}
+
+impl Server {
+    fn disconnect(&mut self) -> io::Result<()> {
+        self.xml_writer.inner().get_ref().shutdown()
+    }
+}
+
+/// Tracks the devices of several INDI servers at once.  Each server is
+/// registered under a name, which is used to namespace its devices and to
+/// route commands back to the connection the device lives on.
+///
+/// Example usage:
+/// ```no_run
+/// let (mut aggregator, events) = indi::Aggregator::new();
+/// aggregator.connect("pier1", "pier1.local:7624").unwrap();
+/// aggregator.connect("pier2", "pier2.local:7624").unwrap();
+///
+/// for event in events.iter() {
+///     if let indi::ServerEvent::Command { server, connection, command: Ok(command) } = event {
+///         aggregator.update(&server, connection, command).unwrap();
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Aggregator {
+    servers: HashMap<String, Server>,
+    sender: mpsc::Sender<ServerEvent>,
+    /// The id given to the next connection.
+    next_id: u64,
+    /// Bytes read from each server, over all of its connections.
+    received: HashMap<String, Arc<AtomicU64>>,
+}
+
+impl Aggregator {
+    /// Creates a new aggregator, along with the receiver on which events from all
+    /// of its servers will be delivered.
+    pub fn new() -> (Aggregator, mpsc::Receiver<ServerEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        (
+            Aggregator {
+                servers: HashMap::new(),
+                sender,
+                next_id: 0,
+                received: HashMap::new(),
+            },
+            receiver,
+        )
+    }
+
+    /// Connects to the INDI server at `addr` and registers it under the name `server`.
+    pub fn connect<A: ToSocketAddrs>(&mut self, server: &str, addr: A) -> Result<(), DeError> {
+        let connection = Connection::new(addr)?;
+        self.add_connection(server, connection)
+    }
+
+    /// Registers an already established connection under the name `server`, requests
+    /// all of its properties, and starts forwarding its commands to the event receiver.
+    /// The connection can be over any transport, such as a Unix socket or a driver's
+    /// pipes.  Any existing connection registered under the same name is disconnected.
+    pub fn add_connection<T: Transport + Send + 'static>(
+        &mut self,
+        server: &str,
+        mut connection: Connection<T>,
+    ) -> Result<(), DeError> {
+        connection.write(&GetProperties {
+            version: INDI_PROTOCOL_VERSION.to_string(),
+            device: None,
+            name: None,
+        })?;
+
+        let mut iter = connection.iter()?;
+        let sender = self.sender.clone();
+        let name = server.to_string();
+        let id = self.next_id;
+        self.next_id += 1;
+        let received = self.received.entry(name.clone()).or_default().clone();
+        thread::spawn(move || {
+            let mut position = 0;
+            while let Some(command) = iter.next() {
+                let read = iter.buffer_position() as u64;
+                received.fetch_add(read - position, Ordering::Relaxed);
+                position = read;
+                let event = ServerEvent::Command {
+                    server: name.clone(),
+                    connection: id,
+                    command,
+                };
+                if sender.send(event).is_err() {
+                    return;
+                }
+            }
+            _ = sender.send(ServerEvent::Disconnected {
+                server: name,
+                connection: id,
+            });
+        });
+
+        let link: Box<dyn Link> = Box::new(connection.connection);
+        let previous = self.servers.insert(
+            server.to_string(),
+            Server {
+                id,
+                xml_writer: Writer::new_with_indent(BufWriter::new(link), b' ', 2),
+                client: Client::new(),
+            },
+        );
+        if let Some(mut previous) = previous {
+            _ = previous.disconnect();
+        }
+        Ok(())
+    }
+
+    /// Whether `connection` is the one currently registered as `server`.
+    /// Events from any other connection are stale.
+    pub fn is_current(&self, server: &str, connection: u64) -> bool {
+        self.servers.get(server).is_some_and(|s| s.id == connection)
+    }
+
+    /// Disconnects `connection` from the server registered as `server` and
+    /// forgets its devices.  Nothing happens if `server` has since been
+    /// registered with another connection.
+    pub fn disconnect(&mut self, server: &str, connection: u64) -> Result<(), DeError> {
+        if self.is_current(server, connection) {
+            if let Some(mut removed) = self.servers.remove(server) {
+                removed.disconnect()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Update the state of the appropriate device property for a command that came from
+    /// `connection` to `server`.  Commands from connections that are no longer registered
+    /// are ignored.
+    pub fn update(
+        &mut self,
+        server: &str,
+        connection: u64,
+        command: serialization::Command,
+    ) -> Result<Option<&Parameter>, UpdateError> {
+        match self.servers.get_mut(server) {
+            Some(s) if s.id == connection => s.client.update(command),
+            _ => Ok(None),
+        }
+    }
+
+    /// Sends the given INDI command to the connection registered as `server`.
+    pub fn write<T: XmlSerialization>(&mut self, server: &str, command: &T) -> Result<(), DeError> {
+        match self.servers.get_mut(server) {
+            Some(s) => {
+                command.write(&mut s.xml_writer)?;
+                s.xml_writer.inner().flush()?;
+                Ok(())
+            }
+            None => Err(DeError::IoError(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                format!("Not connected to server {}", server),
+            ))),
+        }
+    }
+
+    /// How many bytes have been read from `server`, over all the connections
+    /// registered under that name.
+    pub fn bytes_received(&self, server: &str) -> u64 {
+        self.received
+            .get(server)
+            .map_or(0, |received| received.load(Ordering::Relaxed))
+    }
+
+    /// Names of the currently registered servers.
+    pub fn server_names(&self) -> Vec<&String> {
+        self.servers.keys().collect()
+    }
+
+    /// Accessor for the client tracking the devices of a single server.
+    pub fn get_client(&self, server: &str) -> Option<&Client> {
+        self.servers.get(server).map(|s| &s.client)
+    }
+
+    /// Accessor for the devices of every registered server, keyed by server and device name.
+    pub fn get_devices(&self) -> HashMap<DeviceId, &Device> {
+        let mut devices = HashMap::new();
+        for (server, s) in &self.servers {
+            for (device, d) in s.client.get_devices() {
+                devices.insert(
+                    DeviceId {
+                        server: server.clone(),
+                        device: device.clone(),
+                    },
+                    d,
+                );
+            }
+        }
+        devices
+    }
+
+    /// Accessor for a single device.
+    pub fn get_device(&self, id: &DeviceId) -> Option<&Device> {
+        self.servers
+            .get(&id.server)
+            .and_then(|s| s.client.get_devices().get(&id.device))
+    }
+
+    /// Disconnects from every registered server.
+    pub fn clear(&mut self) {
+        for (_, mut s) in self.servers.drain() {
+            _ = s.disconnect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, Read};
+    use std::net::{Shutdown, TcpListener};
+    use std::time::Duration;
+
+    fn fake_server(device: &'static str) -> (std::net::SocketAddr, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = Vec::new();
+            reader.read_until(b'>', &mut request).unwrap();
+            assert!(request.starts_with(b"<getProperties"));
+
+            write!(
+                stream,
+                r#"<defNumberVector device="{}" name="FOCUS" state="Idle" perm="rw"><defNumber name="POSITION" format="%5.0f" min="0" max="100" step="1">10</defNumber></defNumberVector>"#,
+                device
+            )
+            .unwrap();
+            stream.flush().unwrap();
+
+            let mut received = String::new();
+            stream.shutdown(Shutdown::Write).unwrap();
+            reader.read_to_string(&mut received).unwrap();
+            received
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn test_namespaced_devices_and_routing() {
+        let (addr1, server1) = fake_server("Focuser");
+        let (addr2, server2) = fake_server("Focuser");
+
+        let (mut aggregator, events) = Aggregator::new();
+        aggregator.connect("pier1", addr1).unwrap();
+        aggregator.connect("pier2", addr2).unwrap();
+
+        let mut defined = 0;
+        while defined < 2 {
+            match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+                ServerEvent::Command {
+                    server,
+                    connection,
+                    command: Ok(command),
+                } => {
+                    aggregator.update(&server, connection, command).unwrap();
+                    defined += 1;
+                }
+                ServerEvent::Command {
+                    command: Err(e), ..
+                } => panic!("{:?}", e),
+                ServerEvent::Disconnected { .. } => (),
+            }
+        }
+
+        // Both servers sent the same definition.
+        assert!(aggregator.bytes_received("pier1") > 0);
+        assert_eq!(
+            aggregator.bytes_received("pier1"),
+            aggregator.bytes_received("pier2")
+        );
+        assert_eq!(aggregator.bytes_received("pier3"), 0);
+
+        let devices = aggregator.get_devices();
+        assert_eq!(devices.len(), 2);
+        for server in ["pier1", "pier2"] {
+            let id = DeviceId {
+                server: server.to_string(),
+                device: "Focuser".to_string(),
+            };
+            assert!(devices.contains_key(&id));
+            assert_eq!(
+                aggregator.get_device(&id).unwrap().get_parameters().len(),
+                1
+            );
+        }
+
+        aggregator
+            .write(
+                "pier2",
+                &NewNumberVector {
+                    device: "Focuser".to_string(),
+                    name: "FOCUS".to_string(),
+                    timestamp: None,
+                    numbers: vec![OneNumber {
+                        name: "POSITION".to_string(),
+                        min: None,
+                        max: None,
+                        step: None,
+                        value: 42.0,
+                    }],
+                },
+            )
+            .unwrap();
+        assert!(aggregator
+            .write(
+                "pier3",
+                &GetProperties {
+                    version: INDI_PROTOCOL_VERSION.to_string(),
+                    device: None,
+                    name: None,
+                }
+            )
+            .is_err());
+        aggregator.clear();
+
+        assert!(!server1.join().unwrap().contains("newNumberVector"));
+        assert!(server2.join().unwrap().contains("newNumberVector"));
+    }
+
+    #[test]
+    fn test_reconnect_ignores_old_connection() {
+        let (addr1, server1) = fake_server("Old Focuser");
+        let (addr2, server2) = fake_server("New Focuser");
+
+        let (mut aggregator, events) = Aggregator::new();
+        aggregator.connect("pier1", addr1).unwrap();
+        let old = events.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // Reconnecting under the same name drops the first connection, whose
+        // reader then reports it closed.
+        aggregator.connect("pier1", addr2).unwrap();
+        match old {
+            ServerEvent::Command {
+                server,
+                connection,
+                command: Ok(command),
+            } => {
+                assert!(!aggregator.is_current(&server, connection));
+                assert!(aggregator
+                    .update(&server, connection, command)
+                    .unwrap()
+                    .is_none());
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let mut disconnected = false;
+        let mut defined = false;
+        while !disconnected || !defined {
+            match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+                ServerEvent::Command {
+                    server,
+                    connection,
+                    command: Ok(command),
+                } => {
+                    assert!(aggregator.is_current(&server, connection));
+                    assert!(aggregator
+                        .update(&server, connection, command)
+                        .unwrap()
+                        .is_some());
+                    defined = true;
+                }
+                ServerEvent::Command {
+                    command: Err(e), ..
+                } => panic!("{:?}", e),
+                ServerEvent::Disconnected { server, connection } => {
+                    assert!(!aggregator.is_current(&server, connection));
+                    aggregator.disconnect(&server, connection).unwrap();
+                    disconnected = true;
+                }
+            }
+        }
+
+        assert_eq!(aggregator.server_names(), vec!["pier1"]);
+        let devices: Vec<String> = aggregator
+            .get_devices()
+            .into_keys()
+            .map(|id| id.device)
+            .collect();
+        assert_eq!(devices, vec!["New Focuser"]);
+
+        aggregator.clear();
+        server1.join().unwrap();
+        server2.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_connection() {
+        let (local, mut remote) = std::os::unix::net::UnixStream::pair().unwrap();
+        let (mut aggregator, events) = Aggregator::new();
+        aggregator
+            .add_connection("local", Connection::from_transport(local).unwrap())
+            .unwrap();
+
+        let mut reader = BufReader::new(remote.try_clone().unwrap());
+        let mut request = Vec::new();
+        reader.read_until(b'>', &mut request).unwrap();
+        assert!(request.starts_with(b"<getProperties"));
+        write!(
+            remote,
+            r#"<defNumberVector device="Focuser" name="FOCUS" state="Idle" perm="rw"><defNumber name="POSITION" format="%5.0f" min="0" max="100" step="1">10</defNumber></defNumberVector>"#
+        )
+        .unwrap();
+        match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+            ServerEvent::Command {
+                server,
+                connection,
+                command: Ok(command),
+            } => {
+                assert!(aggregator
+                    .update(&server, connection, command)
+                    .unwrap()
+                    .is_some());
+            }
+            other => panic!("{:?}", other),
+        }
+
+        aggregator
+            .write(
+                "local",
+                &EnableBlob {
+                    device: "Focuser".to_string(),
+                    name: None,
+                    enabled: BlobEnable::Never,
+                },
+            )
+            .unwrap();
+        aggregator.clear();
+        let mut received = String::new();
+        reader.read_to_string(&mut received).unwrap();
+        assert!(received.contains("enableBLOB"));
+    }
+}
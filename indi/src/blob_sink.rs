@@ -0,0 +1,416 @@
+use super::*;
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::PathBuf;
+
+/// Name of the file, within a sink's directory, that lists the BLOBs it has saved.
+pub static BLOB_INDEX: &str = "index.tsv";
+
+#[derive(Debug)]
+pub enum BlobSinkError {
+    Io(io::Error),
+    /// The file name template is malformed, or uses an unknown field.
+    Template(String),
+}
+
+impl fmt::Display for BlobSinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlobSinkError::Io(e) => write!(f, "{}", e),
+            BlobSinkError::Template(e) => write!(f, "bad template: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BlobSinkError {}
+
+impl From<io::Error> for BlobSinkError {
+    fn from(e: io::Error) -> Self {
+        BlobSinkError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Device,
+    Property,
+    Element,
+    Timestamp,
+    Sequence,
+    Format,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    /// A field, zero padded to the given width.
+    Field(Field, usize),
+}
+
+/// A file name template, such as `{device}/{property}_{sequence:04}{format}`.
+///
+/// The fields are:
+///  * `{device}`, `{property}` and `{element}`: where the BLOB came from,
+///  * `{timestamp}`: when it was captured, as `20221013T074156.301` (UTC),
+///  * `{sequence}`: a count of the BLOBs saved, which `{sequence:N}` zero pads to N digits,
+///  * `{format}`: the BLOB's format, which INDI gives as an extension such as `.fits`.
+///
+/// Slashes in the template create subdirectories; slashes in field values don't.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobTemplate {
+    parts: Vec<Part>,
+}
+
+impl BlobTemplate {
+    pub fn parse(template: &str) -> Result<BlobTemplate, BlobSinkError> {
+        let mut parts = vec![];
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..].find('}').ok_or_else(|| {
+                BlobSinkError::Template(format!("unclosed field in {}", template))
+            })? + start;
+            let (name, width) = match rest[start + 1..end].split_once(':') {
+                Some((name, width)) => (
+                    name,
+                    width.parse().map_err(|_| {
+                        BlobSinkError::Template(format!("bad width in {{{}:{}}}", name, width))
+                    })?,
+                ),
+                None => (&rest[start + 1..end], 0),
+            };
+            let field = match name {
+                "device" => Field::Device,
+                "property" => Field::Property,
+                "element" => Field::Element,
+                "timestamp" => Field::Timestamp,
+                "sequence" => Field::Sequence,
+                "format" => Field::Format,
+                _ => {
+                    return Err(BlobSinkError::Template(format!(
+                        "unknown field {{{}}}",
+                        name
+                    )))
+                }
+            };
+            parts.push(Part::Field(field, width));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(BlobTemplate { parts })
+    }
+
+    /// The file name for `blob`, the `sequence`th BLOB saved from `vector`.
+    pub fn render(
+        &self,
+        vector: &SetBlobVector,
+        blob: &OneBlob,
+        timestamp: &DateTime<Utc>,
+        sequence: u64,
+    ) -> String {
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => name.push_str(literal),
+                Part::Field(Field::Sequence, width) => {
+                    name.push_str(&format!("{:0width$}", sequence, width = *width))
+                }
+                Part::Field(field, _) => {
+                    let value = match field {
+                        Field::Device => vector.device.clone(),
+                        Field::Property => vector.name.clone(),
+                        Field::Element => blob.name.clone(),
+                        Field::Timestamp => timestamp.format("%Y%m%dT%H%M%S%.3f").to_string(),
+                        Field::Format => blob.format.trim().to_string(),
+                        Field::Sequence => unreachable!(),
+                    };
+                    name.extend(value.chars().map(|c| match c {
+                        '/' | '\\' | ':' | ' ' => '_',
+                        c if c.is_control() => '_',
+                        c => c,
+                    }));
+                }
+            }
+        }
+        // A field of `..`, or one that starts with a dot, mustn't make a
+        // component that leaves the sink's directory or hides the file.
+        name.split('/')
+            .map(|component| match component.strip_prefix('.') {
+                Some(rest) => format!("_{}", rest),
+                None => component.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+impl Default for BlobTemplate {
+    fn default() -> Self {
+        BlobTemplate::parse(BlobSink::DEFAULT_TEMPLATE).unwrap()
+    }
+}
+
+/// Saves the BLOBs from `setBLOBVector` updates to a directory, naming each
+/// file with a `BlobTemplate`.  Files are written under a temporary name and
+/// renamed into place, so a file that exists is complete, and every saved
+/// BLOB gets a line in the directory's `index.tsv`.  Sequence numbers carry
+/// on from the index when a sink is created over an existing directory.
+///
+/// Example usage:
+/// ```no_run
+/// let mut connection = indi::Connection::new("localhost:7624").unwrap();
+/// let mut sink = indi::BlobSink::new("images").unwrap();
+///
+/// for command in connection.iter().unwrap() {
+///     for path in sink.update(&command.unwrap()).unwrap() {
+///         println!("Saved {}", path.display());
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct BlobSink {
+    directory: PathBuf,
+    template: BlobTemplate,
+    sequence: u64,
+}
+
+impl BlobSink {
+    pub const DEFAULT_TEMPLATE: &'static str =
+        "{device}_{property}_{timestamp}_{sequence:04}{format}";
+
+    /// Creates a sink that saves BLOBs into `directory`, creating it if needed.
+    pub fn new<P: AsRef<Path>>(directory: P) -> Result<BlobSink, BlobSinkError> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+        let sequence = BlobSink::last_sequence(&directory.join(BLOB_INDEX))?;
+        Ok(BlobSink {
+            directory,
+            template: BlobTemplate::default(),
+            sequence,
+        })
+    }
+
+    /// Names files with `template` instead of `DEFAULT_TEMPLATE`.
+    pub fn with_template(mut self, template: &str) -> Result<BlobSink, BlobSinkError> {
+        self.template = BlobTemplate::parse(template)?;
+        Ok(self)
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// The sequence number of the last BLOB saved.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Saves the BLOBs in `command`, if it is a `setBLOBVector`, returning the paths written.
+    pub fn update(&mut self, command: &Command) -> Result<Vec<PathBuf>, BlobSinkError> {
+        match command {
+            Command::SetBlobVector(vector) => self.save(vector),
+            _ => Ok(vec![]),
+        }
+    }
+
+    /// Saves each BLOB in `vector` that has a value, returning the paths written.
+    pub fn save(&mut self, vector: &SetBlobVector) -> Result<Vec<PathBuf>, BlobSinkError> {
+        let timestamp = vector.timestamp.unwrap_or_else(Utc::now);
+        let mut paths = vec![];
+        for blob in &vector.blobs {
+            // Empty BLOBs are how drivers clear a property, not images.
+            if blob.value.is_empty() {
+                continue;
+            }
+            self.sequence += 1;
+            let name = self
+                .template
+                .render(vector, blob, &timestamp, self.sequence);
+            let path = self.directory.join(&name);
+            write_atomic(&path, &blob.value)?;
+            self.append_index(vector, blob, &timestamp, &name)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    fn append_index(
+        &self,
+        vector: &SetBlobVector,
+        blob: &OneBlob,
+        timestamp: &DateTime<Utc>,
+        name: &str,
+    ) -> io::Result<()> {
+        let path = self.directory.join(BLOB_INDEX);
+        let new = !path.exists();
+        let mut index = OpenOptions::new().create(true).append(true).open(path)?;
+        if new {
+            writeln!(
+                index,
+                "sequence\ttimestamp\tdevice\tproperty\telement\tformat\tsize\tfile"
+            )?;
+        }
+        writeln!(
+            index,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.sequence,
+            format_timestamp(timestamp),
+            index_field(&vector.device),
+            index_field(&vector.name),
+            index_field(&blob.name),
+            index_field(blob.format.trim()),
+            blob.value.len(),
+            index_field(name),
+        )
+    }
+
+    fn last_sequence(index: &Path) -> io::Result<u64> {
+        match fs::read_to_string(index) {
+            Ok(contents) => Ok(contents
+                .lines()
+                .filter_map(|line| line.split('\t').next()?.parse().ok())
+                .max()
+                .unwrap_or(0)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn index_field(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+/// Writes `contents` to `path` by way of a temporary file in the same
+/// directory, so readers never see a partly written file.
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temporary = path.with_file_name(format!(".{}.part", file_name));
+    let mut file = File::create(&temporary)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("indi-blob-sink-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn set_blob(blobs: &[(&str, &[u8])]) -> SetBlobVector {
+        SetBlobVector {
+            device: "CCD Simulator".to_string(),
+            name: "CCD1".to_string(),
+            state: PropertyState::Ok,
+            timeout: None,
+            timestamp: Some(DateTime::from_str("2022-10-13T07:41:56.301Z").unwrap()),
+            message: None,
+            blobs: blobs
+                .iter()
+                .map(|(name, value)| OneBlob {
+                    name: name.to_string(),
+                    size: value.len() as u64,
+                    enclen: None,
+                    format: ".fits".to_string(),
+                    value: value.to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_template() {
+        let vector = set_blob(&[("CCD1", b"data")]);
+        let timestamp = vector.timestamp.unwrap();
+        assert_eq!(
+            BlobTemplate::default().render(&vector, &vector.blobs[0], &timestamp, 7),
+            "CCD_Simulator_CCD1_20221013T074156.301_0007.fits"
+        );
+        assert_eq!(
+            BlobTemplate::parse("{device}/{element}-{sequence}{format}")
+                .unwrap()
+                .render(&vector, &vector.blobs[0], &timestamp, 12),
+            "CCD_Simulator/CCD1-12.fits"
+        );
+
+        let mut vector = vector;
+        vector.device = "..".to_string();
+        let template = BlobTemplate::parse("{device}/{element}{format}").unwrap();
+        assert_eq!(
+            template.render(&vector, &vector.blobs[0], &timestamp, 1),
+            "_./CCD1.fits"
+        );
+        vector.device = ".hidden".to_string();
+        assert_eq!(
+            template.render(&vector, &vector.blobs[0], &timestamp, 1),
+            "_hidden/CCD1.fits"
+        );
+
+        for template in ["{device", "{nope}", "{sequence:x}"] {
+            assert!(BlobTemplate::parse(template).is_err(), "{}", template);
+        }
+    }
+
+    #[test]
+    fn test_save() {
+        let directory = directory("save");
+        let mut sink = BlobSink::new(&directory)
+            .unwrap()
+            .with_template("{device}/{element}_{sequence:03}{format}")
+            .unwrap();
+
+        let paths = sink
+            .update(&Command::SetBlobVector(set_blob(&[
+                ("CCD1", b"one"),
+                ("EMPTY", b""),
+                ("CCD2", b"two"),
+            ])))
+            .unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                directory.join("CCD_Simulator/CCD1_001.fits"),
+                directory.join("CCD_Simulator/CCD2_002.fits"),
+            ]
+        );
+        assert_eq!(fs::read(&paths[1]).unwrap(), b"two");
+        assert!(!directory.join("CCD_Simulator/.CCD2_002.fits.part").exists());
+
+        let index = fs::read_to_string(directory.join(BLOB_INDEX)).unwrap();
+        let lines: Vec<&str> = index.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[2],
+            "2\t2022-10-13T07:41:56.301\tCCD Simulator\tCCD1\tCCD2\t.fits\t3\tCCD_Simulator/CCD2_002.fits"
+        );
+
+        // A new sink over the same directory carries on the sequence.
+        let mut sink = BlobSink::new(&directory).unwrap();
+        assert_eq!(sink.sequence(), 2);
+        let paths = sink.save(&set_blob(&[("CCD1", b"three")])).unwrap();
+        assert_eq!(
+            paths,
+            vec![directory.join("CCD_Simulator_CCD1_20221013T074156.301_0003.fits")]
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,418 @@
+use super::*;
+
+/// A device, or one of its properties, that a driver is snooping on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnoopTarget {
+    pub device: String,
+    pub name: Option<String>,
+}
+
+impl SnoopTarget {
+    fn matches(&self, device: &str, name: Option<&String>) -> bool {
+        self.device == device
+            && match (&self.name, name) {
+                (Some(wanted), Some(name)) => wanted == name,
+                _ => true,
+            }
+    }
+}
+
+/// Lets a driver follow the properties of other devices, such as a CCD driver
+/// reading the mount's coordinates for its FITS headers.
+///
+/// A driver asks indiserver for another device's properties with `getProperties`,
+/// after which the server forwards that device's definitions, updates and
+/// deletions to the driver alongside the `new*` commands meant for the driver's
+/// own devices.  `Snooper` builds those requests, picks the snooped traffic out of
+/// the driver's input and keeps track of the snooped properties.  A hub that
+/// runs drivers itself, like the simulator's rig, does the server's part with
+/// `SnoopRouter`.
+///
+/// Example usage:
+/// ```no_run
+/// let mut connection = indi::Connection::stdio().unwrap();
+/// let mut snooper = indi::Snooper::new();
+/// snooper
+///     .snoop_on(&mut connection, "Telescope Simulator", Some("EQUATORIAL_EOD_COORD"))
+///     .unwrap();
+///
+/// for command in connection.iter().unwrap() {
+///     let command = command.unwrap();
+///     if snooper.is_snooped(&command) {
+///         if let Ok(Some(param)) = snooper.update(command) {
+///             println!("Mount: {:?}", param);
+///         }
+///     } else {
+///         // A command for one of the driver's own devices.
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Snooper {
+    targets: Vec<SnoopTarget>,
+    client: Client,
+}
+
+impl Default for Snooper {
+    fn default() -> Self {
+        Snooper::new()
+    }
+}
+
+impl Snooper {
+    pub fn new() -> Snooper {
+        Snooper {
+            targets: vec![],
+            client: Client::new(),
+        }
+    }
+
+    /// Starts snooping on `device`'s property `name`, or on all of its properties
+    /// when `name` is `None`.  Returns the request to send to the server.
+    pub fn snoop(&mut self, device: &str, name: Option<&str>) -> GetProperties {
+        let target = SnoopTarget {
+            device: device.to_string(),
+            name: name.map(String::from),
+        };
+        let request = Snooper::request(&target);
+        if !self.targets.contains(&target) {
+            self.targets.push(target);
+        }
+        request
+    }
+
+    /// Starts snooping on `device`'s property `name`, sending the request over `connection`.
+    pub fn snoop_on<T: Transport>(
+        &mut self,
+        connection: &mut Connection<T>,
+        device: &str,
+        name: Option<&str>,
+    ) -> Result<(), DeError> {
+        connection.write(&self.snoop(device, name))
+    }
+
+    /// The requests for everything being snooped on, to be sent again after reconnecting.
+    pub fn requests(&self) -> impl Iterator<Item = GetProperties> + '_ {
+        self.targets.iter().map(Snooper::request)
+    }
+
+    pub fn targets(&self) -> &Vec<SnoopTarget> {
+        &self.targets
+    }
+
+    /// Whether `command` is snooped traffic, rather than a command for the driver's own devices.
+    pub fn is_snooped(&self, command: &Command) -> bool {
+        match snoopable_device(command) {
+            Some(device) => {
+                let name = command.property_name();
+                self.targets.iter().any(|t| t.matches(device, name))
+            }
+            None => false,
+        }
+    }
+
+    /// Updates the snooped properties from `command`.  Commands that aren't snooped
+    /// traffic are ignored.
+    pub fn update(&mut self, command: Command) -> Result<Option<&Parameter>, UpdateError> {
+        if !self.is_snooped(&command) {
+            return Ok(None);
+        }
+        self.client.update(command)
+    }
+
+    /// The snooped devices and their properties.
+    pub fn get_devices(&self) -> &HashMap<String, Device> {
+        self.client.get_devices()
+    }
+
+    /// The client tracking the snooped properties, for typed access with
+    /// `Client::number` and friends.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    fn request(target: &SnoopTarget) -> GetProperties {
+        GetProperties {
+            version: INDI_PROTOCOL_VERSION.to_string(),
+            device: Some(target.device.clone()),
+            name: target.name.clone(),
+        }
+    }
+}
+
+/// The device whose properties `command` describes, if it's the kind of
+/// command a snooping driver is sent.
+fn snoopable_device(command: &Command) -> Option<&String> {
+    match command {
+        Command::DefTextVector(_)
+        | Command::SetTextVector(_)
+        | Command::DefNumberVector(_)
+        | Command::SetNumberVector(_)
+        | Command::DefSwitchVector(_)
+        | Command::SetSwitchVector(_)
+        | Command::DefLightVector(_)
+        | Command::SetLightVector(_)
+        | Command::DefBlobVector(_)
+        | Command::SetBlobVector(_)
+        | Command::DelProperty(_)
+        | Command::Message(_) => command.device_name(),
+        _ => None,
+    }
+}
+
+/// The server side of snooping, for a hub that runs drivers itself: records
+/// the `getProperties` requests each driver sends for other devices, and works
+/// out which drivers a command from another driver should be forwarded to.
+/// Drivers are identified by `K`, such as an index or the driver's name.
+///
+/// Example usage:
+/// ```
+/// let mut router = indi::SnoopRouter::new();
+/// router.request(
+///     "ccd",
+///     &indi::GetProperties {
+///         version: indi::INDI_PROTOCOL_VERSION.to_string(),
+///         device: Some("Telescope Simulator".to_string()),
+///         name: None,
+///     },
+/// );
+///
+/// let update = indi::Command::Message(indi::Message {
+///     device: Some("Telescope Simulator".to_string()),
+///     timestamp: None,
+///     message: Some("Slewing".to_string()),
+/// });
+/// let recipients: Vec<_> = router.recipients(&"mount", &update).collect();
+/// assert_eq!(recipients, vec![&"ccd"]);
+/// ```
+#[derive(Debug)]
+pub struct SnoopRouter<K> {
+    targets: HashMap<K, Vec<SnoopTarget>>,
+}
+
+impl<K: Eq + std::hash::Hash> Default for SnoopRouter<K> {
+    fn default() -> Self {
+        SnoopRouter::new()
+    }
+}
+
+impl<K: Eq + std::hash::Hash> SnoopRouter<K> {
+    pub fn new() -> SnoopRouter<K> {
+        SnoopRouter {
+            targets: HashMap::new(),
+        }
+    }
+
+    /// Records `request`, which the driver `from` sent.  Returns whether it
+    /// was a snoop request; requests that don't name a device aren't.
+    pub fn request(&mut self, from: K, request: &GetProperties) -> bool {
+        let device = match &request.device {
+            Some(device) => device.clone(),
+            None => return false,
+        };
+        let target = SnoopTarget {
+            device,
+            name: request.name.clone(),
+        };
+        let targets = self.targets.entry(from).or_default();
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+        true
+    }
+
+    /// The drivers snooping on what `command`, sent by the driver `from`,
+    /// describes.  A driver is never sent its own commands.
+    pub fn recipients<'a>(
+        &'a self,
+        from: &'a K,
+        command: &'a Command,
+    ) -> impl Iterator<Item = &'a K> + 'a {
+        let device = snoopable_device(command);
+        let name = command.property_name();
+        self.targets
+            .iter()
+            .filter(move |(driver, targets)| {
+                *driver != from
+                    && device.is_some_and(|device| targets.iter().any(|t| t.matches(device, name)))
+            })
+            .map(|(driver, _)| driver)
+    }
+
+    /// Forgets the requests of a driver that has gone away.
+    pub fn remove(&mut self, driver: &K) {
+        self.targets.remove(driver);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_number(device: &str, name: &str, value: f64) -> Command {
+        Command::SetNumberVector(SetNumberVector {
+            device: device.to_string(),
+            name: name.to_string(),
+            state: PropertyState::Ok,
+            timeout: None,
+            timestamp: None,
+            message: None,
+            numbers: vec![OneNumber {
+                name: "RA".to_string(),
+                min: None,
+                max: None,
+                step: None,
+                value,
+            }],
+        })
+    }
+
+    #[test]
+    fn test_snoop_requests() {
+        let mut snooper = Snooper::new();
+        assert_eq!(
+            snooper.snoop("Telescope Simulator", Some("EQUATORIAL_EOD_COORD")),
+            GetProperties {
+                version: INDI_PROTOCOL_VERSION.to_string(),
+                device: Some("Telescope Simulator".to_string()),
+                name: Some("EQUATORIAL_EOD_COORD".to_string()),
+            }
+        );
+        snooper.snoop("Telescope Simulator", Some("EQUATORIAL_EOD_COORD"));
+        snooper.snoop("Focuser Simulator", None);
+
+        let requests: Vec<GetProperties> = snooper.requests().collect();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].device, Some("Focuser Simulator".to_string()));
+        assert_eq!(requests[1].name, None);
+    }
+
+    #[test]
+    fn test_snooped_traffic() {
+        let mut snooper = Snooper::new();
+        snooper.snoop("Telescope Simulator", Some("EQUATORIAL_EOD_COORD"));
+        snooper.snoop("Focuser Simulator", None);
+
+        assert!(snooper.is_snooped(&set_number(
+            "Telescope Simulator",
+            "EQUATORIAL_EOD_COORD",
+            1.0
+        )));
+        assert!(!snooper.is_snooped(&set_number("Telescope Simulator", "TARGET_EOD_COORD", 1.0)));
+        assert!(snooper.is_snooped(&set_number("Focuser Simulator", "ABS_FOCUS_POSITION", 1.0)));
+        assert!(!snooper.is_snooped(&set_number("CCD Simulator", "CCD_EXPOSURE", 1.0)));
+        assert!(snooper.is_snooped(&Command::Message(Message {
+            device: Some("Telescope Simulator".to_string()),
+            timestamp: None,
+            message: Some("Slewing".to_string()),
+        })));
+
+        // Clients setting a snooped property are talking to that device's driver, not this one.
+        assert!(
+            !snooper.is_snooped(&Command::NewNumberVector(NewNumberVector {
+                device: "Focuser Simulator".to_string(),
+                name: "ABS_FOCUS_POSITION".to_string(),
+                timestamp: None,
+                numbers: vec![],
+            }))
+        );
+    }
+
+    #[test]
+    fn test_router() {
+        let mut router = SnoopRouter::new();
+        let request = |device: Option<&str>, name: Option<&str>| GetProperties {
+            version: INDI_PROTOCOL_VERSION.to_string(),
+            device: device.map(String::from),
+            name: name.map(String::from),
+        };
+        assert!(router.request(
+            "ccd",
+            &request(Some("Telescope Simulator"), Some("EQUATORIAL_EOD_COORD"))
+        ));
+        assert!(router.request("focuser", &request(Some("Telescope Simulator"), None)));
+        assert!(!router.request("mount", &request(None, None)));
+
+        fn recipients<'a>(
+            router: &SnoopRouter<&'a str>,
+            from: &'a str,
+            command: &Command,
+        ) -> Vec<&'a str> {
+            let mut recipients: Vec<&str> = router.recipients(&from, command).copied().collect();
+            recipients.sort();
+            recipients
+        }
+        let coords = set_number("Telescope Simulator", "EQUATORIAL_EOD_COORD", 5.5);
+        assert_eq!(
+            recipients(&router, "mount", &coords),
+            vec!["ccd", "focuser"]
+        );
+        assert_eq!(recipients(&router, "ccd", &coords), vec!["focuser"]);
+        assert_eq!(
+            recipients(
+                &router,
+                "mount",
+                &set_number("Telescope Simulator", "TIME_LST", 1.0)
+            ),
+            vec!["focuser"]
+        );
+        assert!(recipients(
+            &router,
+            "mount",
+            &set_number("CCD Simulator", "CCD_EXPOSURE", 1.0)
+        )
+        .is_empty());
+        // Clients' commands to the snooped device aren't forwarded to snoopers.
+        let new = Command::NewNumberVector(NewNumberVector {
+            device: "Telescope Simulator".to_string(),
+            name: "EQUATORIAL_EOD_COORD".to_string(),
+            timestamp: None,
+            numbers: vec![],
+        });
+        assert!(recipients(&router, "client", &new).is_empty());
+
+        router.remove(&"focuser");
+        assert_eq!(recipients(&router, "mount", &coords), vec!["ccd"]);
+    }
+
+    #[test]
+    fn test_snooped_updates() {
+        let xml = r#"
+<defNumberVector device="Telescope Simulator" name="EQUATORIAL_EOD_COORD" state="Idle" perm="rw">
+    <defNumber name="RA" format="%010.6m" min="0" max="24" step="0">0</defNumber>
+</defNumberVector>
+<setNumberVector device="Telescope Simulator" name="EQUATORIAL_EOD_COORD" state="Ok">
+    <oneNumber name="RA">5.5</oneNumber>
+</setNumberVector>
+<newNumberVector device="CCD Simulator" name="CCD_EXPOSURE">
+    <oneNumber name="CCD_EXPOSURE_VALUE">1</oneNumber>
+</newNumberVector>
+"#;
+        let transport = Duplex::new(std::io::empty(), Vec::new());
+        let mut connection = Connection::from_transport(transport).unwrap();
+        let mut snooper = Snooper::new();
+        snooper
+            .snoop_on(&mut connection, "Telescope Simulator", None)
+            .unwrap();
+
+        let mut own = vec![];
+        for command in CommandIter::from_xml(xml) {
+            let command = command.unwrap();
+            if snooper.is_snooped(&command) {
+                snooper.update(command).unwrap();
+            } else {
+                own.push(command);
+            }
+        }
+
+        assert_eq!(own.len(), 1);
+        assert_eq!(own[0].device_name(), Some(&"CCD Simulator".to_string()));
+        match snooper.get_devices()["Telescope Simulator"]
+            .get_parameters()
+            .get("EQUATORIAL_EOD_COORD")
+        {
+            Some(Parameter::NumberVector(coords)) => assert_eq!(coords.values["RA"].value, 5.5),
+            e => panic!("Unexpected: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,592 @@
+use super::*;
+
+use std::fmt;
+
+const CARD: usize = 80;
+/// The most characters a string value can have between its quotes.
+const TEXT: usize = CARD - 12;
+const BLOCK: usize = 2880;
+
+#[derive(Debug, PartialEq)]
+pub enum FitsError {
+    /// The data doesn't start with a `SIMPLE` card.
+    NotFits,
+    /// The header has no `END` card.
+    MissingEnd,
+}
+
+impl fmt::Display for FitsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FitsError::NotFits => write!(f, "not a FITS file"),
+            FitsError::MissingEnd => write!(f, "FITS header has no END card"),
+        }
+    }
+}
+
+impl std::error::Error for FitsError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FitsValue {
+    Text(String),
+    Number(f64),
+}
+
+/// A keyword, value and comment for a FITS header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FitsCard {
+    pub keyword: &'static str,
+    pub value: FitsValue,
+    pub comment: &'static str,
+}
+
+impl FitsCard {
+    fn text(keyword: &'static str, value: String, comment: &'static str) -> FitsCard {
+        FitsCard {
+            keyword,
+            value: FitsValue::Text(value),
+            comment,
+        }
+    }
+
+    fn number(keyword: &'static str, value: f64, comment: &'static str) -> FitsCard {
+        FitsCard {
+            keyword,
+            value: FitsValue::Number(value),
+            comment,
+        }
+    }
+
+    /// The card as the 80 characters of a FITS header record.  Long strings
+    /// are cut short so that their closing quote still fits.
+    pub fn to_record(&self) -> String {
+        let value = match &self.value {
+            // Strings are at least 8 characters between the quotes, and numbers
+            // are right justified to column 30.
+            FitsValue::Text(value) => {
+                let mut quoted = String::new();
+                for c in value
+                    .chars()
+                    .filter(|c| c.is_ascii() && !c.is_ascii_control())
+                {
+                    let escaped = if c == '\'' {
+                        "''".to_string()
+                    } else {
+                        c.to_string()
+                    };
+                    if quoted.len() + escaped.len() > TEXT {
+                        break;
+                    }
+                    quoted.push_str(&escaped);
+                }
+                format!("'{:<8}'", quoted)
+            }
+            FitsValue::Number(value) => {
+                let number = value.to_string();
+                if number.len() > 20 {
+                    format!("{:>20E}", value)
+                } else {
+                    format!("{:>20}", number)
+                }
+            }
+        };
+        let mut record = format!("{:<8}= {} / {}", self.keyword, value, self.comment);
+        record.retain(|c| c.is_ascii() && !c.is_ascii_control());
+        record.truncate(CARD);
+        format!("{:<80}", record)
+    }
+}
+
+/// Which devices to take header values from.  Devices left as `None` are
+/// found by looking for the first device that has the relevant property; the
+/// camera defaults to the device that sent the BLOB.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FitsDevices {
+    pub mount: Option<String>,
+    pub focuser: Option<String>,
+    pub filter_wheel: Option<String>,
+    pub camera: Option<String>,
+}
+
+/// Adds the state of the rest of the rig to the FITS images cameras send,
+/// mapping standard INDI properties to the usual FITS keywords:
+///
+/// | Keyword | Property |
+/// |---------|----------|
+/// | `TELESCOP` | the mount's device name |
+/// | `RA`, `DEC`, `OBJCTRA`, `OBJCTDEC` | `EQUATORIAL_EOD_COORD` |
+/// | `FOCALLEN`, `APTDIA` | `TELESCOPE_INFO` |
+/// | `SITELAT`, `SITELONG`, `SITEELEV` | `GEOGRAPHIC_COORD` |
+/// | `FOCUSPOS` | `ABS_FOCUS_POSITION` |
+/// | `FOCTEMP` | `FOCUS_TEMPERATURE` |
+/// | `FILTER` | `FILTER_SLOT` and `FILTER_NAME` |
+/// | `INSTRUME` | the camera's device name |
+/// | `CCD-TEMP` | `CCD_TEMPERATURE` |
+/// | `DATE-END` | the BLOB's timestamp |
+///
+/// INDI stamps a BLOB when the exposure ends, so the time is written as
+/// `DATE-END`; the driver's own `DATE-OBS`, if it wrote one, is the start.
+///
+/// Keywords the driver already wrote are left alone.
+///
+/// Example usage:
+/// ```no_run
+/// let mut connection = indi::Connection::new("localhost:7624").unwrap();
+/// let mut client = indi::Client::new();
+/// let mut sink = indi::BlobSink::new("images").unwrap();
+/// let enricher = indi::FitsEnricher::default();
+///
+/// for command in connection.iter().unwrap() {
+///     match command.unwrap() {
+///         indi::Command::SetBlobVector(mut blobs) => {
+///             enricher.enrich_vector(&client, &mut blobs);
+///             sink.save(&blobs).unwrap();
+///         }
+///         command => {
+///             client.update(command).unwrap();
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FitsEnricher {
+    pub devices: FitsDevices,
+}
+
+impl FitsEnricher {
+    pub fn new(devices: FitsDevices) -> FitsEnricher {
+        FitsEnricher { devices }
+    }
+
+    /// The header cards for an image from `camera` whose exposure ended at
+    /// `timestamp`.
+    pub fn cards(&self, client: &Client, camera: &str, timestamp: &DateTime<Utc>) -> Vec<FitsCard> {
+        let mut cards = vec![];
+        let number = |device: &Option<String>, property: &str, element: &str| {
+            let device = find_device(client, device, property)?;
+            client.number(device, property).ok()?.get(element).ok()
+        };
+
+        let mount = find_device(client, &self.devices.mount, "EQUATORIAL_EOD_COORD");
+        if let Some(mount) = mount {
+            cards.push(FitsCard::text("TELESCOP", mount.to_string(), "Mount"));
+        }
+        let ra = number(&self.devices.mount, "EQUATORIAL_EOD_COORD", "RA");
+        let dec = number(&self.devices.mount, "EQUATORIAL_EOD_COORD", "DEC");
+        if let (Some(ra), Some(dec)) = (ra, dec) {
+            cards.push(FitsCard::number(
+                "RA",
+                ra * 15.0,
+                "Mount RA (degrees, JNow)",
+            ));
+            cards.push(FitsCard::number("DEC", dec, "Mount Dec (degrees, JNow)"));
+            cards.push(FitsCard::text(
+                "OBJCTRA",
+                sexagesimal(ra, false, 2),
+                "Mount RA (hours, JNow)",
+            ));
+            cards.push(FitsCard::text(
+                "OBJCTDEC",
+                sexagesimal(dec, true, 1),
+                "Mount Dec (degrees, JNow)",
+            ));
+        }
+        if let Some(focal_length) = number(
+            &self.devices.mount,
+            "TELESCOPE_INFO",
+            "TELESCOPE_FOCAL_LENGTH",
+        ) {
+            cards.push(FitsCard::number(
+                "FOCALLEN",
+                focal_length,
+                "Focal length (mm)",
+            ));
+        }
+        if let Some(aperture) = number(&self.devices.mount, "TELESCOPE_INFO", "TELESCOPE_APERTURE")
+        {
+            cards.push(FitsCard::number("APTDIA", aperture, "Aperture (mm)"));
+        }
+
+        // Site coordinates are usually on the mount, but any device may have them.
+        let site = match mount {
+            Some(mount) if client.number(mount, "GEOGRAPHIC_COORD").is_ok() => {
+                Some(mount.to_string())
+            }
+            _ => None,
+        };
+        if let Some(lat) = number(&site, "GEOGRAPHIC_COORD", "LAT") {
+            cards.push(FitsCard::number("SITELAT", lat, "Site latitude (degrees)"));
+        }
+        if let Some(long) = number(&site, "GEOGRAPHIC_COORD", "LONG") {
+            // INDI measures longitude 0 to 360 degrees east.
+            let long = if long > 180.0 { long - 360.0 } else { long };
+            cards.push(FitsCard::number(
+                "SITELONG",
+                long,
+                "Site longitude (degrees east)",
+            ));
+        }
+        if let Some(elevation) = number(&site, "GEOGRAPHIC_COORD", "ELEV") {
+            cards.push(FitsCard::number(
+                "SITEELEV",
+                elevation,
+                "Site elevation (m)",
+            ));
+        }
+
+        if let Some(position) = number(
+            &self.devices.focuser,
+            "ABS_FOCUS_POSITION",
+            "FOCUS_ABSOLUTE_POSITION",
+        ) {
+            cards.push(FitsCard::number(
+                "FOCUSPOS",
+                position,
+                "Focuser position (steps)",
+            ));
+        }
+        if let Some(temperature) = number(&self.devices.focuser, "FOCUS_TEMPERATURE", "TEMPERATURE")
+        {
+            cards.push(FitsCard::number(
+                "FOCTEMP",
+                temperature,
+                "Focuser temperature (C)",
+            ));
+        }
+
+        if let Some(filter) = self.filter(client) {
+            cards.push(FitsCard::text("FILTER", filter, "Filter"));
+        }
+
+        let camera = self.devices.camera.as_deref().unwrap_or(camera);
+        cards.push(FitsCard::text("INSTRUME", camera.to_string(), "Camera"));
+        if let Ok(temperature) = client
+            .number(camera, "CCD_TEMPERATURE")
+            .and_then(|t| t.get("CCD_TEMPERATURE_VALUE"))
+        {
+            cards.push(FitsCard::number(
+                "CCD-TEMP",
+                temperature,
+                "CCD temperature (C)",
+            ));
+        }
+        cards.push(FitsCard::text(
+            "DATE-END",
+            format_timestamp(timestamp),
+            "End of exposure (UTC)",
+        ));
+        cards
+    }
+
+    fn filter(&self, client: &Client) -> Option<String> {
+        let device = find_device(client, &self.devices.filter_wheel, "FILTER_SLOT")?;
+        let slot = client
+            .number(device, "FILTER_SLOT")
+            .ok()?
+            .get("FILTER_SLOT_VALUE")
+            .ok()?;
+        let names = client.text(device, "FILTER_NAME").ok();
+        match names.and_then(|n| n.get(&format!("FILTER_SLOT_NAME_{}", slot.round())).ok()) {
+            Some(name) => Some(name.to_string()),
+            None => Some(format!("{}", slot.round())),
+        }
+    }
+
+    /// Adds the header cards for an image from `camera`, whose exposure ended
+    /// at `timestamp`, to the FITS file `data`.
+    pub fn enrich(
+        &self,
+        client: &Client,
+        camera: &str,
+        timestamp: &DateTime<Utc>,
+        data: &[u8],
+    ) -> Result<Vec<u8>, FitsError> {
+        add_cards(data, &self.cards(client, camera, timestamp))
+    }
+
+    /// Enriches the FITS BLOBs in `vector` in place.  Other formats, and FITS
+    /// files that can't be parsed, are left as they are.
+    pub fn enrich_vector(&self, client: &Client, vector: &mut SetBlobVector) {
+        let timestamp = vector.timestamp.unwrap_or_else(Utc::now);
+        for blob in &mut vector.blobs {
+            if blob.format.trim() != ".fits" || blob.value.is_empty() {
+                continue;
+            }
+            if let Ok(value) = self.enrich(client, &vector.device, &timestamp, &blob.value) {
+                blob.size = value.len() as u64;
+                blob.value = value;
+            }
+        }
+    }
+}
+
+fn find_device<'a>(
+    client: &'a Client,
+    device: &'a Option<String>,
+    property: &str,
+) -> Option<&'a str> {
+    match device {
+        Some(device) => Some(device),
+        None => {
+            let mut devices: Vec<&String> = client
+                .get_devices()
+                .iter()
+                .filter(|(_, d)| d.get_parameters().contains_key(property))
+                .map(|(name, _)| name)
+                .collect();
+            devices.sort();
+            devices.first().map(|d| d.as_str())
+        }
+    }
+}
+
+/// Formats `value` as `HH MM SS.ss`, or `+DD MM SS.s` when `signed`.
+fn sexagesimal(value: f64, signed: bool, precision: usize) -> String {
+    let sign = if value < 0.0 { '-' } else { '+' };
+    let scale = 10f64.powi(precision as i32);
+    // Round once, in the smallest unit shown, so 59.999 seconds carries over.
+    let total = (value.abs() * 3600.0 * scale).round();
+    let seconds = (total % (60.0 * scale)) / scale;
+    let minutes = (total / (60.0 * scale)).floor() as u64 % 60;
+    let degrees = (total / (3600.0 * scale)).floor() as u64;
+    let width = if precision > 0 { precision + 3 } else { 2 };
+    let formatted = format!(
+        "{:02} {:02} {:0width$.precision$}",
+        degrees,
+        minutes,
+        seconds,
+        width = width,
+        precision = precision
+    );
+    if signed {
+        format!("{}{}", sign, formatted)
+    } else {
+        formatted
+    }
+}
+
+/// Adds `cards` to the primary header of the FITS file `data`, skipping
+/// keywords that are already there and numbers that FITS can't represent,
+/// such as NaN.
+pub fn add_cards(data: &[u8], cards: &[FitsCard]) -> Result<Vec<u8>, FitsError> {
+    if !data.starts_with(b"SIMPLE  =") {
+        return Err(FitsError::NotFits);
+    }
+    let end = data
+        .chunks_exact(CARD)
+        .position(|card| card.starts_with(b"END     "))
+        .ok_or(FitsError::MissingEnd)?;
+    let existing = &data[..end * CARD];
+    let data_start = ((end + 1) * CARD).div_ceil(BLOCK) * BLOCK;
+
+    let mut header = existing.to_vec();
+    for card in cards {
+        if matches!(card.value, FitsValue::Number(value) if !value.is_finite()) {
+            continue;
+        }
+        let keyword = format!("{:<8}", card.keyword);
+        if existing
+            .chunks_exact(CARD)
+            .any(|c| c.starts_with(keyword.as_bytes()))
+        {
+            continue;
+        }
+        header.extend_from_slice(card.to_record().as_bytes());
+    }
+    header.extend_from_slice(format!("{:<80}", "END").as_bytes());
+    header.resize(header.len().div_ceil(BLOCK) * BLOCK, b' ');
+
+    header.extend_from_slice(data.get(data_start..).unwrap_or_default());
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> Client {
+        let xml = r#"
+<defNumberVector device="Telescope Simulator" name="EQUATORIAL_EOD_COORD" state="Ok" perm="rw">
+    <defNumber name="RA" format="%010.6m" min="0" max="24" step="0">5.5</defNumber>
+    <defNumber name="DEC" format="%010.6m" min="-90" max="90" step="0">-20.25</defNumber>
+</defNumberVector>
+<defNumberVector device="Telescope Simulator" name="GEOGRAPHIC_COORD" state="Ok" perm="rw">
+    <defNumber name="LAT" format="%010.6m" min="-90" max="90" step="0">51.5</defNumber>
+    <defNumber name="LONG" format="%010.6m" min="0" max="360" step="0">359.5</defNumber>
+    <defNumber name="ELEV" format="%g" min="-200" max="10000" step="0">35</defNumber>
+</defNumberVector>
+<defNumberVector device="Focuser Simulator" name="ABS_FOCUS_POSITION" state="Ok" perm="rw">
+    <defNumber name="FOCUS_ABSOLUTE_POSITION" format="%6.0f" min="0" max="100000" step="1">36700</defNumber>
+</defNumberVector>
+<defNumberVector device="Filter Simulator" name="FILTER_SLOT" state="Ok" perm="rw">
+    <defNumber name="FILTER_SLOT_VALUE" format="%3.0f" min="1" max="5" step="1">2</defNumber>
+</defNumberVector>
+<defTextVector device="Filter Simulator" name="FILTER_NAME" state="Idle" perm="rw">
+    <defText name="FILTER_SLOT_NAME_1">Red</defText>
+    <defText name="FILTER_SLOT_NAME_2">Green</defText>
+</defTextVector>
+<defNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" state="Idle" perm="rw">
+    <defNumber name="CCD_TEMPERATURE_VALUE" format="%5.2f" min="-50" max="50" step="0">-10</defNumber>
+</defNumberVector>
+"#;
+        let mut client = Client::new();
+        for command in CommandIter::from_xml(xml) {
+            client.update(command.unwrap()).unwrap();
+        }
+        client
+    }
+
+    fn fits(cards: &[&str], data: &[u8]) -> Vec<u8> {
+        let mut fits = vec![];
+        for card in cards.iter().chain(&["END"]) {
+            fits.extend_from_slice(format!("{:<80}", card).as_bytes());
+        }
+        fits.resize(BLOCK, b' ');
+        fits.extend_from_slice(data);
+        fits
+    }
+
+    fn header(fits: &[u8]) -> Vec<String> {
+        fits.chunks_exact(CARD)
+            .map(|c| String::from_utf8_lossy(c).trim_end().to_string())
+            .take_while(|c| c != "END")
+            .collect()
+    }
+
+    #[test]
+    fn test_records() {
+        assert_eq!(
+            FitsCard::text("FILTER", "O'III".to_string(), "Filter").to_record(),
+            format!("{:<80}", "FILTER  = 'O''III  ' / Filter")
+        );
+        assert_eq!(
+            FitsCard::number("CCD-TEMP", -10.5, "CCD temperature (C)").to_record(),
+            format!(
+                "{:<80}",
+                "CCD-TEMP=                -10.5 / CCD temperature (C)"
+            )
+        );
+        assert_eq!(
+            FitsCard::number("EXPTIME", 1e300, "Exposure (s)").to_record(),
+            format!(
+                "{:<80}",
+                format!("EXPTIME = {:>20} / Exposure (s)", "1E300")
+            )
+        );
+
+        // Long strings lose characters, not their closing quote, and a quote
+        // that doesn't fit doubled is dropped.
+        let record = FitsCard::text("OBJECT", "x".repeat(100), "Object").to_record();
+        assert_eq!(record, format!("OBJECT  = '{}'", "x".repeat(68)));
+        let record = FitsCard::text("OBJECT", "x".repeat(67) + "'y", "Object").to_record();
+        assert_eq!(
+            record,
+            format!("{:<80}", format!("OBJECT  = '{}'", "x".repeat(67)))
+        );
+    }
+
+    #[test]
+    fn test_sexagesimal() {
+        assert_eq!(sexagesimal(5.5, false, 2), "05 30 00.00");
+        assert_eq!(sexagesimal(-20.25, true, 1), "-20 15 00.0");
+        assert_eq!(sexagesimal(0.999999999, true, 1), "+01 00 00.0");
+    }
+
+    #[test]
+    fn test_cards() {
+        let timestamp = DateTime::from_str("2022-10-13T07:41:56.301Z").unwrap();
+        let cards = FitsEnricher::default().cards(&client(), "CCD Simulator", &timestamp);
+        let value = |keyword: &str| {
+            cards
+                .iter()
+                .find(|c| c.keyword == keyword)
+                .map(|c| c.value.clone())
+        };
+        assert_eq!(
+            value("TELESCOP"),
+            Some(FitsValue::Text("Telescope Simulator".to_string()))
+        );
+        assert_eq!(value("RA"), Some(FitsValue::Number(82.5)));
+        assert_eq!(
+            value("OBJCTDEC"),
+            Some(FitsValue::Text("-20 15 00.0".to_string()))
+        );
+        assert_eq!(value("SITELONG"), Some(FitsValue::Number(-0.5)));
+        assert_eq!(value("FOCUSPOS"), Some(FitsValue::Number(36700.0)));
+        assert_eq!(value("FILTER"), Some(FitsValue::Text("Green".to_string())));
+        assert_eq!(value("CCD-TEMP"), Some(FitsValue::Number(-10.0)));
+        assert_eq!(
+            value("DATE-END"),
+            Some(FitsValue::Text("2022-10-13T07:41:56.301".to_string()))
+        );
+        assert_eq!(value("FOCALLEN"), None);
+    }
+
+    #[test]
+    fn test_enrich_vector() {
+        let data = [7u8; 100];
+        // Enough driver cards that the new ones spill into a second header block.
+        let mut cards = vec![
+            "SIMPLE  =                    T".to_string(),
+            "BITPIX  =                    8".to_string(),
+            "NAXIS   =                    1".to_string(),
+            "NAXIS1  =                  100".to_string(),
+            "INSTRUME= 'Driver Name'".to_string(),
+        ];
+        cards.extend((0..25).map(|i| format!("COMMENT {}", i)));
+        let cards: Vec<&str> = cards.iter().map(|c| c.as_str()).collect();
+
+        let mut vector = SetBlobVector {
+            device: "CCD Simulator".to_string(),
+            name: "CCD1".to_string(),
+            state: PropertyState::Ok,
+            timeout: None,
+            timestamp: Some(DateTime::from_str("2022-10-13T07:41:56.301Z").unwrap()),
+            message: None,
+            blobs: vec![OneBlob {
+                name: "CCD1".to_string(),
+                size: 0,
+                enclen: None,
+                format: ".fits".to_string(),
+                value: fits(&cards, &data),
+            }],
+        };
+        FitsEnricher::default().enrich_vector(&client(), &mut vector);
+
+        let enriched = &vector.blobs[0].value;
+        assert_eq!(enriched.len(), 2 * BLOCK + data.len());
+        assert_eq!(vector.blobs[0].size, enriched.len() as u64);
+        assert_eq!(&enriched[2 * BLOCK..], &data);
+
+        let header = header(enriched);
+        assert_eq!(header[..cards.len()], cards);
+        assert!(header.contains(&"FILTER  = 'Green   ' / Filter".to_string()));
+        // The driver's INSTRUME is kept.
+        assert_eq!(
+            header.iter().filter(|c| c.starts_with("INSTRUME")).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_add_cards_skips_non_finite() {
+        let fits = fits(&["SIMPLE  =                    T"], &[]);
+        let cards = [
+            FitsCard::number("CCD-TEMP", f64::NAN, "CCD temperature (C)"),
+            FitsCard::number("FOCTEMP", f64::INFINITY, "Focuser temperature (C)"),
+            FitsCard::number("FOCUSPOS", 100.0, "Focuser position (steps)"),
+        ];
+        let header = header(&add_cards(&fits, &cards).unwrap());
+        assert_eq!(header.len(), 2);
+        assert!(header[1].starts_with("FOCUSPOS"));
+    }
+
+    #[test]
+    fn test_add_cards_errors() {
+        assert_eq!(add_cards(b"not fits", &[]), Err(FitsError::NotFits));
+        assert_eq!(
+            add_cards(&fits(&["SIMPLE  =                    T"], &[])[..CARD], &[]),
+            Err(FitsError::MissingEnd)
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! Just enough JSON to describe properties to scripts and web pages.
+use super::*;
+
+use std::fmt::Write as _;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn option(value: &Option<String>) -> Json {
+        match value {
+            Some(value) => Json::String(value.clone()),
+            None => Json::Null,
+        }
+    }
+
+    fn write(&self, out: &mut String, indent: usize) {
+        match self {
+            Json::Null => out.push_str("null"),
+            // JSON has no NaN or infinity.
+            Json::Number(n) if !n.is_finite() => out.push_str("null"),
+            Json::Number(n) => write!(out, "{}", n).unwrap(),
+            Json::String(s) => write_string(out, s),
+            Json::Array(items) if items.is_empty() => out.push_str("[]"),
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write!(out, "\n{:width$}", "", width = indent + 2).unwrap();
+                    item.write(out, indent + 2);
+                }
+                write!(out, "\n{:width$}]", "", width = indent).unwrap();
+            }
+            Json::Object(fields) if fields.is_empty() => out.push_str("{}"),
+            Json::Object(fields) => {
+                out.push('{');
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write!(out, "\n{:width$}", "", width = indent + 2).unwrap();
+                    write_string(out, name);
+                    out.push_str(": ");
+                    value.write(out, indent + 2);
+                }
+                write!(out, "\n{:width$}}}", "", width = indent).unwrap();
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        self.write(&mut out, 0);
+        f.write_str(&out)
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Describes `param` and the values of the elements `include` accepts, or
+/// returns `None` if it accepts none of them.  BLOBs are described by their
+/// format and size rather than their contents.
+pub fn parameter_json(param: &Parameter, include: impl Fn(&str) -> bool) -> Option<Json> {
+    let element = |label: &Option<String>, value: Json| {
+        Json::Object(vec![
+            ("label".to_string(), Json::option(label)),
+            ("value".to_string(), value),
+        ])
+    };
+    let elements: Vec<(&String, Json)> = match param {
+        Parameter::TextVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, element(&v.label, Json::String(v.value.clone()))))
+            .collect(),
+        Parameter::NumberVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, element(&v.label, Json::Number(v.value))))
+            .collect(),
+        Parameter::SwitchVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| {
+                let value = Json::String(format!("{:?}", v.value));
+                (name, element(&v.label, value))
+            })
+            .collect(),
+        Parameter::LightVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| {
+                let value = Json::String(format!("{:?}", v.value));
+                (name, element(&v.label, value))
+            })
+            .collect(),
+        Parameter::BlobVector(p) => p
+            .values
+            .iter()
+            .map(|(name, blob)| {
+                let value = Json::Object(vec![
+                    ("format".to_string(), Json::option(&blob.format)),
+                    (
+                        "size".to_string(),
+                        Json::Number(blob.value.as_ref().map_or(0, |v| v.len()) as f64),
+                    ),
+                ]);
+                (name, element(&blob.label, value))
+            })
+            .collect(),
+    };
+    let mut elements: Vec<(String, Json)> = elements
+        .into_iter()
+        .filter(|(name, _)| include(name))
+        .map(|(name, json)| (name.clone(), json))
+        .collect();
+    if elements.is_empty() {
+        return None;
+    }
+    elements.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let perm = match param {
+        Parameter::TextVector(p) => Some(&p.perm),
+        Parameter::NumberVector(p) => Some(&p.perm),
+        Parameter::SwitchVector(p) => Some(&p.perm),
+        Parameter::LightVector(_) => None,
+        Parameter::BlobVector(p) => Some(&p.perm),
+    };
+    Some(Json::Object(vec![
+        ("type".to_string(), Json::String(param.kind().to_string())),
+        ("label".to_string(), Json::option(param.get_label())),
+        ("group".to_string(), Json::option(param.get_group())),
+        (
+            "state".to_string(),
+            Json::String(format!("{:?}", param.get_state())),
+        ),
+        (
+            "perm".to_string(),
+            perm.map_or(Json::Null, |p| {
+                Json::String(format!("{:?}", p).to_lowercase())
+            }),
+        ),
+        (
+            "timestamp".to_string(),
+            param
+                .get_timestamp()
+                .map_or(Json::Null, |t| Json::String(t.to_rfc3339())),
+        ),
+        ("elements".to_string(), Json::Object(elements)),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let json = Json::Object(vec![
+            ("a".to_string(), Json::Number(1.5)),
+            ("b\"".to_string(), Json::String("x\ny".to_string())),
+            ("c".to_string(), Json::Object(vec![])),
+            ("d".to_string(), Json::Number(f64::NAN)),
+            (
+                "e".to_string(),
+                Json::Array(vec![Json::Null, Json::Array(vec![])]),
+            ),
+        ]);
+        assert_eq!(
+            json.to_string(),
+            "{\n  \"a\": 1.5,\n  \"b\\\"\": \"x\\ny\",\n  \"c\": {},\n  \"d\": null,\n  \"e\": [\n    null,\n    []\n  ]\n}"
+        );
+    }
+
+    #[test]
+    fn test_parameter_json() {
+        let param = Parameter::NumberVector(NumberVector {
+            name: "CCD_TEMPERATURE".to_string(),
+            group: None,
+            label: Some("Temperature".to_string()),
+            state: PropertyState::Ok,
+            perm: PropertyPerm::RW,
+            timeout: None,
+            timestamp: None,
+            values: HashMap::from([(
+                "CCD_TEMPERATURE_VALUE".to_string(),
+                Number {
+                    label: None,
+                    format: "%5.2f".to_string(),
+                    min: -50.0,
+                    max: 50.0,
+                    step: 0.0,
+                    value: -10.0,
+                },
+            )]),
+        });
+        assert_eq!(
+            parameter_json(&param, |_| true).unwrap().to_string(),
+            r#"{
+  "type": "number vector",
+  "label": "Temperature",
+  "group": null,
+  "state": "Ok",
+  "perm": "rw",
+  "timestamp": null,
+  "elements": {
+    "CCD_TEMPERATURE_VALUE": {
+      "label": null,
+      "value": -10
+    }
+  }
+}"#
+        );
+        assert_eq!(parameter_json(&param, |_| false), None);
+    }
+}
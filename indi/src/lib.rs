@@ -1,6 +1,4 @@
-use quick_xml::events;
 use quick_xml::events::attributes::AttrError;
-use quick_xml::events::attributes::Attribute;
 use quick_xml::events::BytesText;
 use quick_xml::events::Event;
 use quick_xml::Result as XmlResult;
@@ -10,7 +8,12 @@ use derivative::Derivative;
 
 use std::borrow::Cow;
 use std::io::{BufReader, BufWriter};
-use std::net::{Shutdown, TcpStream};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::Child;
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
 
 use std::num;
 use std::str;
@@ -27,7 +30,35 @@ pub static INDI_PROTOCOL_VERSION: &str = "1.7";
 
 pub mod serialization;
 pub use serialization::*;
-#[derive(Debug, PartialEq)]
+
+pub mod aggregator;
+pub use aggregator::*;
+
+pub mod transport;
+pub use transport::*;
+
+pub mod driver;
+pub use driver::*;
+
+pub mod property;
+pub use property::*;
+
+pub mod blob_sink;
+pub use blob_sink::*;
+
+pub mod fits;
+pub use fits::*;
+
+pub mod pattern;
+pub use pattern::*;
+
+pub mod json;
+pub use json::*;
+
+pub mod rules;
+pub use rules::*;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum PropertyState {
     Idle,
     Ok,
@@ -35,40 +66,40 @@ pub enum PropertyState {
     Alert,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SwitchState {
     On,
     Off,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SwitchRule {
     OneOfMany,
     AtMostOne,
     AnyOfMany,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum PropertyPerm {
     RO,
     WO,
     RW,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum BlobEnable {
     Never,
     Also,
     Only,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Switch {
     pub label: Option<String>,
     pub value: SwitchState,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SwitchVector {
     pub name: String,
     pub group: Option<String>,
@@ -82,7 +113,7 @@ pub struct SwitchVector {
     pub values: HashMap<String, Switch>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Number {
     pub label: Option<String>,
     pub format: String,
@@ -92,7 +123,7 @@ pub struct Number {
     pub value: f64,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct NumberVector {
     pub name: String,
     pub group: Option<String>,
@@ -105,13 +136,23 @@ pub struct NumberVector {
     pub values: HashMap<String, Number>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Light {
-    label: Option<String>,
-    value: PropertyState,
+    pub label: Option<String>,
+    pub value: PropertyState,
 }
 
-#[derive(Debug, PartialEq)]
+impl Light {
+    pub fn get_label(&self) -> &Option<String> {
+        &self.label
+    }
+
+    pub fn get_value(&self) -> &PropertyState {
+        &self.value
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LightVector {
     pub name: String,
     pub label: Option<String>,
@@ -122,13 +163,13 @@ pub struct LightVector {
     pub values: HashMap<String, Light>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Text {
     pub label: Option<String>,
     pub value: String,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TextVector {
     pub name: String,
     pub group: Option<String>,
@@ -142,14 +183,30 @@ pub struct TextVector {
     pub values: HashMap<String, Text>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Blob {
     pub label: Option<String>,
     pub format: Option<String>,
     pub value: Option<Vec<u8>>,
 }
 
-#[derive(Debug, PartialEq)]
+impl Blob {
+    pub fn get_label(&self) -> &Option<String> {
+        &self.label
+    }
+
+    /// The format of the last value received, such as `.fits`.
+    pub fn get_format(&self) -> &Option<String> {
+        &self.format
+    }
+
+    /// The last value received, if BLOBs have been enabled.
+    pub fn get_value(&self) -> &Option<Vec<u8>> {
+        &self.value
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct BlobVector {
     pub name: String,
     pub label: Option<String>,
@@ -163,7 +220,7 @@ pub struct BlobVector {
     pub values: HashMap<String, Blob>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Parameter {
     TextVector(TextVector),
     NumberVector(NumberVector),
@@ -210,14 +267,37 @@ impl Parameter {
             Parameter::BlobVector(p) => &p.state,
         }
     }
+    /// When the driver says the property last changed.
+    pub fn get_timestamp(&self) -> &Option<DateTime<Utc>> {
+        match self {
+            Parameter::TextVector(p) => &p.timestamp,
+            Parameter::NumberVector(p) => &p.timestamp,
+            Parameter::SwitchVector(p) => &p.timestamp,
+            Parameter::LightVector(p) => &p.timestamp,
+            Parameter::BlobVector(p) => &p.timestamp,
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum UpdateError {
     ParameterMissing(String),
     ParameterTypeMismatch(String),
 }
 
+impl std::fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateError::ParameterMissing(name) => write!(f, "unknown parameter {}", name),
+            UpdateError::ParameterTypeMismatch(name) => {
+                write!(f, "parameter {} has a different type", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for UpdateError {}
+
 pub enum Action {
     Define,
     Update,
@@ -337,6 +417,7 @@ pub trait CommandToUpdate {
 #[derive(Debug)]
 pub struct Client {
     devices: HashMap<String, Device>,
+    clock_skew: Option<chrono::Duration>,
 }
 
 impl Client {
@@ -344,6 +425,7 @@ impl Client {
     pub fn new() -> Client {
         Client {
             devices: HashMap::new(),
+            clock_skew: None,
         }
     }
 
@@ -352,6 +434,8 @@ impl Client {
         &mut self,
         command: serialization::Command,
     ) -> Result<Option<&Parameter>, UpdateError> {
+        self.observe_timestamp(&command, Utc::now());
+
         let name = command.device_name();
         match name {
             Some(name) => {
@@ -371,31 +455,98 @@ impl Client {
     pub fn clear(&mut self) {
         self.devices.clear();
     }
+
+    /// An estimate of how far the server's clock is ahead of ours, taken from the
+    /// timestamps of the updates it sends.  Includes network and processing delay,
+    /// so it is only a rough guide.  `None` until a timestamped update arrives.
+    pub fn clock_skew(&self) -> Option<chrono::Duration> {
+        self.clock_skew
+    }
+
+    fn observe_timestamp(&mut self, command: &serialization::Command, received: DateTime<Utc>) {
+        // Definitions replay the time a property last changed, which can be long
+        // before it was sent, so only updates and messages are used.
+        let timestamp = match command {
+            Command::SetTextVector(_)
+            | Command::SetNumberVector(_)
+            | Command::SetSwitchVector(_)
+            | Command::SetLightVector(_)
+            | Command::SetBlobVector(_)
+            | Command::Message(_) => command.timestamp(),
+            _ => None,
+        };
+        if let Some(timestamp) = timestamp {
+            let sample = timestamp - received;
+            // An exponential moving average, so one slow update doesn't swing it.
+            self.clock_skew = Some(match self.clock_skew {
+                Some(skew) => skew + (sample - skew) / 8,
+                None => sample,
+            });
+        }
+    }
 }
 
 #[derive(Derivative)]
 #[derivative(Debug)]
-pub struct Connection {
-    connection: TcpStream,
+pub struct Connection<T: Transport = TcpStream> {
+    connection: T,
     #[derivative(Debug = "ignore")]
-    xml_writer: Writer<BufWriter<TcpStream>>,
+    xml_writer: Writer<BufWriter<T>>,
 }
 
-impl Connection {
+impl Connection<TcpStream> {
     /// Creates a new connection to an INDI server at the specified address.
     pub fn new<A: ToSocketAddrs>(addr: A) -> std::io::Result<Connection> {
-        let connection = TcpStream::connect(addr)?;
-        let xml_writer = Writer::new_with_indent(BufWriter::new(connection.try_clone()?), b' ', 2);
+        Connection::from_transport(TcpStream::connect(addr)?)
+    }
+}
+
+#[cfg(unix)]
+impl Connection<UnixStream> {
+    /// Creates a new connection to an INDI server listening on the Unix domain socket at `path`.
+    pub fn new_unix<P: AsRef<Path>>(path: P) -> std::io::Result<Connection<UnixStream>> {
+        Connection::from_transport(UnixStream::connect(path)?)
+    }
+}
+
+impl Connection<ChildTransport> {
+    /// Creates a new connection to a driver process over its stdin and stdout.  The child
+    /// must have been spawned with both piped, and they are taken from it.
+    pub fn from_child(child: &mut Child) -> std::io::Result<Connection<ChildTransport>> {
+        let missing = |pipe| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("child process {} is not piped", pipe),
+            )
+        };
+        let stdin = child.stdin.take().ok_or_else(|| missing("stdin"))?;
+        let stdout = child.stdout.take().ok_or_else(|| missing("stdout"))?;
+        Connection::from_transport(Duplex::new(stdout, stdin))
+    }
+}
+
+impl Connection<StdioTransport> {
+    /// Creates a new connection over this process' stdin and stdout, which is how
+    /// an INDI server talks to the drivers it runs.
+    pub fn stdio() -> std::io::Result<Connection<StdioTransport>> {
+        Connection::from_transport(Duplex::new(std::io::stdin(), std::io::stdout()))
+    }
+}
+
+impl<T: Transport> Connection<T> {
+    /// Creates a new connection over an already established transport.
+    pub fn from_transport(transport: T) -> std::io::Result<Connection<T>> {
+        let xml_writer = Writer::new_with_indent(BufWriter::new(transport.try_clone()?), b' ', 2);
 
         Ok(Connection {
-            connection,
+            connection: transport,
             xml_writer,
         })
     }
 
     /// Disconnects from the INDI server
     pub fn disconnect(&self) -> Result<(), std::io::Error> {
-        self.connection.shutdown(Shutdown::Both)
+        self.connection.shutdown()
     }
 
     /// Creates an interator that yields commands from the the connected INDI server.
@@ -414,7 +565,7 @@ impl Connection {
     ///     println!("Command: {:?}", command);
     ///     client.update(command.unwrap());
     /// }
-    pub fn iter(&self) -> Result<serialization::CommandIter<BufReader<TcpStream>>, std::io::Error> {
+    pub fn iter(&self) -> Result<serialization::CommandIter<BufReader<T>>, std::io::Error> {
         let mut xml_reader = Reader::from_reader(BufReader::new(self.connection.try_clone()?));
         xml_reader.trim_text(true);
         xml_reader.expand_empty_elements(true);
@@ -431,7 +582,7 @@ impl Connection {
     ///     name: None,
     /// }).unwrap();
     ///
-    pub fn write<T: XmlSerialization>(&mut self, command: &T) -> Result<(), DeError> {
+    pub fn write<C: XmlSerialization>(&mut self, command: &C) -> Result<(), DeError> {
         command.write(&mut self.xml_writer)?;
         self.xml_writer.inner().flush()?;
         Ok(())
@@ -744,4 +895,185 @@ mod device_tests {
             panic!("Unexpected");
         }
     }
+    #[test]
+    fn test_update_light() {
+        let mut device = Device::new();
+        let timestamp = DateTime::from_str("2022-10-13T07:41:56.301Z").unwrap();
+
+        let def_light = DefLightVector {
+            device: String::from_str("CCD Simulator").unwrap(),
+            name: String::from_str("Status").unwrap(),
+            label: Some(String::from_str("thingo").unwrap()),
+            group: Some(String::from_str("group").unwrap()),
+            state: PropertyState::Ok,
+            timestamp: Some(timestamp),
+            message: None,
+            lights: vec![DefLight {
+                name: String::from_str("cooler").unwrap(),
+                label: Some(String::from_str("asdf").unwrap()),
+                value: PropertyState::Idle,
+            }],
+        };
+        device
+            .update(serialization::Command::DefLightVector(def_light))
+            .unwrap();
+
+        let set_light = SetLightVector {
+            device: String::from_str("CCD Simulator").unwrap(),
+            name: String::from_str("Status").unwrap(),
+            state: PropertyState::Alert,
+            timestamp: Some(timestamp),
+            message: None,
+            lights: vec![OneLight {
+                name: String::from_str("cooler").unwrap(),
+                value: PropertyState::Busy,
+            }],
+        };
+        device
+            .update(serialization::Command::SetLightVector(set_light))
+            .unwrap();
+
+        if let Parameter::LightVector(stored) = device.get_parameters().get("Status").unwrap() {
+            assert_eq!(
+                stored,
+                &LightVector {
+                    name: String::from_str("Status").unwrap(),
+                    group: Some(String::from_str("group").unwrap()),
+                    label: Some(String::from_str("thingo").unwrap()),
+                    state: PropertyState::Alert,
+                    timestamp: Some(timestamp),
+                    values: HashMap::from([(
+                        String::from_str("cooler").unwrap(),
+                        Light {
+                            label: Some(String::from_str("asdf").unwrap()),
+                            value: PropertyState::Busy,
+                        }
+                    )])
+                }
+            );
+            let light = &stored.values["cooler"];
+            assert_eq!(light.get_label().as_deref(), Some("asdf"));
+            assert_eq!(light.get_value(), &PropertyState::Busy);
+        } else {
+            panic!("Unexpected");
+        }
+    }
+}
+
+#[cfg(test)]
+mod client_tests {
+    use super::*;
+
+    fn message(timestamp: &str) -> Command {
+        Command::Message(Message {
+            device: Some(String::from("Telescope Simulator")),
+            timestamp: Some(DateTime::from_str(timestamp).unwrap()),
+            message: None,
+        })
+    }
+
+    #[test]
+    fn test_clock_skew() {
+        let mut client = Client::new();
+        let received = DateTime::from_str("2022-10-13T07:42:00Z").unwrap();
+        assert_eq!(client.clock_skew(), None);
+
+        client.observe_timestamp(&message("2022-10-13T07:42:08Z"), received);
+        assert_eq!(client.clock_skew(), Some(chrono::Duration::seconds(8)));
+
+        client.observe_timestamp(&message("2022-10-13T07:42:00Z"), received);
+        assert_eq!(client.clock_skew(), Some(chrono::Duration::seconds(7)));
+
+        // Definitions can carry old timestamps and are ignored.
+        let def_light = Command::DefLightVector(DefLightVector {
+            device: String::from("Telescope Simulator"),
+            name: String::from("STATUS"),
+            label: None,
+            group: None,
+            state: PropertyState::Ok,
+            timestamp: Some(DateTime::from_str("2022-10-12T00:00:00Z").unwrap()),
+            message: None,
+            lights: vec![],
+        });
+        client.observe_timestamp(&def_light, received);
+        assert_eq!(client.clock_skew(), Some(chrono::Duration::seconds(7)));
+    }
+}
+
+#[cfg(test)]
+mod connection_tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_in_memory_connection() {
+        let xml = r#"<message device="Telescope Simulator" timestamp="2022-10-02T00:37:07" message="hello"/>"#;
+        let written = SharedBuffer::default();
+        let transport = Duplex::new(Cursor::new(xml.as_bytes().to_vec()), written.clone());
+        let mut connection = Connection::from_transport(transport).unwrap();
+
+        connection
+            .write(&GetProperties {
+                version: INDI_PROTOCOL_VERSION.to_string(),
+                device: None,
+                name: None,
+            })
+            .unwrap();
+        assert_eq!(
+            *written.0.lock().unwrap(),
+            br#"<getProperties version="1.7"/>"#
+        );
+
+        let commands: Vec<Command> = connection.iter().unwrap().map(|c| c.unwrap()).collect();
+        assert_eq!(commands.len(), 1);
+        match &commands[0] {
+            Command::Message(message) => assert_eq!(message.message, Some("hello".to_string())),
+            e => panic!("Unexpected: {:?}", e),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_connection() {
+        let (local, mut remote) = UnixStream::pair().unwrap();
+        let mut connection = Connection::from_transport(local).unwrap();
+        let iter = connection.iter().unwrap();
+
+        remote
+            .write_all(br#"<delProperty device="CCD Simulator" name="CCD1"/>"#)
+            .unwrap();
+        remote.shutdown(std::net::Shutdown::Write).unwrap();
+        let commands: Vec<Command> = iter.map(|c| c.unwrap()).collect();
+        assert_eq!(commands.len(), 1);
+
+        connection
+            .write(&EnableBlob {
+                device: "CCD Simulator".to_string(),
+                name: None,
+                enabled: BlobEnable::Also,
+            })
+            .unwrap();
+        connection.disconnect().unwrap();
+
+        let mut received = String::new();
+        std::io::Read::read_to_string(&mut remote, &mut received).unwrap();
+        assert_eq!(
+            received,
+            r#"<enableBLOB device="CCD Simulator">Also</enableBLOB>"#
+        );
+    }
 }
@@ -1,65 +1,416 @@
-use indi;
+//! A command line client for INDI servers, along the lines of `indi_getprop`
+//! and `indi_setprop`.
+use indi::*;
+
+use std::env;
+use std::error::Error;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+static USAGE: &str = "usage: indi [-h host:port] [-t seconds] <command> [args...]
+
+Properties are named device.property.element, where each part may use the
+wildcards * and ?, and missing parts match everything.
+
+commands:
+  get [pattern...]                   print the values of matching properties
+  set device.property.element=value  set property values
+  watch [pattern...]                 print values of matching properties as they change
+  dump [pattern...]                  print matching properties as JSON
+  blob [-d directory] [-f template] [-e] [pattern...]
+                                     save incoming BLOBs to disk, naming files
+                                     with a template such as
+                                     {device}_{property}_{timestamp}_{sequence:04}{format}
+                                     -e adds mount, focuser, filter and site
+                                     details to FITS headers
+
+options:
+  -h host:port  server to connect to (default localhost:7624)
+  -t seconds    how long the server may stay quiet before its properties are
+                taken as complete (default 2)";
+
+type Commands = mpsc::Receiver<Result<Command, DeError>>;
+
+/// Element values to set, grouped by device and property.
+type Assignments<'a> = Vec<(&'a str, &'a str, Vec<(&'a str, &'a str)>)>;
 
 fn main() {
-    let mut connection = indi::Connection::new("localhost:7624").unwrap();
-    connection
-        .write(&indi::GetProperties {
-            version: indi::INDI_PROTOCOL_VERSION.to_string(),
-            device: None,
-            name: None,
-        })
-        .unwrap();
-
-    let mut client = indi::Client::new();
-
-    for command in connection.iter().unwrap() {
+    let mut args = env::args().skip(1);
+    let mut addr = "localhost:7624".to_string();
+    let mut wait = Duration::from_secs(2);
+
+    let command = loop {
+        match args.next().as_deref() {
+            Some("-h") | Some("--host") => addr = args.next().unwrap_or_else(|| usage()),
+            Some("-t") | Some("--timeout") => {
+                wait = args
+                    .next()
+                    .and_then(|t| t.parse().ok())
+                    .map(Duration::from_secs_f64)
+                    .unwrap_or_else(|| usage())
+            }
+            Some("--help") | None => usage(),
+            Some(command) => break command.to_string(),
+        }
+    };
+    let args: Vec<String> = args.collect();
+
+    let result = match command.as_str() {
+        "get" => get(&addr, wait, &args),
+        "set" => set(&addr, wait, &args),
+        "watch" => watch(&addr, &args),
+        "dump" => dump(&addr, wait, &args),
+        "blob" => blob(&addr, &args),
+        _ => usage(),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+/// Connects to `addr` and asks for every property.  Commands from the server
+/// are read on a separate thread so they can be waited on with a timeout.
+fn connect(addr: &str) -> Result<(Connection, Commands), Box<dyn Error>> {
+    let mut connection = Connection::new(addr)?;
+    connection.write(&GetProperties {
+        version: INDI_PROTOCOL_VERSION.to_string(),
+        device: None,
+        name: None,
+    })?;
+
+    let (sender, receiver) = mpsc::channel();
+    let iter = connection.iter()?;
+    thread::spawn(move || {
+        for command in iter {
+            if sender.send(command).is_err() {
+                break;
+            }
+        }
+    });
+    Ok((connection, receiver))
+}
+
+/// Updates `client` until the server has been quiet for `wait`.
+fn settle(client: &mut Client, commands: &Commands, wait: Duration) {
+    while let Ok(command) = commands.recv_timeout(wait) {
         match command {
             Ok(command) => {
                 if let Err(e) = client.update(command) {
-                    println!("error: {:?}", e)
+                    eprintln!("error: {}", e);
                 }
             }
-            Err(e) => match e {
-                e => println!("error: {:?}", e),
-            },
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+}
+
+fn patterns(args: &[String]) -> Vec<Pattern> {
+    if args.is_empty() {
+        vec![Pattern::parse("")]
+    } else {
+        args.iter().map(|arg| Pattern::parse(arg)).collect()
+    }
+}
+
+fn get(addr: &str, wait: Duration, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let patterns = patterns(args);
+    let (_connection, commands) = connect(addr)?;
+    let mut client = Client::new();
+    settle(&mut client, &commands, wait);
+
+    let mut devices: Vec<(&String, &Device)> = client.get_devices().iter().collect();
+    devices.sort_by_key(|(name, _)| *name);
+    for (device_name, device) in devices {
+        for name in device.parameter_names() {
+            print_values(&patterns, device_name, &device.get_parameters()[name]);
+        }
+    }
+    Ok(())
+}
+
+fn set(addr: &str, wait: Duration, args: &[String]) -> Result<(), Box<dyn Error>> {
+    if args.is_empty() {
+        usage();
+    }
+    let assignments = args
+        .iter()
+        .map(|arg| Assignment::parse(arg))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (mut connection, commands) = connect(addr)?;
+    let mut client = Client::new();
+    settle(&mut client, &commands, wait);
+
+    // Elements of the same property are sent together, as drivers expect.
+    let mut properties: Assignments = vec![];
+    for a in &assignments {
+        match properties
+            .iter_mut()
+            .find(|(device, property, _)| *device == a.device && *property == a.property)
+        {
+            Some((_, _, values)) => values.push((&a.element, &a.value)),
+            None => properties.push((&a.device, &a.property, vec![(&a.element, &a.value)])),
+        }
+    }
+
+    for (device, property, values) in properties {
+        connection.write(&client.new_vector_from_str(device, property, &values)?)?;
+    }
+    Ok(())
+}
+
+fn watch(addr: &str, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let patterns = patterns(args);
+    let (_connection, commands) = connect(addr)?;
+    let mut client = Client::new();
+
+    for command in commands.iter() {
+        let command = match command {
+            Ok(command) => command,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                continue;
+            }
+        };
+        let device = match command.device_name() {
+            Some(device) => device.clone(),
+            None => continue,
+        };
+        match client.update(command) {
+            Ok(Some(param)) => print_values(&patterns, &device, param),
+            Ok(None) => {}
+            Err(e) => eprintln!("error: {}", e),
         }
     }
+    Err("disconnected".into())
 }
 
-/*
-<getProperties/>
-<enableBLOB device="CCD Simulator">Also</enableBLOB>
-<newSwitchVector  device="CCD Simulator" name="CONNECTION">
-    <oneSwitch name="CONNECT">
-On
-    </oneSwitch>
-</newSwitchVector>
-
-<newSwitchVector  device="Telescope Simulator" name="CONNECTION">
-    <oneSwitch name="CONNECT">
-On
-    </oneSwitch>
-</newSwitchVector>
-
-<newNumberVector device="CCD Simulator" name="CCD_FRAME" state="Ok" timeout="60" timestamp="2022-10-02T00:36:02">
-    <oneNumber name="X">
-0
-    </oneNumber>
-    <oneNumber name="Y">
-0
-    </oneNumber>
-    <oneNumber name="WIDTH">
-10
-    </oneNumber>
-    <oneNumber name="HEIGHT">
-10
-    </oneNumber>
-</newNumberVector>
-
-<newNumberVector device="CCD Simulator" name="CCD_EXPOSURE" label="Expose" group="Main Control" state="Idle" perm="rw" timeout="60" timestamp="2022-10-01T23:34:57">
-    <oneNumber name="CCD_EXPOSURE_VALUE">
-1
-    </oneNumber>
-</newNumberVector>
-
-*/
+fn dump(addr: &str, wait: Duration, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let patterns = patterns(args);
+    let (_connection, commands) = connect(addr)?;
+    let mut client = Client::new();
+    settle(&mut client, &commands, wait);
+
+    let mut devices: Vec<(&String, &Device)> = client.get_devices().iter().collect();
+    devices.sort_by_key(|(name, _)| *name);
+    let mut json = vec![];
+    for (device_name, device) in devices {
+        let properties: Vec<(String, Json)> = device
+            .parameter_names()
+            .iter()
+            .filter_map(|name| {
+                let param = &device.get_parameters()[name];
+                let json = parameter_json(param, |element| {
+                    patterns
+                        .iter()
+                        .any(|p| p.matches(device_name, name, element))
+                })?;
+                Some((name.clone(), json))
+            })
+            .collect();
+        if !properties.is_empty() {
+            json.push((device_name.clone(), Json::Object(properties)));
+        }
+    }
+    println!("{}", Json::Object(json));
+    Ok(())
+}
+
+fn blob(addr: &str, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut directory = ".".to_string();
+    let mut template = BlobSink::DEFAULT_TEMPLATE.to_string();
+    let mut enricher = None;
+    let mut args = args;
+    loop {
+        match args {
+            [flag, ..] if flag == "-e" => {
+                enricher = Some(FitsEnricher::default());
+                args = &args[1..];
+                continue;
+            }
+            [flag, value, ..] if flag == "-d" => directory = value.clone(),
+            [flag, value, ..] if flag == "-f" => template = value.clone(),
+            [flag, ..] if flag == "-d" || flag == "-f" => usage(),
+            _ => break,
+        }
+        args = &args[2..];
+    }
+    let patterns = patterns(args);
+    let mut sink = BlobSink::new(directory)?.with_template(&template)?;
+    let (mut connection, commands) = connect(addr)?;
+    let mut client = Client::new();
+
+    for command in commands.iter() {
+        match command {
+            Ok(Command::DefBlobVector(def)) => {
+                if patterns
+                    .iter()
+                    .any(|p| p.matches_property(&def.device, &def.name))
+                {
+                    connection.write(&EnableBlob {
+                        device: def.device,
+                        name: Some(def.name),
+                        enabled: BlobEnable::Also,
+                    })?;
+                }
+            }
+            Ok(Command::SetBlobVector(mut set)) => {
+                set.blobs.retain(|blob| {
+                    patterns
+                        .iter()
+                        .any(|p| p.matches(&set.device, &set.name, &blob.name))
+                });
+                if let Some(enricher) = &enricher {
+                    enricher.enrich_vector(&client, &mut set);
+                }
+                for path in sink.save(&set)? {
+                    println!("{}", path.display());
+                }
+            }
+            // The rest of the rig is only followed for the FITS headers.
+            Ok(command) if enricher.is_some() => {
+                if let Err(e) = client.update(command) {
+                    eprintln!("error: {}", e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+    Err("disconnected".into())
+}
+
+fn print_values(patterns: &[Pattern], device: &str, param: &Parameter) {
+    for (element, value) in values(param) {
+        if patterns
+            .iter()
+            .any(|p| p.matches(device, param.get_name(), element))
+        {
+            println!(
+                "{}.{}.{}={}",
+                device,
+                param.get_name(),
+                element,
+                value_string(&value)
+            );
+        }
+    }
+}
+
+/// A property's element values, sorted by element name.
+fn values(param: &Parameter) -> Vec<(&String, Value<'_>)> {
+    let mut values: Vec<(&String, Value)> = match param {
+        Parameter::TextVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, Value::Text(&v.value)))
+            .collect(),
+        Parameter::NumberVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, Value::Number(v.value)))
+            .collect(),
+        Parameter::SwitchVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, Value::State(format!("{:?}", v.value))))
+            .collect(),
+        Parameter::LightVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, Value::State(format!("{:?}", v.value))))
+            .collect(),
+        Parameter::BlobVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, Value::Blob(v)))
+            .collect(),
+    };
+    values.sort_by_key(|(name, _)| *name);
+    values
+}
+
+enum Value<'a> {
+    Text(&'a String),
+    Number(f64),
+    State(String),
+    Blob(&'a Blob),
+}
+
+fn value_string(value: &Value) -> String {
+    match value {
+        Value::Text(value) => value.to_string(),
+        Value::Number(value) => value.to_string(),
+        Value::State(value) => value.clone(),
+        Value::Blob(blob) => format!(
+            "<{} bytes{}>",
+            blob.value.as_ref().map_or(0, |v| v.len()),
+            blob.format
+                .as_ref()
+                .map_or(String::new(), |f| format!(" {}", f))
+        ),
+    }
+}
+
+/// A `device.property.element=value` argument to `set`.
+#[derive(Debug, PartialEq)]
+struct Assignment {
+    device: String,
+    property: String,
+    element: String,
+    value: String,
+}
+
+impl Assignment {
+    fn parse(spec: &str) -> Result<Assignment, String> {
+        let invalid = || format!("expected device.property.element=value, not {:?}", spec);
+        let (name, value) = spec.split_once('=').ok_or_else(invalid)?;
+        let mut parts = name.splitn(3, '.');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(device), Some(property), Some(element))
+                if !device.is_empty() && !property.is_empty() && !element.is_empty() =>
+            {
+                Ok(Assignment {
+                    device: device.to_string(),
+                    property: property.to_string(),
+                    element: element.to_string(),
+                    value: value.to_string(),
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assignment() {
+        assert_eq!(
+            Assignment::parse("CCD Simulator.CCD_EXPOSURE.CCD_EXPOSURE_VALUE=1.5"),
+            Ok(Assignment {
+                device: "CCD Simulator".to_string(),
+                property: "CCD_EXPOSURE".to_string(),
+                element: "CCD_EXPOSURE_VALUE".to_string(),
+                value: "1.5".to_string(),
+            })
+        );
+        assert_eq!(
+            Assignment::parse("a.b.c=x=y").unwrap().value,
+            "x=y".to_string()
+        );
+        assert!(Assignment::parse("a.b.c").is_err());
+        assert!(Assignment::parse("a.b=1").is_err());
+        assert!(Assignment::parse("a..c=1").is_err());
+    }
+}
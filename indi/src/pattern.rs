@@ -0,0 +1,93 @@
+//! Glob patterns for picking out devices, properties and elements by name.
+
+/// A `device.property.element` pattern, where each part may use the `*` and `?`
+/// wildcards.  Missing parts match everything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub device: String,
+    pub property: String,
+    pub element: String,
+}
+
+impl Pattern {
+    pub fn parse(spec: &str) -> Pattern {
+        let mut parts = spec.splitn(3, '.');
+        let mut next = || match parts.next() {
+            Some(part) if !part.is_empty() => part.to_string(),
+            _ => "*".to_string(),
+        };
+        Pattern {
+            device: next(),
+            property: next(),
+            element: next(),
+        }
+    }
+
+    pub fn matches_property(&self, device: &str, property: &str) -> bool {
+        glob(&self.device, device) && glob(&self.property, property)
+    }
+
+    pub fn matches(&self, device: &str, property: &str, element: &str) -> bool {
+        self.matches_property(device, property) && glob(&self.element, element)
+    }
+}
+
+/// Matches `text` against a pattern where `*` matches any run of characters and
+/// `?` any one character.
+pub fn glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and how much of the text it has consumed.
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("CCD Simulator", "CCD Simulator"));
+        assert!(glob("*", ""));
+        assert!(glob("*", "CCD Simulator"));
+        assert!(glob("CCD*", "CCD Simulator"));
+        assert!(glob("*Simulator", "CCD Simulator"));
+        assert!(glob("C?D*tor", "CCD Simulator"));
+        assert!(glob("*_*_*", "CCD_EXPOSURE_VALUE"));
+        assert!(!glob("CCD", "CCD Simulator"));
+        assert!(!glob("*Camera", "CCD Simulator"));
+        assert!(!glob("?", ""));
+    }
+
+    #[test]
+    fn test_pattern() {
+        let pattern = Pattern::parse("CCD*.CCD_EXPOSURE");
+        assert_eq!(pattern.element, "*");
+        assert!(pattern.matches("CCD Simulator", "CCD_EXPOSURE", "CCD_EXPOSURE_VALUE"));
+        assert!(!pattern.matches("Telescope Simulator", "CCD_EXPOSURE", "CCD_EXPOSURE_VALUE"));
+        assert!(Pattern::parse("").matches("a", "b", "c"));
+        assert!(Pattern::parse("..X").matches("a", "b", "X"));
+    }
+}
@@ -0,0 +1,672 @@
+use super::*;
+
+use std::fmt;
+
+/// Errors looking up a property, or one of its values, through a typed handle.
+#[derive(Debug, PartialEq)]
+pub enum PropertyError {
+    DeviceMissing(String),
+    PropertyMissing(String),
+    /// The property exists, but is of a different kind: (name, expected, found).
+    TypeMismatch(String, &'static str, &'static str),
+    ElementMissing(String),
+    ReadOnly(String),
+    /// A number outside its element's range: (element, value, min, max).
+    OutOfRange(String, f64, f64, f64),
+    /// A value that couldn't be parsed: (element, value, what was expected).
+    InvalidValue(String, String, &'static str),
+    /// A property of a kind clients can't set: (name, kind).
+    NotSettable(String, &'static str),
+    /// Switches that would break their vector's rule: (name, rule).
+    BreaksRule(String, SwitchRule),
+}
+
+impl fmt::Display for PropertyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PropertyError::DeviceMissing(device) => write!(f, "unknown device {}", device),
+            PropertyError::PropertyMissing(name) => write!(f, "unknown property {}", name),
+            PropertyError::TypeMismatch(name, expected, found) => {
+                write!(f, "property {} is a {}, not a {}", name, found, expected)
+            }
+            PropertyError::ElementMissing(name) => write!(f, "unknown element {}", name),
+            PropertyError::ReadOnly(name) => write!(f, "property {} is read only", name),
+            PropertyError::OutOfRange(name, value, min, max) => {
+                write!(
+                    f,
+                    "{} is outside {}'s range of {} to {}",
+                    value, name, min, max
+                )
+            }
+            PropertyError::InvalidValue(name, value, expected) => {
+                write!(f, "expected {} for {}, not {:?}", expected, name, value)
+            }
+            PropertyError::NotSettable(name, kind) => write!(f, "{} {} can't be set", kind, name),
+            PropertyError::BreaksRule(name, SwitchRule::AtMostOne) => {
+                write!(f, "at most one switch of {} may be On", name)
+            }
+            PropertyError::BreaksRule(name, _) => {
+                write!(f, "exactly one switch of {} must be On", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PropertyError {}
+
+impl Parameter {
+    /// A human readable name for the kind of property, such as "number vector".
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Parameter::TextVector(_) => TextVector::KIND,
+            Parameter::NumberVector(_) => NumberVector::KIND,
+            Parameter::SwitchVector(_) => SwitchVector::KIND,
+            Parameter::LightVector(_) => LightVector::KIND,
+            Parameter::BlobVector(_) => BlobVector::KIND,
+        }
+    }
+}
+
+/// A kind of property that a `PropertyHandle` can be made for.
+pub trait PropertyKind: Sized {
+    const KIND: &'static str;
+
+    fn from_parameter(param: &Parameter) -> Option<&Self>;
+}
+
+impl PropertyKind for TextVector {
+    const KIND: &'static str = "text vector";
+
+    fn from_parameter(param: &Parameter) -> Option<&Self> {
+        match param {
+            Parameter::TextVector(p) => Some(p),
+            _ => None,
+        }
+    }
+}
+
+impl PropertyKind for NumberVector {
+    const KIND: &'static str = "number vector";
+
+    fn from_parameter(param: &Parameter) -> Option<&Self> {
+        match param {
+            Parameter::NumberVector(p) => Some(p),
+            _ => None,
+        }
+    }
+}
+
+impl PropertyKind for SwitchVector {
+    const KIND: &'static str = "switch vector";
+
+    fn from_parameter(param: &Parameter) -> Option<&Self> {
+        match param {
+            Parameter::SwitchVector(p) => Some(p),
+            _ => None,
+        }
+    }
+}
+
+impl PropertyKind for LightVector {
+    const KIND: &'static str = "light vector";
+
+    fn from_parameter(param: &Parameter) -> Option<&Self> {
+        match param {
+            Parameter::LightVector(p) => Some(p),
+            _ => None,
+        }
+    }
+}
+
+impl PropertyKind for BlobVector {
+    const KIND: &'static str = "blob vector";
+
+    fn from_parameter(param: &Parameter) -> Option<&Self> {
+        match param {
+            Parameter::BlobVector(p) => Some(p),
+            _ => None,
+        }
+    }
+}
+
+/// A typed view of one property of a device, for reading its values and building
+/// the commands that change them.
+///
+/// Example usage:
+/// ```no_run
+/// # let client = indi::Client::new();
+/// let exposure = client.number("CCD Simulator", "CCD_EXPOSURE").unwrap();
+/// println!("exposure: {}", exposure.get("CCD_EXPOSURE_VALUE").unwrap());
+///
+/// let command = exposure.set(&[("CCD_EXPOSURE_VALUE", 2.5)]).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct PropertyHandle<'a, V> {
+    device: &'a str,
+    vector: &'a V,
+}
+
+pub type TextProperty<'a> = PropertyHandle<'a, TextVector>;
+pub type NumberProperty<'a> = PropertyHandle<'a, NumberVector>;
+pub type SwitchProperty<'a> = PropertyHandle<'a, SwitchVector>;
+pub type LightProperty<'a> = PropertyHandle<'a, LightVector>;
+pub type BlobProperty<'a> = PropertyHandle<'a, BlobVector>;
+
+impl<'a, V> PropertyHandle<'a, V> {
+    pub fn device(&self) -> &'a str {
+        self.device
+    }
+
+    /// The full state of the property.
+    pub fn vector(&self) -> &'a V {
+        self.vector
+    }
+}
+
+fn element<'a, T>(values: &'a HashMap<String, T>, name: &str) -> Result<&'a T, PropertyError> {
+    values
+        .get(name)
+        .ok_or_else(|| PropertyError::ElementMissing(name.to_string()))
+}
+
+/// Checks that a property can be written and that all of `names` belong to it.
+fn writable<'b, T>(
+    property: &str,
+    perm: &PropertyPerm,
+    values: &HashMap<String, T>,
+    names: impl Iterator<Item = &'b str>,
+) -> Result<(), PropertyError> {
+    if *perm == PropertyPerm::RO {
+        return Err(PropertyError::ReadOnly(property.to_string()));
+    }
+    for name in names {
+        element(values, name)?;
+    }
+    Ok(())
+}
+
+impl<'a> PropertyHandle<'a, TextVector> {
+    pub fn get(&self, name: &str) -> Result<&'a str, PropertyError> {
+        Ok(&element(&self.vector.values, name)?.value)
+    }
+
+    /// Builds the command that sets the named texts.
+    pub fn set(&self, values: &[(&str, &str)]) -> Result<NewTextVector, PropertyError> {
+        let vector = self.vector;
+        writable(
+            &vector.name,
+            &vector.perm,
+            &vector.values,
+            values.iter().map(|(name, _)| *name),
+        )?;
+        Ok(NewTextVector {
+            device: self.device.to_string(),
+            name: vector.name.clone(),
+            timestamp: Some(Utc::now()),
+            texts: values
+                .iter()
+                .map(|(name, value)| OneText {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+        })
+    }
+}
+
+impl<'a> PropertyHandle<'a, NumberVector> {
+    pub fn get(&self, name: &str) -> Result<f64, PropertyError> {
+        Ok(element(&self.vector.values, name)?.value)
+    }
+
+    /// Builds the command that sets the named numbers.
+    pub fn set(&self, values: &[(&str, f64)]) -> Result<NewNumberVector, PropertyError> {
+        let vector = self.vector;
+        writable(
+            &vector.name,
+            &vector.perm,
+            &vector.values,
+            values.iter().map(|(name, _)| *name),
+        )?;
+        for (name, value) in values {
+            let number = &vector.values[*name];
+            // Drivers leave min and max equal for numbers with no range.
+            if number.min < number.max && !(number.min..=number.max).contains(value) {
+                return Err(PropertyError::OutOfRange(
+                    name.to_string(),
+                    *value,
+                    number.min,
+                    number.max,
+                ));
+            }
+        }
+        Ok(NewNumberVector {
+            device: self.device.to_string(),
+            name: vector.name.clone(),
+            timestamp: Some(Utc::now()),
+            numbers: values
+                .iter()
+                .map(|(name, value)| OneNumber {
+                    name: name.to_string(),
+                    min: None,
+                    max: None,
+                    step: None,
+                    value: *value,
+                })
+                .collect(),
+        })
+    }
+}
+
+impl<'a> PropertyHandle<'a, SwitchVector> {
+    pub fn get(&self, name: &str) -> Result<&'a SwitchState, PropertyError> {
+        Ok(&element(&self.vector.values, name)?.value)
+    }
+
+    /// Builds the command that sets the named switches, checking the result
+    /// against the vector's rule.  As drivers turn the other switches of a
+    /// `OneOfMany` or `AtMostOne` vector Off when one is turned On, only one may
+    /// be turned On at a time, and the last On switch of a `OneOfMany` vector
+    /// can't just be turned Off.
+    pub fn set(&self, values: &[(&str, SwitchState)]) -> Result<NewSwitchVector, PropertyError> {
+        let vector = self.vector;
+        writable(
+            &vector.name,
+            &vector.perm,
+            &vector.values,
+            values.iter().map(|(name, _)| *name),
+        )?;
+        let turned_on = values
+            .iter()
+            .filter(|(_, value)| *value == SwitchState::On)
+            .count();
+        let left_on = vector
+            .values
+            .iter()
+            .filter(|(name, switch)| {
+                switch.value == SwitchState::On && !values.iter().any(|(n, _)| n == name)
+            })
+            .count();
+        let breaks = match vector.rule {
+            SwitchRule::OneOfMany => turned_on > 1 || (turned_on == 0 && left_on == 0),
+            SwitchRule::AtMostOne => turned_on > 1,
+            SwitchRule::AnyOfMany => false,
+        };
+        if breaks {
+            return Err(PropertyError::BreaksRule(
+                vector.name.clone(),
+                vector.rule.clone(),
+            ));
+        }
+        Ok(NewSwitchVector {
+            device: self.device.to_string(),
+            name: vector.name.clone(),
+            timestamp: Some(Utc::now()),
+            switches: values
+                .iter()
+                .map(|(name, value)| OneSwitch {
+                    name: name.to_string(),
+                    value: value.clone(),
+                })
+                .collect(),
+        })
+    }
+}
+
+impl<'a> PropertyHandle<'a, LightVector> {
+    pub fn get(&self, name: &str) -> Result<&'a PropertyState, PropertyError> {
+        Ok(&element(&self.vector.values, name)?.value)
+    }
+}
+
+impl<'a> PropertyHandle<'a, BlobVector> {
+    pub fn get(&self, name: &str) -> Result<&'a Blob, PropertyError> {
+        element(&self.vector.values, name)
+    }
+}
+
+impl Device {
+    /// A typed handle for the property `name`.
+    pub fn property<'a, V: PropertyKind>(
+        &'a self,
+        device: &'a str,
+        name: &str,
+    ) -> Result<PropertyHandle<'a, V>, PropertyError> {
+        let param = self
+            .get_parameters()
+            .get(name)
+            .ok_or_else(|| PropertyError::PropertyMissing(name.to_string()))?;
+        match V::from_parameter(param) {
+            Some(vector) => Ok(PropertyHandle { device, vector }),
+            None => Err(PropertyError::TypeMismatch(
+                name.to_string(),
+                V::KIND,
+                param.kind(),
+            )),
+        }
+    }
+}
+
+impl Client {
+    /// A typed handle for `device`'s property `name`.
+    pub fn property<'a, V: PropertyKind>(
+        &'a self,
+        device: &'a str,
+        name: &str,
+    ) -> Result<PropertyHandle<'a, V>, PropertyError> {
+        self.get_devices()
+            .get(device)
+            .ok_or_else(|| PropertyError::DeviceMissing(device.to_string()))?
+            .property(device, name)
+    }
+
+    pub fn text<'a>(
+        &'a self,
+        device: &'a str,
+        name: &str,
+    ) -> Result<TextProperty<'a>, PropertyError> {
+        self.property(device, name)
+    }
+
+    pub fn number<'a>(
+        &'a self,
+        device: &'a str,
+        name: &str,
+    ) -> Result<NumberProperty<'a>, PropertyError> {
+        self.property(device, name)
+    }
+
+    pub fn switch<'a>(
+        &'a self,
+        device: &'a str,
+        name: &str,
+    ) -> Result<SwitchProperty<'a>, PropertyError> {
+        self.property(device, name)
+    }
+
+    pub fn light<'a>(
+        &'a self,
+        device: &'a str,
+        name: &str,
+    ) -> Result<LightProperty<'a>, PropertyError> {
+        self.property(device, name)
+    }
+
+    pub fn blob<'a>(
+        &'a self,
+        device: &'a str,
+        name: &str,
+    ) -> Result<BlobProperty<'a>, PropertyError> {
+        self.property(device, name)
+    }
+
+    /// Builds the command that sets elements of `device`'s property `name` from
+    /// text, such as a command line or a form, parsing each value as the kind of
+    /// property requires.  Switches are `On` or `Off`.
+    pub fn new_vector_from_str(
+        &self,
+        device: &str,
+        name: &str,
+        values: &[(&str, &str)],
+    ) -> Result<Command, PropertyError> {
+        let param = self
+            .get_devices()
+            .get(device)
+            .ok_or_else(|| PropertyError::DeviceMissing(device.to_string()))?
+            .get_parameters()
+            .get(name)
+            .ok_or_else(|| PropertyError::PropertyMissing(name.to_string()))?;
+        let invalid = |element: &str, value: &str, expected| {
+            PropertyError::InvalidValue(element.to_string(), value.to_string(), expected)
+        };
+        Ok(match param {
+            Parameter::TextVector(_) => {
+                Command::NewTextVector(self.text(device, name)?.set(values)?)
+            }
+            Parameter::NumberVector(_) => {
+                let values = values
+                    .iter()
+                    .map(|(element, value)| match value.trim().parse() {
+                        Ok(number) => Ok((*element, number)),
+                        Err(_) => Err(invalid(element, value, "a number")),
+                    })
+                    .collect::<Result<Vec<(&str, f64)>, PropertyError>>()?;
+                Command::NewNumberVector(self.number(device, name)?.set(&values)?)
+            }
+            Parameter::SwitchVector(_) => {
+                let values = values
+                    .iter()
+                    .map(|(element, value)| match value.trim() {
+                        "On" | "on" => Ok((*element, SwitchState::On)),
+                        "Off" | "off" => Ok((*element, SwitchState::Off)),
+                        _ => Err(invalid(element, value, "On or Off")),
+                    })
+                    .collect::<Result<Vec<(&str, SwitchState)>, PropertyError>>()?;
+                Command::NewSwitchVector(self.switch(device, name)?.set(&values)?)
+            }
+            _ => return Err(PropertyError::NotSettable(name.to_string(), param.kind())),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> Client {
+        let xml = r#"
+<defNumberVector device="CCD Simulator" name="CCD_EXPOSURE" state="Idle" perm="rw">
+    <defNumber name="CCD_EXPOSURE_VALUE" format="%5.2f" min="0" max="3600" step="1">1.5</defNumber>
+</defNumberVector>
+<defNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" state="Idle" perm="ro">
+    <defNumber name="CCD_TEMPERATURE_VALUE" format="%5.2f" min="-50" max="50" step="0">-10</defNumber>
+</defNumberVector>
+<defSwitchVector device="CCD Simulator" name="CONNECTION" state="Ok" perm="rw" rule="OneOfMany">
+    <defSwitch name="CONNECT">On</defSwitch>
+    <defSwitch name="DISCONNECT">Off</defSwitch>
+</defSwitchVector>
+<defSwitchVector device="CCD Simulator" name="CCD_FAN" state="Idle" perm="rw" rule="AtMostOne">
+    <defSwitch name="FAN_LOW">Off</defSwitch>
+    <defSwitch name="FAN_HIGH">Off</defSwitch>
+</defSwitchVector>
+<defTextVector device="CCD Simulator" name="DRIVER_INFO" state="Idle" perm="ro">
+    <defText name="DRIVER_NAME">CCD Simulator</defText>
+</defTextVector>
+<defTextVector device="CCD Simulator" name="UPLOAD_SETTINGS" state="Idle" perm="rw">
+    <defText name="UPLOAD_DIR">/tmp</defText>
+</defTextVector>
+<defLightVector device="CCD Simulator" name="STATUS" state="Idle">
+    <defLight name="COOLING">Idle</defLight>
+</defLightVector>
+"#;
+        let mut client = Client::new();
+        for command in CommandIter::from_xml(xml) {
+            client.update(command.unwrap()).unwrap();
+        }
+        client
+    }
+
+    #[test]
+    fn test_get() {
+        let client = client();
+        let exposure = client.number("CCD Simulator", "CCD_EXPOSURE").unwrap();
+        assert_eq!(exposure.get("CCD_EXPOSURE_VALUE"), Ok(1.5));
+        assert_eq!(exposure.vector().perm, PropertyPerm::RW);
+
+        let connection = client.switch("CCD Simulator", "CONNECTION").unwrap();
+        assert_eq!(connection.get("CONNECT"), Ok(&SwitchState::On));
+
+        let info = client.text("CCD Simulator", "DRIVER_INFO").unwrap();
+        assert_eq!(info.get("DRIVER_NAME"), Ok("CCD Simulator"));
+    }
+
+    #[test]
+    fn test_set() {
+        let client = client();
+        let command = client
+            .number("CCD Simulator", "CCD_EXPOSURE")
+            .unwrap()
+            .set(&[("CCD_EXPOSURE_VALUE", 2.5)])
+            .unwrap();
+        assert_eq!(command.device, "CCD Simulator");
+        assert_eq!(command.name, "CCD_EXPOSURE");
+        assert_eq!(command.numbers.len(), 1);
+        assert_eq!(command.numbers[0].name, "CCD_EXPOSURE_VALUE");
+        assert_eq!(command.numbers[0].value, 2.5);
+
+        let command = client
+            .switch("CCD Simulator", "CONNECTION")
+            .unwrap()
+            .set(&[("DISCONNECT", SwitchState::On)])
+            .unwrap();
+        assert_eq!(command.switches[0].value, SwitchState::On);
+    }
+
+    #[test]
+    fn test_switch_rules() {
+        let client = client();
+        let connection = client.switch("CCD Simulator", "CONNECTION").unwrap();
+        let breaks = PropertyError::BreaksRule("CONNECTION".to_string(), SwitchRule::OneOfMany);
+        assert_eq!(
+            connection
+                .set(&[
+                    ("CONNECT", SwitchState::On),
+                    ("DISCONNECT", SwitchState::On)
+                ])
+                .unwrap_err(),
+            breaks
+        );
+        assert_eq!(
+            connection
+                .set(&[("CONNECT", SwitchState::Off)])
+                .unwrap_err(),
+            breaks
+        );
+        assert_eq!(
+            breaks.to_string(),
+            "exactly one switch of CONNECTION must be On"
+        );
+        assert!(connection
+            .set(&[
+                ("CONNECT", SwitchState::Off),
+                ("DISCONNECT", SwitchState::On)
+            ])
+            .is_ok());
+        assert!(connection.set(&[("DISCONNECT", SwitchState::Off)]).is_ok());
+
+        let fan = client.switch("CCD Simulator", "CCD_FAN").unwrap();
+        assert_eq!(
+            fan.set(&[("FAN_LOW", SwitchState::On), ("FAN_HIGH", SwitchState::On)])
+                .unwrap_err()
+                .to_string(),
+            "at most one switch of CCD_FAN may be On"
+        );
+        assert!(fan.set(&[("FAN_HIGH", SwitchState::On)]).is_ok());
+        assert!(fan.set(&[("FAN_LOW", SwitchState::Off)]).is_ok());
+    }
+
+    #[test]
+    fn test_errors() {
+        let client = client();
+        assert_eq!(
+            client
+                .number("Telescope Simulator", "CCD_EXPOSURE")
+                .unwrap_err(),
+            PropertyError::DeviceMissing("Telescope Simulator".to_string())
+        );
+        assert_eq!(
+            client.number("CCD Simulator", "CCD_GAIN").unwrap_err(),
+            PropertyError::PropertyMissing("CCD_GAIN".to_string())
+        );
+
+        let err = client.switch("CCD Simulator", "CCD_EXPOSURE").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "property CCD_EXPOSURE is a number vector, not a switch vector"
+        );
+
+        let exposure = client.number("CCD Simulator", "CCD_EXPOSURE").unwrap();
+        assert_eq!(
+            exposure.get("CCD_EXPOSURE"),
+            Err(PropertyError::ElementMissing("CCD_EXPOSURE".to_string()))
+        );
+        assert_eq!(
+            exposure.set(&[("CCD_EXPOSURE", 1.0)]).unwrap_err(),
+            PropertyError::ElementMissing("CCD_EXPOSURE".to_string())
+        );
+
+        let temperature = client.number("CCD Simulator", "CCD_TEMPERATURE").unwrap();
+        assert_eq!(
+            temperature
+                .set(&[("CCD_TEMPERATURE_VALUE", 0.0)])
+                .unwrap_err(),
+            PropertyError::ReadOnly("CCD_TEMPERATURE".to_string())
+        );
+
+        let err = exposure.set(&[("CCD_EXPOSURE_VALUE", 4000.0)]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "4000 is outside CCD_EXPOSURE_VALUE's range of 0 to 3600"
+        );
+    }
+
+    #[test]
+    fn test_new_vector_from_str() {
+        let client = client();
+        match client
+            .new_vector_from_str(
+                "CCD Simulator",
+                "CCD_EXPOSURE",
+                &[("CCD_EXPOSURE_VALUE", " 2.5")],
+            )
+            .unwrap()
+        {
+            Command::NewNumberVector(command) => assert_eq!(command.numbers[0].value, 2.5),
+            other => panic!("{:?}", other),
+        }
+        match client
+            .new_vector_from_str("CCD Simulator", "CONNECTION", &[("DISCONNECT", "on")])
+            .unwrap()
+        {
+            Command::NewSwitchVector(command) => {
+                assert_eq!(command.switches[0].value, SwitchState::On)
+            }
+            other => panic!("{:?}", other),
+        }
+        match client
+            .new_vector_from_str(
+                "CCD Simulator",
+                "UPLOAD_SETTINGS",
+                &[("UPLOAD_DIR", "/data")],
+            )
+            .unwrap()
+        {
+            Command::NewTextVector(command) => assert_eq!(command.texts[0].value, "/data"),
+            other => panic!("{:?}", other),
+        }
+
+        let error = |name, element, value| {
+            client
+                .new_vector_from_str("CCD Simulator", name, &[(element, value)])
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("CCD_EXPOSURE", "CCD_EXPOSURE_VALUE", "long"),
+            "expected a number for CCD_EXPOSURE_VALUE, not \"long\""
+        );
+        assert_eq!(
+            error("CONNECTION", "CONNECT", "yes"),
+            "expected On or Off for CONNECT, not \"yes\""
+        );
+        assert_eq!(
+            error("CCD_EXPOSURE", "CCD_EXPOSURE_VALUE", "4000"),
+            "4000 is outside CCD_EXPOSURE_VALUE's range of 0 to 3600"
+        );
+        assert_eq!(
+            error("STATUS", "COOLING", "Ok"),
+            "light vector STATUS can't be set"
+        );
+        assert_eq!(error("CCD_GAIN", "GAIN", "1"), "unknown property CCD_GAIN");
+    }
+}
@@ -0,0 +1,1016 @@
+//! Rules that watch the state of a `Client` and say when to act.  A rules file
+//! is a list of rules, each a `rule` line naming it followed by its clauses:
+//!
+//! ```text
+//! # Warn when the CCD has been off its setpoint of -10 for five minutes.
+//! rule ccd-temperature
+//!     when abs({CCD Simulator.CCD_TEMPERATURE.CCD_TEMPERATURE_VALUE} + 10) > 1
+//!     for 5m
+//!     log CCD is at {CCD Simulator.CCD_TEMPERATURE.CCD_TEMPERATURE_VALUE}
+//!
+//! rule alert
+//!     when {*.*} == "Alert"
+//!     webhook http://localhost:8123/api/webhook/indi A property is in alert
+//!
+//! rule parked-while-imaging
+//!     when {Telescope Simulator.TELESCOPE_PARK.PARK} == "On" and {CCD Simulator.CCD_EXPOSURE} == "Busy"
+//!     set {CCD Simulator.CCD_ABORT_EXPOSURE.ABORT} On
+//!     log Aborted the exposure, the mount is parked
+//! ```
+//!
+//! `{device.property.element}` is an element's value: a number, `On` or `Off`
+//! for switches, the text of texts and the state of lights.
+//! `{device.property}` is a property's state.  `*` and `?` are wildcards, and
+//! a comparison is true if it is true of any of the things matched.
+//! Conditions can use numbers, "strings", `+ - * /`, `== != < <= > >=`, `and`,
+//! `or`, `not`, `abs(...)` and parentheses.  Comparisons with things that
+//! aren't defined are false.
+//!
+//! A rule fires when its condition becomes true, or when it has been true for
+//! the `for` duration (`30s`, `5m`, `2h`), and again only after it has been
+//! false.  With wildcards, it fires for each thing that newly matches.  It can `log` a message, POST to a `webhook` with an optional
+//! message, and `set` elements.  Messages can include references, which are
+//! replaced by their values.  Lines starting with `#` are comments.
+use super::*;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub enum RulesError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RulesError::Io(e) => write!(f, "{}", e),
+            RulesError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for RulesError {}
+
+impl From<io::Error> for RulesError {
+    fn from(err: io::Error) -> Self {
+        RulesError::Io(err)
+    }
+}
+
+/// What a rule does when it fires.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleAction {
+    Log(String),
+    /// POST the firing as JSON to an `http://` URL.
+    Webhook {
+        url: String,
+        message: Option<String>,
+    },
+    /// Set one element of a property, leaving the others as they are.
+    Set {
+        device: String,
+        property: String,
+        element: String,
+        value: String,
+    },
+}
+
+impl RuleAction {
+    /// The action with the references in its message replaced by their values.
+    fn interpolate(&self, client: &Client) -> RuleAction {
+        let message = |template: &str| {
+            interpolate(template, |spec| {
+                Ok(Reference::parse(spec)?.evaluate(client).to_string())
+            })
+            .unwrap_or_else(|_| template.to_string())
+        };
+        match self {
+            RuleAction::Log(template) => RuleAction::Log(message(template)),
+            RuleAction::Webhook { url, message: m } => RuleAction::Webhook {
+                url: url.clone(),
+                message: m.as_deref().map(message),
+            },
+            action => action.clone(),
+        }
+    }
+}
+
+/// A rule that fired, and what it asks to be done.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Firing {
+    pub rule: String,
+    /// The properties or elements a wildcard in the condition matched.
+    pub matches: Vec<String>,
+    pub actions: Vec<RuleAction>,
+}
+
+/// A value of an expression.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Missing,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    /// The values of what a wildcard matched, by name.
+    List(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            Value::List(items) => items.iter().any(|(_, v)| v.truthy()),
+            _ => false,
+        }
+    }
+
+    /// The items of a list that are true, or whether anything else is.
+    fn matched(self) -> Value {
+        match self {
+            Value::List(items) => {
+                Value::List(items.into_iter().filter(|(_, v)| v.truthy()).collect())
+            }
+            value => Value::Bool(value.truthy()),
+        }
+    }
+
+    /// Both sides of a true `and` or `or`, keeping what either matched.
+    fn merge(self, other: Value) -> Value {
+        match (self.matched(), other.matched()) {
+            (Value::List(mut a), Value::List(b)) => {
+                a.extend(b);
+                Value::List(a)
+            }
+            (Value::List(a), _) | (_, Value::List(a)) => Value::List(a),
+            (a, b) => Value::Bool(a.truthy() || b.truthy()),
+        }
+    }
+
+    fn map(self, f: &dyn Fn(Value) -> Value) -> Value {
+        match self {
+            Value::List(items) => {
+                Value::List(items.into_iter().map(|(n, v)| (n, v.map(f))).collect())
+            }
+            value => f(value),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Missing => write!(f, "?"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", s),
+            Value::List(items) => {
+                for (i, (name, value)) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", name, value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A reference to the value of an element, or the state of a property.
+#[derive(Debug, Clone, PartialEq)]
+struct Reference {
+    pattern: Pattern,
+    state: bool,
+    wildcard: bool,
+}
+
+impl Reference {
+    fn parse(spec: &str) -> Result<Reference, String> {
+        let parts: Vec<&str> = spec.splitn(3, '.').collect();
+        if parts.len() < 2 || parts.iter().any(|p| p.is_empty()) {
+            return Err(format!("invalid reference {{{}}}", spec));
+        }
+        Ok(Reference {
+            pattern: Pattern::parse(spec),
+            state: parts.len() == 2,
+            wildcard: spec.contains(['*', '?']),
+        })
+    }
+
+    fn evaluate(&self, client: &Client) -> Value {
+        let mut devices: Vec<_> = client.get_devices().iter().collect();
+        devices.sort_by_key(|(name, _)| *name);
+        let mut found = vec![];
+        for (device_name, device) in devices {
+            if !glob(&self.pattern.device, device_name) {
+                continue;
+            }
+            for name in device.parameter_names() {
+                let param = match device.get_parameters().get(name) {
+                    Some(param) if glob(&self.pattern.property, name) => param,
+                    _ => continue,
+                };
+                if self.state {
+                    let state = Value::String(format!("{:?}", param.get_state()));
+                    found.push((format!("{}.{}", device_name, name), state));
+                    continue;
+                }
+                for (element, value) in element_values(param) {
+                    if glob(&self.pattern.element, element) {
+                        found.push((format!("{}.{}.{}", device_name, name, element), value));
+                    }
+                }
+            }
+        }
+        if self.wildcard {
+            Value::List(found)
+        } else {
+            found.pop().map_or(Value::Missing, |(_, value)| value)
+        }
+    }
+}
+
+/// The values of the elements of `param`, by name.
+fn element_values(param: &Parameter) -> Vec<(&String, Value)> {
+    let mut values: Vec<(&String, Value)> = match param {
+        Parameter::TextVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, Value::String(v.value.clone())))
+            .collect(),
+        Parameter::NumberVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, Value::Number(v.value)))
+            .collect(),
+        Parameter::SwitchVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, Value::String(format!("{:?}", v.value))))
+            .collect(),
+        Parameter::LightVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, Value::String(format!("{:?}", v.value))))
+            .collect(),
+        Parameter::BlobVector(p) => p.values.keys().map(|name| (name, Value::Missing)).collect(),
+    };
+    values.sort_by_key(|(name, _)| *name);
+    values
+}
+
+/// Replaces each `{reference}` in `template` with what `resolve` makes of it.
+fn interpolate(
+    template: &str,
+    mut resolve: impl FnMut(&str) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| "missing }".to_string())?;
+        out.push_str(&rest[..start]);
+        out.push_str(&resolve(&rest[start + 1..start + end])?);
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Literal(Value),
+    Reference(Reference),
+    Abs(Box<Expr>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn evaluate(&self, client: &Client) -> Value {
+        match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Reference(reference) => reference.evaluate(client),
+            Expr::Abs(e) => e.evaluate(client).map(&|v| match v {
+                Value::Number(n) => Value::Number(n.abs()),
+                _ => Value::Missing,
+            }),
+            Expr::Neg(e) => e.evaluate(client).map(&|v| match v {
+                Value::Number(n) => Value::Number(-n),
+                _ => Value::Missing,
+            }),
+            Expr::Not(e) => Value::Bool(!e.evaluate(client).truthy()),
+            Expr::Binary(op, a, b) => binary(*op, a.evaluate(client), b.evaluate(client)),
+            Expr::And(a, b) => {
+                let (a, b) = (a.evaluate(client), b.evaluate(client));
+                if a.truthy() && b.truthy() {
+                    a.merge(b)
+                } else {
+                    Value::Bool(false)
+                }
+            }
+            Expr::Or(a, b) => {
+                let (a, b) = (a.evaluate(client), b.evaluate(client));
+                match (a.truthy(), b.truthy()) {
+                    (true, true) => a.merge(b),
+                    (true, false) => a.matched(),
+                    (false, true) => b.matched(),
+                    (false, false) => Value::Bool(false),
+                }
+            }
+        }
+    }
+}
+
+/// Applies `op` to each of what a wildcard matched.
+fn binary(op: Op, a: Value, b: Value) -> Value {
+    match (a, b) {
+        (Value::List(items), b) => Value::List(
+            items
+                .into_iter()
+                .map(|(name, a)| (name, binary(op, a, b.clone())))
+                .collect(),
+        ),
+        (a, Value::List(items)) => Value::List(
+            items
+                .into_iter()
+                .map(|(name, b)| (name, binary(op, a.clone(), b)))
+                .collect(),
+        ),
+        (a, b) => scalar(op, a, b),
+    }
+}
+
+fn scalar(op: Op, a: Value, b: Value) -> Value {
+    if a == Value::Missing || b == Value::Missing {
+        return Value::Missing;
+    }
+    let ordering = match (&a, &b) {
+        (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+    match (op, a, b) {
+        (Op::Add, Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+        (Op::Sub, Value::Number(a), Value::Number(b)) => Value::Number(a - b),
+        (Op::Mul, Value::Number(a), Value::Number(b)) => Value::Number(a * b),
+        (Op::Div, Value::Number(a), Value::Number(b)) => Value::Number(a / b),
+        (Op::Eq, a, b) => Value::Bool(a == b),
+        (Op::Ne, a, b) => Value::Bool(a != b),
+        (Op::Lt | Op::Le | Op::Gt | Op::Ge, _, _) => match ordering {
+            Some(ordering) => Value::Bool(match op {
+                Op::Lt => ordering.is_lt(),
+                Op::Le => ordering.is_le(),
+                Op::Gt => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }),
+            None => Value::Missing,
+        },
+        _ => Value::Missing,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    String(String),
+    Reference(String),
+    Word(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::String(s) => write!(f, "{:?}", s),
+            Token::Reference(spec) => write!(f, "{{{}}}", spec),
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+static SYMBOLS: [&str; 12] = [
+    "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "(", ")",
+];
+
+fn tokenize(mut text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    loop {
+        text = text.trim_start();
+        let c = match text.chars().next() {
+            Some(c) => c,
+            None => return Ok(tokens),
+        };
+        let length = if c == '{' {
+            let end = text.find('}').ok_or("missing }")?;
+            tokens.push(Token::Reference(text[1..end].to_string()));
+            end + 1
+        } else if c == '"' {
+            let end = text[1..].find('"').ok_or("missing closing \"")? + 1;
+            tokens.push(Token::String(text[1..end].to_string()));
+            end + 1
+        } else if c.is_ascii_digit() || c == '.' {
+            let end = text
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.'))
+                .unwrap_or(text.len());
+            let number = &text[..end];
+            let number = number
+                .parse()
+                .map_err(|_| format!("invalid number {}", number))?;
+            tokens.push(Token::Number(number));
+            end
+        } else if c.is_alphabetic() || c == '_' {
+            let end = text
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(text.len());
+            tokens.push(Token::Word(text[..end].to_string()));
+            end
+        } else {
+            let symbol = SYMBOLS
+                .into_iter()
+                .find(|s| text.starts_with(s))
+                .ok_or_else(|| format!("unexpected {}", c))?;
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        };
+        text = &text[length..];
+    }
+}
+
+/// A recursive descent parser for conditions, from the loosest binding
+/// operator to the tightest.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, token: Token) -> bool {
+        let found = self.tokens.get(self.position) == Some(&token);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        self.eat(Token::Word(word.to_string()))
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Symbol(s)) if s == symbol => Ok(()),
+            Some(token) => Err(format!("expected {}, not {}", symbol, token)),
+            None => Err(format!("expected {}", symbol)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut e = self.and()?;
+        while self.eat_word("or") {
+            e = Expr::Or(Box::new(e), Box::new(self.and()?));
+        }
+        Ok(e)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut e = self.not()?;
+        while self.eat_word("and") {
+            e = Expr::And(Box::new(e), Box::new(self.not()?));
+        }
+        Ok(e)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.eat_word("not") {
+            Ok(Expr::Not(Box::new(self.not()?)))
+        } else {
+            self.comparison()
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let a = self.sum()?;
+        for (symbol, op) in [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ] {
+            if self.eat(Token::Symbol(symbol)) {
+                return Ok(Expr::Binary(op, Box::new(a), Box::new(self.sum()?)));
+            }
+        }
+        Ok(a)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut e = self.product()?;
+        loop {
+            let op = if self.eat(Token::Symbol("+")) {
+                Op::Add
+            } else if self.eat(Token::Symbol("-")) {
+                Op::Sub
+            } else {
+                return Ok(e);
+            };
+            e = Expr::Binary(op, Box::new(e), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut e = self.unary()?;
+        loop {
+            let op = if self.eat(Token::Symbol("*")) {
+                Op::Mul
+            } else if self.eat(Token::Symbol("/")) {
+                Op::Div
+            } else {
+                return Ok(e);
+            };
+            e = Expr::Binary(op, Box::new(e), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat(Token::Symbol("-")) {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else {
+            self.atom()
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Literal(Value::Number(n))),
+            Some(Token::String(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Token::Reference(spec)) => Ok(Expr::Reference(Reference::parse(&spec)?)),
+            Some(Token::Word(word)) if word == "abs" => {
+                self.expect("(")?;
+                let e = self.or()?;
+                self.expect(")")?;
+                Ok(Expr::Abs(Box::new(e)))
+            }
+            Some(Token::Symbol("(")) => {
+                let e = self.or()?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Err("unexpected end of condition".to_string()),
+        }
+    }
+}
+
+fn parse_condition(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        position: 0,
+    };
+    let e = parser.or()?;
+    match parser.next() {
+        Some(token) => Err(format!("unexpected {}", token)),
+        None => Ok(e),
+    }
+}
+
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let unit = match &text[split..] {
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("invalid duration {:?}", text)),
+    };
+    match text[..split].parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(Duration::from_secs_f64(n * unit)),
+        _ => Err(format!("invalid duration {:?}", text)),
+    }
+}
+
+/// Checks the references in a message.
+fn check_message(message: &str) -> Result<(), String> {
+    interpolate(message, |spec| {
+        Reference::parse(spec).map(|_| String::new())
+    })
+    .map(|_| ())
+}
+
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    line: usize,
+    condition: Option<Expr>,
+    duration: Duration,
+    actions: Vec<RuleAction>,
+    /// When each thing the condition matches started matching, and whether
+    /// the rule has fired for it.  Conditions without wildcards use "".
+    matched: HashMap<String, (Instant, bool)>,
+}
+
+#[derive(Debug, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Rules, RulesError> {
+        Rules::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Rules, RulesError> {
+        let mut rules: Vec<Rule> = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message| RulesError::Parse {
+                line: i + 1,
+                message,
+            };
+            let (clause, argument) = match line.split_once(char::is_whitespace) {
+                Some((clause, argument)) => (clause, argument.trim()),
+                None => (line, ""),
+            };
+            if clause == "rule" {
+                if argument.is_empty() {
+                    return Err(error("rule needs a name".to_string()));
+                }
+                rules.push(Rule {
+                    name: argument.to_string(),
+                    line: i + 1,
+                    condition: None,
+                    duration: Duration::ZERO,
+                    actions: vec![],
+                    matched: HashMap::new(),
+                });
+                continue;
+            }
+            let rule = match rules.last_mut() {
+                Some(rule) => rule,
+                None => return Err(error(format!("{} outside a rule", clause))),
+            };
+            match clause {
+                "when" if rule.condition.is_some() => {
+                    return Err(error(format!("{} has two conditions", rule.name)))
+                }
+                "when" => rule.condition = Some(parse_condition(argument).map_err(error)?),
+                "for" => rule.duration = parse_duration(argument).map_err(error)?,
+                "log" => {
+                    check_message(argument).map_err(error)?;
+                    rule.actions.push(RuleAction::Log(argument.to_string()));
+                }
+                "webhook" => {
+                    let (url, message) = match argument.split_once(char::is_whitespace) {
+                        Some((url, message)) => (url, Some(message.trim().to_string())),
+                        None => (argument, None),
+                    };
+                    if !url.starts_with("http://") {
+                        return Err(error(format!(
+                            "webhook needs an http:// URL, not {:?}",
+                            url
+                        )));
+                    }
+                    if let Some(message) = &message {
+                        check_message(message).map_err(error)?;
+                    }
+                    rule.actions.push(RuleAction::Webhook {
+                        url: url.to_string(),
+                        message,
+                    });
+                }
+                "set" => {
+                    let (target, value) = argument
+                        .strip_prefix('{')
+                        .and_then(|a| a.split_once('}'))
+                        .ok_or_else(|| error(format!("invalid set {:?}", argument)))?;
+                    let parts: Vec<&str> = target.splitn(3, '.').collect();
+                    let value = value.trim();
+                    match parts.as_slice() {
+                        [device, property, element]
+                            if !target.contains(['*', '?']) && !value.is_empty() =>
+                        {
+                            rule.actions.push(RuleAction::Set {
+                                device: device.to_string(),
+                                property: property.to_string(),
+                                element: element.to_string(),
+                                value: value.to_string(),
+                            })
+                        }
+                        _ => return Err(error(format!("invalid set {:?}", argument))),
+                    }
+                }
+                _ => return Err(error(format!("unknown clause {:?}", clause))),
+            }
+        }
+
+        for rule in &rules {
+            let error = |message: &str| RulesError::Parse {
+                line: rule.line,
+                message: format!("{} {}", rule.name, message),
+            };
+            if rule.condition.is_none() {
+                return Err(error("has no condition"));
+            }
+            if rule.actions.is_empty() {
+                return Err(error("does nothing"));
+            }
+        }
+        Ok(Rules { rules })
+    }
+
+    /// Evaluates every rule against `client`, returning those that fire.  Call
+    /// it after each update, and now and then between them so that `for`
+    /// durations can pass.
+    pub fn evaluate(&mut self, client: &Client, now: Instant) -> Vec<Firing> {
+        let mut firings = vec![];
+        for rule in &mut self.rules {
+            let value = match &rule.condition {
+                Some(condition) => condition.evaluate(client),
+                None => continue,
+            };
+            if !value.truthy() {
+                rule.matched.clear();
+                continue;
+            }
+            let names: Vec<String> = match value.matched() {
+                Value::List(items) if !items.is_empty() => {
+                    items.into_iter().map(|(name, _)| name).collect()
+                }
+                _ => vec![String::new()],
+            };
+            rule.matched.retain(|name, _| names.contains(name));
+            let mut matches = vec![];
+            let mut due = false;
+            for name in names {
+                let (since, fired) = rule.matched.entry(name.clone()).or_insert((now, false));
+                if *fired || now.duration_since(*since) < rule.duration {
+                    continue;
+                }
+                *fired = true;
+                due = true;
+                if !name.is_empty() {
+                    matches.push(name);
+                }
+            }
+            if !due {
+                continue;
+            }
+            firings.push(Firing {
+                rule: rule.name.clone(),
+                matches,
+                actions: rule.actions.iter().map(|a| a.interpolate(client)).collect(),
+            });
+        }
+        firings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(xml: &str) -> Client {
+        let mut client = Client::new();
+        for command in CommandIter::from_xml(xml) {
+            client.update(command.unwrap()).unwrap();
+        }
+        client
+    }
+
+    static DEVICES: &str = r#"
+<defNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" state="Busy" perm="rw">
+    <defNumber name="CCD_TEMPERATURE_VALUE" format="%5.2f" min="-50" max="50" step="0">-7.5</defNumber>
+</defNumberVector>
+<defNumberVector device="CCD Simulator" name="CCD_EXPOSURE" state="Busy" perm="rw">
+    <defNumber name="CCD_EXPOSURE_VALUE" format="%5.2f" min="0" max="3600" step="0">30</defNumber>
+</defNumberVector>
+<defSwitchVector device="Telescope Simulator" name="TELESCOPE_PARK" state="Alert" perm="rw" rule="OneOfMany">
+    <defSwitch name="PARK">On</defSwitch>
+    <defSwitch name="UNPARK">Off</defSwitch>
+</defSwitchVector>
+"#;
+
+    fn condition(text: &str) -> Value {
+        parse_condition(text).unwrap().evaluate(&client(DEVICES))
+    }
+
+    #[test]
+    fn test_condition() {
+        assert_eq!(
+            condition("abs({CCD Simulator.CCD_TEMPERATURE.CCD_TEMPERATURE_VALUE} + 10)"),
+            Value::Number(2.5)
+        );
+        assert_eq!(condition("1 + 2 * -3 == -5"), Value::Bool(true));
+        assert_eq!(condition("(1 + 2) * 3 >= 10"), Value::Bool(false));
+        assert_eq!(
+            condition(
+                r#"{Telescope Simulator.TELESCOPE_PARK.PARK} == "On" and {CCD Simulator.CCD_EXPOSURE} == "Busy""#
+            ),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            condition(r#"not {Telescope Simulator.TELESCOPE_PARK.PARK} == "On""#),
+            Value::Bool(false)
+        );
+        assert_eq!(condition("{Focuser.FOCUS.POSITION} > 1"), Value::Missing);
+        assert_eq!(condition("{Focuser.FOCUS.POSITION} != 1"), Value::Missing);
+        assert_eq!(
+            condition(r#"{*.*} == "Alert" or {CCD*.*.*} > 20"#).matched(),
+            Value::List(vec![
+                (
+                    "Telescope Simulator.TELESCOPE_PARK".to_string(),
+                    Value::Bool(true)
+                ),
+                (
+                    "CCD Simulator.CCD_EXPOSURE.CCD_EXPOSURE_VALUE".to_string(),
+                    Value::Bool(true)
+                ),
+            ])
+        );
+
+        let error = |text| parse_condition(text).unwrap_err();
+        assert_eq!(error("1 +"), "unexpected end of condition");
+        assert_eq!(error("(1"), "expected )");
+        assert_eq!(error("1 2"), "unexpected 2");
+        assert_eq!(
+            error("{CCD Simulator} == 1"),
+            "invalid reference {CCD Simulator}"
+        );
+        assert_eq!(error("1 ~ 2"), "unexpected ~");
+        assert_eq!(error(r#""Alert"#), "missing closing \"");
+    }
+
+    #[test]
+    fn test_parse() {
+        let rules = Rules::parse(
+            "
+# comment
+rule parked-while-imaging
+    when {Telescope Simulator.TELESCOPE_PARK.PARK} == \"On\"
+    for 1m30s
+",
+        );
+        assert_eq!(
+            rules.unwrap_err().to_string(),
+            "line 5: invalid duration \"1m30s\""
+        );
+
+        let error = |text| Rules::parse(text).unwrap_err().to_string();
+        assert_eq!(error("when 1 == 1"), "line 1: when outside a rule");
+        assert_eq!(error("rule a\n  log {x}"), "line 2: invalid reference {x}");
+        assert_eq!(error("rule a\n  when 1 == 1"), "line 1: a does nothing");
+        assert_eq!(error("rule a\n  log hi"), "line 1: a has no condition");
+        assert_eq!(
+            error("rule a\n  webhook https://example.com"),
+            "line 2: webhook needs an http:// URL, not \"https://example.com\""
+        );
+        assert_eq!(
+            error("rule a\n  set {CCD.*.ABORT} On"),
+            "line 2: invalid set \"{CCD.*.ABORT} On\""
+        );
+        assert_eq!(
+            error("rule a\n  email me"),
+            "line 2: unknown clause \"email\""
+        );
+
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("0.5h"), Ok(Duration::from_secs(1800)));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut rules = Rules::parse(
+            r#"
+rule ccd-temperature
+    when abs({CCD Simulator.CCD_TEMPERATURE.CCD_TEMPERATURE_VALUE} + 10) > 1
+    for 5m
+    log CCD is at {CCD Simulator.CCD_TEMPERATURE.CCD_TEMPERATURE_VALUE}
+
+rule alert
+    when {*.*} == "Alert"
+    webhook http://localhost:8123/hook
+
+rule parked-while-imaging
+    when {Telescope Simulator.TELESCOPE_PARK.PARK} == "On" and {CCD Simulator.CCD_EXPOSURE} == "Busy"
+    set {CCD Simulator.CCD_ABORT_EXPOSURE.ABORT} On
+"#,
+        )
+        .unwrap();
+        let mut client = client(DEVICES);
+        let start = Instant::now();
+
+        let firings = rules.evaluate(&client, start);
+        assert_eq!(
+            firings,
+            vec![
+                Firing {
+                    rule: "alert".to_string(),
+                    matches: vec!["Telescope Simulator.TELESCOPE_PARK".to_string()],
+                    actions: vec![RuleAction::Webhook {
+                        url: "http://localhost:8123/hook".to_string(),
+                        message: None,
+                    }],
+                },
+                Firing {
+                    rule: "parked-while-imaging".to_string(),
+                    matches: vec![],
+                    actions: vec![RuleAction::Set {
+                        device: "CCD Simulator".to_string(),
+                        property: "CCD_ABORT_EXPOSURE".to_string(),
+                        element: "ABORT".to_string(),
+                        value: "On".to_string(),
+                    }],
+                },
+            ]
+        );
+        // Rules fire once, until they've been false.
+        assert_eq!(
+            rules.evaluate(&client, start + Duration::from_secs(60)),
+            vec![]
+        );
+
+        let firings = rules.evaluate(&client, start + Duration::from_secs(300));
+        assert_eq!(firings.len(), 1);
+        assert_eq!(
+            firings[0].actions,
+            vec![RuleAction::Log("CCD is at -7.5".to_string())]
+        );
+
+        let mut commands = CommandIter::from_xml(
+            r#"<setSwitchVector device="Telescope Simulator" name="TELESCOPE_PARK" state="Ok">
+    <oneSwitch name="PARK">Off</oneSwitch>
+</setSwitchVector>
+<setSwitchVector device="Telescope Simulator" name="TELESCOPE_PARK" state="Alert">
+    <oneSwitch name="PARK">Off</oneSwitch>
+</setSwitchVector>"#,
+        );
+        client.update(commands.next().unwrap().unwrap()).unwrap();
+        assert_eq!(
+            rules.evaluate(&client, start + Duration::from_secs(301)),
+            vec![]
+        );
+        client.update(commands.next().unwrap().unwrap()).unwrap();
+        let firings = rules.evaluate(&client, start + Duration::from_secs(302));
+        assert_eq!(firings.len(), 1);
+        assert_eq!(firings[0].rule, "alert");
+
+        // Another property going into Alert fires the rule again, for it alone.
+        client
+            .update(
+                CommandIter::from_xml(
+                    r#"<setNumberVector device="CCD Simulator" name="CCD_EXPOSURE" state="Alert">
+    <oneNumber name="CCD_EXPOSURE_VALUE">30</oneNumber>
+</setNumberVector>"#,
+                )
+                .next()
+                .unwrap()
+                .unwrap(),
+            )
+            .unwrap();
+        let firings = rules.evaluate(&client, start + Duration::from_secs(303));
+        assert_eq!(firings.len(), 1);
+        assert_eq!(firings[0].rule, "alert");
+        assert_eq!(
+            firings[0].matches,
+            vec!["CCD Simulator.CCD_EXPOSURE".to_string()]
+        );
+    }
+}
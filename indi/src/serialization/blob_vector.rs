@@ -1,6 +1,4 @@
 use quick_xml::events::Event;
-use quick_xml::name::QName;
-use quick_xml::Reader;
 
 use std::str;
 
@@ -79,284 +77,238 @@ impl XmlSerialization for EnableBlob {
             creator = creator.with_attribute(("name", &name[..]));
         }
 
-        match self.enabled {
-            BlobEnable::Never => creator.write_text_content(BytesText::new("Never")),
-            BlobEnable::Also => creator.write_text_content(BytesText::new("Also")),
-            BlobEnable::Only => creator.write_text_content(BytesText::new("Only")),
-        }?;
+        creator.write_text_content(BytesText::new(self.enabled.as_str()))?;
 
         Ok(xml_writer)
     }
 }
 
-pub struct DefBlobIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
-}
+impl XmlSerialization for DefBlob {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        let mut creator = xml_writer
+            .create_element("defBLOB")
+            .with_attribute(("name", &*self.name));
 
-impl<'a, T: std::io::BufRead> Iterator for DefBlobIter<'a, T> {
-    type Item = Result<DefBlob, DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.next_blob() {
-            Ok(Some(switch)) => {
-                return Some(Ok(switch));
-            }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
-            }
+        if let Some(label) = &self.label {
+            creator = creator.with_attribute(("label", &label[..]));
         }
+        creator.write_empty()?;
+
+        Ok(xml_writer)
     }
 }
-impl<'a, T: std::io::BufRead> DefBlobIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> DefBlobIter<T> {
-        DefBlobIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
-        }
-    }
 
-    pub fn blob_vector(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<DefBlobVector, DeError> {
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-        let mut label: Option<String> = None;
-        let mut group: Option<String> = None;
-        let mut state: Option<PropertyState> = None;
-        let mut perm: Option<PropertyPerm> = None;
-        let mut timeout: Option<u32> = None;
-        let mut timestamp: Option<DateTime<Utc>> = None;
-        let mut message: Option<String> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                QName(b"label") => label = Some(attr_value),
-                QName(b"group") => group = Some(attr_value),
-                QName(b"state") => state = Some(PropertyState::try_from(attr, xml_reader)?),
-                QName(b"perm") => perm = Some(PropertyPerm::try_from(attr, xml_reader)?),
-                QName(b"timeout") => timeout = Some(attr_value.parse::<u32>()?),
-                QName(b"timestamp") => {
-                    timestamp = Some(DateTime::from_str(&format!("{}Z", &attr_value))?)
-                }
-                QName(b"message") => message = Some(attr_value),
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
-                }
-            }
-        }
-        Ok(DefBlobVector {
-            device: device.ok_or(DeError::MissingAttr(&"device"))?,
-            name: name.ok_or(DeError::MissingAttr(&"name"))?,
-            label: label,
-            group: group,
-            state: state.ok_or(DeError::MissingAttr(&"state"))?,
-            perm: perm.ok_or(DeError::MissingAttr(&"perm"))?,
-            timeout: timeout,
-            timestamp: timestamp,
-            message: message,
-            blobs: Vec::new(),
-        })
-    }
+impl XmlSerialization for OneBlob {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        let mut creator = xml_writer
+            .create_element("oneBLOB")
+            .with_attribute(("name", &*self.name))
+            .with_attribute(("format", &*self.format));
 
-    fn next_blob(&mut self) -> Result<Option<DefBlob>, DeError> {
-        let event = self.xml_reader.read_event_into(&mut self.buf)?;
-        match event {
-            Event::Start(e) => match e.name() {
-                QName(b"defBLOB") => {
-                    let mut name: Result<String, DeError> = Err(DeError::MissingAttr(&"name"));
-                    let mut label: Option<String> = None;
-
-                    for attr in e.attributes() {
-                        let attr = attr?;
-                        let attr_value = attr
-                            .decode_and_unescape_value(self.xml_reader)?
-                            .into_owned();
-
-                        match attr.key {
-                            QName(b"name") => name = Ok(attr_value),
-                            QName(b"label") => label = Some(attr_value),
-                            key => {
-                                return Err(DeError::UnexpectedAttr(format!(
-                                    "Unexpected attribute {}",
-                                    str::from_utf8(key.into_inner())?
-                                )))
-                            }
-                        }
-                    }
+        creator = creator.with_attribute(("size", self.size.to_string().as_str()));
+        if let Some(enclen) = &self.enclen {
+            creator = creator.with_attribute(("enclen", enclen.to_string().as_str()));
+        }
+        creator.write_text_content(BytesText::new(base64::encode(&self.value).as_str()))?;
 
-                    let trailing_event = self.xml_reader.read_event_into(&mut self.buf)?;
-                    match trailing_event {
-                        Event::End(_) => (),
-                        e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                    }
+        Ok(xml_writer)
+    }
+}
 
-                    Ok(Some(DefBlob {
-                        name: name?,
-                        label: label,
-                    }))
+impl XmlSerialization for DefBlobVector {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        mut xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        {
+            let mut creator = xml_writer
+                .create_element("defBLOBVector")
+                .with_attribute(("device", &*self.device))
+                .with_attribute(("name", &*self.name));
+
+            if let Some(label) = &self.label {
+                creator = creator.with_attribute(("label", &label[..]));
+            }
+            if let Some(group) = &self.group {
+                creator = creator.with_attribute(("group", &group[..]));
+            }
+            creator = creator.with_attribute(("state", self.state.as_str()));
+            creator = creator.with_attribute(("perm", self.perm.as_str()));
+            if let Some(timeout) = &self.timeout {
+                creator = creator.with_attribute(("timeout", timeout.to_string().as_str()));
+            }
+            if let Some(timestamp) = &self.timestamp {
+                creator =
+                    creator.with_attribute(("timestamp", format_timestamp(timestamp).as_str()));
+            }
+            if let Some(message) = &self.message {
+                creator = creator.with_attribute(("message", &message[..]));
+            }
+            xml_writer = creator.write_inner_content(|xml_writer| {
+                for blob in self.blobs.iter() {
+                    blob.write(xml_writer)?;
                 }
-                tag => Err(DeError::UnexpectedTag(
-                    str::from_utf8(tag.into_inner())?.to_string(),
-                )),
-            },
-            Event::End(_) => Ok(None),
-            Event::Eof => Ok(None),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
+                Ok(())
+            })?;
         }
-    }
-}
 
-pub struct SetBlobIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
+        Ok(xml_writer)
+    }
 }
 
-impl<'a, T: std::io::BufRead> Iterator for SetBlobIter<'a, T> {
-    type Item = Result<OneBlob, DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.next_blob() {
-            Ok(Some(switch)) => {
-                return Some(Ok(switch));
+impl XmlSerialization for SetBlobVector {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        mut xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        {
+            let mut creator = xml_writer
+                .create_element("setBLOBVector")
+                .with_attribute(("device", &*self.device))
+                .with_attribute(("name", &*self.name));
+
+            creator = creator.with_attribute(("state", self.state.as_str()));
+            if let Some(timeout) = &self.timeout {
+                creator = creator.with_attribute(("timeout", timeout.to_string().as_str()));
             }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
+            if let Some(timestamp) = &self.timestamp {
+                creator =
+                    creator.with_attribute(("timestamp", format_timestamp(timestamp).as_str()));
             }
+            if let Some(message) = &self.message {
+                creator = creator.with_attribute(("message", &message[..]));
+            }
+            xml_writer = creator.write_inner_content(|xml_writer| {
+                for blob in self.blobs.iter() {
+                    blob.write(xml_writer)?;
+                }
+                Ok(())
+            })?;
         }
+
+        Ok(xml_writer)
     }
 }
-impl<'a, T: std::io::BufRead> SetBlobIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> SetBlobIter<T> {
-        SetBlobIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
+
+pub type DefBlobIter<'a, T> = ElementIter<'a, T, DefBlob>;
+pub type SetBlobIter<'a, T> = ElementIter<'a, T, OneBlob>;
+
+impl XmlElement for DefBlob {
+    const TAG: &'static str = "defBLOB";
+    const ATTRS: &'static [&'static str] = &["name", "label"];
+
+    fn from_xml(attrs: Attrs, value: Option<BytesText>) -> Result<Self, DeError> {
+        if let Some(value) = value {
+            return Err(Event::Text(value).unexpected());
         }
+        Ok(DefBlob {
+            name: required(attrs.name, "name")?,
+            label: attrs.label,
+        })
     }
+}
 
-    pub fn blob_vector(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<SetBlobVector, DeError> {
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-        let mut state: Option<PropertyState> = None;
-        let mut timeout: Option<u32> = None;
-        let mut timestamp: Option<DateTime<Utc>> = None;
-        let mut message: Option<String> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                QName(b"state") => state = Some(PropertyState::try_from(attr, xml_reader)?),
-                QName(b"timeout") => timeout = Some(attr_value.parse::<u32>()?),
-                QName(b"timestamp") => {
-                    timestamp = Some(DateTime::from_str(&format!("{}Z", &attr_value))?)
-                }
-                QName(b"message") => message = Some(attr_value),
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
+impl XmlElement for OneBlob {
+    const TAG: &'static str = "oneBLOB";
+    const ATTRS: &'static [&'static str] = &["name", "size", "enclen", "format"];
+
+    fn from_xml(attrs: Attrs, value: Option<BytesText>) -> Result<Self, DeError> {
+        let size = required(attrs.size, "size")?;
+        let value = match value {
+            Some(value) => {
+                let esc = value.into_inner();
+                let capacity = usize::try_from(size).unwrap_or(usize::MAX).min(esc.len());
+                let mut result = Vec::with_capacity(capacity);
+                for line in esc.split(|b| *b == b'\n') {
+                    base64::decode_config_buf(line, base64::STANDARD, &mut result)?;
                 }
+                result
             }
-        }
-        Ok(SetBlobVector {
-            device: device.ok_or(DeError::MissingAttr(&"device"))?,
-            name: name.ok_or(DeError::MissingAttr(&"name"))?,
-            state: state.ok_or(DeError::MissingAttr(&"state"))?,
-            timeout: timeout,
-            timestamp: timestamp,
-            message: message,
+            None => Vec::new(),
+        };
+
+        Ok(OneBlob {
+            name: required(attrs.name, "name")?,
+            size,
+            enclen: attrs.enclen,
+            format: required(attrs.format, "format")?,
+            value,
+        })
+    }
+}
+
+impl XmlVector for DefBlobVector {
+    const ATTRS: &'static [&'static str] = &[
+        "device",
+        "name",
+        "label",
+        "group",
+        "state",
+        "perm",
+        "timeout",
+        "timestamp",
+        "message",
+    ];
+    type Element = DefBlob;
+
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError> {
+        Ok(DefBlobVector {
+            device: required(attrs.device, "device")?,
+            name: required(attrs.name, "name")?,
+            label: attrs.label,
+            group: attrs.group,
+            state: required(attrs.state, "state")?,
+            perm: required(attrs.perm, "perm")?,
+            timeout: attrs.timeout,
+            timestamp: attrs.timestamp,
+            message: attrs.message,
             blobs: Vec::new(),
         })
     }
 
-    fn next_blob(&mut self) -> Result<Option<OneBlob>, DeError> {
-        let event = self.xml_reader.read_event_into(&mut self.buf)?;
-        match event {
-            Event::Start(e) => match e.name() {
-                QName(b"oneBLOB") => {
-                    let mut name: Result<String, DeError> = Err(DeError::MissingAttr(&"name"));
-                    let mut size: Result<u64, DeError> = Err(DeError::MissingAttr(&"size"));
-                    let mut enclen: Option<u64> = None;
-                    let mut format: Result<String, DeError> = Err(DeError::MissingAttr(&"format"));
-
-                    for attr in e.attributes() {
-                        let attr = attr?;
-                        let attr_value = attr
-                            .decode_and_unescape_value(self.xml_reader)?
-                            .into_owned();
-
-                        match attr.key {
-                            QName(b"name") => name = Ok(attr_value),
-                            QName(b"format") => format = Ok(attr_value),
-                            QName(b"size") => size = Ok(attr_value.parse::<u64>()?),
-                            QName(b"enclen") => enclen = Some(attr_value.parse::<u64>()?),
-                            key => {
-                                return Err(DeError::UnexpectedAttr(format!(
-                                    "Unexpected attribute {}",
-                                    str::from_utf8(key.into_inner())?
-                                )))
-                            }
-                        }
-                    }
+    fn push(&mut self, element: DefBlob) {
+        self.blobs.push(element);
+    }
+}
 
-                    let value: Result<Vec<u8>, DeError> = match self
-                        .xml_reader
-                        .read_event_into(self.buf)
-                    {
-                        Ok(Event::Text(e)) => match size {
-                            Ok(size) => {
-                                let mut result = Vec::with_capacity(size.try_into().unwrap());
-                                let esc = e.into_inner();
-
-                                for line in esc.split(|b| *b == b'\n') {
-                                    base64::decode_config_buf(line, base64::STANDARD, &mut result)
-                                        .unwrap();
-                                }
-
-                                Ok(result)
-                            }
-                            Err(_) => Err(DeError::MissingAttr(&"size")),
-                        },
-                        e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                    };
+impl XmlVector for SetBlobVector {
+    const ATTRS: &'static [&'static str] =
+        &["device", "name", "state", "timeout", "timestamp", "message"];
+    type Element = OneBlob;
 
-                    let trailing_event = self.xml_reader.read_event_into(&mut self.buf)?;
-                    match trailing_event {
-                        Event::End(_) => (),
-                        e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                    }
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError> {
+        Ok(SetBlobVector {
+            device: required(attrs.device, "device")?,
+            name: required(attrs.name, "name")?,
+            state: required(attrs.state, "state")?,
+            timeout: attrs.timeout,
+            timestamp: attrs.timestamp,
+            message: attrs.message,
+            blobs: Vec::new(),
+        })
+    }
 
-                    Ok(Some(OneBlob {
-                        name: name?,
-                        size: size?,
-                        enclen: enclen,
-                        format: format?,
-                        value: value?,
-                    }))
-                }
-                tag => Err(DeError::UnexpectedTag(
-                    str::from_utf8(tag.into_inner())?.to_string(),
-                )),
-            },
-            Event::End(_) => Ok(None),
-            Event::Eof => Ok(None),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-        }
+    fn push(&mut self, element: OneBlob) {
+        self.blobs.push(element);
+    }
+}
+
+impl XmlElement for EnableBlob {
+    const TAG: &'static str = "enableBLOB";
+    const ATTRS: &'static [&'static str] = &["device", "name"];
+
+    fn from_xml(attrs: Attrs, value: Option<BytesText>) -> Result<Self, DeError> {
+        Ok(EnableBlob {
+            device: required(attrs.device, "device")?,
+            name: attrs.name,
+            enabled: BlobEnable::parse(&text(&value)?)?,
+        })
     }
 }
 
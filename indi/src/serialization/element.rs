@@ -0,0 +1,174 @@
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::Reader;
+
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::str;
+
+use super::super::*;
+use super::*;
+
+/// The attributes of an INDI vector or element.  Only the attributes a type
+/// lists in its `ATTRS` are accepted; anything else is an error.
+#[derive(Debug, Default)]
+pub struct Attrs {
+    pub device: Option<String>,
+    pub name: Option<String>,
+    pub label: Option<String>,
+    pub group: Option<String>,
+    pub state: Option<PropertyState>,
+    pub perm: Option<PropertyPerm>,
+    pub rule: Option<SwitchRule>,
+    pub timeout: Option<u32>,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub message: Option<String>,
+    pub version: Option<String>,
+    pub format: Option<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub step: Option<f64>,
+    pub size: Option<u64>,
+    pub enclen: Option<u64>,
+}
+
+impl Attrs {
+    /// Parses the attributes of `start`, rejecting any not in `allowed`.  Values are
+    /// only copied into a `String` for the attributes that are kept as strings.
+    pub fn parse<T: std::io::BufRead>(
+        xml_reader: &Reader<T>,
+        start: &BytesStart,
+        allowed: &[&str],
+    ) -> Result<Attrs, DeError> {
+        let mut attrs = Attrs::default();
+
+        for attr in start.attributes() {
+            let attr = attr?;
+            let key = str::from_utf8(attr.key.into_inner())?;
+            if !allowed.contains(&key) {
+                return Err(DeError::UnexpectedAttr(key.to_string()));
+            }
+            let value = attr.decode_and_unescape_value(xml_reader)?;
+
+            match key {
+                "device" => attrs.device = Some(value.into_owned()),
+                "name" => attrs.name = Some(value.into_owned()),
+                "label" => attrs.label = Some(value.into_owned()),
+                "group" => attrs.group = Some(value.into_owned()),
+                "state" => attrs.state = Some(PropertyState::parse(&value)?),
+                "perm" => attrs.perm = Some(PropertyPerm::parse(&value)?),
+                "rule" => attrs.rule = Some(SwitchRule::parse(&value)?),
+                "timeout" => attrs.timeout = Some(value.parse()?),
+                "timestamp" => attrs.timestamp = Some(parse_timestamp(&value)?),
+                "message" => attrs.message = Some(value.into_owned()),
+                "version" => attrs.version = Some(value.into_owned()),
+                "format" => attrs.format = Some(value.into_owned()),
+                "min" => attrs.min = Some(value.parse()?),
+                "max" => attrs.max = Some(value.parse()?),
+                "step" => attrs.step = Some(value.parse()?),
+                "size" => attrs.size = Some(value.parse()?),
+                "enclen" => attrs.enclen = Some(value.parse()?),
+                key => return Err(DeError::UnexpectedAttr(key.to_string())),
+            }
+        }
+        Ok(attrs)
+    }
+}
+
+/// Unwraps a required attribute.
+pub fn required<V>(value: Option<V>, attr: &'static str) -> Result<V, DeError> {
+    value.ok_or(DeError::MissingAttr(attr))
+}
+
+/// The text content of an element, or an empty string if it has none.
+pub fn text<'a>(value: &'a Option<BytesText>) -> Result<Cow<'a, str>, DeError> {
+    match value {
+        Some(text) => Ok(text.unescape()?),
+        None => Ok(Cow::Borrowed("")),
+    }
+}
+
+/// An element with attributes and an optional text value, such as `oneNumber`.
+pub trait XmlElement: Sized {
+    const TAG: &'static str;
+    const ATTRS: &'static [&'static str];
+
+    fn from_xml(attrs: Attrs, value: Option<BytesText>) -> Result<Self, DeError>;
+}
+
+/// A vector command, such as `setNumberVector`, made up of `Element`s.
+pub trait XmlVector: Sized {
+    const ATTRS: &'static [&'static str];
+    type Element: XmlElement;
+
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError>;
+    fn push(&mut self, element: Self::Element);
+
+    fn from_start<T: std::io::BufRead>(
+        xml_reader: &Reader<T>,
+        start: &BytesStart,
+    ) -> Result<Self, DeError> {
+        Self::from_attrs(Attrs::parse(xml_reader, start, Self::ATTRS)?)
+    }
+}
+
+/// Reads the value and closing tag of an element whose start tag has already been read.
+pub fn read_element<T: std::io::BufRead, E: XmlElement>(
+    xml_reader: &mut Reader<T>,
+    buf: &mut Vec<u8>,
+    attrs: Attrs,
+) -> Result<E, DeError> {
+    buf.clear();
+    match xml_reader.read_event_into(buf)? {
+        Event::Text(value) => {
+            let element = E::from_xml(attrs, Some(value))?;
+            buf.clear();
+            match xml_reader.read_event_into(buf)? {
+                Event::End(_) => Ok(element),
+                e => Err(e.unexpected()),
+            }
+        }
+        Event::End(_) => E::from_xml(attrs, None),
+        e => Err(e.unexpected()),
+    }
+}
+
+/// Iterates over the elements of a vector, up to its closing tag.
+pub struct ElementIter<'a, T: std::io::BufRead, E> {
+    xml_reader: &'a mut Reader<T>,
+    buf: &'a mut Vec<u8>,
+    element: PhantomData<E>,
+}
+
+impl<'a, T: std::io::BufRead, E: XmlElement> Iterator for ElementIter<'a, T, E> {
+    type Item = Result<E, DeError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_element().transpose()
+    }
+}
+
+impl<'a, T: std::io::BufRead, E: XmlElement> ElementIter<'a, T, E> {
+    pub fn new(command_iter: &mut CommandIter<T>) -> ElementIter<'_, T, E> {
+        ElementIter {
+            xml_reader: &mut command_iter.xml_reader,
+            buf: &mut command_iter.buf,
+            element: PhantomData,
+        }
+    }
+
+    fn next_element(&mut self) -> Result<Option<E>, DeError> {
+        self.buf.clear();
+        let attrs = match self.xml_reader.read_event_into(self.buf)? {
+            Event::Start(e) if e.name().into_inner() == E::TAG.as_bytes() => {
+                Attrs::parse(self.xml_reader, &e, E::ATTRS)?
+            }
+            Event::Start(e) => {
+                return Err(DeError::UnexpectedTag(
+                    str::from_utf8(e.name().into_inner())?.to_string(),
+                ))
+            }
+            Event::End(_) | Event::Eof => return Ok(None),
+            e => return Err(e.unexpected()),
+        };
+        read_element(self.xml_reader, self.buf, attrs).map(Some)
+    }
+}
@@ -1,6 +1,5 @@
 use quick_xml::events::Event;
-use quick_xml::name::QName;
-use quick_xml::{Reader, Writer};
+use quick_xml::Writer;
 
 use std::str;
 
@@ -28,70 +27,20 @@ impl XmlSerialization for GetProperties {
     }
 }
 
-pub struct GetPropertiesIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
-}
+impl XmlElement for GetProperties {
+    const TAG: &'static str = "getProperties";
+    const ATTRS: &'static [&'static str] = &["version", "device", "name"];
 
-impl<'a, T: std::io::BufRead> Iterator for GetPropertiesIter<'a, T> {
-    type Item = Result<(), DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.next_get_properties() {
-            Ok(Some(m)) => {
-                return Some(Ok(m));
-            }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
-            }
-        }
-    }
-}
-impl<'a, T: std::io::BufRead> GetPropertiesIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> GetPropertiesIter<T> {
-        GetPropertiesIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
-        }
-    }
-
-    pub fn get_properties(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<GetProperties, DeError> {
-        let mut version: Result<String, DeError> = Err(DeError::MissingAttr(&"version"));
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"version") => version = Ok(attr_value),
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
-                }
-            }
+    fn from_xml(attrs: Attrs, value: Option<BytesText>) -> Result<Self, DeError> {
+        if let Some(value) = value {
+            return Err(Event::Text(value).unexpected());
         }
         Ok(GetProperties {
-            version: version?,
-            device: device,
-            name: name,
+            version: required(attrs.version, "version")?,
+            device: attrs.device,
+            name: attrs.name,
         })
     }
-
-    fn next_get_properties(&mut self) -> Result<Option<()>, DeError> {
-        let trailing_event = self.xml_reader.read_event_into(&mut self.buf)?;
-        match trailing_event {
-            Event::End(_) => Ok(None),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-        }
-    }
 }
 
 #[cfg(test)]
@@ -1,7 +1,3 @@
-use quick_xml::events::Event;
-use quick_xml::name::QName;
-use quick_xml::Reader;
-
 use std::str;
 
 use super::super::*;
@@ -60,248 +56,182 @@ impl CommandToUpdate for SetLightVector {
     }
 }
 
-pub struct DefLightIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
+impl XmlSerialization for DefLight {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        let mut creator = xml_writer
+            .create_element("defLight")
+            .with_attribute(("name", &*self.name));
+
+        if let Some(label) = &self.label {
+            creator = creator.with_attribute(("label", &label[..]));
+        }
+        creator.write_text_content(BytesText::new(self.value.as_str()))?;
+
+        Ok(xml_writer)
+    }
+}
+
+impl XmlSerialization for OneLight {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        let creator = xml_writer
+            .create_element("oneLight")
+            .with_attribute(("name", &*self.name));
+
+        creator.write_text_content(BytesText::new(self.value.as_str()))?;
+
+        Ok(xml_writer)
+    }
 }
 
-impl<'a, T: std::io::BufRead> Iterator for DefLightIter<'a, T> {
-    type Item = Result<DefLight, DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.next_light() {
-            Ok(Some(light)) => {
-                return Some(Ok(light));
+impl XmlSerialization for DefLightVector {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        mut xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        {
+            let mut creator = xml_writer
+                .create_element("defLightVector")
+                .with_attribute(("device", &*self.device))
+                .with_attribute(("name", &*self.name));
+
+            if let Some(label) = &self.label {
+                creator = creator.with_attribute(("label", &label[..]));
+            }
+            if let Some(group) = &self.group {
+                creator = creator.with_attribute(("group", &group[..]));
+            }
+            creator = creator.with_attribute(("state", self.state.as_str()));
+            if let Some(timestamp) = &self.timestamp {
+                creator =
+                    creator.with_attribute(("timestamp", format_timestamp(timestamp).as_str()));
             }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
+            if let Some(message) = &self.message {
+                creator = creator.with_attribute(("message", &message[..]));
             }
+            xml_writer = creator.write_inner_content(|xml_writer| {
+                for light in self.lights.iter() {
+                    light.write(xml_writer)?;
+                }
+                Ok(())
+            })?;
         }
+
+        Ok(xml_writer)
     }
 }
-impl<'a, T: std::io::BufRead> DefLightIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> DefLightIter<T> {
-        DefLightIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
-        }
-    }
 
-    pub fn light_vector(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<DefLightVector, DeError> {
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-        let mut label: Option<String> = None;
-        let mut group: Option<String> = None;
-        let mut state: Option<PropertyState> = None;
-        let mut timestamp: Option<DateTime<Utc>> = None;
-        let mut message: Option<String> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                QName(b"label") => label = Some(attr_value),
-                QName(b"group") => group = Some(attr_value),
-                QName(b"state") => state = Some(PropertyState::try_from(attr, xml_reader)?),
-                QName(b"timestamp") => {
-                    timestamp = Some(DateTime::from_str(&format!("{}Z", &attr_value))?)
-                }
-                QName(b"message") => message = Some(attr_value),
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
-                }
+impl XmlSerialization for SetLightVector {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        mut xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        {
+            let mut creator = xml_writer
+                .create_element("setLightVector")
+                .with_attribute(("device", &*self.device))
+                .with_attribute(("name", &*self.name));
+
+            creator = creator.with_attribute(("state", self.state.as_str()));
+            if let Some(timestamp) = &self.timestamp {
+                creator =
+                    creator.with_attribute(("timestamp", format_timestamp(timestamp).as_str()));
+            }
+            if let Some(message) = &self.message {
+                creator = creator.with_attribute(("message", &message[..]));
             }
+            xml_writer = creator.write_inner_content(|xml_writer| {
+                for light in self.lights.iter() {
+                    light.write(xml_writer)?;
+                }
+                Ok(())
+            })?;
         }
-        Ok(DefLightVector {
-            device: device.ok_or(DeError::MissingAttr(&"device"))?,
-            name: name.ok_or(DeError::MissingAttr(&"name"))?,
-            label: label,
-            group: group,
-            state: state.ok_or(DeError::MissingAttr(&"state"))?,
-            timestamp: timestamp,
-            message: message,
-            lights: Vec::new(),
-        })
-    }
 
-    fn next_light(&mut self) -> Result<Option<DefLight>, DeError> {
-        let event = self.xml_reader.read_event_into(&mut self.buf)?;
-        match event {
-            Event::Start(e) => match e.name() {
-                QName(b"defLight") => {
-                    let mut name: Result<String, DeError> = Err(DeError::MissingAttr(&"name"));
-                    let mut label: Option<String> = None;
-
-                    for attr in e.attributes() {
-                        let attr = attr?;
-                        let attr_value = attr
-                            .decode_and_unescape_value(self.xml_reader)?
-                            .into_owned();
-
-                        match attr.key {
-                            QName(b"name") => name = Ok(attr_value),
-                            QName(b"label") => label = Some(attr_value),
-                            key => {
-                                return Err(DeError::UnexpectedAttr(format!(
-                                    "Unexpected attribute {}",
-                                    str::from_utf8(key.into_inner())?
-                                )))
-                            }
-                        }
-                    }
+        Ok(xml_writer)
+    }
+}
 
-                    let value: Result<PropertyState, DeError> =
-                        match self.xml_reader.read_event_into(self.buf) {
-                            Ok(Event::Text(e)) => PropertyState::try_from_event(e),
-                            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                        };
+pub type DefLightIter<'a, T> = ElementIter<'a, T, DefLight>;
+pub type SetLightIter<'a, T> = ElementIter<'a, T, OneLight>;
 
-                    let trailing_event = self.xml_reader.read_event_into(&mut self.buf)?;
-                    match trailing_event {
-                        Event::End(_) => (),
-                        e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                    }
+impl XmlElement for DefLight {
+    const TAG: &'static str = "defLight";
+    const ATTRS: &'static [&'static str] = &["name", "label"];
 
-                    Ok(Some(DefLight {
-                        name: name?,
-                        label: label,
-                        value: value?,
-                    }))
-                }
-                tag => Err(DeError::UnexpectedTag(
-                    str::from_utf8(tag.into_inner())?.to_string(),
-                )),
-            },
-            Event::End(_) => Ok(None),
-            Event::Eof => Ok(None),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-        }
+    fn from_xml(attrs: Attrs, value: Option<BytesText>) -> Result<Self, DeError> {
+        Ok(DefLight {
+            name: required(attrs.name, "name")?,
+            label: attrs.label,
+            value: PropertyState::parse(&text(&value)?)?,
+        })
     }
 }
 
-pub struct SetLightIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
-}
+impl XmlElement for OneLight {
+    const TAG: &'static str = "oneLight";
+    const ATTRS: &'static [&'static str] = &["name"];
 
-impl<'a, T: std::io::BufRead> Iterator for SetLightIter<'a, T> {
-    type Item = Result<OneLight, DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.next_light() {
-            Ok(Some(light)) => {
-                return Some(Ok(light));
-            }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
-            }
-        }
+    fn from_xml(attrs: Attrs, value: Option<BytesText>) -> Result<Self, DeError> {
+        Ok(OneLight {
+            name: required(attrs.name, "name")?,
+            value: PropertyState::parse(&text(&value)?)?,
+        })
     }
 }
-impl<'a, T: std::io::BufRead> SetLightIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> SetLightIter<T> {
-        SetLightIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
-        }
-    }
 
-    pub fn light_vector(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<SetLightVector, DeError> {
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-        let mut state: Option<PropertyState> = None;
-        let mut timestamp: Option<DateTime<Utc>> = None;
-        let mut message: Option<String> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                QName(b"state") => state = Some(PropertyState::try_from(attr, xml_reader)?),
-                QName(b"timestamp") => {
-                    timestamp = Some(DateTime::from_str(&format!("{}Z", &attr_value))?)
-                }
-                QName(b"message") => message = Some(attr_value),
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
-                }
-            }
-        }
-        Ok(SetLightVector {
-            device: device.ok_or(DeError::MissingAttr(&"device"))?,
-            name: name.ok_or(DeError::MissingAttr(&"name"))?,
-            state: state.ok_or(DeError::MissingAttr(&"state"))?,
-            timestamp: timestamp,
-            message: message,
+impl XmlVector for DefLightVector {
+    const ATTRS: &'static [&'static str] = &[
+        "device",
+        "name",
+        "label",
+        "group",
+        "state",
+        "timestamp",
+        "message",
+    ];
+    type Element = DefLight;
+
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError> {
+        Ok(DefLightVector {
+            device: required(attrs.device, "device")?,
+            name: required(attrs.name, "name")?,
+            label: attrs.label,
+            group: attrs.group,
+            state: required(attrs.state, "state")?,
+            timestamp: attrs.timestamp,
+            message: attrs.message,
             lights: Vec::new(),
         })
     }
 
-    fn next_light(&mut self) -> Result<Option<OneLight>, DeError> {
-        let event = self.xml_reader.read_event_into(&mut self.buf)?;
-        match event {
-            Event::Start(e) => match e.name() {
-                QName(b"oneLight") => {
-                    let mut name: Result<String, DeError> = Err(DeError::MissingAttr(&"name"));
-
-                    for attr in e.attributes() {
-                        let attr = attr?;
-                        let attr_value = attr
-                            .decode_and_unescape_value(self.xml_reader)?
-                            .into_owned();
-
-                        match attr.key {
-                            QName(b"name") => name = Ok(attr_value),
-                            key => {
-                                return Err(DeError::UnexpectedAttr(format!(
-                                    "Unexpected attribute {}",
-                                    str::from_utf8(key.into_inner())?
-                                )))
-                            }
-                        }
-                    }
+    fn push(&mut self, element: DefLight) {
+        self.lights.push(element);
+    }
+}
 
-                    let value: Result<PropertyState, DeError> =
-                        match self.xml_reader.read_event_into(self.buf) {
-                            Ok(Event::Text(e)) => PropertyState::try_from_event(e),
-                            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                        };
+impl XmlVector for SetLightVector {
+    const ATTRS: &'static [&'static str] = &["device", "name", "state", "timestamp", "message"];
+    type Element = OneLight;
 
-                    let trailing_event = self.xml_reader.read_event_into(&mut self.buf)?;
-                    match trailing_event {
-                        Event::End(_) => (),
-                        e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                    }
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError> {
+        Ok(SetLightVector {
+            device: required(attrs.device, "device")?,
+            name: required(attrs.name, "name")?,
+            state: required(attrs.state, "state")?,
+            timestamp: attrs.timestamp,
+            message: attrs.message,
+            lights: Vec::new(),
+        })
+    }
 
-                    Ok(Some(OneLight {
-                        name: name?,
-                        value: value?,
-                    }))
-                }
-                tag => Err(DeError::UnexpectedTag(
-                    str::from_utf8(tag.into_inner())?.to_string(),
-                )),
-            },
-            Event::End(_) => Ok(None),
-            Event::Eof => Ok(None),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-        }
+    fn push(&mut self, element: OneLight) {
+        self.lights.push(element);
     }
 }
 
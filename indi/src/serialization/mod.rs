@@ -1,3 +1,6 @@
+pub mod element;
+pub use element::*;
+
 pub mod number_vector;
 pub use number_vector::DefNumberIter;
 pub use number_vector::NewNumberIter;
@@ -22,23 +25,28 @@ pub use blob_vector::DefBlobIter;
 pub use blob_vector::SetBlobIter;
 
 pub mod message;
-pub use message::MessageIter;
 
 pub mod del_property;
-pub use del_property::DelPropertyIter;
 
 pub mod get_properties;
+
+pub mod timestamp;
 use super::*;
-pub use get_properties::GetPropertiesIter;
+pub use timestamp::{format_timestamp, parse_timestamp};
 
+use quick_xml::events::BytesStart;
 use quick_xml::name::QName;
 use quick_xml::Result as XmlResult;
 use quick_xml::{Reader, Writer};
 
+use std::fmt;
+
+#[cfg(test)]
+mod round_trip;
 #[cfg(test)]
 mod tests;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Command {
     // Commands from Device to Connections
     DefTextVector(DefTextVector),
@@ -90,6 +98,75 @@ impl Command {
             Command::EnableBlob(c) => Some(&c.device),
         }
     }
+
+    /// The name of the property the command is about, if it names one.
+    pub fn property_name(&self) -> Option<&String> {
+        match self {
+            Command::DefTextVector(c) => Some(&c.name),
+            Command::SetTextVector(c) => Some(&c.name),
+            Command::NewTextVector(c) => Some(&c.name),
+            Command::DefNumberVector(c) => Some(&c.name),
+            Command::SetNumberVector(c) => Some(&c.name),
+            Command::NewNumberVector(c) => Some(&c.name),
+            Command::DefSwitchVector(c) => Some(&c.name),
+            Command::SetSwitchVector(c) => Some(&c.name),
+            Command::NewSwitchVector(c) => Some(&c.name),
+            Command::DefLightVector(c) => Some(&c.name),
+            Command::SetLightVector(c) => Some(&c.name),
+            Command::DefBlobVector(c) => Some(&c.name),
+            Command::SetBlobVector(c) => Some(&c.name),
+            Command::Message(_) => None,
+            Command::DelProperty(c) => c.name.as_ref(),
+            Command::GetProperties(c) => c.name.as_ref(),
+            Command::EnableBlob(c) => c.name.as_ref(),
+        }
+    }
+
+    /// The timestamp the command carries, if any.
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Command::DefTextVector(c) => c.timestamp,
+            Command::SetTextVector(c) => c.timestamp,
+            Command::NewTextVector(c) => c.timestamp,
+            Command::DefNumberVector(c) => c.timestamp,
+            Command::SetNumberVector(c) => c.timestamp,
+            Command::NewNumberVector(c) => c.timestamp,
+            Command::DefSwitchVector(c) => c.timestamp,
+            Command::SetSwitchVector(c) => c.timestamp,
+            Command::NewSwitchVector(c) => c.timestamp,
+            Command::DefLightVector(c) => c.timestamp,
+            Command::SetLightVector(c) => c.timestamp,
+            Command::DefBlobVector(c) => c.timestamp,
+            Command::SetBlobVector(c) => c.timestamp,
+            Command::Message(c) => c.timestamp,
+            Command::DelProperty(c) => c.timestamp,
+            Command::GetProperties(_) => None,
+            Command::EnableBlob(_) => None,
+        }
+    }
+
+    /// The name of the XML element the command is sent as, e.g. `setNumberVector`.
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Command::DefTextVector(_) => "defTextVector",
+            Command::SetTextVector(_) => "setTextVector",
+            Command::NewTextVector(_) => "newTextVector",
+            Command::DefNumberVector(_) => "defNumberVector",
+            Command::SetNumberVector(_) => "setNumberVector",
+            Command::NewNumberVector(_) => "newNumberVector",
+            Command::DefSwitchVector(_) => "defSwitchVector",
+            Command::SetSwitchVector(_) => "setSwitchVector",
+            Command::NewSwitchVector(_) => "newSwitchVector",
+            Command::DefLightVector(_) => "defLightVector",
+            Command::SetLightVector(_) => "setLightVector",
+            Command::DefBlobVector(_) => "defBLOBVector",
+            Command::SetBlobVector(_) => "setBLOBVector",
+            Command::Message(_) => "message",
+            Command::DelProperty(_) => "delProperty",
+            Command::GetProperties(_) => "getProperties",
+            Command::EnableBlob(_) => "enableBLOB",
+        }
+    }
 }
 
 impl XmlSerialization for Command {
@@ -98,17 +175,28 @@ impl XmlSerialization for Command {
         xml_writer: &'a mut Writer<T>,
     ) -> XmlResult<&'a mut Writer<T>> {
         match self {
+            Command::DefTextVector(c) => c.write(xml_writer),
+            Command::SetTextVector(c) => c.write(xml_writer),
             Command::NewTextVector(c) => c.write(xml_writer),
+            Command::DefNumberVector(c) => c.write(xml_writer),
+            Command::SetNumberVector(c) => c.write(xml_writer),
             Command::NewNumberVector(c) => c.write(xml_writer),
+            Command::DefSwitchVector(c) => c.write(xml_writer),
+            Command::SetSwitchVector(c) => c.write(xml_writer),
             Command::NewSwitchVector(c) => c.write(xml_writer),
+            Command::DefLightVector(c) => c.write(xml_writer),
+            Command::SetLightVector(c) => c.write(xml_writer),
+            Command::DefBlobVector(c) => c.write(xml_writer),
+            Command::SetBlobVector(c) => c.write(xml_writer),
+            Command::Message(c) => c.write(xml_writer),
+            Command::DelProperty(c) => c.write(xml_writer),
             Command::EnableBlob(c) => c.write(xml_writer),
-
-            _ => panic!("asdf"),
+            Command::GetProperties(c) => c.write(xml_writer),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DefTextVector {
     pub device: String,
     pub name: String,
@@ -123,14 +211,14 @@ pub struct DefTextVector {
     pub texts: Vec<DefText>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DefText {
     pub name: String,
     pub label: Option<String>,
     pub value: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SetTextVector {
     pub device: String,
     pub name: String,
@@ -142,7 +230,7 @@ pub struct SetTextVector {
     pub texts: Vec<OneText>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct NewTextVector {
     pub device: String,
     pub name: String,
@@ -151,13 +239,13 @@ pub struct NewTextVector {
     pub texts: Vec<OneText>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct OneText {
     pub name: String,
     pub value: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DefNumberVector {
     pub device: String,
     pub name: String,
@@ -172,7 +260,7 @@ pub struct DefNumberVector {
     pub numbers: Vec<DefNumber>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DefNumber {
     pub name: String,
     pub label: Option<String>,
@@ -183,7 +271,7 @@ pub struct DefNumber {
     pub value: f64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SetNumberVector {
     pub device: String,
     pub name: String,
@@ -195,7 +283,7 @@ pub struct SetNumberVector {
     pub numbers: Vec<OneNumber>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct NewNumberVector {
     pub device: String,
     pub name: String,
@@ -204,7 +292,7 @@ pub struct NewNumberVector {
     pub numbers: Vec<OneNumber>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct OneNumber {
     pub name: String,
     pub min: Option<f64>,
@@ -213,7 +301,7 @@ pub struct OneNumber {
     pub value: f64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DefSwitchVector {
     pub device: String,
     pub name: String,
@@ -229,14 +317,14 @@ pub struct DefSwitchVector {
     pub switches: Vec<DefSwitch>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DefSwitch {
     pub name: String,
     pub label: Option<String>,
     pub value: SwitchState,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SetSwitchVector {
     pub device: String,
     pub name: String,
@@ -247,7 +335,7 @@ pub struct SetSwitchVector {
 
     pub switches: Vec<OneSwitch>,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct NewSwitchVector {
     pub device: String,
     pub name: String,
@@ -256,13 +344,13 @@ pub struct NewSwitchVector {
     pub switches: Vec<OneSwitch>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct OneSwitch {
     pub name: String,
     pub value: SwitchState,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DefLightVector {
     pub device: String,
     pub name: String,
@@ -275,14 +363,14 @@ pub struct DefLightVector {
     pub lights: Vec<DefLight>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DefLight {
-    name: String,
-    label: Option<String>,
-    value: PropertyState,
+    pub name: String,
+    pub label: Option<String>,
+    pub value: PropertyState,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SetLightVector {
     pub device: String,
     pub name: String,
@@ -293,13 +381,13 @@ pub struct SetLightVector {
     pub lights: Vec<OneLight>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct OneLight {
-    name: String,
-    value: PropertyState,
+    pub name: String,
+    pub value: PropertyState,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DefBlobVector {
     pub device: String,
     pub name: String,
@@ -314,13 +402,13 @@ pub struct DefBlobVector {
     pub blobs: Vec<DefBlob>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DefBlob {
-    name: String,
-    label: Option<String>,
+    pub name: String,
+    pub label: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SetBlobVector {
     pub device: String,
     pub name: String,
@@ -332,16 +420,22 @@ pub struct SetBlobVector {
     pub blobs: Vec<OneBlob>,
 }
 
-#[derive(Debug, PartialEq)]
+/// One BLOB of a `setBLOBVector`, with its value already decoded from base64.
+#[derive(Debug, Clone, PartialEq)]
 pub struct OneBlob {
-    name: String,
-    size: u64,
-    enclen: Option<u64>,
-    format: String,
-    value: Vec<u8>,
+    pub name: String,
+    /// The size of the decoded value in bytes, or before compression if the
+    /// format ends in `.z`.
+    pub size: u64,
+    /// The length of the base64 text the value was sent as, if the sender
+    /// gave it.
+    pub enclen: Option<u64>,
+    /// The file suffix of the value, such as `.fits`.
+    pub format: String,
+    pub value: Vec<u8>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct EnableBlob {
     pub device: String,
     pub name: Option<String>,
@@ -349,14 +443,14 @@ pub struct EnableBlob {
     pub enabled: BlobEnable,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Message {
     pub device: Option<String>,
     pub timestamp: Option<DateTime<Utc>>,
     pub message: Option<String>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DelProperty {
     pub device: String,
     pub name: Option<String>,
@@ -364,7 +458,7 @@ pub struct DelProperty {
     pub message: Option<String>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct GetProperties {
     pub version: String,
     pub device: Option<String>,
@@ -388,11 +482,189 @@ pub enum DeError {
     ParseFloatError(num::ParseFloatError),
     ParseSexagesimalError(String),
     ParseDateTimeError(ParseError),
+    DecodeBase64(base64::DecodeError),
     MissingAttr(&'static str),
     BadAttr(AttrError),
     UnexpectedAttr(String),
     UnexpectedEvent(String),
     UnexpectedTag(String),
+    UnexpectedValue(String),
+    /// An error along with where in the stream it happened.
+    WithContext(Box<DeError>, ErrorContext),
+}
+
+/// Location of a parse error within a stream of INDI commands.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ErrorContext {
+    /// Byte offset into the stream at which the error was detected.
+    pub position: usize,
+    /// Name of the top level element being parsed, e.g. `defNumberVector`.
+    pub element: Option<String>,
+    pub device: Option<String>,
+    pub property: Option<String>,
+}
+
+impl ErrorContext {
+    fn from_start<T: std::io::BufRead>(xml_reader: &Reader<T>, e: &BytesStart) -> ErrorContext {
+        let mut context = ErrorContext {
+            element: str::from_utf8(e.name().into_inner()).ok().map(String::from),
+            ..Default::default()
+        };
+        for attr in e.attributes().flatten() {
+            let value = match attr.decode_and_unescape_value(xml_reader) {
+                Ok(value) => Some(value.into_owned()),
+                Err(_) => None,
+            };
+            match attr.key {
+                QName(b"device") => context.device = value,
+                QName(b"name") => context.property = value,
+                _ => (),
+            }
+        }
+        context
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "byte {}", self.position)?;
+        if let Some(element) = &self.element {
+            write!(f, " in <{}>", element)?;
+        }
+        match (&self.device, &self.property) {
+            (Some(device), Some(property)) => write!(f, " of {}.{}", device, property),
+            (Some(device), None) => write!(f, " of {}", device),
+            (None, Some(property)) => write!(f, " of {}", property),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl DeError {
+    /// The underlying error, without any context attached to it.
+    pub fn kind(&self) -> &DeError {
+        match self {
+            DeError::WithContext(e, _) => e.kind(),
+            e => e,
+        }
+    }
+
+    /// The name of the kind of error, e.g. `ParseFloatError`, ignoring any context.
+    pub fn name(&self) -> &'static str {
+        match self.kind() {
+            DeError::XmlError(_) => "XmlError",
+            DeError::IoError(_) => "IoError",
+            DeError::DecodeUtf8(_) => "DecodeUtf8",
+            DeError::DecodeLatin(_) => "DecodeLatin",
+            DeError::ParseIntError(_) => "ParseIntError",
+            DeError::ParseFloatError(_) => "ParseFloatError",
+            DeError::ParseSexagesimalError(_) => "ParseSexagesimalError",
+            DeError::ParseDateTimeError(_) => "ParseDateTimeError",
+            DeError::DecodeBase64(_) => "DecodeBase64",
+            DeError::MissingAttr(_) => "MissingAttr",
+            DeError::BadAttr(_) => "BadAttr",
+            DeError::UnexpectedAttr(_) => "UnexpectedAttr",
+            DeError::UnexpectedEvent(_) => "UnexpectedEvent",
+            DeError::UnexpectedTag(_) => "UnexpectedTag",
+            DeError::UnexpectedValue(_) => "UnexpectedValue",
+            DeError::WithContext(e, _) => e.name(),
+        }
+    }
+
+    /// Where in the stream the error happened, if known.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            DeError::WithContext(_, context) => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Attaches `context` to this error, unless it already has one.
+    pub fn with_context(self, context: ErrorContext) -> DeError {
+        match self {
+            DeError::WithContext(..) => self,
+            e => DeError::WithContext(Box::new(e), context),
+        }
+    }
+}
+
+impl fmt::Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeError::XmlError(e) => write!(f, "XML error: {}", e),
+            DeError::IoError(e) => write!(f, "I/O error: {}", e),
+            DeError::DecodeUtf8(e) => write!(f, "invalid UTF-8: {}", e),
+            DeError::DecodeLatin(e) => write!(f, "invalid Latin-1: {}", e),
+            DeError::ParseIntError(e) => write!(f, "invalid integer: {}", e),
+            DeError::ParseFloatError(e) => write!(f, "invalid number: {}", e),
+            DeError::ParseSexagesimalError(s) => write!(f, "invalid sexagesimal number {:?}", s),
+            DeError::ParseDateTimeError(e) => write!(f, "invalid timestamp: {}", e),
+            DeError::DecodeBase64(e) => write!(f, "invalid base64: {}", e),
+            DeError::MissingAttr(attr) => write!(f, "missing attribute {:?}", attr),
+            DeError::BadAttr(e) => write!(f, "malformed attribute: {}", e),
+            DeError::UnexpectedAttr(attr) => write!(f, "unexpected attribute {:?}", attr),
+            DeError::UnexpectedEvent(event) => write!(f, "unexpected {}", event),
+            DeError::UnexpectedTag(tag) => write!(f, "unexpected element <{}>", tag),
+            DeError::UnexpectedValue(value) => write!(f, "unexpected value {:?}", value),
+            DeError::WithContext(e, context) => write!(f, "{} at {}", e, context),
+        }
+    }
+}
+
+impl std::error::Error for DeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DeError::XmlError(e) => Some(e),
+            DeError::IoError(e) => Some(e),
+            DeError::DecodeUtf8(e) => Some(e),
+            DeError::ParseIntError(e) => Some(e),
+            DeError::ParseFloatError(e) => Some(e),
+            DeError::ParseDateTimeError(e) => Some(e),
+            DeError::DecodeBase64(e) => Some(e),
+            DeError::BadAttr(e) => Some(e),
+            DeError::WithContext(e, _) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Turns an event that a parser wasn't expecting into an error describing it.
+pub(crate) trait UnexpectedEvent {
+    fn unexpected(self) -> DeError;
+}
+
+impl UnexpectedEvent for Event<'_> {
+    fn unexpected(self) -> DeError {
+        let name = |name: QName| String::from_utf8_lossy(name.into_inner()).into_owned();
+        DeError::UnexpectedEvent(match self {
+            Event::Start(e) => format!("start of <{}>", name(e.name())),
+            Event::End(e) => format!("end of </{}>", name(e.name())),
+            Event::Empty(e) => format!("empty element <{}/>", name(e.name())),
+            Event::Text(e) => {
+                let text = String::from_utf8_lossy(&e);
+                if text.chars().count() > 32 {
+                    format!("text {:?}...", text.chars().take(32).collect::<String>())
+                } else {
+                    format!("text {:?}", text)
+                }
+            }
+            Event::CData(_) => String::from("CDATA section"),
+            Event::Comment(_) => String::from("comment"),
+            Event::Decl(_) => String::from("XML declaration"),
+            Event::PI(_) => String::from("processing instruction"),
+            Event::DocType(_) => String::from("doctype"),
+            Event::Eof => String::from("end of input"),
+        })
+    }
+}
+
+impl UnexpectedEvent for XmlResult<Event<'_>> {
+    fn unexpected(self) -> DeError {
+        match self {
+            Ok(e) => e.unexpected(),
+            Err(e) => DeError::XmlError(e),
+        }
+    }
 }
 
 impl From<quick_xml::Error> for DeError {
@@ -432,70 +704,108 @@ impl From<ParseError> for DeError {
         DeError::ParseDateTimeError(err)
     }
 }
+impl From<base64::DecodeError> for DeError {
+    fn from(err: base64::DecodeError) -> Self {
+        DeError::DecodeBase64(err)
+    }
+}
 impl From<AttrError> for DeError {
     fn from(err: AttrError) -> Self {
         DeError::BadAttr(err)
     }
 }
 
-impl<'a> SwitchRule {
-    fn try_from<T: std::io::BufRead>(
-        value: Attribute<'a>,
-        xml_reader: &Reader<T>,
-    ) -> Result<Self, DeError> {
-        match value.decode_and_unescape_value(xml_reader)? {
-            Cow::Borrowed("OneOfMany") => Ok(SwitchRule::OneOfMany),
-            Cow::Borrowed("AtMostOne") => Ok(SwitchRule::AtMostOne),
-            Cow::Borrowed("AnyOfMany") => Ok(SwitchRule::AnyOfMany),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
+impl SwitchRule {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SwitchRule::OneOfMany => "OneOfMany",
+            SwitchRule::AtMostOne => "AtMostOne",
+            SwitchRule::AnyOfMany => "AnyOfMany",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, DeError> {
+        match value {
+            "OneOfMany" => Ok(SwitchRule::OneOfMany),
+            "AtMostOne" => Ok(SwitchRule::AtMostOne),
+            "AnyOfMany" => Ok(SwitchRule::AnyOfMany),
+            e => Err(DeError::UnexpectedValue(e.to_string())),
         }
     }
 }
 
-impl<'a> PropertyState {
-    fn try_from<T: std::io::BufRead>(
-        value: Attribute<'a>,
-        xml_reader: &Reader<T>,
-    ) -> Result<Self, DeError> {
-        match value.decode_and_unescape_value(xml_reader)? {
-            Cow::Borrowed("Idle") => Ok(PropertyState::Idle),
-            Cow::Borrowed("Ok") => Ok(PropertyState::Ok),
-            Cow::Borrowed("Busy") => Ok(PropertyState::Busy),
-            Cow::Borrowed("Alert") => Ok(PropertyState::Alert),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
+impl PropertyState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PropertyState::Idle => "Idle",
+            PropertyState::Ok => "Ok",
+            PropertyState::Busy => "Busy",
+            PropertyState::Alert => "Alert",
         }
     }
 
-    fn try_from_event(value: BytesText<'a>) -> Result<Self, DeError> {
-        match value.unescape()? {
-            Cow::Borrowed("Idle") => Ok(PropertyState::Idle),
-            Cow::Borrowed("Ok") => Ok(PropertyState::Ok),
-            Cow::Borrowed("Busy") => Ok(PropertyState::Busy),
-            Cow::Borrowed("Alert") => Ok(PropertyState::Alert),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
+    fn parse(value: &str) -> Result<Self, DeError> {
+        match value {
+            "Idle" => Ok(PropertyState::Idle),
+            "Ok" => Ok(PropertyState::Ok),
+            "Busy" => Ok(PropertyState::Busy),
+            "Alert" => Ok(PropertyState::Alert),
+            e => Err(DeError::UnexpectedValue(e.to_string())),
         }
     }
 }
 
-impl<'a> SwitchState {
-    fn try_from_event(value: BytesText<'a>) -> Result<Self, DeError> {
-        match value.unescape()? {
-            Cow::Borrowed("On") => Ok(SwitchState::On),
-            Cow::Borrowed("Off") => Ok(SwitchState::Off),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
+impl SwitchState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SwitchState::On => "On",
+            SwitchState::Off => "Off",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, DeError> {
+        match value {
+            "On" => Ok(SwitchState::On),
+            "Off" => Ok(SwitchState::Off),
+            e => Err(DeError::UnexpectedValue(e.to_string())),
         }
     }
 }
-impl<'a> PropertyPerm {
-    fn try_from<T: std::io::BufRead>(
-        value: Attribute<'a>,
-        xml_reader: &Reader<T>,
-    ) -> Result<Self, DeError> {
-        match value.decode_and_unescape_value(xml_reader)? {
-            Cow::Borrowed("ro") => Ok(PropertyPerm::RO),
-            Cow::Borrowed("wo") => Ok(PropertyPerm::WO),
-            Cow::Borrowed("rw") => Ok(PropertyPerm::RW),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
+
+impl PropertyPerm {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PropertyPerm::RO => "ro",
+            PropertyPerm::WO => "wo",
+            PropertyPerm::RW => "rw",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, DeError> {
+        match value {
+            "ro" => Ok(PropertyPerm::RO),
+            "wo" => Ok(PropertyPerm::WO),
+            "rw" => Ok(PropertyPerm::RW),
+            e => Err(DeError::UnexpectedValue(e.to_string())),
+        }
+    }
+}
+
+impl BlobEnable {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BlobEnable::Never => "Never",
+            BlobEnable::Also => "Also",
+            BlobEnable::Only => "Only",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, DeError> {
+        match value {
+            "Never" => Ok(BlobEnable::Never),
+            "Also" => Ok(BlobEnable::Also),
+            "Only" => Ok(BlobEnable::Only),
+            e => Err(DeError::UnexpectedValue(e.to_string())),
         }
     }
 }
@@ -503,6 +813,21 @@ impl<'a> PropertyPerm {
 pub struct CommandIter<T: std::io::BufRead> {
     xml_reader: Reader<T>,
     buf: Vec<u8>,
+    // A copy of the current command's start tag and the length of its name, kept
+    // so that an `ErrorContext` only has to be built when something goes wrong.
+    start_tag: Vec<u8>,
+    start_name_len: Option<usize>,
+}
+
+impl<'a> CommandIter<&'a [u8]> {
+    /// Iterates over the commands in `xml`, such as a captured session or a
+    /// test's fixture, without a connection.
+    pub fn from_xml(xml: &'a str) -> CommandIter<&'a [u8]> {
+        let mut xml_reader = Reader::from_str(xml);
+        xml_reader.trim_text(true);
+        xml_reader.expand_empty_elements(true);
+        CommandIter::new(xml_reader)
+    }
 }
 
 impl<T: std::io::BufRead> Iterator for CommandIter<T> {
@@ -523,7 +848,12 @@ impl<T: std::io::BufRead> Iterator for CommandIter<T> {
 impl<T: std::io::BufRead> CommandIter<T> {
     pub fn new(xml_reader: Reader<T>) -> CommandIter<T> {
         let buf = Vec::new();
-        CommandIter { xml_reader, buf }
+        CommandIter {
+            xml_reader,
+            buf,
+            start_tag: Vec::new(),
+            start_name_len: None,
+        }
     }
 
     pub fn buffer_position(&self) -> usize {
@@ -531,168 +861,122 @@ impl<T: std::io::BufRead> CommandIter<T> {
     }
 
     fn next_command(&mut self) -> Result<Option<Command>, DeError> {
+        self.start_name_len = None;
+        self.read_command().map_err(|e| {
+            let mut context = self.error_context();
+            context.position = self.xml_reader.buffer_position();
+            e.with_context(context)
+        })
+    }
+
+    fn error_context(&self) -> ErrorContext {
+        match self.start_name_len {
+            Some(name_len) => {
+                let content = String::from_utf8_lossy(&self.start_tag);
+                let start = BytesStart::from_content(content, name_len);
+                ErrorContext::from_start(&self.xml_reader, &start)
+            }
+            None => ErrorContext::default(),
+        }
+    }
+
+    fn read_command(&mut self) -> Result<Option<Command>, DeError> {
+        // quick-xml appends each event to `buf`, so it has to be cleared between
+        // events to be reused rather than growing for the life of the stream.
+        self.buf.clear();
         let event = self.xml_reader.read_event_into(&mut self.buf)?;
         match event {
-            Event::Start(e) => match e.name() {
-                QName(b"defTextVector") => {
-                    let mut text_vector = DefTextIter::text_vector(&self.xml_reader, &e)?;
-
-                    for text in DefTextIter::new(self) {
-                        let text = text?;
-                        text_vector.texts.push(text);
+            Event::Start(e) => {
+                self.start_tag.clear();
+                self.start_tag.extend_from_slice(&e);
+                self.start_name_len = Some(e.name().into_inner().len());
+                match e.name() {
+                    QName(b"defTextVector") => {
+                        let vector = DefTextVector::from_start(&self.xml_reader, &e)?;
+                        Ok(Some(Command::DefTextVector(self.read_vector(vector)?)))
                     }
-
-                    Ok(Some(Command::DefTextVector(text_vector)))
-                }
-                QName(b"setTextVector") => {
-                    let mut text_vector = SetTextIter::text_vector(&self.xml_reader, &e)?;
-
-                    for text in SetTextIter::new(self) {
-                        let text = text?;
-                        text_vector.texts.push(text);
+                    QName(b"setTextVector") => {
+                        let vector = SetTextVector::from_start(&self.xml_reader, &e)?;
+                        Ok(Some(Command::SetTextVector(self.read_vector(vector)?)))
                     }
-
-                    Ok(Some(Command::SetTextVector(text_vector)))
-                }
-                QName(b"newTextVector") => {
-                    let mut text_vector = NewTextIter::text_vector(&self.xml_reader, &e)?;
-
-                    for text in NewTextIter::new(self) {
-                        let text = text?;
-                        text_vector.texts.push(text);
+                    QName(b"newTextVector") => {
+                        let vector = NewTextVector::from_start(&self.xml_reader, &e)?;
+                        Ok(Some(Command::NewTextVector(self.read_vector(vector)?)))
                     }
-
-                    Ok(Some(Command::NewTextVector(text_vector)))
-                }
-                QName(b"defNumberVector") => {
-                    let mut number_vector = DefNumberIter::number_vector(&self.xml_reader, &e)?;
-
-                    for number in DefNumberIter::new(self) {
-                        let number = number?;
-                        number_vector.numbers.push(number);
+                    QName(b"defNumberVector") => {
+                        let vector = DefNumberVector::from_start(&self.xml_reader, &e)?;
+                        Ok(Some(Command::DefNumberVector(self.read_vector(vector)?)))
                     }
-
-                    Ok(Some(Command::DefNumberVector(number_vector)))
-                }
-                QName(b"setNumberVector") => {
-                    let mut number_vector = SetNumberIter::number_vector(&self.xml_reader, &e)?;
-
-                    for number in SetNumberIter::new(self) {
-                        let number = number?;
-                        number_vector.numbers.push(number);
+                    QName(b"setNumberVector") => {
+                        let vector = SetNumberVector::from_start(&self.xml_reader, &e)?;
+                        Ok(Some(Command::SetNumberVector(self.read_vector(vector)?)))
                     }
-
-                    Ok(Some(Command::SetNumberVector(number_vector)))
-                }
-                QName(b"newNumberVector") => {
-                    let mut number_vector = NewNumberIter::number_vector(&self.xml_reader, &e)?;
-
-                    for number in NewNumberIter::new(self) {
-                        let number = number?;
-                        number_vector.numbers.push(number);
+                    QName(b"newNumberVector") => {
+                        let vector = NewNumberVector::from_start(&self.xml_reader, &e)?;
+                        Ok(Some(Command::NewNumberVector(self.read_vector(vector)?)))
                     }
-
-                    Ok(Some(Command::NewNumberVector(number_vector)))
-                }
-                QName(b"defSwitchVector") => {
-                    let mut switch_vector = DefSwitchIter::switch_vector(&self.xml_reader, &e)?;
-
-                    for switch in DefSwitchIter::new(self) {
-                        let switch = switch?;
-                        switch_vector.switches.push(switch);
+                    QName(b"defSwitchVector") => {
+                        let vector = DefSwitchVector::from_start(&self.xml_reader, &e)?;
+                        Ok(Some(Command::DefSwitchVector(self.read_vector(vector)?)))
                     }
-
-                    Ok(Some(Command::DefSwitchVector(switch_vector)))
-                }
-                QName(b"setSwitchVector") => {
-                    let mut switch_vector = SetSwitchIter::switch_vector(&self.xml_reader, &e)?;
-
-                    for switch in SetSwitchIter::new(self) {
-                        let switch = switch?;
-                        switch_vector.switches.push(switch);
+                    QName(b"setSwitchVector") => {
+                        let vector = SetSwitchVector::from_start(&self.xml_reader, &e)?;
+                        Ok(Some(Command::SetSwitchVector(self.read_vector(vector)?)))
                     }
-
-                    Ok(Some(Command::SetSwitchVector(switch_vector)))
-                }
-                QName(b"newSwitchVector") => {
-                    let mut switch_vector = NewSwitchIter::switch_vector(&self.xml_reader, &e)?;
-
-                    for switch in NewSwitchIter::new(self) {
-                        let switch = switch?;
-                        switch_vector.switches.push(switch);
+                    QName(b"newSwitchVector") => {
+                        let vector = NewSwitchVector::from_start(&self.xml_reader, &e)?;
+                        Ok(Some(Command::NewSwitchVector(self.read_vector(vector)?)))
                     }
-
-                    Ok(Some(Command::NewSwitchVector(switch_vector)))
-                }
-                QName(b"defLightVector") => {
-                    let mut light_vector = DefLightIter::light_vector(&self.xml_reader, &e)?;
-
-                    for light in DefLightIter::new(self) {
-                        let light = light?;
-                        light_vector.lights.push(light);
+                    QName(b"defLightVector") => {
+                        let vector = DefLightVector::from_start(&self.xml_reader, &e)?;
+                        Ok(Some(Command::DefLightVector(self.read_vector(vector)?)))
                     }
-
-                    Ok(Some(Command::DefLightVector(light_vector)))
-                }
-                QName(b"setLightVector") => {
-                    let mut light_vector = SetLightIter::light_vector(&self.xml_reader, &e)?;
-
-                    for light in SetLightIter::new(self) {
-                        let light = light?;
-                        light_vector.lights.push(light);
+                    QName(b"setLightVector") => {
+                        let vector = SetLightVector::from_start(&self.xml_reader, &e)?;
+                        Ok(Some(Command::SetLightVector(self.read_vector(vector)?)))
                     }
-
-                    Ok(Some(Command::SetLightVector(light_vector)))
-                }
-                QName(b"defBLOBVector") => {
-                    let mut blob_vector = DefBlobIter::blob_vector(&self.xml_reader, &e)?;
-
-                    for blob in DefBlobIter::new(self) {
-                        let blob = blob?;
-                        blob_vector.blobs.push(blob);
+                    QName(b"defBLOBVector") => {
+                        let vector = DefBlobVector::from_start(&self.xml_reader, &e)?;
+                        Ok(Some(Command::DefBlobVector(self.read_vector(vector)?)))
                     }
-
-                    Ok(Some(Command::DefBlobVector(blob_vector)))
-                }
-                QName(b"setBLOBVector") => {
-                    let mut blob_vector = SetBlobIter::blob_vector(&self.xml_reader, &e)?;
-
-                    for blob in SetBlobIter::new(self) {
-                        let blob = blob?;
-                        blob_vector.blobs.push(blob);
+                    QName(b"setBLOBVector") => {
+                        let vector = SetBlobVector::from_start(&self.xml_reader, &e)?;
+                        Ok(Some(Command::SetBlobVector(self.read_vector(vector)?)))
                     }
-
-                    Ok(Some(Command::SetBlobVector(blob_vector)))
-                }
-                QName(b"message") => {
-                    let message = MessageIter::message(&self.xml_reader, &e)?;
-                    for _ in MessageIter::new(self) {}
-
-                    Ok(Some(Command::Message(message)))
-                }
-                QName(b"delProperty") => {
-                    let message = DelPropertyIter::del_property(&self.xml_reader, &e)?;
-                    for _ in DelPropertyIter::new(self) {}
-
-                    Ok(Some(Command::DelProperty(message)))
-                }
-
-                QName(b"getProperties") => {
-                    let get_properties = GetPropertiesIter::get_properties(&self.xml_reader, &e)?;
-                    for _ in GetPropertiesIter::new(self) {}
-
-                    Ok(Some(Command::GetProperties(get_properties)))
+                    QName(b"message") => {
+                        let attrs = Attrs::parse(&self.xml_reader, &e, Message::ATTRS)?;
+                        let element = read_element(&mut self.xml_reader, &mut self.buf, attrs)?;
+                        Ok(Some(Command::Message(element)))
+                    }
+                    QName(b"delProperty") => {
+                        let attrs = Attrs::parse(&self.xml_reader, &e, DelProperty::ATTRS)?;
+                        let element = read_element(&mut self.xml_reader, &mut self.buf, attrs)?;
+                        Ok(Some(Command::DelProperty(element)))
+                    }
+                    QName(b"getProperties") => {
+                        let attrs = Attrs::parse(&self.xml_reader, &e, GetProperties::ATTRS)?;
+                        let element = read_element(&mut self.xml_reader, &mut self.buf, attrs)?;
+                        Ok(Some(Command::GetProperties(element)))
+                    }
+                    QName(b"enableBLOB") => {
+                        let attrs = Attrs::parse(&self.xml_reader, &e, EnableBlob::ATTRS)?;
+                        let element = read_element(&mut self.xml_reader, &mut self.buf, attrs)?;
+                        Ok(Some(Command::EnableBlob(element)))
+                    }
+                    tag => Err(DeError::UnexpectedTag(
+                        str::from_utf8(tag.into_inner())?.to_string(),
+                    )),
                 }
-                tag => Err(DeError::UnexpectedTag(
-                    str::from_utf8(tag.into_inner())?.to_string(),
-                )),
-            },
-            Event::End(tag) => {
-                println!("Unexpected end: {}", tag.escape_ascii().to_string());
-                Err(DeError::UnexpectedEvent(format!("{:?}", tag)))
             }
             Event::Eof => Ok(None),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
+            e => Err(e.unexpected()),
+        }
+    }
+
+    fn read_vector<V: XmlVector>(&mut self, mut vector: V) -> Result<V, DeError> {
+        for element in ElementIter::<T, V::Element>::new(self) {
+            vector.push(element?);
         }
+        Ok(vector)
     }
 }
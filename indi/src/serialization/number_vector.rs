@@ -1,7 +1,3 @@
-use quick_xml::events::Event;
-use quick_xml::name::QName;
-use quick_xml::Reader;
-
 use std::str;
 
 // use encoding::all::ISO_8859_1;
@@ -109,10 +105,8 @@ impl XmlSerialization for NewNumberVector {
                 .with_attribute(("name", &*self.name));
 
             if let Some(timestamp) = &self.timestamp {
-                creator = creator.with_attribute((
-                    "timestamp",
-                    format!("{}", timestamp.format("%Y-%m-%dT%H:%M:%S%.3f")).as_str(),
-                ));
+                creator =
+                    creator.with_attribute(("timestamp", format_timestamp(timestamp).as_str()));
             }
             xml_writer = creator.write_inner_content(|xml_writer| {
                 for number in self.numbers.iter() {
@@ -126,8 +120,104 @@ impl XmlSerialization for NewNumberVector {
     }
 }
 
-fn parse_number(e: &BytesText) -> Result<f64, DeError> {
-    let text = &e.unescape()?;
+impl XmlSerialization for DefNumber {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        let mut creator = xml_writer
+            .create_element("defNumber")
+            .with_attribute(("name", &*self.name))
+            .with_attribute(("format", &*self.format));
+
+        if let Some(label) = &self.label {
+            creator = creator.with_attribute(("label", &label[..]));
+        }
+        creator = creator.with_attribute(("min", self.min.to_string().as_str()));
+        creator = creator.with_attribute(("max", self.max.to_string().as_str()));
+        creator = creator.with_attribute(("step", self.step.to_string().as_str()));
+        creator.write_text_content(BytesText::new(self.value.to_string().as_str()))?;
+
+        Ok(xml_writer)
+    }
+}
+
+impl XmlSerialization for DefNumberVector {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        mut xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        {
+            let mut creator = xml_writer
+                .create_element("defNumberVector")
+                .with_attribute(("device", &*self.device))
+                .with_attribute(("name", &*self.name));
+
+            if let Some(label) = &self.label {
+                creator = creator.with_attribute(("label", &label[..]));
+            }
+            if let Some(group) = &self.group {
+                creator = creator.with_attribute(("group", &group[..]));
+            }
+            creator = creator.with_attribute(("state", self.state.as_str()));
+            creator = creator.with_attribute(("perm", self.perm.as_str()));
+            if let Some(timeout) = &self.timeout {
+                creator = creator.with_attribute(("timeout", timeout.to_string().as_str()));
+            }
+            if let Some(timestamp) = &self.timestamp {
+                creator =
+                    creator.with_attribute(("timestamp", format_timestamp(timestamp).as_str()));
+            }
+            if let Some(message) = &self.message {
+                creator = creator.with_attribute(("message", &message[..]));
+            }
+            xml_writer = creator.write_inner_content(|xml_writer| {
+                for number in self.numbers.iter() {
+                    number.write(xml_writer)?;
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(xml_writer)
+    }
+}
+
+impl XmlSerialization for SetNumberVector {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        mut xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        {
+            let mut creator = xml_writer
+                .create_element("setNumberVector")
+                .with_attribute(("device", &*self.device))
+                .with_attribute(("name", &*self.name));
+
+            creator = creator.with_attribute(("state", self.state.as_str()));
+            if let Some(timeout) = &self.timeout {
+                creator = creator.with_attribute(("timeout", timeout.to_string().as_str()));
+            }
+            if let Some(timestamp) = &self.timestamp {
+                creator =
+                    creator.with_attribute(("timestamp", format_timestamp(timestamp).as_str()));
+            }
+            if let Some(message) = &self.message {
+                creator = creator.with_attribute(("message", &message[..]));
+            }
+            xml_writer = creator.write_inner_content(|xml_writer| {
+                for number in self.numbers.iter() {
+                    number.write(xml_writer)?;
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(xml_writer)
+    }
+}
+
+fn parse_number(text: &str) -> Result<f64, DeError> {
     let mut components = text.split([' ', ':']);
 
     let mut val: f64 = match components.next() {
@@ -146,340 +236,114 @@ fn parse_number(e: &BytesText) -> Result<f64, DeError> {
     return Ok(val);
 }
 
-fn next_one_number<T: std::io::BufRead>(
-    xml_reader: &mut Reader<T>,
-    buf: &mut Vec<u8>,
-) -> Result<Option<OneNumber>, DeError> {
-    let event = xml_reader.read_event_into(buf)?;
-    match event {
-        Event::Start(e) => match e.name() {
-            QName(b"oneNumber") => {
-                let mut name: Result<String, DeError> = Err(DeError::MissingAttr(&"name"));
-                let mut min: Option<f64> = None;
-                let mut max: Option<f64> = None;
-                let mut step: Option<f64> = None;
-
-                for attr in e.attributes() {
-                    let attr = attr?;
-                    let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-
-                    match attr.key {
-                        QName(b"name") => name = Ok(attr_value),
-                        QName(b"min") => min = Some(attr_value.parse::<f64>()?),
-                        QName(b"max") => max = Some(attr_value.parse::<f64>()?),
-                        QName(b"step") => step = Some(attr_value.parse::<f64>()?),
-                        key => {
-                            return Err(DeError::UnexpectedAttr(format!(
-                                "Unexpected attribute {}",
-                                str::from_utf8(key.into_inner())?
-                            )))
-                        }
-                    }
-                }
-
-                let value: Result<f64, DeError> = match xml_reader.read_event_into(buf) {
-                    Ok(Event::Text(e)) => parse_number(&e),
-                    e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                };
-
-                let trailing_event = xml_reader.read_event_into(buf)?;
-                match trailing_event {
-                    Event::End(_) => (),
-                    e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                }
-
-                Ok(Some(OneNumber {
-                    name: name?,
-                    min: min,
-                    max: max,
-                    step: step,
-                    value: value?,
-                }))
-            }
-            tag => Err(DeError::UnexpectedTag(
-                str::from_utf8(tag.into_inner())?.to_string(),
-            )),
-        },
-        Event::End(_) => Ok(None),
-        Event::Eof => Ok(None),
-        e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
+pub type DefNumberIter<'a, T> = ElementIter<'a, T, DefNumber>;
+pub type SetNumberIter<'a, T> = ElementIter<'a, T, OneNumber>;
+pub type NewNumberIter<'a, T> = ElementIter<'a, T, OneNumber>;
+
+impl XmlElement for DefNumber {
+    const TAG: &'static str = "defNumber";
+    const ATTRS: &'static [&'static str] = &["name", "label", "format", "min", "max", "step"];
+
+    fn from_xml(attrs: Attrs, value: Option<BytesText>) -> Result<Self, DeError> {
+        Ok(DefNumber {
+            name: required(attrs.name, "name")?,
+            label: attrs.label,
+            format: required(attrs.format, "format")?,
+            min: required(attrs.min, "min")?,
+            max: required(attrs.max, "max")?,
+            step: required(attrs.step, "step")?,
+            value: parse_number(&text(&value)?)?,
+        })
     }
 }
 
-pub struct DefNumberIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
-}
-
-impl<'a, T: std::io::BufRead> Iterator for DefNumberIter<'a, T> {
-    type Item = Result<DefNumber, DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.next_number() {
-            Ok(Some(number)) => {
-                return Some(Ok(number));
-            }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
-            }
-        }
+impl XmlElement for OneNumber {
+    const TAG: &'static str = "oneNumber";
+    const ATTRS: &'static [&'static str] = &["name", "min", "max", "step"];
+
+    fn from_xml(attrs: Attrs, value: Option<BytesText>) -> Result<Self, DeError> {
+        Ok(OneNumber {
+            name: required(attrs.name, "name")?,
+            min: attrs.min,
+            max: attrs.max,
+            step: attrs.step,
+            value: parse_number(&text(&value)?)?,
+        })
     }
 }
-impl<'a, T: std::io::BufRead> DefNumberIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> DefNumberIter<T> {
-        DefNumberIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
-        }
-    }
 
-    pub fn number_vector(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<DefNumberVector, DeError> {
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-        let mut label: Option<String> = None;
-        let mut group: Option<String> = None;
-        let mut state: Option<PropertyState> = None;
-        let mut perm: Option<PropertyPerm> = None;
-        let mut timeout: Option<u32> = None;
-        let mut timestamp: Option<DateTime<Utc>> = None;
-        let mut message: Option<String> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                QName(b"label") => label = Some(attr_value),
-                QName(b"group") => group = Some(attr_value),
-                QName(b"state") => state = Some(PropertyState::try_from(attr, xml_reader)?),
-                QName(b"perm") => perm = Some(PropertyPerm::try_from(attr, xml_reader)?),
-                QName(b"timeout") => timeout = Some(attr_value.parse::<u32>()?),
-                QName(b"timestamp") => {
-                    timestamp = Some(DateTime::from_str(&format!("{}Z", &attr_value))?)
-                }
-                QName(b"message") => message = Some(attr_value),
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
-                }
-            }
-        }
+impl XmlVector for DefNumberVector {
+    const ATTRS: &'static [&'static str] = &[
+        "device",
+        "name",
+        "label",
+        "group",
+        "state",
+        "perm",
+        "timeout",
+        "timestamp",
+        "message",
+    ];
+    type Element = DefNumber;
+
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError> {
         Ok(DefNumberVector {
-            device: device.ok_or(DeError::MissingAttr(&"device"))?,
-            name: name.ok_or(DeError::MissingAttr(&"name"))?,
-            label: label,
-            group: group,
-            state: state.ok_or(DeError::MissingAttr(&"state"))?,
-            perm: perm.ok_or(DeError::MissingAttr(&"perm"))?,
-            timeout: timeout,
-            timestamp: timestamp,
-            message: message,
+            device: required(attrs.device, "device")?,
+            name: required(attrs.name, "name")?,
+            label: attrs.label,
+            group: attrs.group,
+            state: required(attrs.state, "state")?,
+            perm: required(attrs.perm, "perm")?,
+            timeout: attrs.timeout,
+            timestamp: attrs.timestamp,
+            message: attrs.message,
             numbers: Vec::new(),
         })
     }
 
-    fn next_number(&mut self) -> Result<Option<DefNumber>, DeError> {
-        let event = self.xml_reader.read_event_into(&mut self.buf)?;
-        match event {
-            Event::Start(e) => match e.name() {
-                QName(b"defNumber") => {
-                    let mut name: Result<String, DeError> = Err(DeError::MissingAttr(&"name"));
-                    let mut label: Option<String> = None;
-                    let mut format: Result<String, DeError> = Err(DeError::MissingAttr(&"format"));
-                    let mut min: Result<f64, DeError> = Err(DeError::MissingAttr(&"min"));
-                    let mut max: Result<f64, DeError> = Err(DeError::MissingAttr(&"max"));
-                    let mut step: Result<f64, DeError> = Err(DeError::MissingAttr(&"step"));
-
-                    for attr in e.attributes() {
-                        let attr = attr?;
-                        let attr_value = attr
-                            .decode_and_unescape_value(self.xml_reader)?
-                            .into_owned();
-
-                        match attr.key {
-                            QName(b"name") => name = Ok(attr_value),
-                            QName(b"label") => label = Some(attr_value),
-                            QName(b"format") => format = Ok(attr_value),
-                            QName(b"min") => min = Ok(attr_value.parse::<f64>()?),
-                            QName(b"max") => max = Ok(attr_value.parse::<f64>()?),
-                            QName(b"step") => step = Ok(attr_value.parse::<f64>()?),
-                            key => {
-                                return Err(DeError::UnexpectedAttr(format!(
-                                    "Unexpected attribute {}",
-                                    str::from_utf8(key.into_inner())?
-                                )))
-                            }
-                        }
-                    }
-
-                    let value: Result<f64, DeError> =
-                        match self.xml_reader.read_event_into(self.buf) {
-                            Ok(Event::Text(e)) => parse_number(&e),
-                            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                        };
-
-                    let trailing_event = self.xml_reader.read_event_into(&mut self.buf)?;
-                    match trailing_event {
-                        Event::End(_) => (),
-                        e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                    }
-
-                    Ok(Some(DefNumber {
-                        name: name?,
-                        label: label,
-                        format: format?,
-                        min: min?,
-                        max: max?,
-                        step: step?,
-                        value: value?,
-                    }))
-                }
-                tag => Err(DeError::UnexpectedTag(
-                    str::from_utf8(tag.into_inner())?.to_string(),
-                )),
-            },
-            Event::End(_) => Ok(None),
-            Event::Eof => Ok(None),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-        }
+    fn push(&mut self, element: DefNumber) {
+        self.numbers.push(element);
     }
 }
 
-pub struct SetNumberIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
-}
+impl XmlVector for SetNumberVector {
+    const ATTRS: &'static [&'static str] =
+        &["device", "name", "state", "timeout", "timestamp", "message"];
+    type Element = OneNumber;
 
-impl<'a, T: std::io::BufRead> Iterator for SetNumberIter<'a, T> {
-    type Item = Result<OneNumber, DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match next_one_number(&mut self.xml_reader, &mut self.buf) {
-            Ok(Some(number)) => {
-                return Some(Ok(number));
-            }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
-            }
-        }
-    }
-}
-impl<'a, T: std::io::BufRead> SetNumberIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> SetNumberIter<T> {
-        SetNumberIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
-        }
-    }
-
-    pub fn number_vector(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<SetNumberVector, DeError> {
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-        let mut state: Option<PropertyState> = None;
-        let mut timeout: Option<u32> = None;
-        let mut timestamp: Option<DateTime<Utc>> = None;
-        let mut message: Option<String> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                QName(b"state") => state = Some(PropertyState::try_from(attr, xml_reader)?),
-                QName(b"timeout") => timeout = Some(attr_value.parse::<u32>()?),
-                QName(b"timestamp") => {
-                    timestamp = Some(DateTime::from_str(&format!("{}Z", &attr_value))?)
-                }
-                QName(b"message") => message = Some(attr_value),
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
-                }
-            }
-        }
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError> {
         Ok(SetNumberVector {
-            device: device.ok_or(DeError::MissingAttr(&"device"))?,
-            name: name.ok_or(DeError::MissingAttr(&"name"))?,
-            state: state.ok_or(DeError::MissingAttr(&"state"))?,
-            timeout: timeout,
-            timestamp: timestamp,
-            message: message,
+            device: required(attrs.device, "device")?,
+            name: required(attrs.name, "name")?,
+            state: required(attrs.state, "state")?,
+            timeout: attrs.timeout,
+            timestamp: attrs.timestamp,
+            message: attrs.message,
             numbers: Vec::new(),
         })
     }
-}
-
-pub struct NewNumberIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
-}
 
-impl<'a, T: std::io::BufRead> Iterator for NewNumberIter<'a, T> {
-    type Item = Result<OneNumber, DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match next_one_number(&mut self.xml_reader, &mut self.buf) {
-            Ok(Some(number)) => {
-                return Some(Ok(number));
-            }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
-            }
-        }
+    fn push(&mut self, element: OneNumber) {
+        self.numbers.push(element);
     }
 }
-impl<'a, T: std::io::BufRead> NewNumberIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> NewNumberIter<T> {
-        NewNumberIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
-        }
-    }
 
-    pub fn number_vector(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<NewNumberVector, DeError> {
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-        let mut timestamp: Option<DateTime<Utc>> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                QName(b"timestamp") => {
-                    timestamp = Some(DateTime::from_str(&format!("{}Z", &attr_value))?)
-                }
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
-                }
-            }
-        }
+impl XmlVector for NewNumberVector {
+    const ATTRS: &'static [&'static str] = &["device", "name", "timestamp"];
+    type Element = OneNumber;
+
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError> {
         Ok(NewNumberVector {
-            device: device.ok_or(DeError::MissingAttr(&"device"))?,
-            name: name.ok_or(DeError::MissingAttr(&"name"))?,
-            timestamp: timestamp,
+            device: required(attrs.device, "device")?,
+            name: required(attrs.name, "name")?,
+            timestamp: attrs.timestamp,
             numbers: Vec::new(),
         })
     }
+
+    fn push(&mut self, element: OneNumber) {
+        self.numbers.push(element);
+    }
 }
 
 #[cfg(test)]
@@ -577,7 +441,7 @@ mod tests {
 
         let event = reader.read_event_into(&mut buf);
         if let Ok(Event::Text(e)) = event {
-            assert_eq!(-10.505, parse_number(&e).unwrap());
+            assert_eq!(-10.505, parse_number(&e.unescape().unwrap()).unwrap());
         } else {
             panic!("Unexpected");
         }
@@ -593,7 +457,7 @@ mod tests {
 
         let event = reader.read_event_into(&mut buf);
         if let Ok(Event::Text(e)) = event {
-            assert_eq!(-10.505, parse_number(&e).unwrap());
+            assert_eq!(-10.505, parse_number(&e.unescape().unwrap()).unwrap());
         } else {
             panic!("Unexpected");
         }
@@ -609,7 +473,7 @@ mod tests {
 
         let event = reader.read_event_into(&mut buf);
         if let Ok(Event::Text(e)) = event {
-            assert_eq!(-10.505, parse_number(&e).unwrap());
+            assert_eq!(-10.505, parse_number(&e.unescape().unwrap()).unwrap());
         } else {
             panic!("Unexpected");
         }
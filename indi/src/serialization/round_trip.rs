@@ -0,0 +1,578 @@
+use super::*;
+
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+use proptest::sample::Index;
+use std::io::Cursor;
+
+fn name() -> impl Strategy<Value = String> {
+    "[A-Za-z_][A-Za-z0-9_ .-]{0,15}"
+}
+
+fn label() -> impl Strategy<Value = String> {
+    "[A-Za-z0-9 <>&'\"%._:-]{0,20}"
+}
+
+// Text content is trimmed by the reader, so only generate values that survive it.
+fn text() -> impl Strategy<Value = String> {
+    "[A-Za-z0-9 <>&'\"%._:-]{0,40}".prop_map(|s| s.trim().to_string())
+}
+
+fn number() -> impl Strategy<Value = f64> {
+    prop::num::f64::NORMAL | prop::num::f64::SUBNORMAL | prop::num::f64::ZERO
+}
+
+fn timestamp() -> impl Strategy<Value = Option<DateTime<Utc>>> {
+    // Timestamps are written with millisecond precision.
+    option::of((0i64..4_102_444_800_000).prop_map(|ms| Utc.timestamp_millis_opt(ms).unwrap()))
+}
+
+fn property_state() -> impl Strategy<Value = PropertyState> {
+    (0..4u8).prop_map(|i| match i {
+        0 => PropertyState::Idle,
+        1 => PropertyState::Ok,
+        2 => PropertyState::Busy,
+        _ => PropertyState::Alert,
+    })
+}
+
+fn property_perm() -> impl Strategy<Value = PropertyPerm> {
+    (0..3u8).prop_map(|i| match i {
+        0 => PropertyPerm::RO,
+        1 => PropertyPerm::WO,
+        _ => PropertyPerm::RW,
+    })
+}
+
+fn switch_rule() -> impl Strategy<Value = SwitchRule> {
+    (0..3u8).prop_map(|i| match i {
+        0 => SwitchRule::OneOfMany,
+        1 => SwitchRule::AtMostOne,
+        _ => SwitchRule::AnyOfMany,
+    })
+}
+
+fn switch_state() -> impl Strategy<Value = SwitchState> {
+    any::<bool>().prop_map(|on| {
+        if on {
+            SwitchState::On
+        } else {
+            SwitchState::Off
+        }
+    })
+}
+
+fn blob_enable() -> impl Strategy<Value = BlobEnable> {
+    (0..3u8).prop_map(|i| match i {
+        0 => BlobEnable::Never,
+        1 => BlobEnable::Also,
+        _ => BlobEnable::Only,
+    })
+}
+
+fn def_text_vector() -> impl Strategy<Value = Command> {
+    (
+        name(),
+        name(),
+        option::of(label()),
+        option::of(label()),
+        property_state(),
+        property_perm(),
+        option::of(any::<u32>()),
+        timestamp(),
+        option::of(label()),
+        vec((name(), option::of(label()), text()), 0..4),
+    )
+        .prop_map(
+            |(device, name, label, group, state, perm, timeout, timestamp, message, texts)| {
+                Command::DefTextVector(DefTextVector {
+                    device,
+                    name,
+                    label,
+                    group,
+                    state,
+                    perm,
+                    timeout,
+                    timestamp,
+                    message,
+                    texts: texts
+                        .into_iter()
+                        .map(|(name, label, value)| DefText { name, label, value })
+                        .collect(),
+                })
+            },
+        )
+}
+
+fn set_text_vector() -> impl Strategy<Value = Command> {
+    (
+        name(),
+        name(),
+        property_state(),
+        option::of(any::<u32>()),
+        timestamp(),
+        option::of(label()),
+        vec((name(), text()), 0..4),
+    )
+        .prop_map(
+            |(device, name, state, timeout, timestamp, message, texts)| {
+                Command::SetTextVector(SetTextVector {
+                    device,
+                    name,
+                    state,
+                    timeout,
+                    timestamp,
+                    message,
+                    texts: texts
+                        .into_iter()
+                        .map(|(name, value)| OneText { name, value })
+                        .collect(),
+                })
+            },
+        )
+}
+
+fn new_text_vector() -> impl Strategy<Value = Command> {
+    (name(), name(), timestamp(), vec((name(), text()), 0..4)).prop_map(
+        |(device, name, timestamp, texts)| {
+            Command::NewTextVector(NewTextVector {
+                device,
+                name,
+                timestamp,
+                texts: texts
+                    .into_iter()
+                    .map(|(name, value)| OneText { name, value })
+                    .collect(),
+            })
+        },
+    )
+}
+
+fn one_number() -> impl Strategy<Value = OneNumber> {
+    (
+        name(),
+        option::of(number()),
+        option::of(number()),
+        option::of(number()),
+        number(),
+    )
+        .prop_map(|(name, min, max, step, value)| OneNumber {
+            name,
+            min,
+            max,
+            step,
+            value,
+        })
+}
+
+fn def_number_vector() -> impl Strategy<Value = Command> {
+    let def_number = (
+        name(),
+        option::of(label()),
+        label(),
+        number(),
+        number(),
+        number(),
+        number(),
+    )
+        .prop_map(|(name, label, format, min, max, step, value)| DefNumber {
+            name,
+            label,
+            format,
+            min,
+            max,
+            step,
+            value,
+        });
+    (
+        name(),
+        name(),
+        option::of(label()),
+        option::of(label()),
+        property_state(),
+        property_perm(),
+        option::of(any::<u32>()),
+        timestamp(),
+        option::of(label()),
+        vec(def_number, 0..4),
+    )
+        .prop_map(
+            |(device, name, label, group, state, perm, timeout, timestamp, message, numbers)| {
+                Command::DefNumberVector(DefNumberVector {
+                    device,
+                    name,
+                    label,
+                    group,
+                    state,
+                    perm,
+                    timeout,
+                    timestamp,
+                    message,
+                    numbers,
+                })
+            },
+        )
+}
+
+fn set_number_vector() -> impl Strategy<Value = Command> {
+    (
+        name(),
+        name(),
+        property_state(),
+        option::of(any::<u32>()),
+        timestamp(),
+        option::of(label()),
+        vec(one_number(), 0..4),
+    )
+        .prop_map(
+            |(device, name, state, timeout, timestamp, message, numbers)| {
+                Command::SetNumberVector(SetNumberVector {
+                    device,
+                    name,
+                    state,
+                    timeout,
+                    timestamp,
+                    message,
+                    numbers,
+                })
+            },
+        )
+}
+
+fn new_number_vector() -> impl Strategy<Value = Command> {
+    (name(), name(), timestamp(), vec(one_number(), 0..4)).prop_map(
+        |(device, name, timestamp, numbers)| {
+            Command::NewNumberVector(NewNumberVector {
+                device,
+                name,
+                timestamp,
+                numbers,
+            })
+        },
+    )
+}
+
+fn one_switch() -> impl Strategy<Value = OneSwitch> {
+    (name(), switch_state()).prop_map(|(name, value)| OneSwitch { name, value })
+}
+
+fn def_switch_vector() -> impl Strategy<Value = Command> {
+    let def_switch = (name(), option::of(label()), switch_state())
+        .prop_map(|(name, label, value)| DefSwitch { name, label, value });
+    (
+        name(),
+        name(),
+        option::of(label()),
+        option::of(label()),
+        property_state(),
+        property_perm(),
+        switch_rule(),
+        option::of(any::<u32>()),
+        timestamp(),
+        option::of(label()),
+        vec(def_switch, 0..4),
+    )
+        .prop_map(
+            |(
+                device,
+                name,
+                label,
+                group,
+                state,
+                perm,
+                rule,
+                timeout,
+                timestamp,
+                message,
+                switches,
+            )| {
+                Command::DefSwitchVector(DefSwitchVector {
+                    device,
+                    name,
+                    label,
+                    group,
+                    state,
+                    perm,
+                    rule,
+                    timeout,
+                    timestamp,
+                    message,
+                    switches,
+                })
+            },
+        )
+}
+
+fn set_switch_vector() -> impl Strategy<Value = Command> {
+    (
+        name(),
+        name(),
+        property_state(),
+        option::of(any::<u32>()),
+        timestamp(),
+        option::of(label()),
+        vec(one_switch(), 0..4),
+    )
+        .prop_map(
+            |(device, name, state, timeout, timestamp, message, switches)| {
+                Command::SetSwitchVector(SetSwitchVector {
+                    device,
+                    name,
+                    state,
+                    timeout,
+                    timestamp,
+                    message,
+                    switches,
+                })
+            },
+        )
+}
+
+fn new_switch_vector() -> impl Strategy<Value = Command> {
+    (name(), name(), timestamp(), vec(one_switch(), 0..4)).prop_map(
+        |(device, name, timestamp, switches)| {
+            Command::NewSwitchVector(NewSwitchVector {
+                device,
+                name,
+                timestamp,
+                switches,
+            })
+        },
+    )
+}
+
+fn def_light_vector() -> impl Strategy<Value = Command> {
+    let def_light = (name(), option::of(label()), property_state())
+        .prop_map(|(name, label, value)| DefLight { name, label, value });
+    (
+        name(),
+        name(),
+        option::of(label()),
+        option::of(label()),
+        property_state(),
+        timestamp(),
+        option::of(label()),
+        vec(def_light, 0..4),
+    )
+        .prop_map(
+            |(device, name, label, group, state, timestamp, message, lights)| {
+                Command::DefLightVector(DefLightVector {
+                    device,
+                    name,
+                    label,
+                    group,
+                    state,
+                    timestamp,
+                    message,
+                    lights,
+                })
+            },
+        )
+}
+
+fn set_light_vector() -> impl Strategy<Value = Command> {
+    let one_light = (name(), property_state()).prop_map(|(name, value)| OneLight { name, value });
+    (
+        name(),
+        name(),
+        property_state(),
+        timestamp(),
+        option::of(label()),
+        vec(one_light, 0..4),
+    )
+        .prop_map(|(device, name, state, timestamp, message, lights)| {
+            Command::SetLightVector(SetLightVector {
+                device,
+                name,
+                state,
+                timestamp,
+                message,
+                lights,
+            })
+        })
+}
+
+fn def_blob_vector() -> impl Strategy<Value = Command> {
+    let def_blob = (name(), option::of(label())).prop_map(|(name, label)| DefBlob { name, label });
+    (
+        name(),
+        name(),
+        option::of(label()),
+        option::of(label()),
+        property_state(),
+        property_perm(),
+        option::of(any::<u32>()),
+        timestamp(),
+        option::of(label()),
+        vec(def_blob, 0..4),
+    )
+        .prop_map(
+            |(device, name, label, group, state, perm, timeout, timestamp, message, blobs)| {
+                Command::DefBlobVector(DefBlobVector {
+                    device,
+                    name,
+                    label,
+                    group,
+                    state,
+                    perm,
+                    timeout,
+                    timestamp,
+                    message,
+                    blobs,
+                })
+            },
+        )
+}
+
+fn set_blob_vector() -> impl Strategy<Value = Command> {
+    let one_blob = (name(), label(), any::<bool>(), vec(any::<u8>(), 0..64)).prop_map(
+        |(name, format, with_enclen, value)| OneBlob {
+            name,
+            size: value.len() as u64,
+            enclen: with_enclen.then(|| base64::encode(&value).len() as u64),
+            format,
+            value,
+        },
+    );
+    (
+        name(),
+        name(),
+        property_state(),
+        option::of(any::<u32>()),
+        timestamp(),
+        option::of(label()),
+        vec(one_blob, 0..4),
+    )
+        .prop_map(
+            |(device, name, state, timeout, timestamp, message, blobs)| {
+                Command::SetBlobVector(SetBlobVector {
+                    device,
+                    name,
+                    state,
+                    timeout,
+                    timestamp,
+                    message,
+                    blobs,
+                })
+            },
+        )
+}
+
+fn message() -> impl Strategy<Value = Command> {
+    (option::of(name()), timestamp(), option::of(label())).prop_map(
+        |(device, timestamp, message)| {
+            Command::Message(Message {
+                device,
+                timestamp,
+                message,
+            })
+        },
+    )
+}
+
+fn del_property() -> impl Strategy<Value = Command> {
+    (name(), option::of(name()), timestamp(), option::of(label())).prop_map(
+        |(device, name, timestamp, message)| {
+            Command::DelProperty(DelProperty {
+                device,
+                name,
+                timestamp,
+                message,
+            })
+        },
+    )
+}
+
+fn enable_blob() -> impl Strategy<Value = Command> {
+    (name(), option::of(name()), blob_enable()).prop_map(|(device, name, enabled)| {
+        Command::EnableBlob(EnableBlob {
+            device,
+            name,
+            enabled,
+        })
+    })
+}
+
+fn get_properties() -> impl Strategy<Value = Command> {
+    ("[0-9]\\.[0-9]", option::of(name()), option::of(name())).prop_map(|(version, device, name)| {
+        Command::GetProperties(GetProperties {
+            version,
+            device,
+            name,
+        })
+    })
+}
+
+fn command() -> impl Strategy<Value = Command> {
+    prop_oneof![
+        def_text_vector(),
+        set_text_vector(),
+        new_text_vector(),
+        def_number_vector(),
+        set_number_vector(),
+        new_number_vector(),
+        def_switch_vector(),
+        set_switch_vector(),
+        new_switch_vector(),
+        def_light_vector(),
+        set_light_vector(),
+        def_blob_vector(),
+        set_blob_vector(),
+        message(),
+        del_property(),
+        enable_blob(),
+        get_properties(),
+    ]
+}
+
+fn write(command: &Command) -> Vec<u8> {
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    command.write(&mut writer).unwrap();
+    writer.into_inner().into_inner()
+}
+
+fn parse(xml: &[u8]) -> CommandIter<&[u8]> {
+    let mut reader = Reader::from_reader(xml);
+    reader.trim_text(true);
+    reader.expand_empty_elements(true);
+    CommandIter::new(reader)
+}
+
+proptest! {
+    #[test]
+    fn test_write_parse_round_trip(command in command()) {
+        let xml = write(&command);
+        let mut command_iter = parse(&xml);
+
+        match command_iter.next() {
+            Some(Ok(parsed)) => prop_assert_eq!(parsed, command),
+            other => prop_assert!(false, "{:?} parsed as {:?}", String::from_utf8_lossy(&xml), other),
+        }
+        prop_assert!(command_iter.next().is_none());
+    }
+
+    #[test]
+    fn test_mangled_input_does_not_panic(
+        command in command(),
+        index in any::<Index>(),
+        byte in any::<u8>(),
+        truncate in any::<bool>(),
+    ) {
+        let mut xml = write(&command);
+        let i = index.index(xml.len());
+        if truncate {
+            xml.truncate(i);
+        } else {
+            xml[i] = byte;
+        }
+        for _ in parse(&xml).take(xml.len() + 1) {}
+    }
+
+    #[test]
+    fn test_arbitrary_input_does_not_panic(
+        input in "(<[a-zA-Z]{0,16}( [a-z]{1,8}=\"[^\"]{0,8}\")*/?>|</[a-zA-Z]{0,16}>|[^<]{0,16}){0,16}",
+    ) {
+        for _ in parse(input.as_bytes()).take(input.len() + 1) {}
+    }
+}
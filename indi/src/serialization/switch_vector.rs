@@ -1,7 +1,3 @@
-use quick_xml::events::Event;
-use quick_xml::name::QName;
-use quick_xml::Reader;
-
 use std::str;
 
 use super::super::*;
@@ -49,6 +45,8 @@ impl CommandToUpdate for SetSwitchVector {
     fn update_param(self, param: &mut Parameter) -> Result<String, UpdateError> {
         match param {
             Parameter::SwitchVector(switch_vector) => {
+                switch_vector.state = self.state;
+                switch_vector.timeout = self.timeout;
                 switch_vector.timestamp = self.timestamp;
                 for switch in self.switches {
                     if let Some(existing) = switch_vector.values.get_mut(&switch.name) {
@@ -92,10 +90,8 @@ impl XmlSerialization for NewSwitchVector {
                 .with_attribute(("name", &*self.name));
 
             if let Some(timestamp) = &self.timestamp {
-                creator = creator.with_attribute((
-                    "timestamp",
-                    format!("{}", timestamp.format("%Y-%m-%dT%H:%M:%S%.3f")).as_str(),
-                ));
+                creator =
+                    creator.with_attribute(("timestamp", format_timestamp(timestamp).as_str()));
             }
             xml_writer = creator.write_inner_content(|xml_writer| {
                 for number in self.switches.iter() {
@@ -109,325 +105,205 @@ impl XmlSerialization for NewSwitchVector {
     }
 }
 
-fn next_one_switch<T: std::io::BufRead>(
-    xml_reader: &mut Reader<T>,
-    buf: &mut Vec<u8>,
-) -> Result<Option<OneSwitch>, DeError> {
-    let event = xml_reader.read_event_into(buf)?;
-    match event {
-        Event::Start(e) => match e.name() {
-            QName(b"oneSwitch") => {
-                let mut name: Result<String, DeError> = Err(DeError::MissingAttr(&"name"));
-
-                for attr in e.attributes() {
-                    let attr = attr?;
-                    let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-
-                    match attr.key {
-                        QName(b"name") => name = Ok(attr_value),
-                        key => {
-                            return Err(DeError::UnexpectedAttr(format!(
-                                "Unexpected attribute {}",
-                                str::from_utf8(key.into_inner())?
-                            )))
-                        }
-                    }
-                }
-
-                let value: Result<SwitchState, DeError> = match xml_reader.read_event_into(buf) {
-                    Ok(Event::Text(e)) => SwitchState::try_from_event(e),
-                    e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                };
+impl XmlSerialization for DefSwitch {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        let mut creator = xml_writer
+            .create_element("defSwitch")
+            .with_attribute(("name", &*self.name));
 
-                let trailing_event = xml_reader.read_event_into(buf)?;
-                match trailing_event {
-                    Event::End(_) => (),
-                    e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                }
+        if let Some(label) = &self.label {
+            creator = creator.with_attribute(("label", &label[..]));
+        }
+        creator.write_text_content(BytesText::new(self.value.as_str()))?;
 
-                Ok(Some(OneSwitch {
-                    name: name?,
-                    value: value?,
-                }))
-            }
-            tag => Err(DeError::UnexpectedTag(
-                str::from_utf8(tag.into_inner())?.to_string(),
-            )),
-        },
-        Event::End(_) => Ok(None),
-        Event::Eof => Ok(None),
-        e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
+        Ok(xml_writer)
     }
 }
 
-pub struct DefSwitchIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
-}
+impl XmlSerialization for DefSwitchVector {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        mut xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        {
+            let mut creator = xml_writer
+                .create_element("defSwitchVector")
+                .with_attribute(("device", &*self.device))
+                .with_attribute(("name", &*self.name));
 
-impl<'a, T: std::io::BufRead> Iterator for DefSwitchIter<'a, T> {
-    type Item = Result<DefSwitch, DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.next_switch() {
-            Ok(Some(switch)) => {
-                return Some(Ok(switch));
+            if let Some(label) = &self.label {
+                creator = creator.with_attribute(("label", &label[..]));
+            }
+            if let Some(group) = &self.group {
+                creator = creator.with_attribute(("group", &group[..]));
+            }
+            creator = creator.with_attribute(("state", self.state.as_str()));
+            creator = creator.with_attribute(("perm", self.perm.as_str()));
+            creator = creator.with_attribute(("rule", self.rule.as_str()));
+            if let Some(timeout) = &self.timeout {
+                creator = creator.with_attribute(("timeout", timeout.to_string().as_str()));
             }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
+            if let Some(timestamp) = &self.timestamp {
+                creator =
+                    creator.with_attribute(("timestamp", format_timestamp(timestamp).as_str()));
+            }
+            if let Some(message) = &self.message {
+                creator = creator.with_attribute(("message", &message[..]));
             }
+            xml_writer = creator.write_inner_content(|xml_writer| {
+                for switch in self.switches.iter() {
+                    switch.write(xml_writer)?;
+                }
+                Ok(())
+            })?;
         }
+
+        Ok(xml_writer)
     }
 }
-impl<'a, T: std::io::BufRead> DefSwitchIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> DefSwitchIter<T> {
-        DefSwitchIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
-        }
-    }
 
-    pub fn switch_vector(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<DefSwitchVector, DeError> {
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-        let mut label: Option<String> = None;
-        let mut group: Option<String> = None;
-        let mut state: Option<PropertyState> = None;
-        let mut perm: Option<PropertyPerm> = None;
-        let mut rule: Option<SwitchRule> = None;
-        let mut timeout: Option<u32> = None;
-        let mut timestamp: Option<DateTime<Utc>> = None;
-        let mut message: Option<String> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                QName(b"label") => label = Some(attr_value),
-                QName(b"group") => group = Some(attr_value),
-                QName(b"state") => state = Some(PropertyState::try_from(attr, xml_reader)?),
-                QName(b"perm") => perm = Some(PropertyPerm::try_from(attr, xml_reader)?),
-                QName(b"rule") => rule = Some(SwitchRule::try_from(attr, xml_reader)?),
-                QName(b"timeout") => timeout = Some(attr_value.parse::<u32>()?),
-                QName(b"timestamp") => {
-                    timestamp = Some(DateTime::from_str(&format!("{}Z", &attr_value))?)
-                }
-                QName(b"message") => message = Some(attr_value),
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
-                }
+impl XmlSerialization for SetSwitchVector {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        mut xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        {
+            let mut creator = xml_writer
+                .create_element("setSwitchVector")
+                .with_attribute(("device", &*self.device))
+                .with_attribute(("name", &*self.name));
+
+            creator = creator.with_attribute(("state", self.state.as_str()));
+            if let Some(timeout) = &self.timeout {
+                creator = creator.with_attribute(("timeout", timeout.to_string().as_str()));
+            }
+            if let Some(timestamp) = &self.timestamp {
+                creator =
+                    creator.with_attribute(("timestamp", format_timestamp(timestamp).as_str()));
+            }
+            if let Some(message) = &self.message {
+                creator = creator.with_attribute(("message", &message[..]));
             }
+            xml_writer = creator.write_inner_content(|xml_writer| {
+                for switch in self.switches.iter() {
+                    switch.write(xml_writer)?;
+                }
+                Ok(())
+            })?;
         }
-        Ok(DefSwitchVector {
-            device: device.ok_or(DeError::MissingAttr(&"device"))?,
-            name: name.ok_or(DeError::MissingAttr(&"name"))?,
-            label: label,
-            group: group,
-            state: state.ok_or(DeError::MissingAttr(&"state"))?,
-            perm: perm.ok_or(DeError::MissingAttr(&"perm"))?,
-            rule: rule.ok_or(DeError::MissingAttr(&"perm"))?,
-            timeout: timeout,
-            timestamp: timestamp,
-            message: message,
-            switches: Vec::new(),
-        })
-    }
 
-    fn next_switch(&mut self) -> Result<Option<DefSwitch>, DeError> {
-        let event = self.xml_reader.read_event_into(&mut self.buf)?;
-        match event {
-            Event::Start(e) => match e.name() {
-                QName(b"defSwitch") => {
-                    let mut name: Result<String, DeError> = Err(DeError::MissingAttr(&"name"));
-                    let mut label: Option<String> = None;
-
-                    for attr in e.attributes() {
-                        let attr = attr?;
-                        let attr_value = attr
-                            .decode_and_unescape_value(self.xml_reader)?
-                            .into_owned();
-
-                        match attr.key {
-                            QName(b"name") => name = Ok(attr_value),
-                            QName(b"label") => label = Some(attr_value),
-                            key => {
-                                return Err(DeError::UnexpectedAttr(format!(
-                                    "Unexpected attribute {}",
-                                    str::from_utf8(key.into_inner())?
-                                )))
-                            }
-                        }
-                    }
+        Ok(xml_writer)
+    }
+}
 
-                    let value: Result<SwitchState, DeError> =
-                        match self.xml_reader.read_event_into(self.buf) {
-                            Ok(Event::Text(e)) => SwitchState::try_from_event(e),
-                            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                        };
+pub type DefSwitchIter<'a, T> = ElementIter<'a, T, DefSwitch>;
+pub type SetSwitchIter<'a, T> = ElementIter<'a, T, OneSwitch>;
+pub type NewSwitchIter<'a, T> = ElementIter<'a, T, OneSwitch>;
 
-                    let trailing_event = self.xml_reader.read_event_into(&mut self.buf)?;
-                    match trailing_event {
-                        Event::End(_) => (),
-                        e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                    }
+impl XmlElement for DefSwitch {
+    const TAG: &'static str = "defSwitch";
+    const ATTRS: &'static [&'static str] = &["name", "label"];
 
-                    Ok(Some(DefSwitch {
-                        name: name?,
-                        label: label,
-                        value: value?,
-                    }))
-                }
-                tag => Err(DeError::UnexpectedTag(
-                    str::from_utf8(tag.into_inner())?.to_string(),
-                )),
-            },
-            Event::End(_) => Ok(None),
-            Event::Eof => Ok(None),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-        }
+    fn from_xml(attrs: Attrs, value: Option<BytesText>) -> Result<Self, DeError> {
+        Ok(DefSwitch {
+            name: required(attrs.name, "name")?,
+            label: attrs.label,
+            value: SwitchState::parse(&text(&value)?)?,
+        })
     }
 }
 
-pub struct SetSwitchIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
-}
+impl XmlElement for OneSwitch {
+    const TAG: &'static str = "oneSwitch";
+    const ATTRS: &'static [&'static str] = &["name"];
 
-impl<'a, T: std::io::BufRead> Iterator for SetSwitchIter<'a, T> {
-    type Item = Result<OneSwitch, DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match next_one_switch(&mut self.xml_reader, &mut self.buf) {
-            Ok(Some(switch)) => {
-                return Some(Ok(switch));
-            }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
-            }
-        }
+    fn from_xml(attrs: Attrs, value: Option<BytesText>) -> Result<Self, DeError> {
+        Ok(OneSwitch {
+            name: required(attrs.name, "name")?,
+            value: SwitchState::parse(&text(&value)?)?,
+        })
     }
 }
 
-impl<'a, T: std::io::BufRead> SetSwitchIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> SetSwitchIter<T> {
-        SetSwitchIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
-        }
+impl XmlVector for DefSwitchVector {
+    const ATTRS: &'static [&'static str] = &[
+        "device",
+        "name",
+        "label",
+        "group",
+        "state",
+        "perm",
+        "rule",
+        "timeout",
+        "timestamp",
+        "message",
+    ];
+    type Element = DefSwitch;
+
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError> {
+        Ok(DefSwitchVector {
+            device: required(attrs.device, "device")?,
+            name: required(attrs.name, "name")?,
+            label: attrs.label,
+            group: attrs.group,
+            state: required(attrs.state, "state")?,
+            perm: required(attrs.perm, "perm")?,
+            rule: required(attrs.rule, "rule")?,
+            timeout: attrs.timeout,
+            timestamp: attrs.timestamp,
+            message: attrs.message,
+            switches: Vec::new(),
+        })
     }
 
-    pub fn switch_vector(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<SetSwitchVector, DeError> {
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-        let mut state: Option<PropertyState> = None;
-        let mut timeout: Option<u32> = None;
-        let mut timestamp: Option<DateTime<Utc>> = None;
-        let mut message: Option<String> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                QName(b"state") => state = Some(PropertyState::try_from(attr, xml_reader)?),
-                QName(b"timeout") => timeout = Some(attr_value.parse::<u32>()?),
-                QName(b"timestamp") => {
-                    timestamp = Some(DateTime::from_str(&format!("{}Z", &attr_value))?)
-                }
-                QName(b"message") => message = Some(attr_value),
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
-                }
-            }
-        }
+    fn push(&mut self, element: DefSwitch) {
+        self.switches.push(element);
+    }
+}
+
+impl XmlVector for SetSwitchVector {
+    const ATTRS: &'static [&'static str] =
+        &["device", "name", "state", "timeout", "timestamp", "message"];
+    type Element = OneSwitch;
+
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError> {
         Ok(SetSwitchVector {
-            device: device.ok_or(DeError::MissingAttr(&"device"))?,
-            name: name.ok_or(DeError::MissingAttr(&"name"))?,
-            state: state.ok_or(DeError::MissingAttr(&"state"))?,
-            timeout: timeout,
-            timestamp: timestamp,
-            message: message,
+            device: required(attrs.device, "device")?,
+            name: required(attrs.name, "name")?,
+            state: required(attrs.state, "state")?,
+            timeout: attrs.timeout,
+            timestamp: attrs.timestamp,
+            message: attrs.message,
             switches: Vec::new(),
         })
     }
-}
-
-pub struct NewSwitchIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
-}
 
-impl<'a, T: std::io::BufRead> Iterator for NewSwitchIter<'a, T> {
-    type Item = Result<OneSwitch, DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match next_one_switch(&mut self.xml_reader, &mut self.buf) {
-            Ok(Some(switch)) => {
-                return Some(Ok(switch));
-            }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
-            }
-        }
+    fn push(&mut self, element: OneSwitch) {
+        self.switches.push(element);
     }
 }
 
-impl<'a, T: std::io::BufRead> NewSwitchIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> NewSwitchIter<T> {
-        NewSwitchIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
-        }
-    }
+impl XmlVector for NewSwitchVector {
+    const ATTRS: &'static [&'static str] = &["device", "name", "timestamp"];
+    type Element = OneSwitch;
 
-    pub fn switch_vector(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<NewSwitchVector, DeError> {
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-        let mut timestamp: Option<DateTime<Utc>> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                QName(b"timestamp") => {
-                    timestamp = Some(DateTime::from_str(&format!("{}Z", &attr_value))?)
-                }
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
-                }
-            }
-        }
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError> {
         Ok(NewSwitchVector {
-            device: device.ok_or(DeError::MissingAttr(&"device"))?,
-            name: name.ok_or(DeError::MissingAttr(&"name"))?,
-            timestamp: timestamp,
+            device: required(attrs.device, "device")?,
+            name: required(attrs.name, "name")?,
+            timestamp: attrs.timestamp,
             switches: Vec::new(),
         })
     }
+
+    fn push(&mut self, element: OneSwitch) {
+        self.switches.push(element);
+    }
 }
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -469,3 +469,48 @@ fn test_set_simulator_log() {
         }
     }
 }
+
+#[test]
+fn test_error_context() {
+    let xml = r#"<defNumberVector device="CCD Simulator" name="CCD_EXPOSURE" state="Idle" perm="rw">
+<defNumber name="CCD_EXPOSURE_VALUE" format="%5.2f" min="0" max="3600" step="1">
+not a number
+</defNumber>
+</defNumberVector>
+"#;
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    reader.expand_empty_elements(true);
+    let mut command_iter = CommandIter::new(reader);
+
+    let err = command_iter.next().unwrap().unwrap_err();
+    assert!(matches!(err.kind(), DeError::ParseFloatError(_)));
+    assert_eq!(err.name(), "ParseFloatError");
+
+    let context = err.context().unwrap();
+    assert_eq!(context.element.as_deref(), Some("defNumberVector"));
+    assert_eq!(context.device.as_deref(), Some("CCD Simulator"));
+    assert_eq!(context.property.as_deref(), Some("CCD_EXPOSURE"));
+    assert!(context.position > 0 && context.position <= xml.len());
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "invalid number: invalid float literal at byte {} in <defNumberVector> of CCD Simulator.CCD_EXPOSURE",
+            context.position
+        )
+    );
+}
+
+#[test]
+fn test_unexpected_event_description() {
+    let mut reader = Reader::from_str("</defNumberVector>");
+    reader.check_end_names(false);
+    let mut command_iter = CommandIter::new(reader);
+
+    let err = command_iter.next().unwrap().unwrap_err();
+    assert_eq!(err.context().unwrap().element, None);
+    assert_eq!(
+        err.to_string(),
+        "unexpected end of </defNumberVector> at byte 18"
+    );
+}
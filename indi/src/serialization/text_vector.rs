@@ -1,6 +1,3 @@
-use quick_xml::events::Event;
-use quick_xml::name::QName;
-use quick_xml::Reader;
 use std::str;
 
 // use log::warn;
@@ -91,10 +88,8 @@ impl XmlSerialization for NewTextVector {
                 .with_attribute(("name", &*self.name));
 
             if let Some(timestamp) = &self.timestamp {
-                creator = creator.with_attribute((
-                    "timestamp",
-                    format!("{}", timestamp.format("%Y-%m-%dT%H:%M:%S%.3f")).as_str(),
-                ));
+                creator =
+                    creator.with_attribute(("timestamp", format_timestamp(timestamp).as_str()));
             }
             xml_writer = creator.write_inner_content(|xml_writer| {
                 for text in self.texts.iter() {
@@ -108,328 +103,199 @@ impl XmlSerialization for NewTextVector {
     }
 }
 
-fn next_one_text<T: std::io::BufRead>(
-    xml_reader: &mut Reader<T>,
-    buf: &mut Vec<u8>,
-) -> Result<Option<OneText>, DeError> {
-    let event = xml_reader.read_event_into(buf)?;
-    match event {
-        Event::Start(e) => match e.name() {
-            QName(b"oneText") => {
-                let mut name: Result<String, DeError> = Err(DeError::MissingAttr(&"name"));
-
-                for attr in e.attributes() {
-                    let attr = attr?;
-                    let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-
-                    match attr.key {
-                        QName(b"name") => name = Ok(attr_value),
-                        key => {
-                            return Err(DeError::UnexpectedAttr(format!(
-                                "Unexpected attribute {}",
-                                str::from_utf8(key.into_inner())?
-                            )))
-                        }
-                    }
-                }
+impl XmlSerialization for DefText {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        let mut creator = xml_writer
+            .create_element("defText")
+            .with_attribute(("name", &*self.name));
 
-                let value: Result<String, DeError> = match xml_reader.read_event_into(buf) {
-                    Ok(Event::Text(e)) => {
-                        let val = Ok(e.unescape()?.into_owned());
-                        let trailing_event = xml_reader.read_event_into(buf)?;
-                        match trailing_event {
-                            Event::End(_) => (),
-                            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                        }
-                        val
-                    }
-                    Ok(Event::End(_)) => Ok("".to_string()),
-                    e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                };
-
-                Ok(Some(OneText {
-                    name: name?,
-                    value: value?,
-                }))
-            }
-            tag => Err(DeError::UnexpectedTag(
-                str::from_utf8(tag.into_inner())?.to_string(),
-            )),
-        },
-        Event::End(_) => Ok(None),
-        Event::Eof => Ok(None),
-        e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
+        if let Some(label) = &self.label {
+            creator = creator.with_attribute(("label", &label[..]));
+        }
+        creator.write_text_content(BytesText::new(self.value.as_str()))?;
+
+        Ok(xml_writer)
     }
 }
 
-pub struct NewTextIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
-}
+impl XmlSerialization for DefTextVector {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        mut xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        {
+            let mut creator = xml_writer
+                .create_element("defTextVector")
+                .with_attribute(("device", &*self.device))
+                .with_attribute(("name", &*self.name));
 
-impl<'a, T: std::io::BufRead> Iterator for NewTextIter<'a, T> {
-    type Item = Result<OneText, DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match next_one_text(&mut self.xml_reader, &mut self.buf) {
-            Ok(Some(number)) => {
-                return Some(Ok(number));
+            if let Some(label) = &self.label {
+                creator = creator.with_attribute(("label", &label[..]));
             }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
+            if let Some(group) = &self.group {
+                creator = creator.with_attribute(("group", &group[..]));
+            }
+            creator = creator.with_attribute(("state", self.state.as_str()));
+            creator = creator.with_attribute(("perm", self.perm.as_str()));
+            if let Some(timeout) = &self.timeout {
+                creator = creator.with_attribute(("timeout", timeout.to_string().as_str()));
+            }
+            if let Some(timestamp) = &self.timestamp {
+                creator =
+                    creator.with_attribute(("timestamp", format_timestamp(timestamp).as_str()));
             }
+            if let Some(message) = &self.message {
+                creator = creator.with_attribute(("message", &message[..]));
+            }
+            xml_writer = creator.write_inner_content(|xml_writer| {
+                for text in self.texts.iter() {
+                    text.write(xml_writer)?;
+                }
+                Ok(())
+            })?;
         }
+
+        Ok(xml_writer)
     }
 }
 
-impl<'a, T: std::io::BufRead> NewTextIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> NewTextIter<T> {
-        NewTextIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
-        }
-    }
+impl XmlSerialization for SetTextVector {
+    fn write<'a, T: std::io::Write>(
+        &self,
+        mut xml_writer: &'a mut Writer<T>,
+    ) -> XmlResult<&'a mut Writer<T>> {
+        {
+            let mut creator = xml_writer
+                .create_element("setTextVector")
+                .with_attribute(("device", &*self.device))
+                .with_attribute(("name", &*self.name));
 
-    pub fn text_vector(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<NewTextVector, DeError> {
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-        let mut timestamp: Option<DateTime<Utc>> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                QName(b"timestamp") => {
-                    timestamp = Some(DateTime::from_str(&format!("{}Z", &attr_value))?)
-                }
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
-                }
+            creator = creator.with_attribute(("state", self.state.as_str()));
+            if let Some(timeout) = &self.timeout {
+                creator = creator.with_attribute(("timeout", timeout.to_string().as_str()));
+            }
+            if let Some(timestamp) = &self.timestamp {
+                creator =
+                    creator.with_attribute(("timestamp", format_timestamp(timestamp).as_str()));
+            }
+            if let Some(message) = &self.message {
+                creator = creator.with_attribute(("message", &message[..]));
             }
+            xml_writer = creator.write_inner_content(|xml_writer| {
+                for text in self.texts.iter() {
+                    text.write(xml_writer)?;
+                }
+                Ok(())
+            })?;
         }
-        Ok(NewTextVector {
-            device: device.ok_or(DeError::MissingAttr(&"device"))?,
-            name: name.ok_or(DeError::MissingAttr(&"name"))?,
-            timestamp: timestamp,
-            texts: Vec::new(),
-        })
+
+        Ok(xml_writer)
     }
 }
 
-pub struct SetTextIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
-}
+pub type DefTextIter<'a, T> = ElementIter<'a, T, DefText>;
+pub type SetTextIter<'a, T> = ElementIter<'a, T, OneText>;
+pub type NewTextIter<'a, T> = ElementIter<'a, T, OneText>;
 
-impl<'a, T: std::io::BufRead> Iterator for SetTextIter<'a, T> {
-    type Item = Result<OneText, DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match next_one_text(&mut self.xml_reader, &mut self.buf) {
-            Ok(Some(number)) => {
-                return Some(Ok(number));
-            }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
-            }
-        }
+impl XmlElement for DefText {
+    const TAG: &'static str = "defText";
+    const ATTRS: &'static [&'static str] = &["name", "label"];
+
+    fn from_xml(attrs: Attrs, value: Option<BytesText>) -> Result<Self, DeError> {
+        Ok(DefText {
+            name: required(attrs.name, "name")?,
+            label: attrs.label,
+            value: text(&value)?.into_owned(),
+        })
     }
 }
 
-impl<'a, T: std::io::BufRead> SetTextIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> SetTextIter<T> {
-        SetTextIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
-        }
+impl XmlElement for OneText {
+    const TAG: &'static str = "oneText";
+    const ATTRS: &'static [&'static str] = &["name"];
+
+    fn from_xml(attrs: Attrs, value: Option<BytesText>) -> Result<Self, DeError> {
+        Ok(OneText {
+            name: required(attrs.name, "name")?,
+            value: text(&value)?.into_owned(),
+        })
     }
+}
 
-    pub fn text_vector(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<SetTextVector, DeError> {
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-        let mut state: Option<PropertyState> = None;
-        let mut timeout: Option<u32> = None;
-        let mut timestamp: Option<DateTime<Utc>> = None;
-        let mut message: Option<String> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                QName(b"state") => state = Some(PropertyState::try_from(attr, xml_reader)?),
-                QName(b"timeout") => timeout = Some(attr_value.parse::<u32>()?),
-                QName(b"timestamp") => {
-                    timestamp = Some(DateTime::from_str(&format!("{}Z", &attr_value))?)
-                }
-                QName(b"message") => message = Some(attr_value),
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
-                }
-            }
-        }
-        Ok(SetTextVector {
-            device: device.ok_or(DeError::MissingAttr(&"device"))?,
-            name: name.ok_or(DeError::MissingAttr(&"name"))?,
-            state: state.ok_or(DeError::MissingAttr(&"state"))?,
-            timeout: timeout,
-            timestamp: timestamp,
-            message: message,
+impl XmlVector for DefTextVector {
+    const ATTRS: &'static [&'static str] = &[
+        "device",
+        "name",
+        "label",
+        "group",
+        "state",
+        "perm",
+        "timeout",
+        "timestamp",
+        "message",
+    ];
+    type Element = DefText;
+
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError> {
+        Ok(DefTextVector {
+            device: required(attrs.device, "device")?,
+            name: required(attrs.name, "name")?,
+            label: attrs.label,
+            group: attrs.group,
+            state: required(attrs.state, "state")?,
+            perm: required(attrs.perm, "perm")?,
+            timeout: attrs.timeout,
+            timestamp: attrs.timestamp,
+            message: attrs.message,
             texts: Vec::new(),
         })
     }
-}
 
-pub struct DefTextIter<'a, T: std::io::BufRead> {
-    xml_reader: &'a mut Reader<T>,
-    buf: &'a mut Vec<u8>,
+    fn push(&mut self, element: DefText) {
+        self.texts.push(element);
+    }
 }
 
-impl<'a, T: std::io::BufRead> Iterator for DefTextIter<'a, T> {
-    type Item = Result<DefText, DeError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.next_text() {
-            Ok(Some(number)) => {
-                return Some(Ok(number));
-            }
-            Ok(None) => return None,
-            Err(e) => {
-                return Some(Err(e));
-            }
-        }
+impl XmlVector for SetTextVector {
+    const ATTRS: &'static [&'static str] =
+        &["device", "name", "state", "timeout", "timestamp", "message"];
+    type Element = OneText;
+
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError> {
+        Ok(SetTextVector {
+            device: required(attrs.device, "device")?,
+            name: required(attrs.name, "name")?,
+            state: required(attrs.state, "state")?,
+            timeout: attrs.timeout,
+            timestamp: attrs.timestamp,
+            message: attrs.message,
+            texts: Vec::new(),
+        })
     }
-}
 
-impl<'a, T: std::io::BufRead> DefTextIter<'a, T> {
-    pub fn new(command_iter: &mut CommandIter<T>) -> DefTextIter<T> {
-        DefTextIter {
-            xml_reader: &mut command_iter.xml_reader,
-            buf: &mut command_iter.buf,
-        }
+    fn push(&mut self, element: OneText) {
+        self.texts.push(element);
     }
+}
 
-    pub fn text_vector(
-        xml_reader: &Reader<T>,
-        start_event: &events::BytesStart,
-    ) -> Result<DefTextVector, DeError> {
-        let mut device: Option<String> = None;
-        let mut name: Option<String> = None;
-        let mut label: Option<String> = None;
-        let mut group: Option<String> = None;
-        let mut state: Option<PropertyState> = None;
-        let mut perm: Option<PropertyPerm> = None;
-        let mut timeout: Option<u32> = None;
-        let mut timestamp: Option<DateTime<Utc>> = None;
-        let mut message: Option<String> = None;
-
-        for attr in start_event.attributes() {
-            let attr = attr?;
-            let attr_value = attr.decode_and_unescape_value(xml_reader)?.into_owned();
-            match attr.key {
-                QName(b"device") => device = Some(attr_value),
-                QName(b"name") => name = Some(attr_value),
-                QName(b"label") => label = Some(attr_value),
-                QName(b"group") => group = Some(attr_value),
-                QName(b"state") => state = Some(PropertyState::try_from(attr, xml_reader)?),
-                QName(b"perm") => perm = Some(PropertyPerm::try_from(attr, xml_reader)?),
-                QName(b"timeout") => timeout = Some(attr_value.parse::<u32>()?),
-                QName(b"timestamp") => {
-                    timestamp = Some(DateTime::from_str(&format!("{}Z", &attr_value))?)
-                }
-                QName(b"message") => message = Some(attr_value),
-                key => {
-                    return Err(DeError::UnexpectedAttr(format!(
-                        "Unexpected attribute {}",
-                        str::from_utf8(key.into_inner())?
-                    )))
-                }
-            }
-        }
-        Ok(DefTextVector {
-            device: device.ok_or(DeError::MissingAttr(&"device"))?,
-            name: name.ok_or(DeError::MissingAttr(&"name"))?,
-            label: label,
-            group: group,
-            state: state.ok_or(DeError::MissingAttr(&"state"))?,
-            perm: perm.ok_or(DeError::MissingAttr(&"perm"))?,
-            timeout: timeout,
-            timestamp: timestamp,
-            message: message,
+impl XmlVector for NewTextVector {
+    const ATTRS: &'static [&'static str] = &["device", "name", "timestamp"];
+    type Element = OneText;
+
+    fn from_attrs(attrs: Attrs) -> Result<Self, DeError> {
+        Ok(NewTextVector {
+            device: required(attrs.device, "device")?,
+            name: required(attrs.name, "name")?,
+            timestamp: attrs.timestamp,
             texts: Vec::new(),
         })
     }
-    fn next_text(&mut self) -> Result<Option<DefText>, DeError> {
-        let event = self.xml_reader.read_event_into(&mut self.buf)?;
-
-        match event {
-            Event::Start(e) => match e.name() {
-                QName(b"defText") => {
-                    let mut name: Result<String, DeError> = Err(DeError::MissingAttr(&"name"));
-                    let mut label: Option<String> = None;
-
-                    for attr in e.attributes() {
-                        let attr = attr?;
-                        let attr_value = attr
-                            .decode_and_unescape_value(self.xml_reader)?
-                            .into_owned();
-
-                        match attr.key {
-                            QName(b"name") => name = Ok(attr_value),
-                            QName(b"label") => label = Some(attr_value),
-                            key => {
-                                return Err(DeError::UnexpectedAttr(format!(
-                                    "Unexpected attribute {}",
-                                    str::from_utf8(key.into_inner())?
-                                )))
-                            }
-                        }
-                    }
 
-                    let value: Result<String, DeError> =
-                        match self.xml_reader.read_event_into(self.buf) {
-                            Ok(Event::Text(e)) => {
-                                let val = Ok(e.unescape()?.into_owned());
-                                let trailing_event =
-                                    self.xml_reader.read_event_into(&mut self.buf)?;
-                                match trailing_event {
-                                    Event::End(_) => (),
-                                    e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                                }
-                                val
-                            }
-                            Ok(Event::End(_)) => Ok("".to_string()),
-                            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-                        };
-
-                    Ok(Some(DefText {
-                        name: name?,
-                        label: label,
-                        value: value?,
-                    }))
-                }
-                tag => Err(DeError::UnexpectedTag(
-                    str::from_utf8(tag.into_inner())?.to_string(),
-                )),
-            },
-            Event::End(_) => Ok(None),
-            Event::Eof => Ok(None),
-            e => return Err(DeError::UnexpectedEvent(format!("{:?}", e))),
-        }
+    fn push(&mut self, element: OneText) {
+        self.texts.push(element);
     }
 }
 
@@ -0,0 +1,182 @@
+use super::super::*;
+use super::*;
+
+/// Formats a timestamp the way INDI expects: UTC, millisecond precision and no
+/// timezone suffix.
+pub fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+    format!("{}", timestamp.format("%Y-%m-%dT%H:%M:%S%.3f"))
+}
+
+/// Parses an INDI timestamp.  The protocol specifies UTC without a timezone
+/// suffix, but drivers are seen sending:
+///  * whole seconds, or fractions of any precision,
+///  * a trailing `Z`,
+///  * a UTC offset, as `+HH:MM`, `+HHMM` or `+HH`.
+///
+/// Those are parsed directly; anything else (such as leap seconds or a space in
+/// place of the `T`) is left to chrono.
+pub fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, DeError> {
+    let value = value.trim();
+    if let Some(timestamp) = parse_common(value.as_bytes()) {
+        return Ok(timestamp);
+    }
+    if let Ok(timestamp) = DateTime::<FixedOffset>::from_str(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    Ok(DateTime::from_str(&format!("{}Z", value))?)
+}
+
+fn digits(value: &[u8]) -> Option<u32> {
+    value.iter().try_fold(0, |acc, b| match b {
+        b'0'..=b'9' => Some(acc * 10 + (b - b'0') as u32),
+        _ => None,
+    })
+}
+
+fn parse_common(value: &[u8]) -> Option<DateTime<Utc>> {
+    if value.len() < 19
+        || value[4] != b'-'
+        || value[7] != b'-'
+        || value[10] != b'T'
+        || value[13] != b':'
+        || value[16] != b':'
+    {
+        return None;
+    }
+
+    let mut rest = &value[19..];
+    let mut nanos = 0;
+    if let [b'.', fraction @ ..] = rest {
+        let len = fraction.iter().take_while(|b| b.is_ascii_digit()).count();
+        if len == 0 {
+            return None;
+        }
+        // Digits past nanoseconds are dropped, as chrono does.
+        let precision = len.min(9);
+        nanos = digits(&fraction[..precision])? * 10u32.pow(9 - precision as u32);
+        rest = &fraction[len..];
+    }
+    let offset = parse_offset(rest)?;
+
+    let date = NaiveDate::from_ymd_opt(
+        digits(&value[0..4])? as i32,
+        digits(&value[5..7])?,
+        digits(&value[8..10])?,
+    )?;
+    let seconds = digits(&value[17..19])?;
+    // Leap seconds are left to chrono.
+    if seconds > 59 {
+        return None;
+    }
+    let time = date.and_hms_nano_opt(
+        digits(&value[11..13])?,
+        digits(&value[14..16])?,
+        seconds,
+        nanos,
+    )?;
+    Some(Utc.from_utc_datetime(&(time - chrono::Duration::seconds(offset))))
+}
+
+/// The offset east of UTC, in seconds, of a timezone suffix.
+fn parse_offset(zone: &[u8]) -> Option<i64> {
+    let (sign, zone) = match zone {
+        [] | [b'Z'] | [b'z'] => return Some(0),
+        [b'+', zone @ ..] => (1, zone),
+        [b'-', zone @ ..] => (-1, zone),
+        _ => return None,
+    };
+    let (hours, minutes) = match zone {
+        [_, _] => (digits(zone)?, 0),
+        [_, _, b':', _, _] => (digits(&zone[0..2])?, digits(&zone[3..5])?),
+        [_, _, _, _] => (digits(&zone[0..2])?, digits(&zone[2..4])?),
+        _ => return None,
+    };
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        for value in [
+            "2022-10-03T01:00:14",
+            "2022-10-13T07:41:56.301",
+            "2022-10-13T07:41:56.3",
+            "2022-10-13T07:41:56.123456789",
+            "1999-12-31T23:59:59.999",
+        ] {
+            assert_eq!(
+                parse_timestamp(value).unwrap(),
+                utc(&format!("{}Z", value)),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_timestamp_variants() {
+        let expected = utc("2022-10-13T07:41:56Z");
+        for value in [
+            "2022-10-13T07:41:56Z",
+            "2022-10-13T07:41:56z",
+            "2022-10-13T07:41:56+00:00",
+            "2022-10-13T09:41:56+02:00",
+            "2022-10-13T09:41:56+0200",
+            "2022-10-13T09:41:56+02",
+            "2022-10-13T02:11:56-05:30",
+            " 2022-10-13T07:41:56\n",
+            "2022-10-13 07:41:56",
+            "2022-10-13 07:41:56Z",
+        ] {
+            assert_eq!(parse_timestamp(value).unwrap(), expected, "{}", value);
+        }
+
+        assert_eq!(
+            parse_timestamp("2022-10-13T07:41:56.3011234567890Z").unwrap(),
+            utc("2022-10-13T07:41:56.301123456Z")
+        );
+        assert_eq!(
+            parse_timestamp("2022-10-13T09:41:56.5+02:00").unwrap(),
+            utc("2022-10-13T07:41:56.5Z")
+        );
+        assert_eq!(
+            parse_timestamp("2016-12-31T23:59:60").unwrap(),
+            utc("2016-12-31T23:59:60Z")
+        );
+    }
+
+    #[test]
+    fn test_parse_timestamp_errors() {
+        for value in [
+            "",
+            "2022-02-30T07:41:56",
+            "2022-10-13T07:41:56.",
+            "2022-10-13T07:41",
+            "2022-10-13T07:41:56+2",
+            "2022-10-13T07:41:56+24:00",
+            "yesterday",
+        ] {
+            assert!(parse_timestamp(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        let timestamp = utc("2022-10-13T07:41:56.301Z");
+        assert_eq!(format_timestamp(&timestamp), "2022-10-13T07:41:56.301");
+        assert_eq!(
+            parse_timestamp(&format_timestamp(&timestamp)).unwrap(),
+            timestamp
+        );
+    }
+}
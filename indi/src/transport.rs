@@ -0,0 +1,137 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::process::{ChildStdin, ChildStdout};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+/// A bidirectional byte stream that INDI commands can be exchanged over.
+pub trait Transport: Read + Write + Sized {
+    /// Creates a new handle to the same underlying stream, so that reads and
+    /// writes can happen independently (e.g. from separate threads).
+    fn try_clone(&self) -> io::Result<Self>;
+
+    /// Shuts down the stream in both directions.
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl Transport for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+/// A transport made of a separate reader and writer, such as the stdio pipes
+/// of a driver process or a pair of in-memory buffers.
+///
+/// Shutting down a `Duplex` closes the writer, which signals end of input to
+/// whatever is on the other end of it.
+#[derive(Debug)]
+pub struct Duplex<R, W> {
+    reader: Arc<Mutex<R>>,
+    writer: Arc<Mutex<Option<W>>>,
+}
+
+/// Transport for talking to a driver process over its stdin and stdout.
+pub type ChildTransport = Duplex<ChildStdout, ChildStdin>;
+
+/// Transport for talking over this process' own stdin and stdout, as a driver does.
+pub type StdioTransport = Duplex<io::Stdin, io::Stdout>;
+
+impl<R, W> Duplex<R, W> {
+    /// Creates a new transport reading from `reader` and writing to `writer`.
+    pub fn new(reader: R, writer: W) -> Duplex<R, W> {
+        Duplex {
+            reader: Arc::new(Mutex::new(reader)),
+            writer: Arc::new(Mutex::new(Some(writer))),
+        }
+    }
+
+    fn lock_writer(&self) -> io::Result<MutexGuard<'_, Option<W>>> {
+        self.writer
+            .lock()
+            .map_err(|_| io::Error::other("writer lock poisoned"))
+    }
+}
+
+impl<R: Read, W> Read for Duplex<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader
+            .lock()
+            .map_err(|_| io::Error::other("reader lock poisoned"))?
+            .read(buf)
+    }
+}
+
+impl<R, W: Write> Write for Duplex<R, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut *self.lock_writer()? {
+            Some(writer) => writer.write(buf),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut *self.lock_writer()? {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R: Read, W: Write> Transport for Duplex<R, W> {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Duplex {
+            reader: Arc::clone(&self.reader),
+            writer: Arc::clone(&self.writer),
+        })
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        if let Some(mut writer) = self.lock_writer()?.take() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_duplex_shutdown() {
+        let mut duplex = Duplex::new(Cursor::new(b"input".to_vec()), Vec::new());
+        let mut clone = duplex.try_clone().unwrap();
+
+        clone.write_all(b"output").unwrap();
+        assert_eq!(duplex.writer.lock().unwrap().as_ref().unwrap(), b"output");
+
+        let mut input = String::new();
+        duplex.read_to_string(&mut input).unwrap();
+        assert_eq!(input, "input");
+
+        duplex.shutdown().unwrap();
+        assert_eq!(
+            clone.write(b"more").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+    }
+}
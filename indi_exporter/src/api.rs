@@ -0,0 +1,877 @@
+//! The exported properties as JSON over HTTP, for dashboards and scripts that
+//! don't speak INDI.
+//!
+//! `GET /devices` returns every property as
+//! `{"server": {"device": {"property": {...}}}}`.  `GET /events` is a stream of
+//! server-sent events: `update` with a property whenever it is defined or
+//! changes, and `delete` when it is deleted or its server goes away.
+//!
+//! When a token is configured, clients that send it as a bearer token may set
+//! properties by POSTing their new values, form encoded, to
+//! `/devices/<server>/<device>/<property>`.  The new values are sent over the
+//! exporter's own connection to the server, and the answer is the property
+//! once the driver has finished with it.
+use crate::config::Filter;
+use indi::Json;
+use log::debug;
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often to send event stream clients a comment, so that those who have
+/// gone away are noticed.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+/// How many events may wait for an event stream client before it is dropped
+/// for falling behind.
+const EVENT_QUEUE: usize = 256;
+/// How many requests are served at once, counting open event streams.
+const MAX_CONNECTIONS: usize = 64;
+/// How long a client may take to send its request or accept a response.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to wait for a driver to finish with a property that has been set.
+const SET_TIMEOUT: Duration = Duration::from_secs(60);
+/// The largest form accepted.
+const MAX_FORM: usize = 64 * 1024;
+/// The most read of a request's line and headers.
+const MAX_HEADER: u64 = 8 * 1024;
+
+type Properties = BTreeMap<String, BTreeMap<String, BTreeMap<String, Property>>>;
+
+struct Property {
+    json: Json,
+    /// The property as last updated, to check a set against.  BLOBs, which
+    /// can't be set, aren't kept.
+    param: Option<indi::Parameter>,
+    /// How many times it has been defined or updated.
+    updates: u64,
+    /// The last of those updates that had it Busy.
+    last_busy: Option<u64>,
+}
+
+impl Property {
+    /// Whether the driver has answered `command`, sent when the property had
+    /// had `updates` updates: it has gone Busy and finished since, failed, or
+    /// now holds the values that were sent.  Updates a driver sends on its own,
+    /// like a mount's coordinates, don't count until then.
+    fn answered(&self, updates: u64, command: &indi::Command) -> bool {
+        let param = match &self.param {
+            Some(param) if self.updates > updates => param,
+            _ => return false,
+        };
+        match param.get_state() {
+            indi::PropertyState::Busy => false,
+            indi::PropertyState::Alert => true,
+            _ => self.last_busy.is_some_and(|busy| busy > updates) || holds(param, command),
+        }
+    }
+}
+
+/// Whether `param` holds the values `command` sets.
+fn holds(param: &indi::Parameter, command: &indi::Command) -> bool {
+    match (param, command) {
+        (indi::Parameter::TextVector(p), indi::Command::NewTextVector(c)) => c
+            .texts
+            .iter()
+            .all(|t| p.values.get(&t.name).is_some_and(|v| v.value == t.value)),
+        (indi::Parameter::NumberVector(p), indi::Command::NewNumberVector(c)) => c
+            .numbers
+            .iter()
+            .all(|n| p.values.get(&n.name).is_some_and(|v| v.value == n.value)),
+        (indi::Parameter::SwitchVector(p), indi::Command::NewSwitchVector(c)) => c
+            .switches
+            .iter()
+            .all(|s| p.values.get(&s.name).is_some_and(|v| v.value == s.value)),
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// The JSON for each property, by server, device and property name.
+    properties: Properties,
+    /// The event streams being served.
+    subscribers: Vec<mpsc::SyncSender<Arc<str>>>,
+}
+
+impl State {
+    /// Queues `message` for every event stream, dropping those that have gone
+    /// or fallen too far behind.
+    fn send(&mut self, message: &Arc<str>) {
+        self.subscribers
+            .retain(|subscriber| subscriber.try_send(message.clone()).is_ok());
+    }
+
+    fn property(&self, server: &str, device: &str, name: &str) -> Option<&Property> {
+        self.properties
+            .get(server)
+            .and_then(|devices| devices.get(device))
+            .and_then(|properties| properties.get(name))
+    }
+}
+
+/// What the request handlers share with the `Api`.
+struct Shared {
+    filter: Filter,
+    token: Option<String>,
+    /// The servers whose properties are set.
+    aggregator: Arc<Mutex<indi::Aggregator>>,
+    state: Mutex<State>,
+    /// Signalled whenever a property is updated.
+    updated: Condvar,
+    /// How many requests are being served.
+    connections: AtomicUsize,
+}
+
+/// Counts a request as being served until it is dropped.
+struct Slot<'a>(&'a AtomicUsize);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct Api {
+    shared: Arc<Shared>,
+}
+
+impl Api {
+    /// Creates the API, showing only what `filter` exports, and letting clients
+    /// that know `token` set properties on `aggregator`'s servers.  It isn't
+    /// served until `serve` is called.
+    pub fn new(
+        filter: Filter,
+        token: Option<String>,
+        aggregator: Arc<Mutex<indi::Aggregator>>,
+    ) -> Api {
+        Api {
+            shared: Arc::new(Shared {
+                filter,
+                token,
+                aggregator,
+                state: Mutex::new(State::default()),
+                updated: Condvar::new(),
+                connections: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Serves the API on `addr`, from a thread of its own.
+    pub fn serve<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        let shared = self.shared.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                if shared.connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+                    shared.connections.fetch_sub(1, Ordering::Relaxed);
+                    let _ = stream.set_write_timeout(Some(IO_TIMEOUT));
+                    let _ = respond(
+                        &mut stream,
+                        "503 Service Unavailable",
+                        "text/plain",
+                        "too many connections\n",
+                    );
+                    continue;
+                }
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    let _slot = Slot(&shared.connections);
+                    if let Err(e) = handle(stream, &shared) {
+                        debug!("api: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    /// Records `param`, which has just been defined or updated.
+    pub fn update(&self, server: &str, device: &str, param: &indi::Parameter) {
+        let filter = &self.shared.filter;
+        if !filter.property(device, param.get_name()) {
+            return;
+        }
+        let json = match indi::parameter_json(param, |element| {
+            filter.element(device, param.get_name(), element)
+        }) {
+            Some(json) => json,
+            None => return,
+        };
+
+        let message = event(
+            "update",
+            &Json::Object(vec![
+                ("server".to_string(), Json::String(server.to_string())),
+                ("device".to_string(), Json::String(device.to_string())),
+                ("name".to_string(), Json::String(param.get_name().clone())),
+                ("property".to_string(), json.clone()),
+            ]),
+        );
+
+        let mut state = self.shared.state.lock().unwrap();
+        let previous = state.property(server, device, param.get_name());
+        let updates = previous.map_or(0, |property| property.updates + 1);
+        let last_busy = match param.get_state() {
+            indi::PropertyState::Busy => Some(updates),
+            _ => previous.and_then(|property| property.last_busy),
+        };
+        state
+            .properties
+            .entry(server.to_string())
+            .or_default()
+            .entry(device.to_string())
+            .or_default()
+            .insert(
+                param.get_name().clone(),
+                Property {
+                    json,
+                    param: match param {
+                        indi::Parameter::BlobVector(_) => None,
+                        param => Some(param.clone()),
+                    },
+                    updates,
+                    last_busy,
+                },
+            );
+        state.send(&message);
+        self.shared.updated.notify_all();
+    }
+
+    /// Forgets `device`'s property `name`, which has been deleted or whose
+    /// server has gone.
+    pub fn remove(&self, server: &str, device: &str, name: &str) {
+        let message = event(
+            "delete",
+            &Json::Object(vec![
+                ("server".to_string(), Json::String(server.to_string())),
+                ("device".to_string(), Json::String(device.to_string())),
+                ("name".to_string(), Json::String(name.to_string())),
+            ]),
+        );
+
+        let mut state = self.shared.state.lock().unwrap();
+        let devices = match state.properties.get_mut(server) {
+            Some(devices) => devices,
+            None => return,
+        };
+        let properties = match devices.get_mut(device) {
+            Some(properties) => properties,
+            None => return,
+        };
+        if properties.remove(name).is_none() {
+            return;
+        }
+        if properties.is_empty() {
+            devices.remove(device);
+        }
+        if devices.is_empty() {
+            state.properties.remove(server);
+        }
+        state.send(&message);
+    }
+}
+
+/// Formats a server-sent event.
+fn event(event: &str, data: &Json) -> Arc<str> {
+    let mut message = format!("event: {}\n", event);
+    for line in data.to_string().lines() {
+        message.push_str("data: ");
+        message.push_str(line);
+        message.push('\n');
+    }
+    message.push('\n');
+    message.into()
+}
+
+/// An HTTP error: the status line and a message for the body.
+type HttpError = (&'static str, String);
+
+/// Answers one HTTP request on `stream`.
+fn handle(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut head = reader.by_ref().take(MAX_HEADER);
+    let mut request = String::new();
+    head.read_line(&mut request)?;
+    // A line that hasn't ended by the limit is too long.
+    let cut = |line: &str, head: &io::Take<_>| !line.ends_with('\n') && head.limit() == 0;
+    if cut(&request, &head) {
+        return respond(
+            &mut stream,
+            "400 Bad Request",
+            "text/plain",
+            "request line too long\n",
+        );
+    }
+    let mut content_length = 0;
+    let mut authorization = None;
+    loop {
+        let mut header = String::new();
+        head.read_line(&mut header)?;
+        if cut(&header, &head) {
+            return respond(
+                &mut stream,
+                "431 Request Header Fields Too Large",
+                "text/plain",
+                "headers too long\n",
+            );
+        }
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                "authorization" => authorization = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    let mut parts = request.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    // Ignore any query string.
+    let path = path.split('?').next().unwrap_or("");
+    match (method, path) {
+        ("GET", "/devices") => {
+            let json = {
+                let state = shared.state.lock().unwrap();
+                properties_json(&state.properties)
+            };
+            respond(
+                &mut stream,
+                "200 OK",
+                "application/json",
+                &format!("{}\n", json),
+            )
+        }
+        ("GET", "/events") => {
+            let (sender, receiver) = mpsc::sync_channel(EVENT_QUEUE);
+            shared.state.lock().unwrap().subscribers.push(sender);
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: text/event-stream\r\n\
+                 Cache-Control: no-cache\r\n\
+                 Access-Control-Allow-Origin: *\r\n\
+                 \r\n"
+            )?;
+            stream.flush()?;
+            loop {
+                match receiver.recv_timeout(KEEPALIVE) {
+                    Ok(message) => stream.write_all(message.as_bytes())?,
+                    Err(mpsc::RecvTimeoutError::Timeout) => stream.write_all(b": keepalive\n\n")?,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+                stream.flush()?;
+            }
+        }
+        ("POST", _) if path.starts_with("/devices/") => {
+            let result = authorize(shared, authorization.as_deref()).and_then(|()| {
+                if content_length > MAX_FORM {
+                    return Err(("413 Payload Too Large", "form too large".to_string()));
+                }
+                let mut body = vec![0; content_length];
+                reader
+                    .read_exact(&mut body)
+                    .map_err(|e| ("400 Bad Request", e.to_string()))?;
+                post(
+                    shared,
+                    &path["/devices/".len()..],
+                    &String::from_utf8_lossy(&body),
+                )
+            });
+            match result {
+                Ok(json) => respond(
+                    &mut stream,
+                    "200 OK",
+                    "application/json",
+                    &format!("{}\n", json),
+                ),
+                Err((status, message)) => {
+                    respond(&mut stream, status, "text/plain", &format!("{}\n", message))
+                }
+            }
+        }
+        // Browsers ask before POSTing with an Authorization header.
+        ("OPTIONS", _) => {
+            write!(
+                stream,
+                "HTTP/1.1 204 No Content\r\n\
+                 Access-Control-Allow-Origin: *\r\n\
+                 Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n\
+                 Access-Control-Allow-Headers: Authorization, Content-Type\r\n\
+                 Access-Control-Max-Age: 600\r\n\
+                 Connection: close\r\n\
+                 \r\n"
+            )?;
+            stream.flush()
+        }
+        ("GET", _) | ("POST", _) => {
+            respond(&mut stream, "404 Not Found", "text/plain", "not found\n")
+        }
+        _ => respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n",
+        ),
+    }
+}
+
+/// Checks the `Authorization` header of a request that sets properties.
+fn authorize(shared: &Shared, authorization: Option<&str>) -> Result<(), HttpError> {
+    let token = match &shared.token {
+        Some(token) => token,
+        None => return Err(("403 Forbidden", "the API is read only".to_string())),
+    };
+    let given = authorization
+        .and_then(|a| a.strip_prefix("Bearer "))
+        .unwrap_or("");
+    // Compare every byte, so the time taken doesn't give away how much of the
+    // token was right.
+    let matches = given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0;
+    if matches {
+        Ok(())
+    } else {
+        Err((
+            "401 Unauthorized",
+            "a valid bearer token is needed".to_string(),
+        ))
+    }
+}
+
+/// Sets the property at `path`, `server/device/property`, to the values in
+/// `form`, and returns the property once the driver has answered.
+fn post(shared: &Shared, path: &str, form: &str) -> Result<Json, HttpError> {
+    let parts: Vec<String> = path.split('/').map(|p| decode(p, false)).collect();
+    let (server, device, property) = match parts.as_slice() {
+        [server, device, property] => (server, device, property),
+        _ => return Err(("404 Not Found", "not found".to_string())),
+    };
+    let unknown = || {
+        (
+            "404 Not Found",
+            format!("unknown property {}.{} on {}", device, property, server),
+        )
+    };
+
+    let values: Vec<(String, String)> = form
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(name, true), decode(value, true))
+        })
+        .collect();
+    if values.is_empty() {
+        return Err(("400 Bad Request", "no values given".to_string()));
+    }
+    for (element, _) in &values {
+        if !shared.filter.element(device, property, element) {
+            return Err(("403 Forbidden", format!("{} isn't exported", element)));
+        }
+    }
+    let values: Vec<(&str, &str)> = values
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+
+    // The property is checked against the definition the exporter already has,
+    // and set over its connection to the server.
+    let (updates, command) = {
+        let mut aggregator = shared.aggregator.lock().unwrap();
+        let updates = match shared
+            .state
+            .lock()
+            .unwrap()
+            .property(server, device, property)
+        {
+            Some(property) => property.updates,
+            None => return Err(unknown()),
+        };
+        let command = aggregator
+            .get_client(server)
+            .ok_or_else(unknown)?
+            .new_vector_from_str(device, property, &values)
+            .map_err(|e| ("400 Bad Request", e.to_string()))?;
+        aggregator
+            .write(server, &command)
+            .map_err(|e| ("502 Bad Gateway", format!("{}: {}", server, e)))?;
+        (updates, command)
+    };
+
+    // Wait for the driver to say how it went.
+    let deadline = Instant::now() + SET_TIMEOUT;
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        match state.property(server, device, property) {
+            Some(p) if p.answered(updates, &command) => return Ok(p.json.clone()),
+            Some(_) => {}
+            None => return Err(unknown()),
+        }
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() {
+            return Err((
+                "504 Gateway Timeout",
+                format!("{}.{} is still busy", device, property),
+            ));
+        }
+        state = shared.updated.wait_timeout(state, timeout).unwrap().0;
+    }
+}
+
+/// Decodes a percent-encoded URL path segment, or form field if `form`.
+fn decode(s: &str, form: bool) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = s
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) if form => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn properties_json(properties: &Properties) -> Json {
+    Json::Object(
+        properties
+            .iter()
+            .map(|(server, devices)| {
+                let devices = devices
+                    .iter()
+                    .map(|(device, properties)| {
+                        let properties = properties
+                            .iter()
+                            .map(|(name, property)| (name.clone(), property.json.clone()))
+                            .collect();
+                        (device.clone(), Json::Object(properties))
+                    })
+                    .collect();
+                (server.clone(), Json::Object(devices))
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn light(state: indi::PropertyState) -> indi::Parameter {
+        indi::Parameter::LightVector(indi::LightVector {
+            name: "STATUS".to_string(),
+            group: None,
+            label: None,
+            state: indi::PropertyState::Ok,
+            timestamp: None,
+            values: HashMap::from([(
+                "PARKED".to_string(),
+                indi::Light {
+                    label: None,
+                    value: state,
+                },
+            )]),
+        })
+    }
+
+    fn port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    fn get(port: u16, path: &str) -> BufReader<TcpStream> {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        BufReader::new(stream)
+    }
+
+    #[test]
+    fn test_long_request() {
+        let port = port();
+        let api = Api::new(Filter::default(), None, aggregator().0);
+        api.serve(("127.0.0.1", port)).unwrap();
+        let request = |head: String| {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream.write_all(head.as_bytes()).unwrap();
+            let mut status = String::new();
+            BufReader::new(stream).read_line(&mut status).unwrap();
+            status
+        };
+
+        let long = "x".repeat(MAX_HEADER as usize);
+        assert_eq!(
+            request(format!("GET /{} HTTP/1.1\r\n\r\n", long)),
+            "HTTP/1.1 400 Bad Request\r\n"
+        );
+        assert_eq!(
+            request(format!("GET /devices HTTP/1.1\r\nX-Long: {}\r\n\r\n", long)),
+            "HTTP/1.1 431 Request Header Fields Too Large\r\n"
+        );
+        assert_eq!(
+            request("GET /devices HTTP/1.1\r\nHost: localhost\r\n\r\n".to_string()),
+            "HTTP/1.1 200 OK\r\n"
+        );
+    }
+
+    fn aggregator() -> (
+        Arc<Mutex<indi::Aggregator>>,
+        mpsc::Receiver<indi::ServerEvent>,
+    ) {
+        let (aggregator, events) = indi::Aggregator::new();
+        (Arc::new(Mutex::new(aggregator)), events)
+    }
+
+    #[test]
+    fn test_api() {
+        let port = port();
+        let api = Api::new(Filter::default(), None, aggregator().0);
+        api.serve(("127.0.0.1", port)).unwrap();
+        api.update("s", "Telescope", &light(indi::PropertyState::Idle));
+
+        let mut response = String::new();
+        get(port, "/devices").read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains(r#""PARKED": {"#));
+        assert!(response.contains(r#""value": "Idle""#));
+
+        let mut events = get(port, "/events");
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            events.read_line(&mut line).unwrap();
+        }
+        // The stream is subscribed before its headers are sent.
+        api.update("s", "Telescope", &light(indi::PropertyState::Ok));
+        api.remove("s", "Telescope", "STATUS");
+
+        let mut received = String::new();
+        while !received.ends_with("event: delete\n") {
+            line.clear();
+            events.read_line(&mut line).unwrap();
+            received.push_str(&line);
+        }
+        assert!(received.starts_with("event: update\ndata: {\n"));
+        assert!(received.contains(r#""value": "Ok""#));
+
+        let mut response = String::new();
+        get(port, "/devices").read_to_string(&mut response).unwrap();
+        assert!(response.ends_with("{}\n"));
+
+        let mut response = String::new();
+        get(port, "/nothing").read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(
+            stream,
+            "OPTIONS /devices/s/Telescope/STATUS HTTP/1.1\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(response.contains("Access-Control-Allow-Headers: Authorization, Content-Type\r\n"));
+    }
+
+    /// Serves a focuser whose position can be set between 0 and 100.
+    fn fake_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let def = r#"<defNumberVector device="Focuser" name="FOCUS" state="Idle" perm="rw"><defNumber name="POSITION" format="%5.0f" min="0" max="100" step="1">10</defNumber></defNumberVector>"#;
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut received = vec![];
+                reader.read_until(b'>', &mut received).unwrap();
+                assert!(received.starts_with(b"<getProperties"));
+                stream.write_all(def.as_bytes()).unwrap();
+
+                let mut received = String::new();
+                while !received.contains("</newNumberVector>") {
+                    let mut tag = vec![];
+                    if reader.read_until(b'>', &mut tag).unwrap() == 0 {
+                        break;
+                    }
+                    received.push_str(&String::from_utf8_lossy(&tag));
+                }
+                if received.contains("</newNumberVector>") {
+                    assert!(received.contains("42"));
+                    // A routine refresh the driver sends before it gets to
+                    // the new value, which isn't the answer.
+                    write!(
+                        stream,
+                        r#"<setNumberVector device="Focuser" name="FOCUS" state="Ok"><oneNumber name="POSITION">10</oneNumber></setNumberVector>"#
+                    )
+                    .unwrap();
+                    thread::sleep(Duration::from_millis(100));
+                    write!(
+                        stream,
+                        r#"<setNumberVector device="Focuser" name="FOCUS" state="Busy"><oneNumber name="POSITION">30</oneNumber></setNumberVector><setNumberVector device="Focuser" name="FOCUS" state="Ok"><oneNumber name="POSITION">42</oneNumber></setNumberVector>"#
+                    )
+                    .unwrap();
+                }
+            }
+        });
+
+        addr
+    }
+
+    fn post(port: u16, path: &str, token: Option<&str>, form: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(stream, "POST {} HTTP/1.1\r\nHost: localhost\r\n", path).unwrap();
+        if let Some(token) = token {
+            write!(stream, "Authorization: Bearer {}\r\n", token).unwrap();
+        }
+        write!(stream, "Content-Length: {}\r\n\r\n{}", form.len(), form).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_set() {
+        let server = fake_server();
+        let port = port();
+        let (aggregator, events) = aggregator();
+        let api = Arc::new(Api::new(
+            Filter::default(),
+            Some("secret".to_string()),
+            aggregator.clone(),
+        ));
+        api.serve(("127.0.0.1", port)).unwrap();
+        aggregator
+            .lock()
+            .unwrap()
+            .connect(&server, &server)
+            .unwrap();
+        // Pass the server's commands on, as the exporter does.
+        {
+            let (aggregator, api) = (aggregator.clone(), api.clone());
+            thread::spawn(move || {
+                for event in events {
+                    if let indi::ServerEvent::Command {
+                        server,
+                        connection,
+                        command: Ok(command),
+                    } = event
+                    {
+                        let mut aggregator = aggregator.lock().unwrap();
+                        if let Ok(Some(param)) = aggregator.update(&server, connection, command) {
+                            api.update(&server, "Focuser", param);
+                        }
+                    }
+                }
+            });
+        }
+        while api
+            .shared
+            .state
+            .lock()
+            .unwrap()
+            .property(&server, "Focuser", "FOCUS")
+            .is_none()
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let path = format!("/devices/{}/Focuser/FOCUS", server);
+
+        let response = post(port, &path, None, "POSITION=42");
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        let response = post(port, &path, Some("guess"), "POSITION=42");
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        let response = post(
+            port,
+            &format!("/devices/{}/Focuser/SPEED", server),
+            Some("secret"),
+            "POSITION=42",
+        );
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let response = post(port, &path, Some("secret"), "POSITION=500");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(response.ends_with("500 is outside POSITION's range of 0 to 100\n"));
+        let response = post(port, &path, Some("secret"), "SPEED=1");
+        assert!(response.ends_with("unknown element SPEED\n"));
+
+        let response = post(port, &path, Some("secret"), "POSITION=42");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains(r#""state": "Ok""#));
+        assert!(response.contains(r#""value": 42"#));
+
+        let api = Api::new(Filter::default(), None, aggregator);
+        let port = self::port();
+        api.serve(("127.0.0.1", port)).unwrap();
+        let response = post(port, &path, Some("secret"), "POSITION=42");
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    }
+
+    #[test]
+    fn test_slow_subscriber() {
+        let mut state = State::default();
+        let (sender, receiver) = mpsc::sync_channel(EVENT_QUEUE);
+        state.subscribers.push(sender);
+        let message = event("delete", &Json::Null);
+        for _ in 0..EVENT_QUEUE {
+            state.send(&message);
+        }
+        assert_eq!(state.subscribers.len(), 1);
+        // One more than it has room for, and it's dropped rather than waited for.
+        state.send(&message);
+        assert!(state.subscribers.is_empty());
+        assert_eq!(receiver.iter().count(), EVENT_QUEUE);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("CCD%20Simulator", false), "CCD Simulator");
+        assert_eq!(decode("a+b%2Bc", true), "a b+c");
+        assert_eq!(decode("a+b", false), "a+b");
+        assert_eq!(decode("100%", false), "100%");
+    }
+}
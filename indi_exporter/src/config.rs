@@ -0,0 +1,388 @@
+//! The exporter's settings, read from a config file and the command line.
+//!
+//! The config file has one `key = value` setting per line:
+//!
+//! ```text
+//! listen = 0.0.0.0:9186
+//! server = observatory-1:7624
+//! server = observatory-2:7624
+//! exclude = *.CONFIG_PROCESS
+//! log_level = warn
+//! prefix = indi
+//! blobs = true
+//! api = 127.0.0.1:9187
+//! api_token = 8f14e45fceea167a
+//! ```
+//!
+//! `server`, `include` and `exclude` may be given more than once.  A `#` at
+//! the start of a line or after whitespace starts a comment, so values such
+//! as tokens may contain `#`.
+use indi::Pattern;
+use log::LevelFilter;
+
+use std::fmt;
+use std::fs;
+use std::io;
+
+pub static USAGE: &str = "usage: indi_exporter [options] [host:port...]
+
+Exports the properties of INDI servers as Prometheus metrics.
+
+options:
+  -c, --config file        read settings from file, with one key = value per
+                           line; keys are the long option names below, with
+                           server for each host:port
+  -l, --listen addr:port   address to serve metrics on (default 0.0.0.0:9186)
+  -i, --include pattern    only export device.property.element values
+                           matching pattern; may be repeated
+  -x, --exclude pattern    don't export values matching pattern; may be
+                           repeated
+      --log-level level    error, warn, info, debug or trace (default info)
+      --prefix prefix      prefix for metric names (default indi)
+      --blobs              ask servers for BLOBs, to count them and measure
+                           their sizes
+      --api addr:port      also serve the properties as JSON on addr:port, at
+                           /devices, and a stream of changes at /events
+      --api-token token    let API clients that send this bearer token set
+                           properties, by POSTing to /devices/server/device/property
+
+Patterns may use the wildcards * and ?, and missing parts match everything.
+Servers default to localhost:7624.";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    Usage(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            ConfigError::Usage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Config {
+    pub listen: String,
+    pub servers: Vec<String>,
+    pub filter: Filter,
+    pub log_level: LevelFilter,
+    pub prefix: String,
+    /// Whether to ask the servers for BLOBs.
+    pub blobs: bool,
+    /// Where to serve the JSON API, if anywhere.
+    pub api: Option<String>,
+    /// The bearer token needed to set properties through the API.  Without
+    /// one, the API is read only.
+    pub api_token: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: "0.0.0.0:9186".to_string(),
+            servers: vec![],
+            filter: Filter::default(),
+            log_level: LevelFilter::Info,
+            prefix: "indi".to_string(),
+            blobs: false,
+            api: None,
+            api_token: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the settings from the command line `args`, and from the config
+    /// file they name.  Settings on the command line win.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Config, ConfigError> {
+        let args: Vec<String> = args.into_iter().collect();
+        let mut config = match args.iter().position(|a| a == "-c" || a == "--config") {
+            Some(i) => match args.get(i + 1) {
+                Some(path) => Config::parse(&fs::read_to_string(path)?)?,
+                None => return Err(ConfigError::Usage("--config needs a file".to_string())),
+            },
+            None => Config::default(),
+        };
+
+        let mut servers = vec![];
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| ConfigError::Usage(format!("{} needs a value", arg)))
+            };
+            match arg.as_str() {
+                "-h" | "--help" => return Err(ConfigError::Usage(String::new())),
+                "-c" | "--config" => {
+                    value()?;
+                }
+                "-l" | "--listen" => config.set("listen", &value()?)?,
+                "-i" | "--include" => config.set("include", &value()?)?,
+                "-x" | "--exclude" => config.set("exclude", &value()?)?,
+                "--log-level" => config.set("log_level", &value()?)?,
+                "--prefix" => config.set("prefix", &value()?)?,
+                "--blobs" => config.set("blobs", "true")?,
+                "--api" => config.set("api", &value()?)?,
+                "--api-token" => config.set("api_token", &value()?)?,
+                _ if arg.starts_with('-') => {
+                    return Err(ConfigError::Usage(format!("unknown option {}", arg)))
+                }
+                _ => servers.push(arg),
+            }
+        }
+        // Servers on the command line replace those in the file.
+        if !servers.is_empty() {
+            config.servers = servers;
+        }
+        if config.servers.is_empty() {
+            config.servers.push("localhost:7624".to_string());
+        }
+        Ok(config)
+    }
+
+    /// Parses a config file.
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let result = match line.split_once('=') {
+                Some((key, value)) => config.set(key.trim(), value.trim()),
+                None => Err(ConfigError::Usage(format!(
+                    "expected key = value, got {:?}",
+                    line
+                ))),
+            };
+            result.map_err(|e| ConfigError::Parse {
+                line: i + 1,
+                message: e.to_string(),
+            })?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "listen" => self.listen = value.to_string(),
+            "server" => self.servers.push(value.to_string()),
+            "include" => self.filter.include.push(Pattern::parse(value)),
+            "exclude" => self.filter.exclude.push(Pattern::parse(value)),
+            "log_level" | "log-level" => {
+                self.log_level = value
+                    .parse()
+                    .map_err(|_| ConfigError::Usage(format!("invalid log level {:?}", value)))?
+            }
+            "prefix" => {
+                let valid = value.chars().enumerate().all(|(i, c)| {
+                    c.is_ascii_alphabetic() || c == '_' || c == ':' || (i > 0 && c.is_ascii_digit())
+                });
+                if value.is_empty() || !valid {
+                    return Err(ConfigError::Usage(format!(
+                        "invalid metric prefix {:?}",
+                        value
+                    )));
+                }
+                self.prefix = value.to_string()
+            }
+            "blobs" => {
+                self.blobs = value
+                    .parse()
+                    .map_err(|_| ConfigError::Usage(format!("invalid boolean {:?}", value)))?
+            }
+            "api" => self.api = Some(value.to_string()),
+            "api_token" | "api-token" => {
+                if value.is_empty() {
+                    return Err(ConfigError::Usage("empty API token".to_string()));
+                }
+                self.api_token = Some(value.to_string())
+            }
+            _ => return Err(ConfigError::Usage(format!("unknown setting {:?}", key))),
+        }
+        Ok(())
+    }
+
+    /// The full name of the metric `name`.
+    pub fn metric(&self, name: &str) -> String {
+        format!("{}_{}", self.prefix, name)
+    }
+}
+
+/// Which values to export.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Filter {
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+}
+
+impl Filter {
+    /// Whether anything from `device`'s `property` is exported.  Exclusions
+    /// that name an element only leave out that element.
+    pub fn property(&self, device: &str, property: &str) -> bool {
+        (self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|p| p.matches_property(device, property)))
+            && !self
+                .exclude
+                .iter()
+                .any(|p| p.element == "*" && p.matches_property(device, property))
+    }
+
+    /// Whether the value of `element` is exported.
+    pub fn element(&self, device: &str, property: &str, element: &str) -> bool {
+        (self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|p| p.matches(device, property, element)))
+            && !self
+                .exclude
+                .iter()
+                .any(|p| p.matches(device, property, element))
+    }
+}
+
+/// `line` without its comment, which starts at a `#` that begins the line or
+/// follows whitespace.
+fn strip_comment(line: &str) -> &str {
+    let mut previous = None;
+    for (i, c) in line.char_indices() {
+        if c == '#' && previous.is_none_or(char::is_whitespace) {
+            return &line[..i];
+        }
+        previous = Some(c);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &str) -> Vec<String> {
+        args.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::from_args(vec![]).unwrap();
+        assert_eq!(config.listen, "0.0.0.0:9186");
+        assert_eq!(config.servers, vec!["localhost:7624".to_string()]);
+        assert_eq!(config.api, None);
+        assert_eq!(config.api_token, None);
+        assert_eq!(
+            config.metric("device_parameter_number"),
+            "indi_device_parameter_number"
+        );
+    }
+
+    #[test]
+    fn test_args() {
+        let config = Config::from_args(args(
+            "-l 127.0.0.1:9999 -i CCD* --exclude *.CONFIG_PROCESS --log-level warn --prefix obs --blobs --api :9187 a:7624 b:7624",
+        ))
+        .unwrap();
+        assert_eq!(config.listen, "127.0.0.1:9999");
+        assert_eq!(
+            config.servers,
+            vec!["a:7624".to_string(), "b:7624".to_string()]
+        );
+        assert_eq!(config.filter.include, vec![Pattern::parse("CCD*")]);
+        assert_eq!(
+            config.filter.exclude,
+            vec![Pattern::parse("*.CONFIG_PROCESS")]
+        );
+        assert_eq!(config.log_level, LevelFilter::Warn);
+        assert_eq!(config.prefix, "obs");
+        assert!(config.blobs);
+        assert_eq!(config.api.as_deref(), Some(":9187"));
+
+        assert!(matches!(
+            Config::from_args(args("--bogus")),
+            Err(ConfigError::Usage(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args("--listen")),
+            Err(ConfigError::Usage(_))
+        ));
+        assert!(Config::from_args(args("--prefix 9lives")).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            "
+            # comment
+            listen = 127.0.0.1:9999
+            server = a:7624   # the first
+            server = b:7624
+            exclude = Telescope*
+            log_level = debug
+            blobs = true
+            api_token = secret
+            ",
+        )
+        .unwrap();
+        assert_eq!(config.listen, "127.0.0.1:9999");
+        assert_eq!(
+            config.servers,
+            vec!["a:7624".to_string(), "b:7624".to_string()]
+        );
+        assert_eq!(config.filter.exclude, vec![Pattern::parse("Telescope*")]);
+        assert_eq!(config.log_level, LevelFilter::Debug);
+        assert!(config.blobs);
+        assert_eq!(config.api_token.as_deref(), Some("secret"));
+
+        let config =
+            Config::parse("api_token = ab#cd # rotated monthly\n#api_token = old").unwrap();
+        assert_eq!(config.api_token.as_deref(), Some("ab#cd"));
+
+        match Config::parse("listen = x\nlisten\n") {
+            Err(ConfigError::Parse { line: 2, .. }) => {}
+            other => panic!("{:?}", other),
+        }
+        assert!(Config::parse("blobs = yes").is_err());
+        match Config::parse("colour = blue") {
+            Err(ConfigError::Parse { line: 1, .. }) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter {
+            include: vec![Pattern::parse("CCD*"), Pattern::parse("Focuser")],
+            exclude: vec![
+                Pattern::parse("*.CCD_INFO"),
+                Pattern::parse("*.CCD_EXPOSURE.CCD_EXPOSURE_VALUE"),
+            ],
+        };
+        assert!(filter.property("CCD Simulator", "CCD_TEMPERATURE"));
+        assert!(filter.property("Focuser", "ABS_FOCUS_POSITION"));
+        assert!(!filter.property("Telescope Simulator", "EQUATORIAL_EOD_COORD"));
+        assert!(!filter.property("CCD Simulator", "CCD_INFO"));
+
+        assert!(filter.property("CCD Simulator", "CCD_EXPOSURE"));
+        assert!(!filter.element("CCD Simulator", "CCD_EXPOSURE", "CCD_EXPOSURE_VALUE"));
+        assert!(filter.element("CCD Simulator", "CCD_TEMPERATURE", "CCD_TEMPERATURE_VALUE"));
+
+        assert!(Filter::default().element("a", "b", "c"));
+    }
+}
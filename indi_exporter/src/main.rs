@@ -1,135 +1,297 @@
-use indi;
+use chrono::Utc;
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
 use std::env;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use prometheus_exporter::{self, prometheus::*};
+use prometheus_exporter::{self, prometheus};
+
+mod api;
+use api::*;
+
+mod config;
+use config::*;
+
+mod metrics;
+use metrics::*;
+
+/// Logs to stderr, at the level set in the config.
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{} {}: {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Logger = Logger;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let addr = match args.len() {
-        2 => args[1].as_str(),
-        _ => "localhost:7624",
+    let config = match Config::from_args(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(ConfigError::Usage(message)) => {
+            if !message.is_empty() {
+                eprintln!("error: {}\n", message);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+        Err(e) => {
+            eprintln!("error: config: {}", e);
+            process::exit(1);
+        }
     };
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(config.log_level);
 
-    let mut connection = indi::Connection::new(addr).unwrap();
-    connection
-        .send(&indi::GetProperties {
-            version: indi::INDI_PROTOCOL_VERSION.to_string(),
-            device: None,
-            name: None,
-        })
-        .unwrap();
-
-    let mut client = indi::Client::new();
-
-    let binding = "0.0.0.0:9186".parse().unwrap();
-    prometheus_exporter::start(binding).unwrap();
-    let gauge = register_gauge_vec!(
-        opts!("indi_device_parameter_number", "help"),
-        &[
-            "device_name",
-            "param_name",
-            "param_label",
-            "value_name",
-            "value_label"
-        ]
-    )
-    .unwrap();
-    let states = register_gauge_vec!(
-        opts!("indi_device_parameter_state", "help"),
-        &["device_name", "param_name", "param_label", "state"]
-    )
-    .unwrap();
-
-    for command in connection.command_iter().unwrap() {
-        match command {
-            Ok(command) => {
-                println!("Command: {:?}", command);
-                let device_name = command.device_name().unwrap().clone();
-                match client.update(command) {
-                    Err(e) => {
-                        println!("error: {:?}", e)
-                    }
-                    Ok(Some(param_enum)) => {
-                        state_metric(&states, &device_name, &param_enum);
-                        match param_enum {
-                            indi::Parameter::NumberVector(param) => {
-                                for (value_name, value) in &param.values {
-                                    gauge
-                                        .with_label_values(&[
-                                            device_name.as_str(),
-                                            param.name.as_str(),
-                                            param
-                                                .label
-                                                .as_ref()
-                                                .unwrap_or(&"".to_string())
-                                                .as_str(),
-                                            value_name.as_str(),
-                                            value
-                                                .label
-                                                .as_ref()
-                                                .unwrap_or(&"".to_string())
-                                                .as_str(),
-                                        ])
-                                        .set(value.value);
-                                }
-                            }
-                            indi::Parameter::SwitchVector(param) => {
-                                for (value_name, value) in &param.values {
-                                    let v = if value.value == indi::SwitchState::On {
-                                        1.0
-                                    } else {
-                                        0.0
-                                    };
-                                    gauge
-                                        .with_label_values(&[
-                                            device_name.as_str(),
-                                            param.name.as_str(),
-                                            param
-                                                .label
-                                                .as_ref()
-                                                .unwrap_or(&"".to_string())
-                                                .as_str(),
-                                            value_name.as_str(),
-                                            value
-                                                .label
-                                                .as_ref()
-                                                .unwrap_or(&"".to_string())
-                                                .as_str(),
-                                        ])
-                                        .set(v);
-                                }
-                            }
-                            _ => {}
+    let binding = match config
+        .listen
+        .to_socket_addrs()
+        .map(|mut addrs| addrs.next())
+    {
+        Ok(Some(binding)) => binding,
+        Ok(None) => {
+            error!("{}: no address to listen on", config.listen);
+            process::exit(1);
+        }
+        Err(e) => {
+            error!("{}: {}", config.listen, e);
+            process::exit(1);
+        }
+    };
+    let exporter = match prometheus_exporter::start(binding) {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            error!("{}: {}", config.listen, e);
+            process::exit(1);
+        }
+    };
+    let mut metrics = match Metrics::new(&config, prometheus::default_registry()) {
+        Ok(metrics) => metrics,
+        Err(e) => {
+            error!("metrics: {}", e);
+            process::exit(1);
+        }
+    };
+    // Shared with the API, which sets properties over the same connections.
+    let (aggregator, events) = indi::Aggregator::new();
+    let aggregator = Arc::new(Mutex::new(aggregator));
+    let api = Api::new(
+        config.filter.clone(),
+        config.api_token.clone(),
+        aggregator.clone(),
+    );
+    if let Some(addr) = &config.api {
+        if let Err(e) = api.serve(addr) {
+            error!("{}: {}", addr, e);
+            process::exit(1);
+        }
+    }
+    let ages = metrics.ages();
+    thread::spawn(move || loop {
+        let _guard = exporter.wait_request();
+        ages.refresh();
+    });
+
+    let mut retries = HashMap::new();
+    for server in &config.servers {
+        connect(&aggregator, &metrics, server, &mut retries);
+    }
+
+    loop {
+        // Checked before every event, so a busy server can't hold up
+        // reconnecting to the others.
+        let now = Instant::now();
+        let due: Vec<String> = retries
+            .iter()
+            .filter(|(_, (due, _))| *due <= now)
+            .map(|(server, _)| server.clone())
+            .collect();
+        for server in due {
+            connect(&aggregator, &metrics, &server, &mut retries);
+        }
+
+        let now = Instant::now();
+        let timeout = retries
+            .values()
+            .map(|(due, _)| due.saturating_duration_since(now))
+            .min()
+            .unwrap_or(IDLE);
+        let event = match events.recv_timeout(timeout) {
+            Ok(event) => event,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        };
+        let mut aggregator = aggregator.lock().unwrap();
+
+        let (server, connection, command) = match event {
+            // Left over from a connection that has since been replaced.
+            indi::ServerEvent::Command {
+                server, connection, ..
+            }
+            | indi::ServerEvent::Disconnected { server, connection }
+                if !aggregator.is_current(&server, connection) =>
+            {
+                continue
+            }
+            indi::ServerEvent::Command {
+                server,
+                connection,
+                command,
+            } => (server, connection, command),
+            indi::ServerEvent::Disconnected { server, connection } => {
+                warn!("{}: disconnected", server);
+                // Stop exporting the values it had, rather than leave them frozen.
+                if let Some(client) = aggregator.get_client(&server) {
+                    for (device_name, device) in client.get_devices() {
+                        for param in device.get_parameters().values() {
+                            metrics.remove(&server, device_name, param);
+                            api.remove(&server, device_name, param.get_name());
                         }
                     }
-                    Ok(None) => {}
+                }
+                let _ = aggregator.disconnect(&server, connection);
+                metrics.up(&server, false);
+                retries.insert(server, (Instant::now() + RETRY, RETRY));
+                continue;
+            }
+        };
+        metrics.received(&server, aggregator.bytes_received(&server));
+        let command = match command {
+            Ok(command) => command,
+            Err(e) => {
+                error!("{}: {}", server, e);
+                metrics.parse_error(&server, &e);
+                continue;
+            }
+        };
+        debug!("{}: {:?}", server, command);
+        metrics.command(&server, &command, Utc::now());
+        let device_name = match command.device_name() {
+            Some(device_name) => device_name.clone(),
+            None => continue,
+        };
+
+        // Servers only send BLOBs to clients that ask for them.
+        if let indi::Command::DefBlobVector(def) = &command {
+            if config.blobs && metrics.exports(&def.device, &def.name) {
+                let enable = indi::EnableBlob {
+                    device: def.device.clone(),
+                    name: Some(def.name.clone()),
+                    enabled: indi::BlobEnable::Also,
+                };
+                if let Err(e) = aggregator.write(&server, &enable) {
+                    error!("{}: {}", server, e);
+                }
+            }
+        }
+        if let indi::Command::DelProperty(del) = &command {
+            let device = aggregator
+                .get_client(&server)
+                .and_then(|client| client.get_devices().get(&del.device));
+            if let Some(device) = device {
+                for param in device.get_parameters().values() {
+                    if del
+                        .name
+                        .as_ref()
+                        .is_none_or(|name| name == param.get_name())
+                    {
+                        metrics.remove(&server, &del.device, param);
+                        api.remove(&server, &del.device, param.get_name());
+                    }
                 }
             }
-            Err(e) => match e {
-                e => println!("error: {:?}", e),
-            },
+        }
+        let received: Vec<String> = match &command {
+            indi::Command::SetBlobVector(set) => set.blobs.iter().map(|b| b.name.clone()).collect(),
+            _ => vec![],
+        };
+
+        match aggregator.update(&server, connection, command) {
+            Ok(Some(param)) => {
+                metrics.update(&server, &device_name, param);
+                api.update(&server, &device_name, param);
+                if let indi::Parameter::BlobVector(param) = param {
+                    metrics.blobs(&server, &device_name, param, &received);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("{}: {}", server, e);
+                metrics.update_error(&server, &device_name, &e);
+            }
         }
     }
 }
 
-fn state_metric(states: &GaugeVec, device_name: &String, param: &indi::Parameter) {
-    for state in &[
-        indi::PropertyState::Idle,
-        indi::PropertyState::Ok,
-        indi::PropertyState::Busy,
-        indi::PropertyState::Alert,
-    ] {
-        states
-            .with_label_values(&[
-                device_name.as_str(),
-                param.get_name().as_str(),
-                param
-                    .get_label()
-                    .as_ref()
-                    .unwrap_or(&"".to_string())
-                    .as_str(),
-                format!("{:?}", state).as_str(),
-            ])
-            .set(if state == param.get_state() { 1.0 } else { 0.0 });
+/// Servers waiting to be connected to, with when to try next and how long the
+/// last wait was.
+type Retries = HashMap<String, (Instant, Duration)>;
+
+/// How long to wait before reconnecting to a server, doubling after each
+/// failure up to `MAX_RETRY`.
+const RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_secs(60);
+/// How long to wait for events when no reconnects are due.
+const IDLE: Duration = Duration::from_secs(3600);
+/// How long to wait for a server to accept a connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Opens a connection to `server`, giving up after `CONNECT_TIMEOUT`.
+fn open(server: &str) -> io::Result<indi::Connection> {
+    let mut error = io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to");
+    for addr in server.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return indi::Connection::from_transport(stream),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+/// Connects to `server`, or schedules another try if it can't be reached.
+/// The aggregator is only locked once the connection is open, so the API
+/// isn't held up by a server that's slow to answer.
+fn connect(
+    aggregator: &Mutex<indi::Aggregator>,
+    metrics: &Metrics,
+    server: &str,
+    retries: &mut Retries,
+) {
+    let result = open(server)
+        .map_err(indi::DeError::from)
+        .and_then(|connection| {
+            aggregator
+                .lock()
+                .unwrap()
+                .add_connection(server, connection)
+        });
+    match result {
+        Ok(()) => {
+            info!("{}: connected", server);
+            metrics.up(server, true);
+            retries.remove(server);
+        }
+        Err(e) => {
+            let wait = retries
+                .get(server)
+                .map_or(RETRY, |(_, wait)| (*wait * 2).min(MAX_RETRY));
+            warn!("{}: {}, retrying in {:?}", server, e, wait);
+            metrics.up(server, false);
+            retries.insert(server.to_string(), (Instant::now() + wait, wait));
+        }
     }
 }
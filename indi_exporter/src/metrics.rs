@@ -0,0 +1,818 @@
+//! The Prometheus metrics exported for INDI properties.
+use crate::config::{Config, Filter};
+
+use chrono::{DateTime, Utc};
+use prometheus_exporter::prometheus::{
+    exponential_buckets, histogram_opts, opts, GaugeVec, HistogramVec, IntCounterVec, Registry,
+    Result,
+};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+static PROPERTY_STATES: [indi::PropertyState; 4] = [
+    indi::PropertyState::Idle,
+    indi::PropertyState::Ok,
+    indi::PropertyState::Busy,
+    indi::PropertyState::Alert,
+];
+
+fn label(label: &Option<String>) -> &str {
+    label.as_deref().unwrap_or("")
+}
+
+/// When each property was last updated, keyed by server, device, property and
+/// property label.
+type Updates = Arc<Mutex<HashMap<[String; 4], Instant>>>;
+
+pub struct Metrics {
+    filter: Filter,
+    up: GaugeVec,
+    numbers: GaugeVec,
+    states: GaugeVec,
+    timestamps: GaugeVec,
+    lights: GaugeVec,
+    texts: GaugeVec,
+    blobs: IntCounterVec,
+    blob_sizes: HistogramVec,
+    blob_times: GaugeVec,
+    ages: Ages,
+    commands: IntCounterVec,
+    messages: IntCounterVec,
+    parse_errors: IntCounterVec,
+    update_errors: IntCounterVec,
+    delays: HistogramVec,
+    bytes: IntCounterVec,
+    /// The byte count last seen for each server, to add the difference to `bytes`.
+    bytes_seen: HashMap<String, u64>,
+    /// The labels of the text series exported for each element, so the series
+    /// can be removed when the text changes.
+    text_series: HashMap<[String; 4], Vec<String>>,
+    /// The formats counted for each BLOB, so that every format's series can be
+    /// removed along with the BLOB.
+    blob_formats: HashMap<[String; 4], HashSet<String>>,
+}
+
+impl Metrics {
+    /// Creates the metrics, named as `config` says, and registers them with `registry`.
+    pub fn new(config: &Config, registry: &Registry) -> Result<Metrics> {
+        let element = [
+            "server",
+            "device_name",
+            "param_name",
+            "param_label",
+            "value_name",
+            "value_label",
+        ];
+        let blob = [
+            "server",
+            "device_name",
+            "param_name",
+            "param_label",
+            "value_name",
+        ];
+        let metrics = Metrics {
+            filter: config.filter.clone(),
+            up: GaugeVec::new(
+                opts!(config.metric("up"), "Whether the server is connected"),
+                &["server"],
+            )?,
+            numbers: GaugeVec::new(
+                opts!(
+                    config.metric("device_parameter_number"),
+                    "Values of number properties, and of switches as 0 or 1"
+                ),
+                &element,
+            )?,
+            states: GaugeVec::new(
+                opts!(
+                    config.metric("device_parameter_state"),
+                    "Property states, 1 for the current state and 0 for the others"
+                ),
+                &[
+                    "server",
+                    "device_name",
+                    "param_name",
+                    "param_label",
+                    "state",
+                ],
+            )?,
+            timestamps: GaugeVec::new(
+                opts!(
+                    config.metric("device_parameter_timestamp_seconds"),
+                    "When the driver says the property last changed, in seconds since \
+                     the Unix epoch"
+                ),
+                &["server", "device_name", "param_name", "param_label"],
+            )?,
+            lights: GaugeVec::new(
+                opts!(
+                    config.metric("device_parameter_light"),
+                    "Light states, 1 for the current state and 0 for the others"
+                ),
+                &[&element[..], &["state"]].concat(),
+            )?,
+            texts: GaugeVec::new(
+                opts!(
+                    config.metric("device_parameter_text"),
+                    "Values of text properties, in the value label"
+                ),
+                &[&element[..], &["value"]].concat(),
+            )?,
+            blobs: IntCounterVec::new(
+                opts!(
+                    config.metric("device_parameter_blob_received_total"),
+                    "BLOBs received"
+                ),
+                &[&blob[..], &["format"]].concat(),
+            )?,
+            blob_sizes: HistogramVec::new(
+                histogram_opts!(
+                    config.metric("device_parameter_blob_size_bytes"),
+                    "Sizes of the BLOBs received",
+                    // 1 KiB to 1 GiB.
+                    exponential_buckets(1024.0, 4.0, 11)?
+                ),
+                &blob,
+            )?,
+            blob_times: GaugeVec::new(
+                opts!(
+                    config.metric("device_parameter_blob_last_received_timestamp_seconds"),
+                    "When the last BLOB was received, in seconds since the Unix epoch"
+                ),
+                &blob,
+            )?,
+            ages: Ages {
+                gauge: GaugeVec::new(
+                    opts!(
+                        config.metric("device_parameter_age_seconds"),
+                        "Seconds since the property was last defined or updated"
+                    ),
+                    &["server", "device_name", "param_name", "param_label"],
+                )?,
+                updated: Arc::new(Mutex::new(HashMap::new())),
+            },
+            commands: IntCounterVec::new(
+                opts!(
+                    config.metric("commands_received_total"),
+                    "Commands received, by device and XML element"
+                ),
+                &["server", "device_name", "command"],
+            )?,
+            messages: IntCounterVec::new(
+                opts!(
+                    config.metric("messages_received_total"),
+                    "Messages received, by device, or with no device for server messages"
+                ),
+                &["server", "device_name"],
+            )?,
+            parse_errors: IntCounterVec::new(
+                opts!(
+                    config.metric("parse_errors_total"),
+                    "Commands that couldn't be parsed, by kind of error"
+                ),
+                &["server", "error"],
+            )?,
+            update_errors: IntCounterVec::new(
+                opts!(
+                    config.metric("update_errors_total"),
+                    "Updates to undefined properties, or to properties of another type"
+                ),
+                &["server", "device_name", "error"],
+            )?,
+            delays: HistogramVec::new(
+                histogram_opts!(
+                    config.metric("update_delay_seconds"),
+                    "Time from the timestamp of an update or message to its arrival, \
+                     including any difference between the server's clock and ours",
+                    // 1 ms to about 4 minutes.
+                    exponential_buckets(0.001, 4.0, 10)?
+                ),
+                &["server"],
+            )?,
+            bytes: IntCounterVec::new(
+                opts!(
+                    config.metric("received_bytes_total"),
+                    "Bytes read from the server"
+                ),
+                &["server"],
+            )?,
+            bytes_seen: HashMap::new(),
+            text_series: HashMap::new(),
+            blob_formats: HashMap::new(),
+        };
+        registry.register(Box::new(metrics.up.clone()))?;
+        registry.register(Box::new(metrics.numbers.clone()))?;
+        registry.register(Box::new(metrics.states.clone()))?;
+        registry.register(Box::new(metrics.timestamps.clone()))?;
+        registry.register(Box::new(metrics.lights.clone()))?;
+        registry.register(Box::new(metrics.texts.clone()))?;
+        registry.register(Box::new(metrics.blobs.clone()))?;
+        registry.register(Box::new(metrics.blob_sizes.clone()))?;
+        registry.register(Box::new(metrics.blob_times.clone()))?;
+        registry.register(Box::new(metrics.ages.gauge.clone()))?;
+        registry.register(Box::new(metrics.commands.clone()))?;
+        registry.register(Box::new(metrics.messages.clone()))?;
+        registry.register(Box::new(metrics.parse_errors.clone()))?;
+        registry.register(Box::new(metrics.update_errors.clone()))?;
+        registry.register(Box::new(metrics.delays.clone()))?;
+        registry.register(Box::new(metrics.bytes.clone()))?;
+        Ok(metrics)
+    }
+
+    /// Whether anything from `device`'s `property` is exported.
+    pub fn exports(&self, device: &str, property: &str) -> bool {
+        self.filter.property(device, property)
+    }
+
+    /// Handle for refreshing the property ages.
+    pub fn ages(&self) -> Ages {
+        self.ages.clone()
+    }
+
+    pub fn up(&self, server: &str, up: bool) {
+        self.up
+            .with_label_values(&[server])
+            .set(if up { 1.0 } else { 0.0 });
+    }
+
+    /// Exports the values of `param`, which has just been defined or updated.
+    pub fn update(&mut self, server: &str, device: &str, param: &indi::Parameter) {
+        if !self.exports(device, param.get_name()) {
+            return;
+        }
+        self.ages.updated.lock().unwrap().insert(
+            [
+                server.to_string(),
+                device.to_string(),
+                param.get_name().clone(),
+                label(param.get_label()).to_string(),
+            ],
+            Instant::now(),
+        );
+        for state in &PROPERTY_STATES {
+            self.states
+                .with_label_values(&[
+                    server,
+                    device,
+                    param.get_name(),
+                    label(param.get_label()),
+                    &format!("{:?}", state),
+                ])
+                .set(if state == param.get_state() { 1.0 } else { 0.0 });
+        }
+        // Scrapes are stamped with the time they happen, so this is the only
+        // way to line a change up with when it actually took place.
+        if let Some(timestamp) = param.get_timestamp() {
+            self.timestamps
+                .with_label_values(&[server, device, param.get_name(), label(param.get_label())])
+                .set(timestamp.timestamp_millis() as f64 / 1000.0);
+        }
+
+        match param {
+            indi::Parameter::NumberVector(param) => {
+                for (name, value) in &param.values {
+                    if self.filter.element(device, &param.name, name) {
+                        self.numbers
+                            .with_label_values(&[
+                                server,
+                                device,
+                                &param.name,
+                                label(&param.label),
+                                name,
+                                label(&value.label),
+                            ])
+                            .set(value.value);
+                    }
+                }
+            }
+            indi::Parameter::SwitchVector(param) => {
+                for (name, value) in &param.values {
+                    if self.filter.element(device, &param.name, name) {
+                        self.numbers
+                            .with_label_values(&[
+                                server,
+                                device,
+                                &param.name,
+                                label(&param.label),
+                                name,
+                                label(&value.label),
+                            ])
+                            .set(if value.value == indi::SwitchState::On {
+                                1.0
+                            } else {
+                                0.0
+                            });
+                    }
+                }
+            }
+            indi::Parameter::LightVector(param) => {
+                for (name, value) in &param.values {
+                    if !self.filter.element(device, &param.name, name) {
+                        continue;
+                    }
+                    for state in &PROPERTY_STATES {
+                        self.lights
+                            .with_label_values(&[
+                                server,
+                                device,
+                                &param.name,
+                                label(&param.label),
+                                name,
+                                label(value.get_label()),
+                                &format!("{:?}", state),
+                            ])
+                            .set(if state == value.get_value() { 1.0 } else { 0.0 });
+                    }
+                }
+            }
+            indi::Parameter::TextVector(param) => {
+                for (name, value) in &param.values {
+                    if !self.filter.element(device, &param.name, name) {
+                        continue;
+                    }
+                    let labels = vec![
+                        server.to_string(),
+                        device.to_string(),
+                        param.name.clone(),
+                        label(&param.label).to_string(),
+                        name.clone(),
+                        label(&value.label).to_string(),
+                        value.value.clone(),
+                    ];
+                    let key = [
+                        server.to_string(),
+                        device.to_string(),
+                        param.name.clone(),
+                        name.clone(),
+                    ];
+                    if let Some(old) = self.text_series.get(&key) {
+                        if *old == labels {
+                            continue;
+                        }
+                        let old: Vec<&str> = old.iter().map(|l| l.as_str()).collect();
+                        let _ = self.texts.remove_label_values(&old);
+                    }
+                    let values: Vec<&str> = labels.iter().map(|l| l.as_str()).collect();
+                    self.texts.with_label_values(&values).set(1.0);
+                    self.text_series.insert(key, labels);
+                }
+            }
+            indi::Parameter::BlobVector(_) => {}
+        }
+    }
+
+    /// Stops exporting `param`, which has been deleted or whose server has gone.
+    pub fn remove(&mut self, server: &str, device: &str, param: &indi::Parameter) {
+        let name = param.get_name().as_str();
+        let param_label = label(param.get_label());
+        {
+            let mut updated = self.ages.updated.lock().unwrap();
+            let key = [server, device, name, param_label].map(String::from);
+            updated.remove(&key);
+            let _ = self
+                .ages
+                .gauge
+                .remove_label_values(&[server, device, name, param_label]);
+        }
+        let _ = self
+            .timestamps
+            .remove_label_values(&[server, device, name, param_label]);
+        for state in &PROPERTY_STATES {
+            let _ = self.states.remove_label_values(&[
+                server,
+                device,
+                name,
+                param_label,
+                &format!("{:?}", state),
+            ]);
+        }
+
+        match param {
+            indi::Parameter::NumberVector(param) => {
+                for (element, value) in &param.values {
+                    let _ = self.numbers.remove_label_values(&[
+                        server,
+                        device,
+                        name,
+                        param_label,
+                        element,
+                        label(&value.label),
+                    ]);
+                }
+            }
+            indi::Parameter::SwitchVector(param) => {
+                for (element, value) in &param.values {
+                    let _ = self.numbers.remove_label_values(&[
+                        server,
+                        device,
+                        name,
+                        param_label,
+                        element,
+                        label(&value.label),
+                    ]);
+                }
+            }
+            indi::Parameter::LightVector(param) => {
+                for (element, value) in &param.values {
+                    for state in &PROPERTY_STATES {
+                        let _ = self.lights.remove_label_values(&[
+                            server,
+                            device,
+                            name,
+                            param_label,
+                            element,
+                            label(value.get_label()),
+                            &format!("{:?}", state),
+                        ]);
+                    }
+                }
+            }
+            indi::Parameter::TextVector(param) => {
+                for element in param.values.keys() {
+                    let key = [server, device, name, element].map(String::from);
+                    if let Some(old) = self.text_series.remove(&key) {
+                        let old: Vec<&str> = old.iter().map(|l| l.as_str()).collect();
+                        let _ = self.texts.remove_label_values(&old);
+                    }
+                }
+            }
+            indi::Parameter::BlobVector(param) => {
+                for element in param.values.keys() {
+                    let labels = [server, device, name, param_label, element];
+                    let key = [server, device, name, element].map(String::from);
+                    for format in self.blob_formats.remove(&key).unwrap_or_default() {
+                        let _ = self
+                            .blobs
+                            .remove_label_values(&[&labels[..], &[&format]].concat());
+                    }
+                    let _ = self.blob_sizes.remove_label_values(&labels);
+                    let _ = self.blob_times.remove_label_values(&labels);
+                }
+            }
+        }
+    }
+
+    /// Counts `command`, which arrived from `server` at `received`.
+    pub fn command(&self, server: &str, command: &indi::Command, received: DateTime<Utc>) {
+        let device = command.device_name().map_or("", |d| d.as_str());
+        self.commands
+            .with_label_values(&[server, device, command.tag()])
+            .inc();
+        if let indi::Command::Message(_) = command {
+            self.messages.with_label_values(&[server, device]).inc();
+        }
+        // Definitions carry the time the property last changed, not when they
+        // were sent.
+        let timestamp = match command {
+            indi::Command::SetTextVector(_)
+            | indi::Command::SetNumberVector(_)
+            | indi::Command::SetSwitchVector(_)
+            | indi::Command::SetLightVector(_)
+            | indi::Command::SetBlobVector(_)
+            | indi::Command::Message(_) => command.timestamp(),
+            _ => None,
+        };
+        if let Some(timestamp) = timestamp {
+            let delay = (received - timestamp)
+                .num_microseconds()
+                .unwrap_or(i64::MAX);
+            self.delays
+                .with_label_values(&[server])
+                .observe(delay as f64 / 1e6);
+        }
+    }
+
+    pub fn parse_error(&self, server: &str, error: &indi::DeError) {
+        self.parse_errors
+            .with_label_values(&[server, error.name()])
+            .inc();
+    }
+
+    pub fn update_error(&self, server: &str, device: &str, error: &indi::UpdateError) {
+        let name = match error {
+            indi::UpdateError::ParameterMissing(_) => "ParameterMissing",
+            indi::UpdateError::ParameterTypeMismatch(_) => "ParameterTypeMismatch",
+        };
+        self.update_errors
+            .with_label_values(&[server, device, name])
+            .inc();
+    }
+
+    /// Records that `total` bytes have now been read from `server`.
+    pub fn received(&mut self, server: &str, total: u64) {
+        let seen = self.bytes_seen.entry(server.to_string()).or_insert(0);
+        self.bytes
+            .with_label_values(&[server])
+            .inc_by(total.saturating_sub(*seen));
+        *seen = total.max(*seen);
+    }
+
+    /// Counts the BLOBs named in `received`, which have just been stored in `param`.
+    pub fn blobs(
+        &mut self,
+        server: &str,
+        device: &str,
+        param: &indi::BlobVector,
+        received: &[String],
+    ) {
+        let timestamp = param
+            .timestamp
+            .map_or_else(|| Utc::now().timestamp_millis(), |t| t.timestamp_millis())
+            as f64
+            / 1000.0;
+        for name in received {
+            if !self.filter.element(device, &param.name, name) {
+                continue;
+            }
+            let blob = match param.values.get(name) {
+                Some(blob) => blob,
+                None => continue,
+            };
+            let labels = [server, device, &param.name, label(&param.label), name];
+            self.blob_formats
+                .entry([server, device, &param.name, name].map(String::from))
+                .or_default()
+                .insert(label(&blob.format).to_string());
+            self.blobs
+                .with_label_values(&[&labels[..], &[label(&blob.format)]].concat())
+                .inc();
+            self.blob_sizes
+                .with_label_values(&labels)
+                .observe(blob.value.as_ref().map_or(0, |v| v.len()) as f64);
+            self.blob_times.with_label_values(&labels).set(timestamp);
+        }
+    }
+}
+
+/// The ages of the exported properties, which are only worked out when the
+/// metrics are scraped.
+#[derive(Clone)]
+pub struct Ages {
+    gauge: GaugeVec,
+    updated: Updates,
+}
+
+impl Ages {
+    pub fn refresh(&self) {
+        let updated = self.updated.lock().unwrap();
+        for (labels, at) in updated.iter() {
+            let labels: Vec<&str> = labels.iter().map(|l| l.as_str()).collect();
+            self.gauge
+                .with_label_values(&labels)
+                .set(at.elapsed().as_secs_f64());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus_exporter::prometheus::proto::MetricFamily;
+
+    fn family<'a>(families: &'a [MetricFamily], name: &str) -> &'a MetricFamily {
+        families
+            .iter()
+            .find(|f| f.get_name() == name)
+            .unwrap_or_else(|| panic!("{} missing", name))
+    }
+
+    fn labels(metric: &prometheus_exporter::prometheus::proto::Metric) -> Vec<(&str, &str)> {
+        metric
+            .get_label()
+            .iter()
+            .map(|l| (l.get_name(), l.get_value()))
+            .collect()
+    }
+
+    fn text_vector(value: &str) -> indi::Parameter {
+        indi::Parameter::TextVector(indi::TextVector {
+            name: "DRIVER_INFO".to_string(),
+            group: None,
+            label: Some("Driver Info".to_string()),
+            state: indi::PropertyState::Idle,
+            perm: indi::PropertyPerm::RO,
+            timeout: None,
+            timestamp: None,
+            values: HashMap::from([(
+                "DRIVER_VERSION".to_string(),
+                indi::Text {
+                    label: Some("Version".to_string()),
+                    value: value.to_string(),
+                },
+            )]),
+        })
+    }
+
+    #[test]
+    fn test_text() {
+        let registry = Registry::new();
+        let mut metrics = Metrics::new(&Config::default(), &registry).unwrap();
+        metrics.update("s", "CCD Simulator", &text_vector("1.0"));
+        metrics.update("s", "CCD Simulator", &text_vector("1.1"));
+
+        let families = registry.gather();
+        let texts = family(&families, "indi_device_parameter_text");
+        assert_eq!(texts.get_metric().len(), 1);
+        let metric = &texts.get_metric()[0];
+        assert!(labels(metric).contains(&("value", "1.1")));
+        assert!(labels(metric).contains(&("value_label", "Version")));
+        assert_eq!(metric.get_gauge().get_value(), 1.0);
+
+        let states = family(&families, "indi_device_parameter_state");
+        assert_eq!(states.get_metric().len(), 4);
+    }
+
+    #[test]
+    fn test_blobs() {
+        let registry = Registry::new();
+        let mut metrics = Metrics::new(&Config::default(), &registry).unwrap();
+        let timestamp = DateTime::parse_from_rfc3339("2022-10-13T07:41:56.301Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut param = indi::BlobVector {
+            name: "CCD1".to_string(),
+            label: Some("Image".to_string()),
+            group: None,
+            state: indi::PropertyState::Ok,
+            perm: indi::PropertyPerm::RO,
+            timeout: None,
+            timestamp: Some(timestamp),
+            enable_status: indi::BlobEnable::Also,
+            values: HashMap::from([(
+                "CCD1".to_string(),
+                indi::Blob {
+                    label: None,
+                    format: Some(".fits".to_string()),
+                    value: Some(vec![0; 5000]),
+                },
+            )]),
+        };
+        metrics.blobs("s", "CCD Simulator", &param, &["CCD1".to_string()]);
+        metrics.blobs("s", "CCD Simulator", &param, &["CCD1".to_string()]);
+
+        let families = registry.gather();
+        let count = &family(&families, "indi_device_parameter_blob_received_total").get_metric()[0];
+        assert_eq!(count.get_counter().get_value(), 2.0);
+        assert!(labels(count).contains(&("format", ".fits")));
+
+        let sizes = &family(&families, "indi_device_parameter_blob_size_bytes").get_metric()[0];
+        assert_eq!(sizes.get_histogram().get_sample_count(), 2);
+        assert_eq!(sizes.get_histogram().get_sample_sum(), 10000.0);
+
+        let time = &family(
+            &families,
+            "indi_device_parameter_blob_last_received_timestamp_seconds",
+        )
+        .get_metric()[0];
+        assert_eq!(time.get_gauge().get_value(), 1665646916.301);
+
+        // Every format's count goes with the BLOB, not just the last one's.
+        if let Some(blob) = param.values.get_mut("CCD1") {
+            blob.format = Some(".jpg".to_string());
+        }
+        metrics.blobs("s", "CCD Simulator", &param, &["CCD1".to_string()]);
+        let families = registry.gather();
+        let counts = family(&families, "indi_device_parameter_blob_received_total");
+        assert_eq!(counts.get_metric().len(), 2);
+
+        metrics.remove("s", "CCD Simulator", &indi::Parameter::BlobVector(param));
+        assert!(registry.gather().is_empty());
+    }
+
+    #[test]
+    fn test_remove() {
+        let registry = Registry::new();
+        let mut metrics = Metrics::new(&Config::default(), &registry).unwrap();
+        let number = indi::Parameter::NumberVector(indi::NumberVector {
+            name: "CCD_TEMPERATURE".to_string(),
+            group: None,
+            label: None,
+            state: indi::PropertyState::Ok,
+            perm: indi::PropertyPerm::RW,
+            timeout: None,
+            timestamp: None,
+            values: HashMap::from([(
+                "CCD_TEMPERATURE_VALUE".to_string(),
+                indi::Number {
+                    label: Some("Temperature".to_string()),
+                    format: "%5.2f".to_string(),
+                    min: -50.0,
+                    max: 50.0,
+                    step: 0.0,
+                    value: -10.0,
+                },
+            )]),
+        });
+        metrics.up("s", true);
+        metrics.update("s", "CCD Simulator", &number);
+        metrics.update("s", "CCD Simulator", &text_vector("1.0"));
+        metrics.ages().refresh();
+        let families = registry.gather();
+        assert_eq!(
+            family(&families, "indi_device_parameter_age_seconds")
+                .get_metric()
+                .len(),
+            2
+        );
+        assert_eq!(
+            family(&families, "indi_device_parameter_number").get_metric()[0]
+                .get_gauge()
+                .get_value(),
+            -10.0
+        );
+
+        metrics.remove("s", "CCD Simulator", &number);
+        metrics.remove("s", "CCD Simulator", &text_vector("1.0"));
+        metrics.ages().refresh();
+        let families = registry.gather();
+        let names: Vec<&str> = families.iter().map(|f| f.get_name()).collect();
+        assert_eq!(names, vec!["indi_up"]);
+    }
+
+    #[test]
+    fn test_operations() {
+        let registry = Registry::new();
+        let mut metrics = Metrics::new(&Config::default(), &registry).unwrap();
+        let sent = DateTime::parse_from_rfc3339("2022-10-13T07:41:56.301Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let message = indi::Command::Message(indi::Message {
+            device: Some("CCD Simulator".to_string()),
+            timestamp: Some(sent),
+            message: Some("Exposure done".to_string()),
+        });
+        metrics.command("s", &message, sent + chrono::Duration::milliseconds(250));
+        metrics.command("s", &message, sent + chrono::Duration::milliseconds(250));
+        metrics.parse_error(
+            "s",
+            &indi::DeError::MissingAttr("name").with_context(Default::default()),
+        );
+        metrics.update_error(
+            "s",
+            "CCD Simulator",
+            &indi::UpdateError::ParameterMissing("CCD_INFO".to_string()),
+        );
+        metrics.received("s", 100);
+        metrics.received("s", 150);
+
+        let families = registry.gather();
+        let commands = &family(&families, "indi_commands_received_total").get_metric()[0];
+        assert!(labels(commands).contains(&("command", "message")));
+        assert_eq!(commands.get_counter().get_value(), 2.0);
+        let messages = &family(&families, "indi_messages_received_total").get_metric()[0];
+        assert!(labels(messages).contains(&("device_name", "CCD Simulator")));
+        assert_eq!(messages.get_counter().get_value(), 2.0);
+
+        let delays = &family(&families, "indi_update_delay_seconds").get_metric()[0];
+        assert_eq!(delays.get_histogram().get_sample_sum(), 0.5);
+
+        let parse_errors = &family(&families, "indi_parse_errors_total").get_metric()[0];
+        assert!(labels(parse_errors).contains(&("error", "MissingAttr")));
+        let update_errors = &family(&families, "indi_update_errors_total").get_metric()[0];
+        assert!(labels(update_errors).contains(&("error", "ParameterMissing")));
+
+        let bytes = &family(&families, "indi_received_bytes_total").get_metric()[0];
+        assert_eq!(bytes.get_counter().get_value(), 150.0);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let registry = Registry::new();
+        let mut metrics = Metrics::new(&Config::default(), &registry).unwrap();
+        let mut param = text_vector("1.0");
+        metrics.update("s", "CCD Simulator", &param);
+        let families = registry.gather();
+        assert!(families
+            .iter()
+            .all(|f| f.get_name() != "indi_device_parameter_timestamp_seconds"));
+
+        if let indi::Parameter::TextVector(param) = &mut param {
+            param.timestamp = Some(
+                DateTime::parse_from_rfc3339("2022-10-13T07:41:56.301Z")
+                    .unwrap()
+                    .with_timezone(&Utc),
+            );
+        }
+        metrics.update("s", "CCD Simulator", &param);
+        let families = registry.gather();
+        let timestamp =
+            &family(&families, "indi_device_parameter_timestamp_seconds").get_metric()[0];
+        assert!(labels(timestamp).contains(&("param_name", "DRIVER_INFO")));
+        assert_eq!(timestamp.get_gauge().get_value(), 1665646916.301);
+
+        metrics.remove("s", "CCD Simulator", &param);
+        assert!(registry.gather().is_empty());
+    }
+
+    #[test]
+    fn test_filter() {
+        let registry = Registry::new();
+        let config =
+            Config::from_args(vec!["-x".to_string(), "*.DRIVER_INFO".to_string()]).unwrap();
+        let mut metrics = Metrics::new(&config, &registry).unwrap();
+        metrics.update("s", "CCD Simulator", &text_vector("1.0"));
+        assert!(registry.gather().is_empty());
+    }
+}
@@ -0,0 +1,388 @@
+//! Records the numbers and switches of an INDI server to an SQLite database,
+//! and exports them again as CSV, for looking back at a night's focuser drift
+//! or cooler behaviour without running Prometheus.
+use chrono::prelude::*;
+use indi::*;
+
+use std::env;
+use std::error::Error;
+use std::io::{self, BufWriter, Write};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+mod store;
+use store::*;
+
+static USAGE: &str = "usage: indi_logger [-d database] <command> [args...]
+
+commands:
+  record [-s host:port]         record every number and switch update,
+                                reconnecting if the server goes away
+  sessions                      list the nights recorded, noon to noon
+  export [-n YYYY-MM-DD] [-f time] [-t time] [pattern...]
+                                print the values recorded on a night, or
+                                between two times, as CSV
+
+Patterns are device.property.element, where each part may use the wildcards
+* and ?, and missing parts match everything.  Times are RFC 3339, or
+YYYY-MM-DD HH:MM[:SS] in local time.  Switches are recorded as 1 for On and
+0 for Off.
+
+options:
+  -d database   database file (default indi.sqlite)";
+
+/// How long to wait before reconnecting to a server that went away.
+const RETRY: Duration = Duration::from_secs(10);
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut path = "indi.sqlite".to_string();
+    let command = loop {
+        match args.next() {
+            Some(arg) if arg == "-d" => path = args.next().unwrap_or_else(|| usage()),
+            Some(arg) if !arg.starts_with('-') => break arg,
+            _ => usage(),
+        }
+    };
+    let args: Vec<String> = args.collect();
+
+    let mut store = Store::open(&path).unwrap_or_else(|e| {
+        eprintln!("error: {}: {}", path, e);
+        process::exit(1);
+    });
+    let result = match command.as_str() {
+        "record" => record(&mut store, &args),
+        "sessions" => sessions(&store),
+        "export" => export(&store, &args),
+        _ => usage(),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn record(store: &mut Store, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let server = match args {
+        [] => "localhost:7624",
+        [flag, server] if flag == "-s" => server.as_str(),
+        _ => usage(),
+    };
+    loop {
+        match record_server(store, server) {
+            Ok(()) => eprintln!("{} disconnected", server),
+            Err(e) => match e.downcast::<rusqlite::Error>() {
+                Ok(e) => return Err(e),
+                Err(e) => eprintln!("{}: {}", server, e),
+            },
+        }
+        eprintln!("reconnecting in {}s", RETRY.as_secs());
+        thread::sleep(RETRY);
+    }
+}
+
+/// Records updates from `server` until it goes away.
+fn record_server(store: &mut Store, server: &str) -> Result<(), Box<dyn Error>> {
+    let mut connection = Connection::new(server)?;
+    connection.write(&GetProperties {
+        version: INDI_PROTOCOL_VERSION.to_string(),
+        device: None,
+        name: None,
+    })?;
+    eprintln!("{} connected", server);
+
+    let mut client = Client::new();
+    for command in connection.iter()? {
+        let command = match command {
+            Ok(command) => command,
+            Err(e) => {
+                eprintln!("{}: {}", server, e);
+                continue;
+            }
+        };
+        let definition = matches!(
+            command,
+            Command::DefNumberVector(_) | Command::DefSwitchVector(_)
+        );
+        let mut records = match records(&mut client, command, Utc::now()) {
+            Ok(records) => records,
+            Err(e) => {
+                eprintln!("{}: {}", server, e);
+                continue;
+            }
+        };
+        // Definitions are sent again on every reconnect, so only the values
+        // that changed while we were away are new.
+        if definition {
+            let mut changed = vec![];
+            for record in records {
+                let latest = store.latest(&record.device, &record.property, &record.element)?;
+                if latest != Some((record.value, record.state.clone())) {
+                    changed.push(record);
+                }
+            }
+            records = changed;
+        }
+        if !records.is_empty() {
+            store.insert(&records)?;
+        }
+    }
+    Ok(())
+}
+
+/// Updates `client` with `command`, and returns the values of the numbers and
+/// switches it carried.  They're timed by the command's timestamp, moved onto
+/// our clock by the server's estimated skew, or by when it was `received` if
+/// it has no timestamp or the skew isn't known yet.
+fn records(
+    client: &mut Client,
+    command: Command,
+    received: DateTime<Utc>,
+) -> Result<Vec<Record>, UpdateError> {
+    let elements: Vec<String> = match &command {
+        Command::DefNumberVector(c) => c.numbers.iter().map(|n| n.name.clone()).collect(),
+        Command::SetNumberVector(c) => c.numbers.iter().map(|n| n.name.clone()).collect(),
+        Command::DefSwitchVector(c) => c.switches.iter().map(|s| s.name.clone()).collect(),
+        Command::SetSwitchVector(c) => c.switches.iter().map(|s| s.name.clone()).collect(),
+        _ => vec![],
+    };
+    let device = command.device_name().cloned().unwrap_or_default();
+    let time = match (command.timestamp(), client.clock_skew()) {
+        (Some(timestamp), Some(skew)) => timestamp - skew,
+        _ => received,
+    };
+    let param = match client.update(command)? {
+        Some(param) => param,
+        None => return Ok(vec![]),
+    };
+    let value = |element: &String| match param {
+        Parameter::NumberVector(p) => p.values.get(element).map(|n| n.value),
+        Parameter::SwitchVector(p) => p.values.get(element).map(|s| match s.value {
+            SwitchState::On => 1.0,
+            SwitchState::Off => 0.0,
+        }),
+        _ => None,
+    };
+    Ok(elements
+        .iter()
+        .filter_map(|element| {
+            Some(Record {
+                time,
+                device: device.clone(),
+                property: param.get_name().clone(),
+                element: element.clone(),
+                value: value(element)?,
+                state: format!("{:?}", param.get_state()),
+            })
+        })
+        .collect())
+}
+
+fn sessions(store: &Store) -> Result<(), Box<dyn Error>> {
+    let time = |time: &DateTime<Utc>| time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+    for session in store.sessions()? {
+        println!(
+            "{}  {} to {}  {} values",
+            session.night,
+            time(&session.first),
+            time(&session.last),
+            session.count
+        );
+    }
+    Ok(())
+}
+
+/// Parses an RFC 3339 time, or a local one without a time zone.
+fn parse_time(text: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+    .and_then(|time| Local.from_local_datetime(&time).earliest())
+    .map(|time| time.with_timezone(&Utc))
+    .ok_or_else(|| format!("invalid time {:?}", text))
+}
+
+/// Quotes a CSV field if it needs it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn export(store: &Store, args: &[String]) -> Result<(), Box<dyn Error>> {
+    // Stored times sort as text, which only holds for four digit years.
+    let mut from = Utc.timestamp_opt(0, 0).unwrap();
+    let mut to = Utc.with_ymd_and_hms(9999, 12, 31, 23, 59, 59).unwrap();
+    let mut patterns = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "-n" => {
+                let night = value();
+                let date = NaiveDate::parse_from_str(night, "%Y-%m-%d")
+                    .map_err(|_| format!("invalid night {:?}", night))?;
+                (from, to) = night_range(date, &Local).ok_or("invalid night")?;
+            }
+            "-f" => from = parse_time(value())?,
+            "-t" => to = parse_time(value())?,
+            _ if arg.starts_with('-') => usage(),
+            _ => patterns.push(Pattern::parse(arg)),
+        }
+    }
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    writeln!(out, "time,device,property,element,value,state")?;
+    store.query(&from, &to, |record| {
+        if !patterns.is_empty()
+            && !patterns
+                .iter()
+                .any(|p| p.matches(&record.device, &record.property, &record.element))
+        {
+            return Ok(());
+        }
+        writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            record.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            csv_field(&record.device),
+            csv_field(&record.property),
+            csv_field(&record.element),
+            record.value,
+            record.state
+        )
+    })?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records() {
+        let xml = r#"<defNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" state="Busy" perm="rw">
+    <defNumber name="CCD_TEMPERATURE_VALUE" format="%5.2f" min="-50" max="50" step="0">20</defNumber>
+</defNumberVector>
+<defSwitchVector device="CCD Simulator" name="CONNECTION" state="Ok" perm="rw" rule="OneOfMany">
+    <defSwitch name="CONNECT">On</defSwitch>
+    <defSwitch name="DISCONNECT">Off</defSwitch>
+</defSwitchVector>
+<defTextVector device="CCD Simulator" name="DRIVER_INFO" state="Ok" perm="ro">
+    <defText name="DRIVER_NAME">CCD Simulator</defText>
+</defTextVector>
+<setNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" state="Ok">
+    <oneNumber name="CCD_TEMPERATURE_VALUE">-10</oneNumber>
+</setNumberVector>
+"#;
+        let mut client = Client::new();
+        let time = Utc::now();
+        let records: Vec<Vec<Record>> = CommandIter::from_xml(xml)
+            .map(|command| records(&mut client, command.unwrap(), time).unwrap())
+            .collect();
+
+        let record = |property: &str, element: &str, value, state: &str| Record {
+            time,
+            device: "CCD Simulator".to_string(),
+            property: property.to_string(),
+            element: element.to_string(),
+            value,
+            state: state.to_string(),
+        };
+        assert_eq!(
+            records,
+            vec![
+                vec![record(
+                    "CCD_TEMPERATURE",
+                    "CCD_TEMPERATURE_VALUE",
+                    20.0,
+                    "Busy"
+                )],
+                vec![
+                    record("CONNECTION", "CONNECT", 1.0, "Ok"),
+                    record("CONNECTION", "DISCONNECT", 0.0, "Ok"),
+                ],
+                vec![],
+                vec![record(
+                    "CCD_TEMPERATURE",
+                    "CCD_TEMPERATURE_VALUE",
+                    -10.0,
+                    "Ok"
+                )],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let xml = r#"<defNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" state="Busy" perm="rw" timestamp="2026-10-18T20:00:00">
+    <defNumber name="CCD_TEMPERATURE_VALUE" format="%5.2f" min="-50" max="50" step="0">20</defNumber>
+</defNumberVector>
+<setNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" state="Ok" timestamp="2026-10-18T20:00:00">
+    <oneNumber name="CCD_TEMPERATURE_VALUE">19</oneNumber>
+</setNumberVector>
+<setNumberVector device="CCD Simulator" name="CCD_TEMPERATURE" state="Ok" timestamp="2026-10-18T20:00:10">
+    <oneNumber name="CCD_TEMPERATURE_VALUE">18</oneNumber>
+</setNumberVector>
+"#;
+        let mut client = Client::new();
+        let received = Utc::now() - chrono::Duration::hours(1);
+        let mut commands = CommandIter::from_xml(xml).map(|command| command.unwrap());
+        let mut time = |client: &mut Client| {
+            records(client, commands.next().unwrap(), received).unwrap()[0].time
+        };
+
+        // Until the server's clock has been seen, only the time received is known.
+        assert_eq!(time(&mut client), received);
+        let before = Utc::now();
+        assert_eq!(time(&mut client), received);
+        let after = Utc::now();
+        // Then the server's timestamps are moved onto our clock.
+        let ten = chrono::Duration::seconds(10);
+        let time = time(&mut client);
+        assert!(before + ten <= time && time <= after + ten, "{}", time);
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time("2026-10-18T20:00:00+02:00"),
+            Ok("2026-10-18T18:00:00Z".parse().unwrap())
+        );
+        let local = Local
+            .with_ymd_and_hms(2026, 10, 18, 20, 30, 0)
+            .earliest()
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(parse_time("2026-10-18 20:30"), Ok(local));
+        assert_eq!(parse_time("2026-10-18T20:30:00"), Ok(local));
+        assert_eq!(
+            parse_time("yesterday"),
+            Err("invalid time \"yesterday\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("CCD Simulator"), "CCD Simulator");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -0,0 +1,272 @@
+//! The SQLite database of recorded values: one row per element per update.
+use chrono::prelude::*;
+use rusqlite::params;
+
+use std::error::Error;
+use std::io;
+use std::path::Path;
+
+/// The value of one element at one update.  Switches are 1 for On and 0 for
+/// Off.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub time: DateTime<Utc>,
+    pub device: String,
+    pub property: String,
+    pub element: String,
+    pub value: f64,
+    pub state: String,
+}
+
+/// A night's recording, noon to noon local time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub night: NaiveDate,
+    pub first: DateTime<Utc>,
+    pub last: DateTime<Utc>,
+    pub count: u64,
+}
+
+pub struct Store {
+    db: rusqlite::Connection,
+}
+
+// Times are stored as RFC 3339 in UTC with a fixed number of digits, so that
+// they sort as text.
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn parse_time(index: usize, text: &str) -> rusqlite::Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, e.into())
+        })
+}
+
+/// The night `time` falls in, where nights run from noon to noon in `tz`.
+pub fn night<Tz: TimeZone>(time: &DateTime<Utc>, tz: &Tz) -> NaiveDate {
+    (time.with_timezone(tz) - chrono::Duration::hours(12)).date_naive()
+}
+
+/// When the night of `date` starts and ends in `tz`.
+pub fn night_range<Tz: TimeZone>(
+    date: NaiveDate,
+    tz: &Tz,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let noon = |date: NaiveDate| {
+        tz.from_local_datetime(&date.and_hms_opt(12, 0, 0)?)
+            .earliest()
+            .map(|time| time.with_timezone(&Utc))
+    };
+    Some((noon(date)?, noon(date.succ_opt()?)?))
+}
+
+impl Store {
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Store> {
+        let db = rusqlite::Connection::open(path)?;
+        db.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS updates (
+                 time TEXT NOT NULL,
+                 device TEXT NOT NULL,
+                 property TEXT NOT NULL,
+                 element TEXT NOT NULL,
+                 value REAL NOT NULL,
+                 state TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS updates_time ON updates (time);
+             CREATE INDEX IF NOT EXISTS updates_element
+                 ON updates (device, property, element, time);",
+        )?;
+        Ok(Store { db })
+    }
+
+    /// Records the values from one update together.
+    pub fn insert(&mut self, records: &[Record]) -> rusqlite::Result<()> {
+        let tx = self.db.transaction()?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO updates (time, device, property, element, value, state)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for record in records {
+                insert.execute(params![
+                    format_time(&record.time),
+                    record.device,
+                    record.property,
+                    record.element,
+                    record.value,
+                    record.state,
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// The last value and state recorded for an element, if any.
+    pub fn latest(
+        &self,
+        device: &str,
+        property: &str,
+        element: &str,
+    ) -> rusqlite::Result<Option<(f64, String)>> {
+        let mut select = self.db.prepare_cached(
+            "SELECT value, state FROM updates
+             WHERE device = ?1 AND property = ?2 AND element = ?3
+             ORDER BY time DESC, rowid DESC LIMIT 1",
+        )?;
+        let mut rows = select.query(params![device, property, element])?;
+        match rows.next()? {
+            Some(row) => Ok(Some((row.get(0)?, row.get(1)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Passes the records from `from` up to `to` to `f`, in order.
+    pub fn query(
+        &self,
+        from: &DateTime<Utc>,
+        to: &DateTime<Utc>,
+        mut f: impl FnMut(Record) -> io::Result<()>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut select = self.db.prepare(
+            "SELECT time, device, property, element, value, state FROM updates
+             WHERE time >= ?1 AND time < ?2 ORDER BY time, rowid",
+        )?;
+        let mut rows = select.query(params![format_time(from), format_time(to)])?;
+        while let Some(row) = rows.next()? {
+            f(Record {
+                time: parse_time(0, &row.get::<_, String>(0)?)?,
+                device: row.get(1)?,
+                property: row.get(2)?,
+                element: row.get(3)?,
+                value: row.get(4)?,
+                state: row.get(5)?,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The nights with recordings, in local time.
+    pub fn sessions(&self) -> rusqlite::Result<Vec<Session>> {
+        let mut select = self.db.prepare(
+            "SELECT min(time), max(time), count(*) FROM updates
+             GROUP BY date(time, 'localtime', '-12 hours') ORDER BY min(time)",
+        )?;
+        let rows = select.query_map([], |row| {
+            let first = parse_time(0, &row.get::<_, String>(0)?)?;
+            Ok(Session {
+                night: night(&first, &Local),
+                first,
+                last: parse_time(1, &row.get::<_, String>(1)?)?,
+                count: row.get(2)?,
+            })
+        })?;
+        rows.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(time: &str, element: &str, value: f64) -> Record {
+        Record {
+            time: DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .with_timezone(&Utc),
+            device: "Focuser Simulator".to_string(),
+            property: "ABS_FOCUS_POSITION".to_string(),
+            element: element.to_string(),
+            value,
+            state: "Ok".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_store() {
+        let mut store = Store::open(":memory:").unwrap();
+        store
+            .insert(&[
+                record("2026-10-18T20:00:00Z", "FOCUS_ABSOLUTE_POSITION", 100.0),
+                record("2026-10-18T20:00:00.5Z", "FOCUS_ABSOLUTE_POSITION", 101.0),
+            ])
+            .unwrap();
+        store
+            .insert(&[record(
+                "2026-10-25T20:00:00Z",
+                "FOCUS_ABSOLUTE_POSITION",
+                90.0,
+            )])
+            .unwrap();
+
+        let mut records = vec![];
+        let from = "2026-10-18T20:00:00.5Z".parse().unwrap();
+        let to = "2026-10-25T20:00:00Z".parse().unwrap();
+        store
+            .query(&from, &to, |record| {
+                records.push(record);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            records,
+            vec![record(
+                "2026-10-18T20:00:00.5Z",
+                "FOCUS_ABSOLUTE_POSITION",
+                101.0
+            )]
+        );
+
+        assert_eq!(
+            store
+                .latest(
+                    "Focuser Simulator",
+                    "ABS_FOCUS_POSITION",
+                    "FOCUS_ABSOLUTE_POSITION"
+                )
+                .unwrap(),
+            Some((90.0, "Ok".to_string()))
+        );
+        assert_eq!(
+            store
+                .latest(
+                    "Focuser Simulator",
+                    "ABS_FOCUS_POSITION",
+                    "FOCUS_RELATIVE_POSITION"
+                )
+                .unwrap(),
+            None
+        );
+
+        let sessions = store.sessions().unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].count, 2);
+        assert_eq!(
+            sessions[0].first,
+            records[0].time - chrono::Duration::milliseconds(500)
+        );
+        assert_eq!(sessions[0].last, records[0].time);
+        assert_eq!(sessions[1].count, 1);
+    }
+
+    #[test]
+    fn test_night() {
+        let tz = FixedOffset::east_opt(-7 * 3600).unwrap();
+        let date = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let time = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(night(&time("2026-10-19T06:00:00Z"), &tz), date);
+        assert_eq!(night(&time("2026-10-19T18:59:59Z"), &tz), date);
+        assert_eq!(
+            night(&time("2026-10-18T18:59:59Z"), &tz),
+            date.pred_opt().unwrap()
+        );
+        assert_eq!(
+            night_range(date, &tz),
+            Some((time("2026-10-18T19:00:00Z"), time("2026-10-19T19:00:00Z")))
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! Maps INDI properties to MQTT topics, and messages on `set` topics back to
+//! INDI commands.
+//!
+//! Device, property and element names are escaped to make single topic
+//! levels: `/`, `+`, `#` and NUL would change the topic's structure or make it
+//! invalid, so they are percent-encoded along with `%` itself.  Levels the
+//! bridge publishes itself, such as a property's `_state`, start with `_`, so
+//! a leading `_` in a name is encoded too.
+use std::collections::HashMap;
+use std::error::Error;
+
+/// A retained message to publish: (topic, payload).
+pub type Message = (String, String);
+
+pub struct Bridge {
+    prefix: String,
+    client: indi::Client,
+    /// The payload last published on each topic, so that unchanged values
+    /// aren't published again.
+    published: HashMap<String, String>,
+}
+
+/// The leaf a property's state is published on.
+const STATE: &str = "_state";
+/// The level after an element's topic that its new values are sent to.  It's
+/// one level deeper than any topic the bridge publishes, so it can't collide
+/// with a name.
+const SET: &str = "set";
+
+/// Escapes a name for use as one topic level.
+fn escape(name: &str) -> String {
+    let mut level = String::with_capacity(name.len());
+    for (i, c) in name.chars().enumerate() {
+        match c {
+            '%' | '/' | '+' | '#' | '\0' => level.push_str(&format!("%{:02X}", c as u32)),
+            '_' if i == 0 => level.push_str("%5F"),
+            c => level.push(c),
+        }
+    }
+    level
+}
+
+/// Turns a topic level back into the name it was escaped from.
+fn unescape(level: &str) -> String {
+    let bytes = level.as_bytes();
+    let mut name = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = level
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                name.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                name.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&name).into_owned()
+}
+
+fn topic(prefix: &str, device: &str, property: &str, leaf: &str) -> String {
+    format!(
+        "{}/{}/{}/{}",
+        prefix,
+        escape(device),
+        escape(property),
+        leaf
+    )
+}
+
+/// The payloads for the elements of `param`.  BLOBs aren't published.
+fn values(param: &indi::Parameter) -> Vec<(&String, String)> {
+    match param {
+        indi::Parameter::TextVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, v.value.clone()))
+            .collect(),
+        indi::Parameter::NumberVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, v.value.to_string()))
+            .collect(),
+        indi::Parameter::SwitchVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, format!("{:?}", v.value)))
+            .collect(),
+        indi::Parameter::LightVector(p) => p
+            .values
+            .iter()
+            .map(|(name, v)| (name, format!("{:?}", v.value)))
+            .collect(),
+        indi::Parameter::BlobVector(_) => vec![],
+    }
+}
+
+impl Bridge {
+    /// Creates a bridge publishing under the topic `prefix`.
+    pub fn new(prefix: &str) -> Bridge {
+        Bridge {
+            prefix: prefix.to_string(),
+            client: indi::Client::new(),
+            published: HashMap::new(),
+        }
+    }
+
+    /// The topic filter matching the messages that set elements.
+    pub fn subscription(&self) -> String {
+        format!("{}/+/+/+/{}", self.prefix, SET)
+    }
+
+    /// Updates the devices with `command` from the server, and returns the
+    /// messages that bring the topics up to date.
+    pub fn update(&mut self, command: indi::Command) -> Result<Vec<Message>, indi::UpdateError> {
+        let device = match command.device_name() {
+            Some(device) => device.clone(),
+            None => return Ok(vec![]),
+        };
+        if let indi::Command::DelProperty(del) = &command {
+            let deleted = match &del.name {
+                Some(name) => topic(&self.prefix, &device, name, ""),
+                None => format!("{}/{}/", self.prefix, escape(&device)),
+            };
+            let cleared: Vec<String> = self
+                .published
+                .keys()
+                .filter(|topic| topic.starts_with(&deleted))
+                .cloned()
+                .collect();
+            for topic in &cleared {
+                self.published.remove(topic);
+            }
+            self.client.update(command)?;
+            // An empty retained message clears the topic.
+            return Ok(cleared.into_iter().map(|t| (t, String::new())).collect());
+        }
+
+        let param = match self.client.update(command)? {
+            Some(param) => param,
+            None => return Ok(vec![]),
+        };
+        let name = param.get_name();
+        let mut messages = vec![(
+            topic(&self.prefix, &device, name, STATE),
+            format!("{:?}", param.get_state()),
+        )];
+        for (element, value) in values(param) {
+            messages.push((topic(&self.prefix, &device, name, &escape(element)), value));
+        }
+        messages.retain(|(topic, payload)| {
+            self.published
+                .insert(topic.clone(), payload.clone())
+                .as_ref()
+                != Some(payload)
+        });
+        Ok(messages)
+    }
+
+    /// Turns a message on a `set` topic into the command that makes the
+    /// change, after checking it against the property's definition.
+    pub fn set(&self, topic: &str, payload: &str) -> Result<indi::Command, Box<dyn Error>> {
+        let parts: Vec<&str> = topic
+            .strip_prefix(self.prefix.as_str())
+            .and_then(|t| t.strip_prefix('/'))
+            .map_or(vec![], |t| t.split('/').collect());
+        let (device, property, element) = match parts.as_slice() {
+            [device, property, element, SET] => {
+                (unescape(device), unescape(property), unescape(element))
+            }
+            _ => return Err(format!("unexpected topic {}", topic).into()),
+        };
+        Ok(self
+            .client
+            .new_vector_from_str(&device, &property, &[(&element, payload)])?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(xml: &str) -> Vec<indi::Command> {
+        indi::CommandIter::from_xml(xml)
+            .map(|c| c.unwrap())
+            .collect()
+    }
+
+    fn bridge() -> Bridge {
+        let mut bridge = Bridge::new("indi");
+        for command in commands(
+            r#"
+<defNumberVector device="Focuser" name="FOCUS" state="Idle" perm="rw">
+    <defNumber name="POSITION" format="%5.0f" min="0" max="100" step="1">10</defNumber>
+</defNumberVector>
+<defSwitchVector device="Focuser" name="CONNECTION" state="Ok" perm="rw" rule="OneOfMany">
+    <defSwitch name="CONNECT">On</defSwitch>
+    <defSwitch name="DISCONNECT">Off</defSwitch>
+</defSwitchVector>
+<defLightVector device="Focuser" name="STATUS" state="Idle">
+    <defLight name="MOVING">Idle</defLight>
+</defLightVector>
+"#,
+        ) {
+            bridge.update(command).unwrap();
+        }
+        bridge
+    }
+
+    #[test]
+    fn test_update() {
+        let mut bridge = Bridge::new("indi");
+        let mut messages = vec![];
+        for command in commands(
+            r#"
+<defNumberVector device="Focuser" name="FOCUS" state="Idle" perm="rw">
+    <defNumber name="POSITION" format="%5.0f" min="0" max="100" step="1">10</defNumber>
+    <defNumber name="SPEED" format="%5.0f" min="0" max="10" step="1">1</defNumber>
+</defNumberVector>
+<setNumberVector device="Focuser" name="FOCUS" state="Busy">
+    <oneNumber name="POSITION">20</oneNumber>
+</setNumberVector>
+"#,
+        ) {
+            messages.push(bridge.update(command).unwrap());
+        }
+        messages[0].sort();
+        assert_eq!(
+            messages[0],
+            vec![
+                ("indi/Focuser/FOCUS/POSITION".to_string(), "10".to_string()),
+                ("indi/Focuser/FOCUS/SPEED".to_string(), "1".to_string()),
+                ("indi/Focuser/FOCUS/_state".to_string(), "Idle".to_string()),
+            ]
+        );
+        // Only what changed is published again.
+        assert_eq!(
+            messages[1],
+            vec![
+                ("indi/Focuser/FOCUS/_state".to_string(), "Busy".to_string()),
+                ("indi/Focuser/FOCUS/POSITION".to_string(), "20".to_string()),
+            ]
+        );
+
+        let mut cleared = bridge
+            .update(commands(r#"<delProperty device="Focuser" name="FOCUS"/>"#).remove(0))
+            .unwrap();
+        cleared.sort();
+        assert_eq!(cleared.len(), 3);
+        assert_eq!(
+            cleared[0],
+            ("indi/Focuser/FOCUS/POSITION".to_string(), String::new())
+        );
+    }
+
+    #[test]
+    fn test_set() {
+        let bridge = bridge();
+        assert_eq!(bridge.subscription(), "indi/+/+/+/set");
+
+        match bridge.set("indi/Focuser/FOCUS/POSITION/set", "42").unwrap() {
+            indi::Command::NewNumberVector(command) => {
+                assert_eq!(command.device, "Focuser");
+                assert_eq!(command.numbers[0].name, "POSITION");
+                assert_eq!(command.numbers[0].value, 42.0);
+            }
+            other => panic!("{:?}", other),
+        }
+        match bridge
+            .set("indi/Focuser/CONNECTION/DISCONNECT/set", "On")
+            .unwrap()
+        {
+            indi::Command::NewSwitchVector(command) => {
+                assert_eq!(command.switches[0].value, indi::SwitchState::On)
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let error = |topic, payload| bridge.set(topic, payload).unwrap_err().to_string();
+        assert_eq!(
+            error("indi/Focuser/FOCUS/POSITION/set", "500"),
+            "500 is outside POSITION's range of 0 to 100"
+        );
+        assert_eq!(
+            error("indi/Focuser/FOCUS/POSITION/set", "far"),
+            "expected a number for POSITION, not \"far\""
+        );
+        assert_eq!(
+            error("indi/Focuser/CONNECTION/CONNECT/set", "yes"),
+            "expected On or Off for CONNECT, not \"yes\""
+        );
+        assert_eq!(
+            error("indi/Focuser/STATUS/MOVING/set", "Ok"),
+            "light vector STATUS can't be set"
+        );
+        assert_eq!(
+            error("indi/Mount/FOCUS/POSITION/set", "1"),
+            "unknown device Mount"
+        );
+        assert_eq!(
+            error("other/Focuser/FOCUS/POSITION/set", "1"),
+            "unexpected topic other/Focuser/FOCUS/POSITION/set"
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("Rig 1/2 #3 +"), "Rig 1%2F2 %233 %2B");
+        assert_eq!(escape("_state"), "%5Fstate");
+        assert_eq!(escape("MY_STATE"), "MY_STATE");
+        assert_eq!(escape("100%"), "100%25");
+        for name in ["Rig 1/2 #3 +", "_state", "100%", "%2F", "a\0b"] {
+            assert_eq!(unescape(&escape(name)), name);
+        }
+
+        let mut bridge = Bridge::new("indi");
+        let mut messages = vec![];
+        for command in commands(
+            r##"
+<defSwitchVector device="Pier/2" name="MODE" state="Ok" perm="rw" rule="OneOfMany">
+    <defSwitch name="_state">On</defSwitch>
+    <defSwitch name="#1">Off</defSwitch>
+</defSwitchVector>
+"##,
+        ) {
+            messages.extend(bridge.update(command).unwrap());
+        }
+        messages.sort();
+        assert_eq!(
+            messages,
+            vec![
+                ("indi/Pier%2F2/MODE/%231".to_string(), "Off".to_string()),
+                ("indi/Pier%2F2/MODE/%5Fstate".to_string(), "On".to_string()),
+                ("indi/Pier%2F2/MODE/_state".to_string(), "Ok".to_string()),
+            ]
+        );
+        match bridge.set("indi/Pier%2F2/MODE/%231/set", "On").unwrap() {
+            indi::Command::NewSwitchVector(command) => {
+                assert_eq!(command.device, "Pier/2");
+                assert_eq!(command.switches[0].name, "#1");
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
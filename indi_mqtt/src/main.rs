@@ -0,0 +1,273 @@
+//! A bridge between an INDI server and an MQTT broker, for home automation
+//! systems that speak MQTT rather than INDI.
+use indi::*;
+
+use std::env;
+use std::error::Error;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod bridge;
+mod mqtt;
+use bridge::*;
+
+static USAGE: &str = "usage: indi_mqtt [options]
+
+Publishes the properties of an INDI server to an MQTT broker as retained
+messages: each element's value on <prefix>/<device>/<property>/<element>, and
+the property's state on <prefix>/<device>/<property>/_state.  A message on
+<prefix>/<device>/<property>/<element>/set changes the element.  The bridge
+publishes online or offline on <prefix>/status.
+
+In names, /, +, #, % and a leading _ are percent-encoded, as %2F, %2B, %23,
+%25 and %5F.
+
+options:
+  -s host:port     INDI server (default localhost:7624)
+  -b host:port     MQTT broker (default localhost:1883)
+  --prefix prefix  topic prefix (default indi)
+  --client-id id   MQTT client identifier (default indi_mqtt)
+  -u user          broker user name
+  -P password      broker password";
+
+/// How long the broker waits to hear from us before publishing our will.
+const KEEP_ALIVE: u16 = 60;
+
+enum Event {
+    Indi(Result<Command, DeError>),
+    Mqtt(mqtt::Packet),
+    Closed(String),
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut server = "localhost:7624".to_string();
+    let mut broker = "localhost:1883".to_string();
+    let mut prefix = "indi".to_string();
+    let mut connect = mqtt::Connect {
+        client_id: "indi_mqtt".to_string(),
+        keep_alive: KEEP_ALIVE,
+        ..Default::default()
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-s" | "--server" => server = args.next().unwrap_or_else(|| usage()),
+            "-b" | "--broker" => broker = args.next().unwrap_or_else(|| usage()),
+            "--prefix" => prefix = args.next().unwrap_or_else(|| usage()),
+            "--client-id" => connect.client_id = args.next().unwrap_or_else(|| usage()),
+            "-u" | "--user" => connect.username = Some(args.next().unwrap_or_else(|| usage())),
+            "-P" | "--password" => connect.password = Some(args.next().unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+    let prefix = prefix.trim_end_matches('/');
+    if prefix.is_empty() || prefix.contains(['+', '#']) {
+        eprintln!("error: invalid topic prefix {:?}", prefix);
+        process::exit(1);
+    }
+
+    if let Err(e) = run(&server, &broker, prefix, connect) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+/// Bridges `server` and `broker` until either goes away.
+fn run(
+    server: &str,
+    broker: &str,
+    prefix: &str,
+    mut connect: mqtt::Connect,
+) -> Result<(), Box<dyn Error>> {
+    let mut connection = Connection::new(server)?;
+    connection.write(&GetProperties {
+        version: INDI_PROTOCOL_VERSION.to_string(),
+        device: None,
+        name: None,
+    })?;
+
+    let status = format!("{}/status", prefix);
+    connect.will = Some((status.clone(), "offline".to_string()));
+    let mut client = mqtt::Client::connect(broker, &connect)?;
+    client.publish(&status, b"online", true)?;
+    let mut bridge = Bridge::new(prefix);
+    client.subscribe(&bridge.subscription())?;
+
+    let (sender, events) = mpsc::channel();
+    {
+        let sender = sender.clone();
+        let commands = connection.iter()?;
+        let server = server.to_string();
+        thread::spawn(move || {
+            for command in commands {
+                if sender.send(Event::Indi(command)).is_err() {
+                    return;
+                }
+            }
+            let _ = sender.send(Event::Closed(format!("{} disconnected", server)));
+        });
+    }
+    {
+        let mut reader = client.reader()?;
+        thread::spawn(move || loop {
+            match mqtt::read(&mut reader) {
+                Ok(packet) => {
+                    if sender.send(Event::Mqtt(packet)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    let _ = sender.send(Event::Closed(format!("broker: {}", e)));
+                    return;
+                }
+            }
+        });
+    }
+
+    let ping = Duration::from_secs(KEEP_ALIVE as u64 / 2);
+    let mut last_sent = Instant::now();
+    loop {
+        if last_sent.elapsed() >= ping {
+            client.ping()?;
+            last_sent = Instant::now();
+        }
+        let event = match events.recv_timeout(ping.saturating_sub(last_sent.elapsed())) {
+            Ok(event) => event,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err("lost the server and the broker".into())
+            }
+        };
+        match event {
+            Event::Indi(Ok(command)) => match bridge.update(command) {
+                Ok(messages) => {
+                    for (topic, payload) in messages {
+                        client.publish(&topic, payload.as_bytes(), true)?;
+                        last_sent = Instant::now();
+                    }
+                }
+                Err(e) => eprintln!("{}: {}", server, e),
+            },
+            Event::Indi(Err(e)) => eprintln!("{}: {}", server, e),
+            // Retained messages on set topics are old requests, not new ones.
+            Event::Mqtt(mqtt::Packet::Publish { retain: true, .. }) => {}
+            Event::Mqtt(mqtt::Packet::Publish { topic, payload, .. }) => {
+                match bridge.set(&topic, &String::from_utf8_lossy(&payload)) {
+                    Ok(command) => connection.write(&command)?,
+                    Err(e) => eprintln!("{}: {}", topic, e),
+                }
+            }
+            Event::Mqtt(_) => {}
+            Event::Closed(reason) => {
+                let _ = client.publish(&status, b"offline", true);
+                let _ = client.disconnect();
+                return Err(reason.into());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn publish(topic: &str, payload: &str) -> Vec<u8> {
+        let mut out = vec![0x30, (2 + topic.len() + payload.len()) as u8];
+        out.extend_from_slice(&(topic.len() as u16).to_be_bytes());
+        out.extend_from_slice(topic.as_bytes());
+        out.extend_from_slice(payload.as_bytes());
+        out
+    }
+
+    #[test]
+    fn test_run() {
+        let indi = TcpListener::bind("127.0.0.1:0").unwrap();
+        let indi_addr = indi.local_addr().unwrap().to_string();
+        let indi = thread::spawn(move || {
+            let (mut stream, _) = indi.accept().unwrap();
+            stream
+                .write_all(
+                    br#"<defNumberVector device="Focuser" name="FOCUS" state="Idle" perm="rw">
+    <defNumber name="POSITION" format="%5.0f" min="0" max="100" step="1">10</defNumber>
+</defNumberVector>
+"#,
+                )
+                .unwrap();
+            // Wait for the change the broker asks for, then go away.
+            let mut received = vec![];
+            let mut buffer = [0; 1024];
+            while !String::from_utf8_lossy(&received).contains("</newNumberVector>") {
+                match stream.read(&mut buffer).unwrap() {
+                    0 => break,
+                    n => received.extend_from_slice(&buffer[..n]),
+                }
+            }
+            String::from_utf8(received).unwrap()
+        });
+
+        let broker = TcpListener::bind("127.0.0.1:0").unwrap();
+        let broker_addr = broker.local_addr().unwrap().to_string();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = broker.accept().unwrap();
+            let mut reader = stream.try_clone().unwrap();
+            assert_eq!(mqtt::read(&mut reader).unwrap(), mqtt::Packet::Other(1));
+            stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+
+            let mut published = vec![];
+            loop {
+                match mqtt::read(&mut reader) {
+                    Ok(mqtt::Packet::Publish { topic, payload, .. }) => {
+                        let payload = String::from_utf8(payload).unwrap();
+                        if topic == "indi/Focuser/FOCUS/POSITION" {
+                            // A stale retained request is ignored; a new one isn't.
+                            let mut set = publish("indi/Focuser/FOCUS/POSITION/set", "50");
+                            set[0] |= 0x01;
+                            stream.write_all(&set).unwrap();
+                            stream
+                                .write_all(&publish("indi/Focuser/FOCUS/POSITION/set", "42"))
+                                .unwrap();
+                        }
+                        published.push((topic, payload));
+                    }
+                    Ok(mqtt::Packet::Other(8)) => stream.write_all(&[0x90, 3, 0, 1, 0]).unwrap(),
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+            }
+            published
+        });
+
+        let connect = mqtt::Connect {
+            client_id: "test".to_string(),
+            keep_alive: KEEP_ALIVE,
+            ..Default::default()
+        };
+        let err = run(&indi_addr, &broker_addr, "indi", connect).unwrap_err();
+        assert_eq!(err.to_string(), format!("{} disconnected", indi_addr));
+
+        let received = indi.join().unwrap();
+        assert!(received.contains(r#"<newNumberVector device="Focuser" name="FOCUS""#));
+        assert!(received.contains(">42<"), "{}", received);
+        assert!(!received.contains(">50<"), "{}", received);
+
+        let published = broker.join().unwrap();
+        let published: Vec<(&str, &str)> = published
+            .iter()
+            .map(|(t, p)| (t.as_str(), p.as_str()))
+            .collect();
+        assert_eq!(published[0], ("indi/status", "online"));
+        assert!(published.contains(&("indi/Focuser/FOCUS/_state", "Idle")));
+        assert!(published.contains(&("indi/Focuser/FOCUS/POSITION", "10")));
+        assert_eq!(published.last(), Some(&("indi/status", "offline")));
+    }
+}
@@ -0,0 +1,292 @@
+//! Just enough of MQTT 3.1.1 to publish retained messages and receive those of
+//! a subscription, all at QoS 0.
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// The settings sent when connecting to a broker.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Connect {
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// A retained message the broker publishes if we go away without
+    /// disconnecting: (topic, payload).
+    pub will: Option<(String, String)>,
+    /// The longest we may go without sending anything, in seconds.
+    pub keep_alive: u16,
+}
+
+/// A packet received from the broker.
+#[derive(Debug, PartialEq)]
+pub enum Packet {
+    ConnAck {
+        code: u8,
+    },
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        retain: bool,
+    },
+    SubAck,
+    PingResp,
+    /// Anything else, by packet type.
+    Other(u8),
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn push_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Frames `body` as a packet of type `kind`.
+fn packet(kind: u8, flags: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![kind << 4 | flags];
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+impl Connect {
+    fn encode(&self) -> Vec<u8> {
+        let mut flags = 0x02; // Clean session.
+        if self.username.is_some() {
+            flags |= 0x80;
+        }
+        if self.password.is_some() {
+            flags |= 0x40;
+        }
+        if self.will.is_some() {
+            flags |= 0x24; // Will, retained.
+        }
+        let mut body = vec![];
+        push_string(&mut body, "MQTT");
+        body.push(4); // Protocol level 3.1.1.
+        body.push(flags);
+        body.extend_from_slice(&self.keep_alive.to_be_bytes());
+        push_string(&mut body, &self.client_id);
+        if let Some((topic, message)) = &self.will {
+            push_string(&mut body, topic);
+            push_string(&mut body, message);
+        }
+        if let Some(username) = &self.username {
+            push_string(&mut body, username);
+        }
+        if let Some(password) = &self.password {
+            push_string(&mut body, password);
+        }
+        packet(CONNECT, 0, &body)
+    }
+}
+
+fn publish(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = vec![];
+    push_string(&mut body, topic);
+    body.extend_from_slice(payload);
+    packet(PUBLISH, retain as u8, &body)
+}
+
+fn subscribe(id: u16, filter: &str) -> Vec<u8> {
+    let mut body = id.to_be_bytes().to_vec();
+    push_string(&mut body, filter);
+    body.push(0); // QoS 0.
+    packet(SUBSCRIBE, 0x02, &body)
+}
+
+/// Reads the next packet from `reader`.
+pub fn read<R: Read>(reader: &mut R) -> io::Result<Packet> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    let (kind, flags) = (byte[0] >> 4, byte[0] & 0x0f);
+    let mut length = 0;
+    for i in 0..4 {
+        reader.read_exact(&mut byte)?;
+        length |= ((byte[0] & 0x7f) as usize) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            break;
+        } else if i == 3 {
+            return Err(invalid("malformed remaining length"));
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    match kind {
+        CONNACK if length == 2 => Ok(Packet::ConnAck { code: body[1] }),
+        PUBLISH => {
+            let topic_length = match body.get(..2) {
+                Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]) as usize,
+                None => return Err(invalid("truncated publish")),
+            };
+            let topic = body
+                .get(2..2 + topic_length)
+                .ok_or_else(|| invalid("truncated publish"))?;
+            let topic =
+                String::from_utf8(topic.to_vec()).map_err(|_| invalid("topic isn't UTF-8"))?;
+            // QoS 1 and 2 messages carry a packet identifier.
+            let start = 2 + topic_length + if flags & 0x06 != 0 { 2 } else { 0 };
+            Ok(Packet::Publish {
+                topic,
+                payload: body.get(start..).unwrap_or_default().to_vec(),
+                retain: flags & 0x01 != 0,
+            })
+        }
+        SUBACK => Ok(Packet::SubAck),
+        PINGRESP => Ok(Packet::PingResp),
+        kind => Ok(Packet::Other(kind)),
+    }
+}
+
+/// A connection to an MQTT broker.
+pub struct Client {
+    stream: TcpStream,
+    next_id: u16,
+}
+
+impl Client {
+    /// Connects to the broker at `addr`, and waits for it to accept.
+    pub fn connect<A: ToSocketAddrs>(addr: A, connect: &Connect) -> io::Result<Client> {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.write_all(&connect.encode())?;
+        match read(&mut stream)? {
+            Packet::ConnAck { code: 0 } => Ok(Client { stream, next_id: 1 }),
+            Packet::ConnAck { code } => {
+                let reason = match code {
+                    1 => "unacceptable protocol version",
+                    2 => "client identifier rejected",
+                    3 => "server unavailable",
+                    4 => "bad user name or password",
+                    5 => "not authorized",
+                    _ => "unknown reason",
+                };
+                Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    format!("broker refused connection: {}", reason),
+                ))
+            }
+            _ => Err(invalid("expected CONNACK")),
+        }
+    }
+
+    /// A reader for the packets the broker sends, for `read`.
+    pub fn reader(&self) -> io::Result<BufReader<TcpStream>> {
+        Ok(BufReader::new(self.stream.try_clone()?))
+    }
+
+    pub fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> io::Result<()> {
+        self.stream.write_all(&publish(topic, payload, retain))
+    }
+
+    pub fn subscribe(&mut self, filter: &str) -> io::Result<()> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.stream.write_all(&subscribe(id, filter))
+    }
+
+    pub fn ping(&mut self) -> io::Result<()> {
+        self.stream.write_all(&packet(PINGREQ, 0, &[]))
+    }
+
+    pub fn disconnect(&mut self) -> io::Result<()> {
+        self.stream.write_all(&packet(DISCONNECT, 0, &[]))?;
+        self.stream.shutdown(std::net::Shutdown::Both)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_packet_length() {
+        assert_eq!(packet(PINGREQ, 0, &[]), vec![0xc0, 0]);
+        let long = packet(PUBLISH, 1, &[0; 321]);
+        assert_eq!(&long[..3], &[0x31, 0xc1, 0x02]);
+        assert_eq!(long.len(), 324);
+    }
+
+    #[test]
+    fn test_read_publish() {
+        let bytes = publish("indi/Focuser/FOCUS/POSITION", b"42", true);
+        assert_eq!(
+            read(&mut &bytes[..]).unwrap(),
+            Packet::Publish {
+                topic: "indi/Focuser/FOCUS/POSITION".to_string(),
+                payload: b"42".to_vec(),
+                retain: true,
+            }
+        );
+
+        // A QoS 1 message, with its packet identifier.
+        let mut body = vec![];
+        push_string(&mut body, "a/b");
+        body.extend_from_slice(&[0, 7]);
+        body.extend_from_slice(b"x");
+        match read(&mut &packet(PUBLISH, 0x02, &body)[..]).unwrap() {
+            Packet::Publish { payload, .. } => assert_eq!(payload, b"x"),
+            other => panic!("{:?}", other),
+        }
+        assert!(read(&mut &[0x30, 0x80][..]).is_err());
+    }
+
+    #[test]
+    fn test_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let broker = thread::spawn(move || {
+            let mut received = vec![];
+            for code in [5, 0] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut header = [0; 2];
+                stream.read_exact(&mut header).unwrap();
+                let mut body = vec![0; header[1] as usize];
+                stream.read_exact(&mut body).unwrap();
+                received.push((header, body));
+                stream.write_all(&[CONNACK << 4, 2, 0, code]).unwrap();
+            }
+            received
+        });
+
+        let connect = Connect {
+            client_id: "bridge".to_string(),
+            username: Some("user".to_string()),
+            password: Some("pass".to_string()),
+            will: Some(("indi/status".to_string(), "offline".to_string())),
+            keep_alive: 60,
+        };
+        let err = Client::connect(addr, &connect).err().unwrap();
+        assert_eq!(err.to_string(), "broker refused connection: not authorized");
+        Client::connect(addr, &connect).unwrap();
+
+        let received = broker.join().unwrap();
+        let (header, body) = &received[1];
+        assert_eq!(header[0], CONNECT << 4);
+        assert_eq!(&body[..10], b"\x00\x04MQTT\x04\xe6\x00\x3c");
+        assert!(body.ends_with(b"\x00\x04user\x00\x04pass"));
+    }
+}
@@ -0,0 +1,174 @@
+//! A proxy between INDI clients and an indiserver that enforces a policy file:
+//! which clients may change which properties, and which may receive BLOBs.
+use indi::*;
+
+use std::env;
+use std::error::Error;
+use std::net::{TcpListener, TcpStream};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+mod policy;
+use policy::*;
+
+static USAGE: &str = "usage: indi_proxy [-l address:port] [-s host:port] <policy file>
+
+Passes INDI traffic between clients and a server, allowing each client only
+what the policy file gives it.  The file is read again for every new client.
+
+options:
+  -l address:port  address to listen on (default 0.0.0.0:7625)
+  -s host:port     server to proxy (default localhost:7624)";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut listen = "0.0.0.0:7625".to_string();
+    let mut server = "localhost:7624".to_string();
+    let mut path = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-l" | "--listen" => listen = args.next().unwrap_or_else(|| usage()),
+            "-s" | "--server" => server = args.next().unwrap_or_else(|| usage()),
+            "--help" => usage(),
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+
+    let mut policy = Policy::load(&path).unwrap_or_else(|e| {
+        eprintln!("error: {}: {}", path, e);
+        process::exit(1);
+    });
+    let listener = TcpListener::bind(&listen).unwrap_or_else(|e| {
+        eprintln!("error: {}: {}", listen, e);
+        process::exit(1);
+    });
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept: {}", e);
+                continue;
+            }
+        };
+        match Policy::load(&path) {
+            Ok(reloaded) => policy = reloaded,
+            Err(e) => eprintln!("{}: {}, keeping the previous policy", path, e),
+        }
+        let peer = match stream.peer_addr() {
+            Ok(peer) => peer,
+            Err(e) => {
+                eprintln!("accept: {}", e);
+                continue;
+            }
+        };
+        let access = policy.access(peer.ip());
+        if access.denied {
+            eprintln!("{}: denied", peer);
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+            continue;
+        }
+
+        eprintln!("{}: connected", peer);
+        let server = server.clone();
+        thread::spawn(move || {
+            match proxy(stream, &server, access) {
+                Ok(()) => eprintln!("{}: disconnected", peer),
+                Err(e) => eprintln!("{}: {}", peer, e),
+            };
+        });
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+/// Passes traffic between `client` and a new connection to `server` until
+/// either side disconnects.
+fn proxy(client: TcpStream, server: &str, access: Access) -> Result<(), Box<dyn Error>> {
+    let mut upstream = Connection::new(server)?;
+    let downstream = Connection::from_transport(client)?;
+    let from_server = upstream.iter()?;
+    let from_client = downstream.iter()?;
+    // Written to by both directions: the server's traffic, and refusals.
+    let downstream = Arc::new(Mutex::new(downstream));
+    // The server's properties, so refusals can carry their current values.
+    let properties = Arc::new(Mutex::new(indi::Client::new()));
+
+    let server_thread = {
+        let downstream = downstream.clone();
+        let properties = properties.clone();
+        let access = access.clone();
+        thread::spawn(move || {
+            for command in from_server {
+                let command = match command {
+                    Ok(command) => command,
+                    Err(e) => {
+                        eprintln!("server: {}", e);
+                        continue;
+                    }
+                };
+                // BLOBs can't be refused, so there's no need to keep images.
+                if !matches!(
+                    command,
+                    Command::DefBlobVector(_) | Command::SetBlobVector(_)
+                ) {
+                    let _ = properties.lock().unwrap().update(command.clone());
+                }
+                if let Some(command) = access.to_client(command) {
+                    if downstream.lock().unwrap().write(&command).is_err() {
+                        break;
+                    }
+                }
+            }
+            let _ = downstream.lock().unwrap().disconnect();
+        })
+    };
+
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        for command in from_client {
+            let command = match command {
+                Ok(command) => command,
+                Err(e) => {
+                    eprintln!("client: {}", e);
+                    continue;
+                }
+            };
+            let device = command.device_name().cloned().unwrap_or_default();
+            let name = command.property_name().cloned().unwrap_or_default();
+            match access.to_server(command) {
+                Ok(command) => upstream.write(&command)?,
+                Err(reason) => {
+                    eprintln!("{}", reason);
+                    let current = properties
+                        .lock()
+                        .unwrap()
+                        .get_devices()
+                        .get(&device)
+                        .and_then(|d| d.get_parameters().get(&name))
+                        .and_then(|param| refusal(&device, param));
+                    let mut downstream = downstream.lock().unwrap();
+                    downstream.write(&Command::Message(Message {
+                        device: None,
+                        timestamp: None,
+                        message: Some(reason),
+                    }))?;
+                    if let Some(current) = current {
+                        downstream.write(&current)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    })();
+
+    let _ = upstream.disconnect();
+    let _ = server_thread.join();
+    result
+}
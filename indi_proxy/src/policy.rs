@@ -0,0 +1,499 @@
+//! The proxy's policy file.  Each line names the clients it applies to, by
+//! address, network or `*`, followed by a rule:
+//!
+//! ```text
+//! # Everyone may watch, but only the observatory PC may move things.
+//! *               no-blobs
+//! 192.168.1.10    write *
+//! 192.168.1.10    blobs
+//! 10.0.0.0/8      write Telescope Simulator.EQUATORIAL_EOD_COORD
+//! 203.0.113.0/24  deny
+//! 203.0.113.5     allow
+//! ```
+//!
+//! Rules apply in order, so later lines override earlier ones:
+//!
+//! * `write <pattern>` lets clients change properties matching a
+//!   `device.property` pattern.
+//! * `read-only` takes back any write access given so far.
+//! * `blobs` and `no-blobs` turn BLOBs on or off.
+//! * `deny` refuses the connection, and `allow` accepts it again.
+//!
+//! Clients no line gives write access to are read-only observers.  A `#` at
+//! the start of a line, or on its own after whitespace, starts a comment, so
+//! patterns can still contain names like `Rig #2`.
+use indi::*;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum PolicyError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::Io(e) => write!(f, "{}", e),
+            PolicyError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+impl From<io::Error> for PolicyError {
+    fn from(err: io::Error) -> Self {
+        PolicyError::Io(err)
+    }
+}
+
+/// The clients a line applies to.
+#[derive(Debug, PartialEq)]
+enum Clients {
+    Any,
+    Network(IpAddr, u32),
+}
+
+/// Strips a comment: a `#` at the start of `line`, or one standing on its own
+/// after whitespace, and everything after it.
+fn strip_comment(line: &str) -> &str {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with('#') {
+        return "";
+    }
+    let mut previous = None;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let alone = chars.peek().is_none_or(|(_, next)| next.is_whitespace());
+        if c == '#' && previous.is_some_and(char::is_whitespace) && alone {
+            return &line[..i];
+        }
+        previous = Some(c);
+    }
+    line
+}
+
+impl Clients {
+    fn parse(spec: &str) -> Result<Clients, String> {
+        if spec == "*" {
+            return Ok(Clients::Any);
+        }
+        let (addr, prefix) = match spec.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (spec, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address {:?}", addr))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse() {
+                Ok(prefix) if prefix <= bits => prefix,
+                _ => return Err(format!("invalid prefix length {:?}", prefix)),
+            },
+            None => bits,
+        };
+        Ok(Clients::Network(addr, prefix))
+    }
+
+    fn contains(&self, client: IpAddr) -> bool {
+        match (self, client.to_canonical()) {
+            (Clients::Any, _) => true,
+            (Clients::Network(IpAddr::V4(net), prefix), IpAddr::V4(client)) => {
+                let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+                u32::from(*net) & mask == u32::from(client) & mask
+            }
+            (Clients::Network(IpAddr::V6(net), prefix), IpAddr::V6(client)) => {
+                let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+                u128::from(*net) & mask == u128::from(client) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Rule {
+    Write(Pattern),
+    ReadOnly,
+    Blobs(bool),
+    Deny,
+    Allow,
+}
+
+#[derive(Debug, Default)]
+pub struct Policy {
+    rules: Vec<(Clients, Rule)>,
+}
+
+impl Policy {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Policy, PolicyError> {
+        Policy::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Policy, PolicyError> {
+        let mut rules = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message| PolicyError::Parse {
+                line: i + 1,
+                message,
+            };
+            let (clients, rule) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let clients = Clients::parse(clients).map_err(error)?;
+            let (rule, argument) = match rule.trim().split_once(char::is_whitespace) {
+                Some((rule, argument)) => (rule, Some(argument.trim())),
+                None => (rule.trim(), None),
+            };
+            let rule = match (rule, argument) {
+                ("write", Some(pattern)) => Rule::Write(Pattern::parse(pattern)),
+                ("read-only", None) => Rule::ReadOnly,
+                ("blobs", None) => Rule::Blobs(true),
+                ("no-blobs", None) => Rule::Blobs(false),
+                ("deny", None) => Rule::Deny,
+                ("allow", None) => Rule::Allow,
+                _ => return Err(error(format!("invalid rule {:?}", line))),
+            };
+            rules.push((clients, rule));
+        }
+        Ok(Policy { rules })
+    }
+
+    /// What `client` may do.
+    pub fn access(&self, client: IpAddr) -> Access {
+        let mut access = Access {
+            denied: false,
+            writes: vec![],
+            blobs: true,
+        };
+        for (clients, rule) in &self.rules {
+            if !clients.contains(client) {
+                continue;
+            }
+            match rule {
+                Rule::Write(pattern) => access.writes.push(pattern.clone()),
+                Rule::ReadOnly => access.writes.clear(),
+                Rule::Blobs(blobs) => access.blobs = *blobs,
+                Rule::Deny => access.denied = true,
+                Rule::Allow => access.denied = false,
+            }
+        }
+        access
+    }
+}
+
+/// What one client may do.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Access {
+    pub denied: bool,
+    pub writes: Vec<Pattern>,
+    pub blobs: bool,
+}
+
+impl Access {
+    pub fn may_write(&self, device: &str, property: &str) -> bool {
+        self.writes
+            .iter()
+            .any(|p| p.matches_property(device, property))
+    }
+
+    /// Checks a command from the client before it is passed on to the server,
+    /// returning why it was refused if it may not be.
+    pub fn to_server(&self, command: Command) -> Result<Command, String> {
+        match command {
+            Command::NewTextVector(_)
+            | Command::NewNumberVector(_)
+            | Command::NewSwitchVector(_) => {
+                let device = command.device_name().map_or("", |d| d.as_str());
+                let property = command.property_name().map_or("", |p| p.as_str());
+                if self.may_write(device, property) {
+                    Ok(command)
+                } else {
+                    Err(format!(
+                        "permission denied to change {}.{}",
+                        device, property
+                    ))
+                }
+            }
+            Command::EnableBlob(mut enable) if !self.blobs => {
+                enable.enabled = BlobEnable::Never;
+                Ok(Command::EnableBlob(enable))
+            }
+            command => Ok(command),
+        }
+    }
+
+    /// Filters a command from the server before it is passed on to the client.
+    /// Properties the client may not change are shown to it as read only.
+    pub fn to_client(&self, command: Command) -> Option<Command> {
+        let may_write = |device: &str, name: &str| self.may_write(device, name);
+        match command {
+            Command::SetBlobVector(_) if !self.blobs => None,
+            Command::DefTextVector(mut def) => {
+                if !may_write(&def.device, &def.name) {
+                    def.perm = PropertyPerm::RO;
+                }
+                Some(Command::DefTextVector(def))
+            }
+            Command::DefNumberVector(mut def) => {
+                if !may_write(&def.device, &def.name) {
+                    def.perm = PropertyPerm::RO;
+                }
+                Some(Command::DefNumberVector(def))
+            }
+            Command::DefSwitchVector(mut def) => {
+                if !may_write(&def.device, &def.name) {
+                    def.perm = PropertyPerm::RO;
+                }
+                Some(Command::DefSwitchVector(def))
+            }
+            command => Some(command),
+        }
+    }
+}
+
+/// The current value of `param`, a property of `device`, with state `Alert`:
+/// what a client whose change was refused is sent, so that it stops waiting
+/// for one.  `None` for properties that can't be changed.
+pub fn refusal(device: &str, param: &Parameter) -> Option<Command> {
+    let state = PropertyState::Alert;
+    match param {
+        Parameter::TextVector(param) => Some(Command::SetTextVector(SetTextVector {
+            device: device.to_string(),
+            name: param.name.clone(),
+            state,
+            timeout: None,
+            timestamp: None,
+            message: None,
+            texts: param
+                .values
+                .iter()
+                .map(|(name, text)| OneText {
+                    name: name.clone(),
+                    value: text.value.clone(),
+                })
+                .collect(),
+        })),
+        Parameter::NumberVector(param) => Some(Command::SetNumberVector(SetNumberVector {
+            device: device.to_string(),
+            name: param.name.clone(),
+            state,
+            timeout: None,
+            timestamp: None,
+            message: None,
+            numbers: param
+                .values
+                .iter()
+                .map(|(name, number)| OneNumber {
+                    name: name.clone(),
+                    min: None,
+                    max: None,
+                    step: None,
+                    value: number.value,
+                })
+                .collect(),
+        })),
+        Parameter::SwitchVector(param) => Some(Command::SetSwitchVector(SetSwitchVector {
+            device: device.to_string(),
+            name: param.name.clone(),
+            state,
+            timeout: None,
+            timestamp: None,
+            message: None,
+            switches: param
+                .values
+                .iter()
+                .map(|(name, switch)| OneSwitch {
+                    name: name.clone(),
+                    value: switch.value.clone(),
+                })
+                .collect(),
+        })),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static POLICY: &str = "
+        # comment
+        *               no-blobs
+        192.168.1.10    write *       # everything
+        192.168.1.10    blobs
+        10.0.0.0/8      write Telescope Simulator.EQUATORIAL_EOD_COORD
+        10.1.0.0/16     read-only
+        203.0.113.0/24  deny
+        203.0.113.5     allow
+        ::1             write *
+        10.9.0.0/16     write Rig #2.*  # the second rig
+    ";
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    fn new_number(device: &str, name: &str) -> Command {
+        Command::NewNumberVector(NewNumberVector {
+            device: device.to_string(),
+            name: name.to_string(),
+            timestamp: None,
+            numbers: vec![],
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        let policy = Policy::parse(POLICY).unwrap();
+        assert_eq!(policy.rules.len(), 9);
+        assert_eq!(
+            policy.rules[3],
+            (
+                Clients::Network(ip("10.0.0.0"), 8),
+                Rule::Write(Pattern::parse("Telescope Simulator.EQUATORIAL_EOD_COORD"))
+            )
+        );
+
+        for bad in [
+            "* writ *",
+            "* write",
+            "10.0.0.0/33 deny",
+            "host deny",
+            "* deny x",
+            "* allow x",
+        ] {
+            match Policy::parse(&format!("\n{}", bad)) {
+                Err(PolicyError::Parse { line: 2, .. }) => {}
+                other => panic!("{:?}: {:?}", bad, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_access() {
+        let policy = Policy::parse(POLICY).unwrap();
+
+        let observer = policy.access(ip("192.168.1.20"));
+        assert!(!observer.denied);
+        assert!(!observer.blobs);
+        assert!(!observer.may_write("Telescope Simulator", "EQUATORIAL_EOD_COORD"));
+
+        let owner = policy.access(ip("192.168.1.10"));
+        assert!(owner.blobs);
+        assert!(owner.may_write("CCD Simulator", "CCD_EXPOSURE"));
+
+        let guest = policy.access(ip("10.2.3.4"));
+        assert!(guest.may_write("Telescope Simulator", "EQUATORIAL_EOD_COORD"));
+        assert!(!guest.may_write("Telescope Simulator", "TELESCOPE_PARK"));
+        assert!(!policy
+            .access(ip("10.1.3.4"))
+            .may_write("Telescope Simulator", "EQUATORIAL_EOD_COORD"));
+
+        assert!(policy.access(ip("203.0.113.9")).denied);
+        assert!(!policy.access(ip("203.0.113.5")).denied);
+        assert!(policy.access(ip("::1")).may_write("a", "b"));
+        assert!(policy.access(ip("::ffff:192.168.1.10")).may_write("a", "b"));
+        assert!(policy
+            .access(ip("10.9.1.2"))
+            .may_write("Rig #2", "CCD_EXPOSURE"));
+    }
+
+    #[test]
+    fn test_to_server() {
+        let policy = Policy::parse(POLICY).unwrap();
+        let guest = policy.access(ip("10.2.3.4"));
+        assert!(guest
+            .to_server(new_number("Telescope Simulator", "EQUATORIAL_EOD_COORD"))
+            .is_ok());
+        assert_eq!(
+            guest.to_server(new_number("CCD Simulator", "CCD_EXPOSURE")),
+            Err("permission denied to change CCD Simulator.CCD_EXPOSURE".to_string())
+        );
+
+        let enable = Command::EnableBlob(EnableBlob {
+            device: "CCD Simulator".to_string(),
+            name: None,
+            enabled: BlobEnable::Also,
+        });
+        match guest.to_server(enable) {
+            Ok(Command::EnableBlob(e)) => assert_eq!(e.enabled, BlobEnable::Never),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_refusal() {
+        let mut client = Client::new();
+        for command in CommandIter::from_xml(
+            r#"<defNumberVector device="CCD Simulator" name="CCD_EXPOSURE" state="Idle" perm="rw">
+    <defNumber name="CCD_EXPOSURE_VALUE" format="%5.2f" min="0" max="3600" step="0">30</defNumber>
+</defNumberVector>"#,
+        ) {
+            client.update(command.unwrap()).unwrap();
+        }
+        let param = &client.get_devices()["CCD Simulator"].get_parameters()["CCD_EXPOSURE"];
+        match refusal("CCD Simulator", param) {
+            Some(Command::SetNumberVector(set)) => {
+                assert_eq!(set.device, "CCD Simulator");
+                assert_eq!(set.state, PropertyState::Alert);
+                assert_eq!(set.numbers[0].name, "CCD_EXPOSURE_VALUE");
+                assert_eq!(set.numbers[0].value, 30.0);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_to_client() {
+        let policy = Policy::parse(POLICY).unwrap();
+        let guest = policy.access(ip("10.2.3.4"));
+        let def = |name: &str| {
+            Command::DefNumberVector(DefNumberVector {
+                device: "Telescope Simulator".to_string(),
+                name: name.to_string(),
+                label: None,
+                group: None,
+                state: PropertyState::Idle,
+                perm: PropertyPerm::RW,
+                timeout: None,
+                timestamp: None,
+                message: None,
+                numbers: vec![],
+            })
+        };
+        let perm = |command| match command {
+            Some(Command::DefNumberVector(def)) => def.perm,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(
+            perm(guest.to_client(def("EQUATORIAL_EOD_COORD"))),
+            PropertyPerm::RW
+        );
+        assert_eq!(
+            perm(guest.to_client(def("TELESCOPE_INFO"))),
+            PropertyPerm::RO
+        );
+
+        let blob = Command::SetBlobVector(SetBlobVector {
+            device: "CCD Simulator".to_string(),
+            name: "CCD1".to_string(),
+            state: PropertyState::Ok,
+            timeout: None,
+            timestamp: None,
+            message: None,
+            blobs: vec![],
+        });
+        assert_eq!(guest.to_client(blob.clone()), None);
+        assert!(policy.access(ip("192.168.1.10")).to_client(blob).is_some());
+    }
+}
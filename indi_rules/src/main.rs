@@ -0,0 +1,309 @@
+//! Watches an INDI server and acts when the rules in a rules file become true:
+//! logging, calling webhooks and setting properties.  See `indi::rules` for
+//! the format of the file.
+use indi::*;
+
+use std::env;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+static USAGE: &str = "usage: indi_rules [-s host:port] <rules file>
+
+Watches an INDI server and acts when the rules in the rules file become true.
+
+options:
+  -s host:port     server to watch (default localhost:7624)";
+
+/// How often rules are evaluated when nothing changes, so that their `for`
+/// durations can pass.
+const TICK: Duration = Duration::from_secs(1);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut server = "localhost:7624".to_string();
+    let mut path = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-s" | "--server" => server = args.next().unwrap_or_else(|| usage()),
+            "--help" => usage(),
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+
+    let rules = Rules::load(&path).unwrap_or_else(|e| {
+        eprintln!("error: {}: {}", path, e);
+        process::exit(1);
+    });
+    if let Err(e) = run(&server, rules) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+/// Evaluates `rules` against `server` until it goes away.
+fn run(server: &str, mut rules: Rules) -> Result<(), Box<dyn Error>> {
+    let mut connection = Connection::new(server)?;
+    connection.write(&GetProperties {
+        version: INDI_PROTOCOL_VERSION.to_string(),
+        device: None,
+        name: None,
+    })?;
+
+    let (sender, events) = mpsc::channel();
+    let iter = connection.iter()?;
+    thread::spawn(move || {
+        for command in iter {
+            if sender.send(command).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut client = Client::new();
+    loop {
+        match events.recv_timeout(TICK) {
+            Ok(Ok(command)) => {
+                if let Err(e) = client.update(command) {
+                    eprintln!("{}: {}", server, e);
+                }
+            }
+            Ok(Err(e)) => eprintln!("{}: {}", server, e),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(format!("{} disconnected", server).into())
+            }
+        }
+        for firing in rules.evaluate(&client, Instant::now()) {
+            act(&firing, &client, &mut connection);
+        }
+    }
+}
+
+/// Carries out the actions of a rule that fired, logging any that fail.
+fn act<T: Transport>(firing: &Firing, client: &Client, connection: &mut Connection<T>) {
+    let rule = &firing.rule;
+    for action in &firing.actions {
+        match action {
+            RuleAction::Log(message) if firing.matches.is_empty() => {
+                eprintln!("{}: {}", rule, message)
+            }
+            RuleAction::Log(message) => {
+                eprintln!("{}: {} ({})", rule, message, firing.matches.join(", "))
+            }
+            RuleAction::Webhook { url, message } => {
+                let (rule, url) = (rule.clone(), url.clone());
+                let body = webhook_json(firing, message).to_string();
+                thread::spawn(move || {
+                    if let Err(e) = post(&url, &body) {
+                        eprintln!("{}: webhook {}: {}", rule, url, e);
+                    }
+                });
+            }
+            RuleAction::Set {
+                device,
+                property,
+                element,
+                value,
+            } => {
+                eprintln!(
+                    "{}: setting {}.{}.{} to {}",
+                    rule, device, property, element, value
+                );
+                let result: Result<(), Box<dyn Error>> = client
+                    .new_vector_from_str(device, property, &[(element, value)])
+                    .map_err(Into::into)
+                    .and_then(|command| Ok(connection.write(&command)?));
+                if let Err(e) = result {
+                    eprintln!("{}: {}.{}.{}: {}", rule, device, property, element, e);
+                }
+            }
+        }
+    }
+}
+
+fn webhook_json(firing: &Firing, message: &Option<String>) -> Json {
+    Json::Object(vec![
+        ("rule".to_string(), Json::String(firing.rule.clone())),
+        ("message".to_string(), Json::option(message)),
+        (
+            "matches".to_string(),
+            Json::Array(
+                firing
+                    .matches
+                    .iter()
+                    .map(|m| Json::String(m.clone()))
+                    .collect(),
+            ),
+        ),
+        (
+            "time".to_string(),
+            Json::String(chrono::Utc::now().to_rfc3339()),
+        ),
+    ])
+}
+
+/// POSTs a JSON `body` to an `http://host[:port]/path` URL.
+fn post(url: &str, body: &str) -> Result<(), Box<dyn Error>> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or("only http:// URLs are supported")?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let addr = match host.rsplit_once(':') {
+        Some((_, port)) if !port.ends_with(']') => host.to_string(),
+        _ => format!("{}:80", host),
+    };
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("no address for {}", host))?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, WEBHOOK_TIMEOUT)?;
+    stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+    stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    )?;
+
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(format!("unexpected response {:?}", status.trim()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn webhook(response: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![0; 4096];
+            let n = stream.read(&mut request).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8_lossy(&request[..n]).to_string()
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_post() {
+        let (url, handle) = webhook("HTTP/1.1 204 No Content\r\n\r\n");
+        post(&url, r#"{"rule": "alert"}"#).unwrap();
+        let request = handle.join().unwrap();
+        assert!(
+            request.starts_with("POST /hook HTTP/1.1\r\n"),
+            "{}",
+            request
+        );
+        assert!(request.contains("Content-Length: 17\r\n"), "{}", request);
+        assert!(
+            request.ends_with("\r\n\r\n{\"rule\": \"alert\"}"),
+            "{}",
+            request
+        );
+
+        let (url, handle) = webhook("HTTP/1.1 500 Internal Server Error\r\n\r\n");
+        assert_eq!(
+            post(&url, "{}").unwrap_err().to_string(),
+            "unexpected response \"HTTP/1.1 500 Internal Server Error\""
+        );
+        handle.join().unwrap();
+
+        assert_eq!(
+            post("https://example.com", "{}").unwrap_err().to_string(),
+            "only http:// URLs are supported"
+        );
+    }
+
+    /// A writer whose output can be looked at while a connection owns it.
+    #[derive(Clone, Default)]
+    struct Written(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for Written {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_act() {
+        let mut client = Client::new();
+        for command in CommandIter::from_xml(
+            r#"<defSwitchVector device="CCD Simulator" name="CCD_ABORT_EXPOSURE" state="Idle" perm="rw" rule="AtMostOne">
+    <defSwitch name="ABORT">Off</defSwitch>
+</defSwitchVector>
+<defLightVector device="CCD Simulator" name="STATUS" state="Idle">
+    <defLight name="COOLING">Idle</defLight>
+</defLightVector>"#,
+        ) {
+            client.update(command.unwrap()).unwrap();
+        }
+
+        let written = Written::default();
+        let mut connection =
+            Connection::from_transport(Duplex::new(std::io::empty(), written.clone())).unwrap();
+        let set = |property: &str, element: &str, value: &str| RuleAction::Set {
+            device: "CCD Simulator".to_string(),
+            property: property.to_string(),
+            element: element.to_string(),
+            value: value.to_string(),
+        };
+        let firing = Firing {
+            rule: "abort".to_string(),
+            matches: vec![],
+            actions: vec![
+                set("CCD_ABORT_EXPOSURE", "ABORT", "On"),
+                // These are logged, and nothing is sent.
+                set("CCD_ABORT_EXPOSURE", "ABORT", "yes"),
+                set("STATUS", "COOLING", "Ok"),
+            ],
+        };
+        act(&firing, &client, &mut connection);
+
+        let written = String::from_utf8(written.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            written.matches("<newSwitchVector").count(),
+            1,
+            "{}",
+            written
+        );
+        assert!(
+            written.contains(r#"name="CCD_ABORT_EXPOSURE""#),
+            "{}",
+            written
+        );
+        assert!(written.contains("On</oneSwitch>"), "{}", written);
+    }
+}
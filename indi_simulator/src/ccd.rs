@@ -0,0 +1,381 @@
+use super::*;
+use properties::*;
+use sky::{Conditions, Field, Rng};
+
+/// Focuser position that brings stars to a point.
+pub const BEST_FOCUS: f64 = 50000.0;
+/// Arcseconds of blur per focuser step away from `BEST_FOCUS`.
+const DEFOCUS_PER_STEP: f64 = 0.01;
+/// Degrees per second the cooler changes the sensor temperature by.
+const COOLING_RATE: f64 = 1.0;
+
+/// A simulated camera.  It follows the telescope, focuser and filter wheel,
+/// and images the stars the telescope is pointing at, blurred by seeing and
+/// focus and dimmed by the filter.
+#[derive(Debug)]
+pub struct Ccd {
+    properties: Properties,
+    config: SimulatorConfig,
+    snooper: Snooper,
+    enricher: FitsEnricher,
+    rng: Rng,
+    /// When the current exposure started, and how long it is.
+    exposure: Option<(DateTime<Utc>, f64)>,
+    temperature: f64,
+    /// The temperature the cooler is heading for.
+    cooler_target: Option<f64>,
+    last_tick: Option<DateTime<Utc>>,
+}
+
+impl Ccd {
+    pub fn new(config: &SimulatorConfig) -> Ccd {
+        let mut properties = Properties::new(&config.ccd);
+        define_connection(&mut properties);
+        properties.define_numbers(
+            "CCD_EXPOSURE",
+            "Expose",
+            "Main Control",
+            PropertyPerm::RW,
+            vec![number(
+                "CCD_EXPOSURE_VALUE",
+                "Duration (s)",
+                "%5.2f",
+                (0.001, 3600.0, 1.0),
+                1.0,
+            )],
+        );
+        properties.define_switches(
+            "CCD_ABORT_EXPOSURE",
+            "Abort",
+            "Main Control",
+            SwitchRule::AtMostOne,
+            vec![switch("ABORT", "Abort", false)],
+        );
+        properties.define_numbers(
+            "CCD_TEMPERATURE",
+            "Temperature",
+            "Main Control",
+            PropertyPerm::RW,
+            vec![number(
+                "CCD_TEMPERATURE_VALUE",
+                "Temperature (C)",
+                "%5.2f",
+                (-50.0, 50.0, 0.0),
+                20.0,
+            )],
+        );
+        properties.define_numbers(
+            "CCD_INFO",
+            "CCD Information",
+            "Image Info",
+            PropertyPerm::RO,
+            vec![
+                number(
+                    "CCD_MAX_X",
+                    "Max. Width",
+                    "%.f",
+                    (1.0, 16000.0, 0.0),
+                    config.width as f64,
+                ),
+                number(
+                    "CCD_MAX_Y",
+                    "Max. Height",
+                    "%.f",
+                    (1.0, 16000.0, 0.0),
+                    config.height as f64,
+                ),
+                number(
+                    "CCD_PIXEL_SIZE",
+                    "Pixel size (um)",
+                    "%.2f",
+                    (1.0, 40.0, 0.0),
+                    config.pixel_size,
+                ),
+                number(
+                    "CCD_PIXEL_SIZE_X",
+                    "Pixel size X",
+                    "%.2f",
+                    (1.0, 40.0, 0.0),
+                    config.pixel_size,
+                ),
+                number(
+                    "CCD_PIXEL_SIZE_Y",
+                    "Pixel size Y",
+                    "%.2f",
+                    (1.0, 40.0, 0.0),
+                    config.pixel_size,
+                ),
+                number(
+                    "CCD_BITSPERPIXEL",
+                    "Bits per pixel",
+                    "%.f",
+                    (8.0, 64.0, 0.0),
+                    16.0,
+                ),
+            ],
+        );
+        properties.define_numbers(
+            "SIMULATOR_SETTINGS",
+            "Settings",
+            "Simulator Config",
+            PropertyPerm::RW,
+            vec![
+                number(
+                    "SIM_SEEING",
+                    "Seeing (arcsec)",
+                    "%4.2f",
+                    (0.0, 60.0, 0.0),
+                    config.seeing,
+                ),
+                number(
+                    "SIM_NOISE",
+                    "Read noise (e-)",
+                    "%4.1f",
+                    (0.0, 1000.0, 0.0),
+                    config.noise,
+                ),
+                number(
+                    "SIM_SKYGLOW",
+                    "Sky glow (e-/s)",
+                    "%4.1f",
+                    (0.0, 10000.0, 0.0),
+                    10.0,
+                ),
+                number(
+                    "SIM_LIMITINGMAG",
+                    "Limiting mag",
+                    "%4.1f",
+                    (0.0, 20.0, 0.0),
+                    15.0,
+                ),
+            ],
+        );
+        properties.define_blob("CCD1", "Image", "Image Settings");
+
+        let mut snooper = Snooper::new();
+        snooper.snoop(&config.telescope, None);
+        snooper.snoop(&config.focuser, None);
+        snooper.snoop(&config.filter_wheel, None);
+        let enricher = FitsEnricher::new(FitsDevices {
+            mount: Some(config.telescope.clone()),
+            focuser: Some(config.focuser.clone()),
+            filter_wheel: Some(config.filter_wheel.clone()),
+            camera: None,
+        });
+
+        Ccd {
+            properties,
+            config: config.clone(),
+            snooper,
+            enricher,
+            rng: Rng::new(config.seed),
+            exposure: None,
+            temperature: 20.0,
+            cooler_target: None,
+            last_tick: None,
+        }
+    }
+
+    fn snooped(&self, device: &str, property: &str, element: &str) -> Option<f64> {
+        self.snooper
+            .client()
+            .number(device, property)
+            .and_then(|p| p.get(element))
+            .ok()
+    }
+
+    fn filter(&self) -> Option<String> {
+        let client = self.snooper.client();
+        let wheel = &self.config.filter_wheel;
+        let slot = self.snooped(wheel, "FILTER_SLOT", "FILTER_SLOT_VALUE")?;
+        let names = client.text(wheel, "FILTER_NAME").ok()?;
+        names
+            .get(&format!("FILTER_SLOT_NAME_{}", slot.round()))
+            .ok()
+            .map(String::from)
+    }
+
+    /// Takes an exposure of `duration` seconds started at `start`, as a FITS file.
+    fn expose(&mut self, start: DateTime<Utc>, duration: f64) -> Vec<u8> {
+        let telescope = &self.config.telescope;
+        let ra = self
+            .snooped(telescope, "EQUATORIAL_EOD_COORD", "RA")
+            .unwrap_or(0.0);
+        let dec = self
+            .snooped(telescope, "EQUATORIAL_EOD_COORD", "DEC")
+            .unwrap_or(0.0);
+        let focal_length = self
+            .snooped(telescope, "TELESCOPE_INFO", "TELESCOPE_FOCAL_LENGTH")
+            .unwrap_or(900.0);
+        let aperture = self
+            .snooped(telescope, "TELESCOPE_INFO", "TELESCOPE_APERTURE")
+            .unwrap_or(120.0);
+        let focus = self
+            .snooped(
+                &self.config.focuser,
+                "ABS_FOCUS_POSITION",
+                "FOCUS_ABSOLUTE_POSITION",
+            )
+            .unwrap_or(BEST_FOCUS);
+        let throughput = match self.filter().map(|f| f.to_lowercase()).as_deref() {
+            None | Some("luminance") | Some("lum") | Some("l") | Some("clear") => 1.0,
+            Some("ha") | Some("oiii") | Some("sii") => 0.05,
+            Some(_) => 0.3,
+        };
+
+        let field = Field {
+            ra: ra * 15.0,
+            dec,
+            scale: 206.265 * self.config.pixel_size / focal_length,
+            width: self.config.width,
+            height: self.config.height,
+        };
+        let settings = |element| self.properties.number("SIMULATOR_SETTINGS", element);
+        let conditions = Conditions {
+            exposure: duration,
+            seeing: settings("SIM_SEEING"),
+            defocus: (focus - BEST_FOCUS).abs() * DEFOCUS_PER_STEP,
+            aperture,
+            throughput,
+            sky: settings("SIM_SKYGLOW"),
+            // Dark current doubles every 6°C.
+            dark: 0.1 * 2f64.powf(self.temperature / 6.0),
+            read_noise: settings("SIM_NOISE"),
+            limiting_magnitude: settings("SIM_LIMITINGMAG"),
+        };
+        let pixels = sky::render(&field, &conditions, &mut self.rng);
+
+        let number = |keyword, value, comment| FitsCard {
+            keyword,
+            value: FitsValue::Number(value),
+            comment,
+        };
+        let cards = [
+            number("EXPTIME", duration, "Exposure time (s)"),
+            number("XPIXSZ", self.config.pixel_size, "Pixel width (um)"),
+            number("YPIXSZ", self.config.pixel_size, "Pixel height (um)"),
+            number("CCD-TEMP", self.temperature, "CCD temperature (C)"),
+        ];
+        let image = sky::fits(field.width, field.height, &pixels, &cards);
+        let name = self.config.ccd.clone();
+        match self
+            .enricher
+            .enrich(self.snooper.client(), &name, &start, &image)
+        {
+            Ok(enriched) => enriched,
+            Err(_) => image,
+        }
+    }
+}
+
+impl SimulatedDevice for Ccd {
+    fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    fn update(&mut self, command: &Command, now: DateTime<Utc>) -> Vec<Command> {
+        match command.property_name().map(|n| n.as_str()) {
+            Some("CCD_EXPOSURE") => match new_number(command, "CCD_EXPOSURE_VALUE") {
+                Some(duration) => {
+                    let duration = duration.clamp(0.001, 3600.0);
+                    self.exposure = Some((now, duration));
+                    vec![self.properties.set_numbers(
+                        "CCD_EXPOSURE",
+                        PropertyState::Busy,
+                        &[("CCD_EXPOSURE_VALUE", duration)],
+                    )]
+                }
+                None => vec![],
+            },
+            Some("CCD_ABORT_EXPOSURE") => {
+                self.exposure = None;
+                vec![
+                    self.properties.set_switches(
+                        "CCD_ABORT_EXPOSURE",
+                        PropertyState::Ok,
+                        &[("ABORT", false)],
+                    ),
+                    self.properties.set_numbers(
+                        "CCD_EXPOSURE",
+                        PropertyState::Idle,
+                        &[("CCD_EXPOSURE_VALUE", 0.0)],
+                    ),
+                ]
+            }
+            Some("CCD_TEMPERATURE") => match new_number(command, "CCD_TEMPERATURE_VALUE") {
+                // The property holds the current temperature, so the setpoint is kept apart.
+                Some(target) => {
+                    self.cooler_target = Some(target.clamp(-50.0, 50.0));
+                    vec![self.properties.set_numbers(
+                        "CCD_TEMPERATURE",
+                        PropertyState::Busy,
+                        &[("CCD_TEMPERATURE_VALUE", self.temperature)],
+                    )]
+                }
+                None => vec![],
+            },
+            _ => self.properties.apply(command).into_iter().collect(),
+        }
+    }
+
+    fn tick(&mut self, now: DateTime<Utc>) -> Vec<Command> {
+        let dt = match self.last_tick.replace(now) {
+            Some(last) => (now - last).num_milliseconds() as f64 / 1000.0,
+            None => 0.0,
+        };
+        let mut updates = vec![];
+
+        if let Some(target) = self.cooler_target {
+            let step = COOLING_RATE * dt;
+            let state = if (target - self.temperature).abs() <= step {
+                self.temperature = target;
+                self.cooler_target = None;
+                PropertyState::Ok
+            } else {
+                self.temperature += step.copysign(target - self.temperature);
+                PropertyState::Busy
+            };
+            updates.push(self.properties.set_numbers(
+                "CCD_TEMPERATURE",
+                state,
+                &[("CCD_TEMPERATURE_VALUE", self.temperature)],
+            ));
+        }
+
+        if let Some((start, duration)) = self.exposure {
+            let remaining = duration - (now - start).num_milliseconds() as f64 / 1000.0;
+            if remaining <= 0.0 {
+                self.exposure = None;
+                let image = self.expose(start, duration);
+                updates.push(self.properties.set_blob("CCD1", ".fits", image));
+                updates.push(self.properties.set_numbers(
+                    "CCD_EXPOSURE",
+                    PropertyState::Ok,
+                    &[("CCD_EXPOSURE_VALUE", 0.0)],
+                ));
+            } else if remaining.ceil()
+                < self
+                    .properties
+                    .number("CCD_EXPOSURE", "CCD_EXPOSURE_VALUE")
+                    .ceil()
+            {
+                // Count down a second at a time.
+                updates.push(self.properties.set_numbers(
+                    "CCD_EXPOSURE",
+                    PropertyState::Busy,
+                    &[("CCD_EXPOSURE_VALUE", remaining.ceil())],
+                ));
+            }
+        }
+        updates
+    }
+
+    fn snoop_requests(&self) -> Vec<GetProperties> {
+        self.snooper.requests().collect()
+    }
+
+    fn snoop(&mut self, command: &Command) {
+        // Updates to properties the snooper hasn't seen defined are ignored.
+        let _ = self.snooper.update(command.clone());
+    }
+}
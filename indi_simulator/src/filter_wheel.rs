@@ -0,0 +1,137 @@
+use super::*;
+use properties::*;
+
+/// Seconds the wheel takes to turn by one slot.
+const SLOT_TIME: f64 = 0.5;
+
+const FILTERS: [&str; 5] = ["Luminance", "Red", "Green", "Blue", "Ha"];
+
+/// A simulated filter wheel.
+#[derive(Debug)]
+pub struct FilterWheel {
+    properties: Properties,
+    /// The slot being moved to, and when it will get there.
+    target: Option<(f64, DateTime<Utc>)>,
+}
+
+impl FilterWheel {
+    pub fn new(config: &SimulatorConfig) -> FilterWheel {
+        let mut properties = Properties::new(&config.filter_wheel);
+        define_connection(&mut properties);
+        properties.define_numbers(
+            "FILTER_SLOT",
+            "Filter Slot",
+            "Main Control",
+            PropertyPerm::RW,
+            vec![number(
+                "FILTER_SLOT_VALUE",
+                "Filter",
+                "%3.0f",
+                (1.0, FILTERS.len() as f64, 1.0),
+                1.0,
+            )],
+        );
+        properties.define_texts(
+            "FILTER_NAME",
+            "Filter",
+            "Filter Settings",
+            PropertyPerm::RW,
+            FILTERS
+                .iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    properties::text(
+                        &format!("FILTER_SLOT_NAME_{}", i + 1),
+                        &format!("Filter#{}", i + 1),
+                        name,
+                    )
+                })
+                .collect(),
+        );
+        FilterWheel {
+            properties,
+            target: None,
+        }
+    }
+}
+
+impl SimulatedDevice for FilterWheel {
+    fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    fn update(&mut self, command: &Command, now: DateTime<Utc>) -> Vec<Command> {
+        match (
+            command.property_name().map(|n| n.as_str()),
+            new_number(command, "FILTER_SLOT_VALUE"),
+        ) {
+            (Some("FILTER_SLOT"), Some(slot)) => {
+                let slot = slot.round().clamp(1.0, FILTERS.len() as f64);
+                let current = self.properties.number("FILTER_SLOT", "FILTER_SLOT_VALUE");
+                let time = (slot - current).abs() * SLOT_TIME;
+                self.target = Some((
+                    slot,
+                    now + chrono::Duration::milliseconds((time * 1000.0) as i64),
+                ));
+                vec![self.properties.set_numbers(
+                    "FILTER_SLOT",
+                    PropertyState::Busy,
+                    &[("FILTER_SLOT_VALUE", current)],
+                )]
+            }
+            _ => self.properties.apply(command).into_iter().collect(),
+        }
+    }
+
+    fn tick(&mut self, now: DateTime<Utc>) -> Vec<Command> {
+        match self.target {
+            Some((slot, arrives)) if now >= arrives => {
+                self.target = None;
+                vec![self.properties.set_numbers(
+                    "FILTER_SLOT",
+                    PropertyState::Ok,
+                    &[("FILTER_SLOT_VALUE", slot)],
+                )]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change_filter() {
+        let mut wheel = FilterWheel::new(&SimulatorConfig::default());
+        let start = Utc::now();
+        wheel.update(
+            &Command::NewNumberVector(NewNumberVector {
+                device: "Filter Simulator".to_string(),
+                name: "FILTER_SLOT".to_string(),
+                timestamp: None,
+                numbers: vec![OneNumber {
+                    name: "FILTER_SLOT_VALUE".to_string(),
+                    min: None,
+                    max: None,
+                    step: None,
+                    value: 5.0,
+                }],
+            }),
+            start,
+        );
+        assert!(wheel
+            .tick(start + chrono::Duration::milliseconds(1900))
+            .is_empty());
+        assert_eq!(wheel.tick(start + chrono::Duration::seconds(2)).len(), 1);
+        assert_eq!(
+            wheel.properties.number("FILTER_SLOT", "FILTER_SLOT_VALUE"),
+            5.0
+        );
+        assert_eq!(
+            wheel.properties.text("FILTER_NAME", "FILTER_SLOT_NAME_5"),
+            "Ha"
+        );
+    }
+}
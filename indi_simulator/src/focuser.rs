@@ -0,0 +1,203 @@
+use super::*;
+use properties::*;
+
+/// Steps per second the focuser moves at.
+const SPEED: f64 = 2000.0;
+
+/// A simulated absolute focuser, with a temperature that falls through the night.
+#[derive(Debug)]
+pub struct Focuser {
+    properties: Properties,
+    position: f64,
+    target: Option<f64>,
+    started: Option<DateTime<Utc>>,
+    last_tick: Option<DateTime<Utc>>,
+}
+
+impl Focuser {
+    pub fn new(config: &SimulatorConfig) -> Focuser {
+        let mut properties = Properties::new(&config.focuser);
+        define_connection(&mut properties);
+        properties.define_numbers(
+            "ABS_FOCUS_POSITION",
+            "Absolute Position",
+            "Main Control",
+            PropertyPerm::RW,
+            vec![number(
+                "FOCUS_ABSOLUTE_POSITION",
+                "Steps",
+                "%.f",
+                (0.0, 100000.0, 1.0),
+                ccd::BEST_FOCUS,
+            )],
+        );
+        properties.define_switches(
+            "FOCUS_ABORT_MOTION",
+            "Abort Motion",
+            "Main Control",
+            SwitchRule::AtMostOne,
+            vec![switch("ABORT", "Abort", false)],
+        );
+        properties.define_numbers(
+            "FOCUS_TEMPERATURE",
+            "Temperature",
+            "Main Control",
+            PropertyPerm::RO,
+            vec![number(
+                "TEMPERATURE",
+                "Celsius",
+                "%6.2f",
+                (-50.0, 70.0, 0.0),
+                Focuser::temperature(0.0),
+            )],
+        );
+        Focuser {
+            properties,
+            position: ccd::BEST_FOCUS,
+            target: None,
+            started: None,
+            last_tick: None,
+        }
+    }
+
+    /// The temperature `hours` after starting, cooling towards 5°C.
+    fn temperature(hours: f64) -> f64 {
+        5.0 + 10.0 * (-hours / 3.0).exp()
+    }
+}
+
+impl SimulatedDevice for Focuser {
+    fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    fn update(&mut self, command: &Command, _now: DateTime<Utc>) -> Vec<Command> {
+        match command.property_name().map(|n| n.as_str()) {
+            Some("ABS_FOCUS_POSITION") => match new_number(command, "FOCUS_ABSOLUTE_POSITION") {
+                Some(target) => {
+                    self.target = Some(target.clamp(0.0, 100000.0).round());
+                    vec![self.properties.set_numbers(
+                        "ABS_FOCUS_POSITION",
+                        PropertyState::Busy,
+                        &[("FOCUS_ABSOLUTE_POSITION", self.position)],
+                    )]
+                }
+                None => vec![],
+            },
+            Some("FOCUS_ABORT_MOTION") => {
+                self.target = None;
+                vec![
+                    self.properties.set_switches(
+                        "FOCUS_ABORT_MOTION",
+                        PropertyState::Ok,
+                        &[("ABORT", false)],
+                    ),
+                    self.properties.set_numbers(
+                        "ABS_FOCUS_POSITION",
+                        PropertyState::Idle,
+                        &[("FOCUS_ABSOLUTE_POSITION", self.position)],
+                    ),
+                ]
+            }
+            _ => self.properties.apply(command).into_iter().collect(),
+        }
+    }
+
+    fn tick(&mut self, now: DateTime<Utc>) -> Vec<Command> {
+        let started = *self.started.get_or_insert(now);
+        let dt = match self.last_tick.replace(now) {
+            Some(last) => (now - last).num_milliseconds() as f64 / 1000.0,
+            None => 0.0,
+        };
+        let mut updates = vec![];
+
+        if let Some(target) = self.target {
+            let step = SPEED * dt;
+            let state = if (target - self.position).abs() <= step {
+                self.position = target;
+                self.target = None;
+                PropertyState::Ok
+            } else {
+                self.position += step.copysign(target - self.position);
+                PropertyState::Busy
+            };
+            updates.push(self.properties.set_numbers(
+                "ABS_FOCUS_POSITION",
+                state,
+                &[("FOCUS_ABSOLUTE_POSITION", self.position.round())],
+            ));
+        }
+
+        let hours = (now - started).num_milliseconds() as f64 / 3_600_000.0;
+        let temperature = Focuser::temperature(hours);
+        if (temperature - self.properties.number("FOCUS_TEMPERATURE", "TEMPERATURE")).abs() >= 0.1 {
+            updates.push(self.properties.set_numbers(
+                "FOCUS_TEMPERATURE",
+                PropertyState::Ok,
+                &[("TEMPERATURE", temperature)],
+            ));
+        }
+        updates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move() {
+        let mut focuser = Focuser::new(&SimulatorConfig::default());
+        let start = Utc::now();
+        focuser.tick(start);
+        focuser.update(
+            &Command::NewNumberVector(NewNumberVector {
+                device: "Focuser Simulator".to_string(),
+                name: "ABS_FOCUS_POSITION".to_string(),
+                timestamp: None,
+                numbers: vec![OneNumber {
+                    name: "FOCUS_ABSOLUTE_POSITION".to_string(),
+                    min: None,
+                    max: None,
+                    step: None,
+                    value: ccd::BEST_FOCUS + 3000.0,
+                }],
+            }),
+            start,
+        );
+
+        focuser.tick(start + chrono::Duration::seconds(1));
+        assert_eq!(focuser.position, ccd::BEST_FOCUS + 2000.0);
+        assert_eq!(
+            focuser.properties.state("ABS_FOCUS_POSITION"),
+            PropertyState::Busy
+        );
+
+        focuser.tick(start + chrono::Duration::seconds(2));
+        assert_eq!(
+            focuser
+                .properties
+                .number("ABS_FOCUS_POSITION", "FOCUS_ABSOLUTE_POSITION"),
+            ccd::BEST_FOCUS + 3000.0
+        );
+        assert_eq!(
+            focuser.properties.state("ABS_FOCUS_POSITION"),
+            PropertyState::Ok
+        );
+    }
+
+    #[test]
+    fn test_temperature() {
+        let mut focuser = Focuser::new(&SimulatorConfig::default());
+        let start = Utc::now();
+        assert!(focuser.tick(start).is_empty());
+        let updates = focuser.tick(start + chrono::Duration::hours(1));
+        assert_eq!(updates.len(), 1);
+        assert!(
+            focuser
+                .properties
+                .number("FOCUS_TEMPERATURE", "TEMPERATURE")
+                < 15.0
+        );
+    }
+}
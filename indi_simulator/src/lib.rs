@@ -0,0 +1,606 @@
+//! Simulated INDI devices: a telescope mount, focuser, filter wheel and a CCD
+//! that images a synthetic star field, for developing and testing clients
+//! without hardware.
+//!
+//! The devices can run together as a self contained server with [`serve`], or
+//! one at a time as a driver under indiserver with [`run_driver`].
+use indi::*;
+
+use chrono::prelude::*;
+use std::collections::HashMap;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub mod properties;
+pub use properties::Properties;
+
+pub mod sky;
+
+pub mod telescope;
+pub use telescope::Telescope;
+
+pub mod focuser;
+pub use focuser::Focuser;
+
+pub mod filter_wheel;
+pub use filter_wheel::FilterWheel;
+
+pub mod ccd;
+pub use ccd::Ccd;
+
+/// Settings shared by the simulated devices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatorConfig {
+    pub telescope: String,
+    pub focuser: String,
+    pub filter_wheel: String,
+    pub ccd: String,
+
+    /// Full width at half maximum of stars, in arcseconds.
+    pub seeing: f64,
+    /// Read noise, in electrons.
+    pub noise: f64,
+    /// Sensor size, in pixels.
+    pub width: usize,
+    pub height: usize,
+    /// Pixel size, in microns.
+    pub pixel_size: f64,
+    /// Seeds the image noise, so runs can be repeated.
+    pub seed: u64,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        SimulatorConfig {
+            telescope: "Telescope Simulator".to_string(),
+            focuser: "Focuser Simulator".to_string(),
+            filter_wheel: "Filter Simulator".to_string(),
+            ccd: "CCD Simulator".to_string(),
+            seeing: 2.5,
+            noise: 5.0,
+            width: 1280,
+            height: 1024,
+            pixel_size: 3.75,
+            seed: 0,
+        }
+    }
+}
+
+/// A simulated device.  Devices are driven by the clients' `new*Vector`
+/// commands and by regular ticks, and return the `set*Vector` updates to send.
+pub trait SimulatedDevice: Send {
+    fn properties(&self) -> &Properties;
+
+    /// Handles a client's `new*Vector` command for this device.
+    fn update(&mut self, command: &Command, now: DateTime<Utc>) -> Vec<Command>;
+
+    /// Moves the simulation on to `now`.
+    fn tick(&mut self, now: DateTime<Utc>) -> Vec<Command>;
+
+    /// The other devices this one follows, as requests to send to indiserver.
+    fn snoop_requests(&self) -> Vec<GetProperties> {
+        vec![]
+    }
+
+    /// Traffic from the other devices.
+    fn snoop(&mut self, _command: &Command) {}
+
+    fn name(&self) -> &str {
+        self.properties().device()
+    }
+}
+
+/// A set of simulated devices, passing each one's updates on to the others
+/// that snoop on them.
+pub struct Rig {
+    devices: Vec<Box<dyn SimulatedDevice>>,
+    /// The devices' snoop requests, by device name.
+    router: SnoopRouter<String>,
+}
+
+impl Rig {
+    pub fn new(devices: Vec<Box<dyn SimulatedDevice>>) -> Rig {
+        let mut router = SnoopRouter::new();
+        for device in &devices {
+            for request in device.snoop_requests() {
+                router.request(device.name().to_string(), &request);
+            }
+        }
+        let mut rig = Rig { devices, router };
+        let definitions: Vec<Command> = rig
+            .devices
+            .iter()
+            .flat_map(|d| d.properties().definitions(None))
+            .collect();
+        rig.share(&definitions);
+        rig
+    }
+
+    /// All four simulated devices.
+    pub fn standard(config: &SimulatorConfig) -> Rig {
+        Rig::new(vec![
+            Box::new(Telescope::new(config)),
+            Box::new(Focuser::new(config)),
+            Box::new(FilterWheel::new(config)),
+            Box::new(Ccd::new(config)),
+        ])
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = &dyn SimulatedDevice> {
+        self.devices.iter().map(|d| d.as_ref())
+    }
+
+    /// The definitions `request` asks for.
+    pub fn definitions(&self, request: &GetProperties) -> Vec<Command> {
+        self.devices
+            .iter()
+            .filter(|d| request.device.is_none() || request.device.as_deref() == Some(d.name()))
+            .flat_map(|d| d.properties().definitions(request.name.as_deref()))
+            .collect()
+    }
+
+    /// Handles a command from a client, or traffic from devices outside the rig,
+    /// returning the updates to send.
+    pub fn update(&mut self, command: &Command, now: DateTime<Utc>) -> Vec<Command> {
+        let updates = match command {
+            Command::NewTextVector(_)
+            | Command::NewNumberVector(_)
+            | Command::NewSwitchVector(_) => {
+                match self
+                    .devices
+                    .iter_mut()
+                    .find(|d| Some(d.name()) == command.device_name().map(|n| n.as_str()))
+                {
+                    Some(device) => device.update(command, now),
+                    None => vec![],
+                }
+            }
+            _ => {
+                self.share(std::slice::from_ref(command));
+                vec![]
+            }
+        };
+        self.share(&updates);
+        updates
+    }
+
+    pub fn tick(&mut self, now: DateTime<Utc>) -> Vec<Command> {
+        let updates: Vec<Command> = self.devices.iter_mut().flat_map(|d| d.tick(now)).collect();
+        self.share(&updates);
+        updates
+    }
+
+    fn share(&mut self, commands: &[Command]) {
+        for command in commands {
+            let from = command.device_name().cloned().unwrap_or_default();
+            let recipients: Vec<&String> = self.router.recipients(&from, command).collect();
+            for device in self.devices.iter_mut() {
+                if recipients.iter().any(|r| r.as_str() == device.name()) {
+                    device.snoop(command);
+                }
+            }
+        }
+    }
+}
+
+enum Event {
+    Connected(usize, Connection),
+    Command(usize, Result<Command, DeError>),
+    Disconnected(usize),
+}
+
+/// How many updates may wait to be written to a client before it's dropped
+/// for falling behind.  Enough for the rig's definitions, and queued updates
+/// are shared between clients, so a slow client doesn't copy every image.
+const CLIENT_QUEUE: usize = 256;
+
+/// A connected client, with a thread writing the updates queued for it.
+struct Client {
+    queue: mpsc::SyncSender<Arc<Command>>,
+    blobs: bool,
+}
+
+impl Client {
+    fn new(mut connection: Connection) -> Client {
+        let (queue, updates) = mpsc::sync_channel::<Arc<Command>>(CLIENT_QUEUE);
+        thread::spawn(move || {
+            for update in updates {
+                if connection.write(&*update).is_err() {
+                    break;
+                }
+            }
+            // Ends the client's reader too, so the server hears it's gone.
+            let _ = connection.disconnect();
+        });
+        Client {
+            queue,
+            blobs: false,
+        }
+    }
+
+    /// Queues `update`, returning false if the client has fallen behind or gone.
+    fn send(&self, update: &Arc<Command>) -> bool {
+        self.queue.try_send(update.clone()).is_ok()
+    }
+}
+
+/// Serves `rig` to clients connecting on `listener`, as indiserver would,
+/// moving the simulation on every `tick`.  Each client is written to by its
+/// own thread, and clients that fall behind are disconnected rather than
+/// holding up the simulation.  Only returns on error.
+pub fn serve(listener: TcpListener, mut rig: Rig, tick: Duration) -> io::Result<()> {
+    let (sender, events) = mpsc::channel();
+    thread::spawn(move || accept(listener, sender));
+
+    let mut clients: HashMap<usize, Client> = HashMap::new();
+    let mut next_tick = Instant::now() + tick;
+    loop {
+        let updates = match events.recv_timeout(next_tick.saturating_duration_since(Instant::now()))
+        {
+            Ok(Event::Connected(id, connection)) => {
+                clients.insert(id, Client::new(connection));
+                continue;
+            }
+            Ok(Event::Disconnected(id)) => {
+                clients.remove(&id);
+                continue;
+            }
+            Ok(Event::Command(id, Ok(Command::GetProperties(request)))) => {
+                let sent = clients.get(&id).is_none_or(|client| {
+                    rig.definitions(&request)
+                        .into_iter()
+                        .all(|definition| client.send(&Arc::new(definition)))
+                });
+                if !sent {
+                    eprintln!("client {}: too slow, disconnecting", id);
+                    clients.remove(&id);
+                }
+                continue;
+            }
+            Ok(Event::Command(id, Ok(Command::EnableBlob(enable)))) => {
+                if let Some(client) = clients.get_mut(&id) {
+                    client.blobs = enable.enabled != BlobEnable::Never;
+                }
+                continue;
+            }
+            Ok(Event::Command(_, Ok(command))) => rig.update(&command, Utc::now()),
+            Ok(Event::Command(id, Err(e))) => {
+                eprintln!("client {}: {}", id, e);
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                next_tick += tick;
+                rig.tick(Utc::now())
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(io::Error::other("listener closed"))
+            }
+        };
+
+        let updates: Vec<Arc<Command>> = updates.into_iter().map(Arc::new).collect();
+        clients.retain(|id, client| {
+            let sent = updates.iter().all(|update| {
+                if let Command::SetBlobVector(_) = **update {
+                    if !client.blobs {
+                        return true;
+                    }
+                }
+                client.send(update)
+            });
+            if !sent {
+                eprintln!("client {}: too slow, disconnecting", id);
+            }
+            sent
+        });
+    }
+}
+
+fn accept(listener: TcpListener, sender: mpsc::Sender<Event>) {
+    for (id, stream) in listener.incoming().enumerate() {
+        let stream: TcpStream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept: {}", e);
+                continue;
+            }
+        };
+        let (connection, commands) = match Connection::from_transport(stream)
+            .and_then(|connection| Ok((connection.iter()?, connection)))
+        {
+            Ok((commands, connection)) => (connection, commands),
+            Err(e) => {
+                eprintln!("accept: {}", e);
+                continue;
+            }
+        };
+        if sender.send(Event::Connected(id, connection)).is_err() {
+            return;
+        }
+        let sender = sender.clone();
+        thread::spawn(move || {
+            for command in commands {
+                if sender.send(Event::Command(id, command)).is_err() {
+                    return;
+                }
+            }
+            let _ = sender.send(Event::Disconnected(id));
+        });
+    }
+}
+
+/// Runs `device` as an INDI driver, talking to indiserver over stdin and stdout.
+/// Returns when indiserver closes the connection.
+pub fn run_driver(device: Box<dyn SimulatedDevice>, tick: Duration) -> Result<(), DeError> {
+    let mut connection = Connection::stdio()?;
+    for request in device.snoop_requests() {
+        connection.write(&request)?;
+    }
+    let mut rig = Rig::new(vec![device]);
+
+    let (sender, commands) = mpsc::channel();
+    let iter = connection.iter()?;
+    thread::spawn(move || {
+        for command in iter {
+            if sender.send(command).is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut next_tick = Instant::now() + tick;
+    loop {
+        let updates =
+            match commands.recv_timeout(next_tick.saturating_duration_since(Instant::now())) {
+                Ok(Ok(Command::GetProperties(request))) => rig.definitions(&request),
+                Ok(Ok(command)) => rig.update(&command, Utc::now()),
+                Ok(Err(e)) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    next_tick += tick;
+                    rig.tick(Utc::now())
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            };
+        for update in updates {
+            connection.write(&update)?;
+        }
+    }
+}
+
+/// The value of `element` in a `new*Vector` number command.
+pub fn new_number(command: &Command, element: &str) -> Option<f64> {
+    match command {
+        Command::NewNumberVector(new) => new
+            .numbers
+            .iter()
+            .find(|n| n.name == element)
+            .map(|n| n.value),
+        _ => None,
+    }
+}
+
+/// Whether `element` is turned on in a `new*Vector` switch command.
+pub fn new_switch(command: &Command, element: &str) -> Option<bool> {
+    match command {
+        Command::NewSwitchVector(new) => new
+            .switches
+            .iter()
+            .find(|s| s.name == element)
+            .map(|s| s.value == SwitchState::On),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_definitions() {
+        let rig = Rig::standard(&SimulatorConfig::default());
+        let all = rig.definitions(&GetProperties {
+            version: INDI_PROTOCOL_VERSION.to_string(),
+            device: None,
+            name: None,
+        });
+        for device in rig.devices() {
+            assert!(all
+                .iter()
+                .any(|d| d.device_name().map(|n| n.as_str()) == Some(device.name())));
+        }
+        // A new client's queue has room for everything it asks for.
+        assert!(all.len() <= CLIENT_QUEUE);
+
+        let one = rig.definitions(&GetProperties {
+            version: INDI_PROTOCOL_VERSION.to_string(),
+            device: Some("Telescope Simulator".to_string()),
+            name: Some("EQUATORIAL_EOD_COORD".to_string()),
+        });
+        assert_eq!(one.len(), 1);
+    }
+
+    fn set_number(device: &str, name: &str, element: &str, value: f64) -> Command {
+        Command::NewNumberVector(NewNumberVector {
+            device: device.to_string(),
+            name: name.to_string(),
+            timestamp: None,
+            numbers: vec![OneNumber {
+                name: element.to_string(),
+                min: None,
+                max: None,
+                step: None,
+                value,
+            }],
+        })
+    }
+
+    #[test]
+    fn test_slow_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer = Connection::new(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let client = Client::new(Connection::from_transport(stream).unwrap());
+
+        let message = |text: &str| {
+            Arc::new(Command::Message(Message {
+                device: None,
+                timestamp: None,
+                message: Some(text.to_string()),
+            }))
+        };
+        assert!(client.send(&message("hello")));
+        match peer.iter().unwrap().next() {
+            Some(Ok(Command::Message(m))) => assert_eq!(m.message.as_deref(), Some("hello")),
+            other => panic!("{:?}", other),
+        }
+
+        // A client that stops reading is dropped once its queue fills, instead
+        // of blocking whoever sends to it.
+        let blob = Arc::new(Command::SetBlobVector(SetBlobVector {
+            device: "CCD Simulator".to_string(),
+            name: "CCD1".to_string(),
+            state: PropertyState::Ok,
+            timeout: None,
+            timestamp: None,
+            message: None,
+            blobs: vec![OneBlob {
+                name: "CCD1".to_string(),
+                size: 1 << 20,
+                enclen: None,
+                format: ".fits".to_string(),
+                value: vec![0; 1 << 20],
+            }],
+        }));
+        assert!((0..CLIENT_QUEUE * 2).any(|_| !client.send(&blob)));
+        let _ = peer.disconnect();
+    }
+
+    #[test]
+    fn test_exposure() {
+        let config = SimulatorConfig {
+            width: 64,
+            height: 48,
+            ..Default::default()
+        };
+        let mut rig = Rig::standard(&config);
+        let start = Utc::now();
+        rig.tick(start);
+        rig.update(
+            &set_number(
+                &config.focuser,
+                "ABS_FOCUS_POSITION",
+                "FOCUS_ABSOLUTE_POSITION",
+                ccd::BEST_FOCUS + 1000.0,
+            ),
+            start,
+        );
+        rig.tick(start + chrono::Duration::seconds(1));
+
+        let exposed = start + chrono::Duration::seconds(1);
+        let updates = rig.update(
+            &set_number(&config.ccd, "CCD_EXPOSURE", "CCD_EXPOSURE_VALUE", 2.0),
+            exposed,
+        );
+        assert_eq!(updates.len(), 1);
+        assert!(rig
+            .tick(exposed + chrono::Duration::milliseconds(500))
+            .iter()
+            .all(|u| !matches!(u, Command::SetBlobVector(_))));
+
+        let updates = rig.tick(exposed + chrono::Duration::seconds(2));
+        let image = updates
+            .iter()
+            .find_map(|u| match u {
+                Command::SetBlobVector(blobs) => Some(&blobs.blobs[0]),
+                _ => None,
+            })
+            .expect("image");
+        assert_eq!(image.format, ".fits");
+
+        // The header describes the rest of the rig as the CCD saw it.
+        let header = String::from_utf8_lossy(&image.value[..2880 * 2]).to_string();
+        let card = |keyword: &str| {
+            header
+                .as_bytes()
+                .chunks(80)
+                .map(|c| String::from_utf8_lossy(c).to_string())
+                .find(|c| c.starts_with(&format!("{:<8}=", keyword)))
+                .unwrap_or_else(|| panic!("{} missing", keyword))
+        };
+        assert!(card("EXPTIME").contains(" 2 "), "{}", card("EXPTIME"));
+        assert!(card("FOCUSPOS").contains("51000"), "{}", card("FOCUSPOS"));
+        assert!(card("TELESCOP").contains("Telescope Simulator"));
+        assert!(card("FILTER").contains("Luminance"));
+        assert!(card("OBJCTRA").contains("05 35"), "{}", card("OBJCTRA"));
+    }
+
+    /// A device that snoops on the telescope's coordinates and records what
+    /// it's sent.
+    struct Spy {
+        properties: Properties,
+        snooped: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl SimulatedDevice for Spy {
+        fn properties(&self) -> &Properties {
+            &self.properties
+        }
+
+        fn update(&mut self, _command: &Command, _now: DateTime<Utc>) -> Vec<Command> {
+            vec![]
+        }
+
+        fn tick(&mut self, _now: DateTime<Utc>) -> Vec<Command> {
+            vec![]
+        }
+
+        fn snoop_requests(&self) -> Vec<GetProperties> {
+            vec![GetProperties {
+                version: INDI_PROTOCOL_VERSION.to_string(),
+                device: Some("Telescope Simulator".to_string()),
+                name: Some("EQUATORIAL_EOD_COORD".to_string()),
+            }]
+        }
+
+        fn snoop(&mut self, command: &Command) {
+            let name = command.property_name().cloned().unwrap_or_default();
+            self.snooped.lock().unwrap().push(name);
+        }
+    }
+
+    #[test]
+    fn test_snooping() {
+        let config = SimulatorConfig::default();
+        let snooped = Arc::default();
+        let mut rig = Rig::new(vec![
+            Box::new(Telescope::new(&config)),
+            Box::new(Focuser::new(&config)),
+            Box::new(Spy {
+                properties: Properties::new("Spy"),
+                snooped: Arc::clone(&snooped),
+            }),
+        ]);
+        let start = Utc::now();
+        rig.tick(start);
+        rig.update(
+            &set_number(
+                &config.focuser,
+                "ABS_FOCUS_POSITION",
+                "FOCUS_ABSOLUTE_POSITION",
+                1000.0,
+            ),
+            start,
+        );
+        rig.tick(start + chrono::Duration::seconds(1));
+
+        // Only what the spy asked for reaches it.
+        let snooped = snooped.lock().unwrap();
+        assert!(!snooped.is_empty());
+        assert!(snooped.iter().all(|name| name == "EQUATORIAL_EOD_COORD"));
+    }
+}
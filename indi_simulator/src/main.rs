@@ -0,0 +1,80 @@
+//! Runs the simulated devices, either together as a server or one at a time
+//! as a driver for indiserver.
+use indi_simulator::*;
+
+use std::env;
+use std::net::TcpListener;
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+static USAGE: &str = "usage: indi_simulator [options]
+
+Serves a simulated telescope, focuser, filter wheel and CCD on a port, or with
+--driver runs one of them as a driver under indiserver.
+
+options:
+  -p port               port to listen on (default 7624)
+  --driver device       run device (telescope, focuser, filter or ccd) over
+                        stdin and stdout
+  --seeing arcsec       star size (default 2.5)
+  --noise electrons     read noise (default 5)
+  --width pixels        sensor width (default 1280)
+  --height pixels       sensor height (default 1024)
+  --seed n              seed for the image noise (default 0)";
+
+/// How often the simulation moves on.
+const TICK: Duration = Duration::from_millis(100);
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut port = 7624;
+    let mut driver = None;
+    let mut config = SimulatorConfig::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-p" | "--port" => port = parse(args.next()),
+            "--driver" => driver = Some(args.next().unwrap_or_else(|| usage())),
+            "--seeing" => config.seeing = parse(args.next()),
+            "--noise" => config.noise = parse(args.next()),
+            "--width" => config.width = parse(args.next()),
+            "--height" => config.height = parse(args.next()),
+            "--seed" => config.seed = parse(args.next()),
+            _ => usage(),
+        }
+    }
+
+    match driver {
+        Some(name) => {
+            let device: Box<dyn SimulatedDevice> = match name.as_str() {
+                "telescope" => Box::new(Telescope::new(&config)),
+                "focuser" => Box::new(Focuser::new(&config)),
+                "filter" => Box::new(FilterWheel::new(&config)),
+                "ccd" => Box::new(Ccd::new(&config)),
+                _ => usage(),
+            };
+            if let Err(e) = run_driver(device, TICK) {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        }
+        None => {
+            let result = TcpListener::bind(("0.0.0.0", port))
+                .and_then(|listener| serve(listener, Rig::standard(&config), TICK));
+            if let Err(e) = result {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn parse<T: FromStr>(arg: Option<String>) -> T {
+    arg.and_then(|a| a.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,467 @@
+use indi::*;
+
+use chrono::prelude::*;
+
+/// The properties a simulated device defines, kept as the definitions sent to
+/// clients so that `getProperties` can be answered with the current values.
+#[derive(Debug)]
+pub struct Properties {
+    device: String,
+    definitions: Vec<Command>,
+}
+
+impl Properties {
+    pub fn new(device: &str) -> Properties {
+        Properties {
+            device: device.to_string(),
+            definitions: vec![],
+        }
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    /// The definitions of property `name`, or of every property when `name` is `None`.
+    pub fn definitions(&self, name: Option<&str>) -> Vec<Command> {
+        self.definitions
+            .iter()
+            .filter(|d| name.is_none() || d.property_name().map(|n| n.as_str()) == name)
+            .cloned()
+            .collect()
+    }
+
+    pub fn define_numbers(
+        &mut self,
+        name: &str,
+        label: &str,
+        group: &str,
+        perm: PropertyPerm,
+        numbers: Vec<DefNumber>,
+    ) {
+        self.definitions
+            .push(Command::DefNumberVector(DefNumberVector {
+                device: self.device.clone(),
+                name: name.to_string(),
+                label: Some(label.to_string()),
+                group: Some(group.to_string()),
+                state: PropertyState::Idle,
+                perm,
+                timeout: Some(60),
+                timestamp: Some(Utc::now()),
+                message: None,
+                numbers,
+            }));
+    }
+
+    pub fn define_switches(
+        &mut self,
+        name: &str,
+        label: &str,
+        group: &str,
+        rule: SwitchRule,
+        switches: Vec<DefSwitch>,
+    ) {
+        self.definitions
+            .push(Command::DefSwitchVector(DefSwitchVector {
+                device: self.device.clone(),
+                name: name.to_string(),
+                label: Some(label.to_string()),
+                group: Some(group.to_string()),
+                state: PropertyState::Idle,
+                perm: PropertyPerm::RW,
+                rule,
+                timeout: Some(60),
+                timestamp: Some(Utc::now()),
+                message: None,
+                switches,
+            }));
+    }
+
+    pub fn define_texts(
+        &mut self,
+        name: &str,
+        label: &str,
+        group: &str,
+        perm: PropertyPerm,
+        texts: Vec<DefText>,
+    ) {
+        self.definitions.push(Command::DefTextVector(DefTextVector {
+            device: self.device.clone(),
+            name: name.to_string(),
+            label: Some(label.to_string()),
+            group: Some(group.to_string()),
+            state: PropertyState::Idle,
+            perm,
+            timeout: Some(60),
+            timestamp: Some(Utc::now()),
+            message: None,
+            texts,
+        }));
+    }
+
+    pub fn define_blob(&mut self, name: &str, label: &str, group: &str) {
+        self.definitions.push(Command::DefBlobVector(DefBlobVector {
+            device: self.device.clone(),
+            name: name.to_string(),
+            label: Some(label.to_string()),
+            group: Some(group.to_string()),
+            state: PropertyState::Idle,
+            perm: PropertyPerm::RO,
+            timeout: Some(60),
+            timestamp: Some(Utc::now()),
+            message: None,
+            blobs: vec![DefBlob {
+                name: name.to_string(),
+                label: Some(label.to_string()),
+            }],
+        }));
+    }
+
+    fn find(&mut self, name: &str) -> Option<&mut Command> {
+        self.definitions
+            .iter_mut()
+            .find(|d| d.property_name().map(|n| n.as_str()) == Some(name))
+    }
+
+    pub fn number(&self, name: &str, element: &str) -> f64 {
+        self.definitions
+            .iter()
+            .find_map(|d| match d {
+                Command::DefNumberVector(v) if v.name == name => v
+                    .numbers
+                    .iter()
+                    .find(|n| n.name == element)
+                    .map(|n| n.value),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("no number {}.{}", name, element))
+    }
+
+    pub fn switch(&self, name: &str, element: &str) -> bool {
+        self.definitions
+            .iter()
+            .find_map(|d| match d {
+                Command::DefSwitchVector(v) if v.name == name => v
+                    .switches
+                    .iter()
+                    .find(|s| s.name == element)
+                    .map(|s| s.value == SwitchState::On),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("no switch {}.{}", name, element))
+    }
+
+    pub fn text(&self, name: &str, element: &str) -> String {
+        self.definitions
+            .iter()
+            .find_map(|d| match d {
+                Command::DefTextVector(v) if v.name == name => v
+                    .texts
+                    .iter()
+                    .find(|t| t.name == element)
+                    .map(|t| t.value.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("no text {}.{}", name, element))
+    }
+
+    pub fn state(&self, name: &str) -> PropertyState {
+        self.definitions
+            .iter()
+            .find_map(|d| match d {
+                Command::DefNumberVector(v) if v.name == name => Some(v.state.clone()),
+                Command::DefSwitchVector(v) if v.name == name => Some(v.state.clone()),
+                Command::DefTextVector(v) if v.name == name => Some(v.state.clone()),
+                Command::DefBlobVector(v) if v.name == name => Some(v.state.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("no property {}", name))
+    }
+
+    /// Updates `values` of number property `name`, returning the update to send.
+    pub fn set_numbers(
+        &mut self,
+        name: &str,
+        state: PropertyState,
+        values: &[(&str, f64)],
+    ) -> Command {
+        let device = self.device.clone();
+        match self.find(name) {
+            Some(Command::DefNumberVector(v)) => {
+                v.state = state.clone();
+                v.timestamp = Some(Utc::now());
+                for (element, value) in values {
+                    if let Some(number) = v.numbers.iter_mut().find(|n| n.name == *element) {
+                        number.value = value.clamp(number.min, number.max.max(number.min));
+                    }
+                }
+                Command::SetNumberVector(SetNumberVector {
+                    device,
+                    name: name.to_string(),
+                    state,
+                    timeout: None,
+                    timestamp: v.timestamp,
+                    message: None,
+                    numbers: v
+                        .numbers
+                        .iter()
+                        .map(|n| OneNumber {
+                            name: n.name.clone(),
+                            min: None,
+                            max: None,
+                            step: None,
+                            value: n.value,
+                        })
+                        .collect(),
+                })
+            }
+            _ => panic!("no number property {}", name),
+        }
+    }
+
+    /// Updates switch property `name`, turning the others off when only one
+    /// may be on, and returns the update to send.
+    pub fn set_switches(
+        &mut self,
+        name: &str,
+        state: PropertyState,
+        values: &[(&str, bool)],
+    ) -> Command {
+        let device = self.device.clone();
+        match self.find(name) {
+            Some(Command::DefSwitchVector(v)) => {
+                v.state = state.clone();
+                v.timestamp = Some(Utc::now());
+                let exclusive = v.rule != SwitchRule::AnyOfMany;
+                for (element, on) in values {
+                    if *on && exclusive {
+                        for switch in v.switches.iter_mut() {
+                            switch.value = SwitchState::Off;
+                        }
+                    }
+                    if let Some(switch) = v.switches.iter_mut().find(|s| s.name == *element) {
+                        switch.value = if *on {
+                            SwitchState::On
+                        } else {
+                            SwitchState::Off
+                        };
+                    }
+                }
+                Command::SetSwitchVector(SetSwitchVector {
+                    device,
+                    name: name.to_string(),
+                    state,
+                    timeout: None,
+                    timestamp: v.timestamp,
+                    message: None,
+                    switches: v
+                        .switches
+                        .iter()
+                        .map(|s| OneSwitch {
+                            name: s.name.clone(),
+                            value: s.value.clone(),
+                        })
+                        .collect(),
+                })
+            }
+            _ => panic!("no switch property {}", name),
+        }
+    }
+
+    pub fn set_texts(
+        &mut self,
+        name: &str,
+        state: PropertyState,
+        values: &[(&str, &str)],
+    ) -> Command {
+        let device = self.device.clone();
+        match self.find(name) {
+            Some(Command::DefTextVector(v)) => {
+                v.state = state.clone();
+                v.timestamp = Some(Utc::now());
+                for (element, value) in values {
+                    if let Some(text) = v.texts.iter_mut().find(|t| t.name == *element) {
+                        text.value = value.to_string();
+                    }
+                }
+                Command::SetTextVector(SetTextVector {
+                    device,
+                    name: name.to_string(),
+                    state,
+                    timeout: None,
+                    timestamp: v.timestamp,
+                    message: None,
+                    texts: v
+                        .texts
+                        .iter()
+                        .map(|t| OneText {
+                            name: t.name.clone(),
+                            value: t.value.clone(),
+                        })
+                        .collect(),
+                })
+            }
+            _ => panic!("no text property {}", name),
+        }
+    }
+
+    /// Sends the BLOB `value` on BLOB property `name`.  BLOBs aren't kept.
+    pub fn set_blob(&mut self, name: &str, format: &str, value: Vec<u8>) -> Command {
+        let device = self.device.clone();
+        match self.find(name) {
+            Some(Command::DefBlobVector(v)) => {
+                v.state = PropertyState::Ok;
+                v.timestamp = Some(Utc::now());
+                Command::SetBlobVector(SetBlobVector {
+                    device,
+                    name: name.to_string(),
+                    state: PropertyState::Ok,
+                    timeout: None,
+                    timestamp: v.timestamp,
+                    message: None,
+                    blobs: vec![OneBlob {
+                        name: name.to_string(),
+                        size: value.len() as u64,
+                        enclen: None,
+                        format: format.to_string(),
+                        value,
+                    }],
+                })
+            }
+            _ => panic!("no BLOB property {}", name),
+        }
+    }
+
+    /// Applies a client's `new*Vector` to the stored values without side
+    /// effects, as for settings a device reads when it needs them.  Returns the
+    /// update to send, or `None` if the command isn't for one of these properties.
+    pub fn apply(&mut self, command: &Command) -> Option<Command> {
+        let name = command.property_name()?;
+        self.find(name)?;
+        match command {
+            Command::NewNumberVector(new) => {
+                let values: Vec<(&str, f64)> = new
+                    .numbers
+                    .iter()
+                    .map(|n| (n.name.as_str(), n.value))
+                    .collect();
+                Some(self.set_numbers(name, PropertyState::Ok, &values))
+            }
+            Command::NewSwitchVector(new) => {
+                let values: Vec<(&str, bool)> = new
+                    .switches
+                    .iter()
+                    .map(|s| (s.name.as_str(), s.value == SwitchState::On))
+                    .collect();
+                Some(self.set_switches(name, PropertyState::Ok, &values))
+            }
+            Command::NewTextVector(new) => {
+                let values: Vec<(&str, &str)> = new
+                    .texts
+                    .iter()
+                    .map(|t| (t.name.as_str(), t.value.as_str()))
+                    .collect();
+                Some(self.set_texts(name, PropertyState::Ok, &values))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub fn number(
+    name: &str,
+    label: &str,
+    format: &str,
+    (min, max, step): (f64, f64, f64),
+    value: f64,
+) -> DefNumber {
+    DefNumber {
+        name: name.to_string(),
+        label: Some(label.to_string()),
+        format: format.to_string(),
+        min,
+        max,
+        step,
+        value,
+    }
+}
+
+pub fn switch(name: &str, label: &str, on: bool) -> DefSwitch {
+    DefSwitch {
+        name: name.to_string(),
+        label: Some(label.to_string()),
+        value: if on {
+            SwitchState::On
+        } else {
+            SwitchState::Off
+        },
+    }
+}
+
+pub fn text(name: &str, label: &str, value: &str) -> DefText {
+    DefText {
+        name: name.to_string(),
+        label: Some(label.to_string()),
+        value: value.to_string(),
+    }
+}
+
+/// Defines the `CONNECTION` switch every INDI device has.
+pub fn define_connection(properties: &mut Properties) {
+    properties.define_switches(
+        "CONNECTION",
+        "Connection",
+        "Main Control",
+        SwitchRule::OneOfMany,
+        vec![
+            switch("CONNECT", "Connect", true),
+            switch("DISCONNECT", "Disconnect", false),
+        ],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties() -> Properties {
+        let mut properties = Properties::new("Test");
+        define_connection(&mut properties);
+        properties.define_numbers(
+            "COORDS",
+            "Coordinates",
+            "Main Control",
+            PropertyPerm::RW,
+            vec![number("RA", "RA", "%g", (0.0, 24.0, 0.0), 1.0)],
+        );
+        properties
+    }
+
+    #[test]
+    fn test_set() {
+        let mut properties = properties();
+        match properties.set_numbers("COORDS", PropertyState::Busy, &[("RA", 30.0)]) {
+            Command::SetNumberVector(set) => {
+                assert_eq!(set.state, PropertyState::Busy);
+                assert_eq!(set.numbers[0].value, 24.0);
+            }
+            e => panic!("Unexpected: {:?}", e),
+        }
+        assert_eq!(properties.number("COORDS", "RA"), 24.0);
+        assert_eq!(properties.state("COORDS"), PropertyState::Busy);
+
+        properties.set_switches("CONNECTION", PropertyState::Ok, &[("DISCONNECT", true)]);
+        assert!(!properties.switch("CONNECTION", "CONNECT"));
+        assert!(properties.switch("CONNECTION", "DISCONNECT"));
+    }
+
+    #[test]
+    fn test_definitions() {
+        let properties = properties();
+        assert_eq!(properties.definitions(None).len(), 2);
+        let coords = properties.definitions(Some("COORDS"));
+        assert_eq!(coords.len(), 1);
+        assert_eq!(coords[0].property_name(), Some(&"COORDS".to_string()));
+    }
+}
@@ -0,0 +1,360 @@
+//! Synthetic star fields.  Stars are generated per patch of sky from a hash of
+//! the patch's position, so the same pointing always shows the same stars.
+use indi::{FitsCard, FitsValue};
+
+use std::f64::consts::PI;
+
+/// Size, in degrees, of the patches of sky stars are generated for.
+const CELL: f64 = 0.25;
+/// Magnitude that gives one electron per second through a 100mm aperture.
+const ZERO_POINT: f64 = 21.2;
+
+/// A small, seedable random number generator (splitmix64).
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Normally distributed with mean 0 and standard deviation 1.
+    pub fn gaussian(&mut self) -> f64 {
+        let u = 1.0 - self.uniform();
+        let v = self.uniform();
+        (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Star {
+    /// Right ascension in degrees.
+    pub ra: f64,
+    /// Declination in degrees.
+    pub dec: f64,
+    pub magnitude: f64,
+}
+
+/// Stars per square degree brighter than `magnitude`, roughly as for the sky
+/// away from the Milky Way.
+fn density(magnitude: f64) -> f64 {
+    0.2 * 10f64.powf(0.35 * (magnitude - 6.0))
+}
+
+/// The stars brighter than `limit` within `radius` degrees of `ra`, `dec` (in degrees).
+pub fn stars(ra: f64, dec: f64, radius: f64, limit: f64) -> Vec<Star> {
+    let mut stars = vec![];
+    let dec_cells = ((dec - radius).max(-90.0) / CELL).floor() as i64
+        ..=((dec + radius).min(90.0 - 1e-9) / CELL).floor() as i64;
+    for dec_cell in dec_cells {
+        let dec_min = dec_cell as f64 * CELL;
+        // The widest the cells in this band get, on the side furthest from the equator.
+        let cos_dec = dec_min.abs().max((dec_min + CELL).abs()).to_radians().cos();
+        let ra_cells = (360.0 / CELL) as i64;
+        let mut cells: Vec<i64> = if cos_dec * 360.0 <= 2.0 * radius + 2.0 * CELL {
+            (0..ra_cells).collect()
+        } else {
+            let half = radius / cos_dec + CELL;
+            (((ra - half) / CELL).floor() as i64..((ra + half) / CELL).ceil() as i64)
+                .map(|cell| cell.rem_euclid(ra_cells))
+                .collect()
+        };
+        cells.sort_unstable();
+        cells.dedup();
+        for ra_cell in cells {
+            stars.extend(
+                cell_stars(ra_cell, dec_cell, limit)
+                    .into_iter()
+                    .filter(|s| separation(ra, dec, s.ra, s.dec) <= radius),
+            );
+        }
+    }
+    stars.sort_by(|a, b| a.magnitude.total_cmp(&b.magnitude));
+    stars
+}
+
+fn cell_stars(ra_cell: i64, dec_cell: i64, limit: f64) -> Vec<Star> {
+    let mut rng = Rng::new((ra_cell as u64) << 32 ^ (dec_cell as u64 & 0xffff_ffff));
+    let dec_min = dec_cell as f64 * CELL;
+    // The cell's area, in square degrees, shrinks towards the poles.
+    let area = CELL.to_radians()
+        * ((dec_min + CELL).to_radians().sin() - dec_min.to_radians().sin())
+        * (180.0 / PI).powi(2);
+    let expected = density(limit) * area;
+    let count = expected.floor() as usize + (rng.uniform() < expected.fract()) as usize;
+    (0..count)
+        .map(|_| {
+            let ra = (ra_cell as f64 + rng.uniform()) * CELL;
+            // Uniform in area rather than in declination.
+            let sin_min = dec_min.to_radians().sin();
+            let sin_max = (dec_min + CELL).to_radians().sin();
+            let dec = (sin_min + rng.uniform() * (sin_max - sin_min))
+                .asin()
+                .to_degrees();
+            // Inverse of the cumulative `density`, so faint stars are more common.
+            let magnitude = limit + (1.0 - rng.uniform()).log10() / 0.35;
+            Star { ra, dec, magnitude }
+        })
+        .collect()
+}
+
+/// Angular distance between two points, in degrees.
+fn separation(ra1: f64, dec1: f64, ra2: f64, dec2: f64) -> f64 {
+    let (ra1, dec1, ra2, dec2) = (
+        ra1.to_radians(),
+        dec1.to_radians(),
+        ra2.to_radians(),
+        dec2.to_radians(),
+    );
+    let cos = dec1.sin() * dec2.sin() + dec1.cos() * dec2.cos() * (ra1 - ra2).cos();
+    cos.clamp(-1.0, 1.0).acos().to_degrees()
+}
+
+/// Where a camera is pointing and what it sees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    /// Right ascension of the centre, in degrees.
+    pub ra: f64,
+    /// Declination of the centre, in degrees.
+    pub dec: f64,
+    /// Arcseconds per pixel.
+    pub scale: f64,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Field {
+    /// The pixel position of `ra`, `dec` (in degrees), with north up and east
+    /// left, or `None` if it's on the far side of the sky.
+    pub fn project(&self, ra: f64, dec: f64) -> Option<(f64, f64)> {
+        let (ra0, dec0) = (self.ra.to_radians(), self.dec.to_radians());
+        let (ra, dec) = (ra.to_radians(), dec.to_radians());
+        let cos_c = dec0.sin() * dec.sin() + dec0.cos() * dec.cos() * (ra - ra0).cos();
+        if cos_c <= 0.0 {
+            return None;
+        }
+        // Gnomonic projection, in arcseconds.
+        let x = dec.cos() * (ra - ra0).sin() / cos_c * 206264.8;
+        let y =
+            (dec0.cos() * dec.sin() - dec0.sin() * dec.cos() * (ra - ra0).cos()) / cos_c * 206264.8;
+        Some((
+            self.width as f64 / 2.0 - x / self.scale,
+            self.height as f64 / 2.0 + y / self.scale,
+        ))
+    }
+
+    /// Radius, in degrees, of a circle that covers the field.
+    fn radius(&self) -> f64 {
+        (self.width as f64).hypot(self.height as f64) / 2.0 * self.scale / 3600.0
+    }
+}
+
+/// Everything other than the pointing that goes into an exposure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conditions {
+    /// Seconds.
+    pub exposure: f64,
+    /// Full width at half maximum of stars, in arcseconds.
+    pub seeing: f64,
+    /// Blur from being out of focus, in arcseconds.
+    pub defocus: f64,
+    /// Millimetres.
+    pub aperture: f64,
+    /// Fraction of light the filter lets through.
+    pub throughput: f64,
+    /// Electrons per second per pixel from the sky.
+    pub sky: f64,
+    /// Electrons per second per pixel from dark current.
+    pub dark: f64,
+    /// Electrons of read noise.
+    pub read_noise: f64,
+    /// Faintest stars to render.
+    pub limiting_magnitude: f64,
+}
+
+/// Offset added to every pixel, as a camera's bias level.
+const BIAS: f64 = 500.0;
+
+/// Renders an exposure of `field`, with rows from the bottom of the image up.
+pub fn render(field: &Field, conditions: &Conditions, rng: &mut Rng) -> Vec<u16> {
+    let (width, height) = (field.width, field.height);
+    let mut signal = vec![0.0; width * height];
+
+    let fwhm = conditions.seeing.hypot(conditions.defocus) / field.scale;
+    let sigma = (fwhm / 2.3548).max(0.3);
+    let radius = (4.0 * sigma).ceil() as i64 + 1;
+    let flux_scale =
+        conditions.exposure * conditions.throughput * (conditions.aperture / 100.0).powi(2)
+            / (2.0 * PI * sigma * sigma);
+    let stars = stars(
+        field.ra,
+        field.dec,
+        field.radius(),
+        conditions.limiting_magnitude,
+    );
+    for star in stars {
+        let (x, y) = match field.project(star.ra, star.dec) {
+            Some(position) => position,
+            None => continue,
+        };
+        let peak = 10f64.powf(-0.4 * (star.magnitude - ZERO_POINT)) * flux_scale;
+        let (cx, cy) = (x.floor() as i64, y.floor() as i64);
+        for py in (cy - radius).max(0)..(cy + radius + 1).min(height as i64) {
+            for px in (cx - radius).max(0)..(cx + radius + 1).min(width as i64) {
+                let dx = px as f64 + 0.5 - x;
+                let dy = py as f64 + 0.5 - y;
+                signal[py as usize * width + px as usize] +=
+                    peak * (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp();
+            }
+        }
+    }
+
+    let background =
+        (conditions.sky * conditions.throughput + conditions.dark) * conditions.exposure;
+    signal
+        .into_iter()
+        .map(|star| {
+            let electrons = star + background;
+            let noisy = BIAS
+                + electrons
+                + rng.gaussian() * electrons.sqrt()
+                + rng.gaussian() * conditions.read_noise;
+            noisy.round().clamp(0.0, u16::MAX as f64) as u16
+        })
+        .collect()
+}
+
+/// A 16 bit FITS image of `pixels`, with rows from the bottom of the image up.
+pub fn fits(width: usize, height: usize, pixels: &[u16], cards: &[FitsCard]) -> Vec<u8> {
+    let number = |keyword, value: f64, comment| FitsCard {
+        keyword,
+        value: FitsValue::Number(value),
+        comment,
+    };
+    let mut header = format!("{:<8}= {:>20} / {:<47}", "SIMPLE", "T", "Standard FITS");
+    for card in [
+        number("BITPIX", 16.0, "Bits per pixel"),
+        number("NAXIS", 2.0, "Number of axes"),
+        number("NAXIS1", width as f64, "Width"),
+        number("NAXIS2", height as f64, "Height"),
+        number("BZERO", 32768.0, "Offset for unsigned data"),
+        number("BSCALE", 1.0, "Scale"),
+    ]
+    .iter()
+    .chain(cards)
+    {
+        header.push_str(&card.to_record());
+    }
+    header.push_str(&format!("{:<80}", "END"));
+
+    let mut data = header.into_bytes();
+    data.resize(data.len().div_ceil(2880) * 2880, b' ');
+    for pixel in pixels {
+        data.extend_from_slice(&((*pixel as i32 - 32768) as i16).to_be_bytes());
+    }
+    data.resize(data.len().div_ceil(2880) * 2880, 0);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conditions() -> Conditions {
+        Conditions {
+            exposure: 1.0,
+            seeing: 2.0,
+            defocus: 0.0,
+            aperture: 100.0,
+            throughput: 1.0,
+            sky: 0.0,
+            dark: 0.0,
+            read_noise: 0.0,
+            limiting_magnitude: 15.0,
+        }
+    }
+
+    #[test]
+    fn test_stars_are_repeatable() {
+        let stars = stars(83.8, -5.4, 1.0, 12.0);
+        assert!(!stars.is_empty());
+        assert_eq!(stars, super::stars(83.8, -5.4, 1.0, 12.0));
+        assert!(stars
+            .iter()
+            .all(|s| separation(83.8, -5.4, s.ra, s.dec) <= 1.0 && s.magnitude <= 12.0));
+        // Across RA = 0 and at the pole.
+        assert!(!super::stars(0.0, 10.0, 1.0, 12.0).is_empty());
+        assert!(!super::stars(0.0, 90.0, 1.0, 12.0).is_empty());
+    }
+
+    #[test]
+    fn test_project() {
+        let field = Field {
+            ra: 90.0,
+            dec: 0.0,
+            scale: 3.6,
+            width: 100,
+            height: 100,
+        };
+        assert_eq!(field.project(90.0, 0.0), Some((50.0, 50.0)));
+        // North is up and east, increasing RA, is left.
+        let (x, y) = field.project(90.0, 0.01).unwrap();
+        assert!((x - 50.0).abs() < 1e-6 && (y - 60.0).abs() < 1e-3);
+        let (x, y) = field.project(90.01, 0.0).unwrap();
+        assert!((x - 40.0).abs() < 1e-3 && (y - 50.0).abs() < 1e-6);
+        assert_eq!(field.project(270.0, 0.0), None);
+    }
+
+    #[test]
+    fn test_render() {
+        let field = Field {
+            ra: 83.8,
+            dec: -5.4,
+            scale: 10.0,
+            width: 200,
+            height: 150,
+        };
+        let pixels = render(&field, &conditions(), &mut Rng::new(1));
+        assert_eq!(pixels.len(), 200 * 150);
+        assert!(pixels.iter().all(|p| *p >= BIAS as u16 - 50));
+        assert!(pixels.iter().any(|p| *p > BIAS as u16 + 100));
+
+        // Noise is seeded.
+        let noisy = Conditions {
+            read_noise: 10.0,
+            sky: 20.0,
+            ..conditions()
+        };
+        assert_eq!(
+            render(&field, &noisy, &mut Rng::new(2)),
+            render(&field, &noisy, &mut Rng::new(2))
+        );
+    }
+
+    #[test]
+    fn test_fits() {
+        let pixels = vec![0, 1, 32768, 65535];
+        let fits = fits(2, 2, &pixels, &[]);
+        assert_eq!(fits.len(), 2 * 2880);
+        assert!(fits.starts_with(b"SIMPLE  =                    T"));
+        assert_eq!(
+            &fits[2880..2888],
+            &[0x80, 0x00, 0x80, 0x01, 0x00, 0x00, 0x7f, 0xff]
+        );
+        // The header is one a FitsEnricher can add to.
+        assert!(indi::add_cards(&fits, &[]).is_ok());
+    }
+}
@@ -0,0 +1,300 @@
+use super::*;
+use properties::*;
+
+/// Degrees per second the mount slews at.
+const SLEW_RATE: f64 = 3.0;
+/// Sidereal hours per solar second, the rate RA drifts at when not tracking.
+const SIDEREAL_RATE: f64 = 1.002_737_909_35 / 3600.0;
+
+/// A simulated equatorial mount.  It slews to coordinates set on
+/// `EQUATORIAL_EOD_COORD`, and drifts in RA when tracking is turned off.
+#[derive(Debug)]
+pub struct Telescope {
+    properties: Properties,
+    /// RA in hours, Dec in degrees.
+    position: (f64, f64),
+    target: Option<(f64, f64)>,
+    last_tick: Option<DateTime<Utc>>,
+    last_sent: Option<DateTime<Utc>>,
+}
+
+impl Telescope {
+    pub fn new(config: &SimulatorConfig) -> Telescope {
+        // Pointing at the Orion nebula.
+        let position = (5.588, -5.39);
+        let mut properties = Properties::new(&config.telescope);
+        define_connection(&mut properties);
+        properties.define_numbers(
+            "EQUATORIAL_EOD_COORD",
+            "Eq. Coordinates",
+            "Main Control",
+            PropertyPerm::RW,
+            vec![
+                number(
+                    "RA",
+                    "RA (hh:mm:ss)",
+                    "%010.6m",
+                    (0.0, 24.0, 0.0),
+                    position.0,
+                ),
+                number(
+                    "DEC",
+                    "DEC (dd:mm:ss)",
+                    "%010.6m",
+                    (-90.0, 90.0, 0.0),
+                    position.1,
+                ),
+            ],
+        );
+        properties.define_switches(
+            "TELESCOPE_ABORT_MOTION",
+            "Abort Motion",
+            "Main Control",
+            SwitchRule::AtMostOne,
+            vec![switch("ABORT", "Abort", false)],
+        );
+        properties.define_switches(
+            "TELESCOPE_TRACK_STATE",
+            "Tracking",
+            "Main Control",
+            SwitchRule::OneOfMany,
+            vec![
+                switch("TRACK_ON", "On", true),
+                switch("TRACK_OFF", "Off", false),
+            ],
+        );
+        properties.define_numbers(
+            "TELESCOPE_INFO",
+            "Scope Properties",
+            "Options",
+            PropertyPerm::RW,
+            vec![
+                number(
+                    "TELESCOPE_APERTURE",
+                    "Aperture (mm)",
+                    "%g",
+                    (10.0, 5000.0, 0.0),
+                    120.0,
+                ),
+                number(
+                    "TELESCOPE_FOCAL_LENGTH",
+                    "Focal Length (mm)",
+                    "%g",
+                    (10.0, 10000.0, 0.0),
+                    900.0,
+                ),
+            ],
+        );
+        properties.define_numbers(
+            "GEOGRAPHIC_COORD",
+            "Location",
+            "Site Management",
+            PropertyPerm::RW,
+            vec![
+                number(
+                    "LAT",
+                    "Lat (dd:mm:ss)",
+                    "%010.6m",
+                    (-90.0, 90.0, 0.0),
+                    51.4769,
+                ),
+                number(
+                    "LONG",
+                    "Lon (dd:mm:ss)",
+                    "%010.6m",
+                    (0.0, 360.0, 0.0),
+                    359.9985,
+                ),
+                number("ELEV", "Elevation (m)", "%g", (-200.0, 10000.0, 0.0), 46.0),
+            ],
+        );
+        Telescope {
+            properties,
+            position,
+            target: None,
+            last_tick: None,
+            last_sent: None,
+        }
+    }
+
+    /// RA in hours and Dec in degrees.
+    pub fn position(&self) -> (f64, f64) {
+        self.position
+    }
+
+    fn send_position(&mut self, state: PropertyState, now: DateTime<Utc>) -> Command {
+        self.last_sent = Some(now);
+        self.properties.set_numbers(
+            "EQUATORIAL_EOD_COORD",
+            state,
+            &[("RA", self.position.0), ("DEC", self.position.1)],
+        )
+    }
+}
+
+/// Moves `from` towards `to` by at most `step`.
+fn approach(from: f64, to: f64, step: f64) -> f64 {
+    if (to - from).abs() <= step {
+        to
+    } else {
+        from + step * (to - from).signum()
+    }
+}
+
+impl SimulatedDevice for Telescope {
+    fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    fn update(&mut self, command: &Command, now: DateTime<Utc>) -> Vec<Command> {
+        match command.property_name().map(|n| n.as_str()) {
+            Some("EQUATORIAL_EOD_COORD") => {
+                let ra = new_number(command, "RA").unwrap_or(self.position.0);
+                let dec = new_number(command, "DEC").unwrap_or(self.position.1);
+                self.target = Some((ra.rem_euclid(24.0), dec.clamp(-90.0, 90.0)));
+                vec![self.send_position(PropertyState::Busy, now)]
+            }
+            Some("TELESCOPE_ABORT_MOTION") => {
+                self.target = None;
+                vec![
+                    self.properties.set_switches(
+                        "TELESCOPE_ABORT_MOTION",
+                        PropertyState::Ok,
+                        &[("ABORT", false)],
+                    ),
+                    self.send_position(PropertyState::Idle, now),
+                ]
+            }
+            _ => self.properties.apply(command).into_iter().collect(),
+        }
+    }
+
+    fn tick(&mut self, now: DateTime<Utc>) -> Vec<Command> {
+        let dt = match self.last_tick.replace(now) {
+            Some(last) => (now - last).num_milliseconds() as f64 / 1000.0,
+            None => 0.0,
+        };
+        if dt <= 0.0 {
+            return vec![];
+        }
+
+        if let Some((ra, dec)) = self.target {
+            let step = SLEW_RATE * dt;
+            // Take the short way round in RA.
+            let ra_offset = ((ra - self.position.0 + 12.0).rem_euclid(24.0) - 12.0) * 15.0;
+            let ra_step = approach(0.0, ra_offset, step) / 15.0;
+            self.position.0 = (self.position.0 + ra_step).rem_euclid(24.0);
+            self.position.1 = approach(self.position.1, dec, step);
+            if (ra_step * 15.0 - ra_offset).abs() < 1e-9 && self.position.1 == dec {
+                self.position.0 = ra;
+                self.target = None;
+                return vec![self.send_position(PropertyState::Ok, now)];
+            }
+            return vec![self.send_position(PropertyState::Busy, now)];
+        }
+
+        if self.properties.switch("TELESCOPE_TRACK_STATE", "TRACK_OFF") {
+            // Without tracking the mount holds its hour angle, so RA follows sidereal time.
+            self.position.0 = (self.position.0 + dt * SIDEREAL_RATE).rem_euclid(24.0);
+            if self
+                .last_sent
+                .is_none_or(|sent| now - sent >= chrono::Duration::seconds(1))
+            {
+                let state = self.properties.state("EQUATORIAL_EOD_COORD");
+                return vec![self.send_position(state, now)];
+            }
+        }
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn goto(ra: f64, dec: f64) -> Command {
+        Command::NewNumberVector(NewNumberVector {
+            device: "Telescope Simulator".to_string(),
+            name: "EQUATORIAL_EOD_COORD".to_string(),
+            timestamp: None,
+            numbers: vec![
+                OneNumber {
+                    name: "RA".to_string(),
+                    min: None,
+                    max: None,
+                    step: None,
+                    value: ra,
+                },
+                OneNumber {
+                    name: "DEC".to_string(),
+                    min: None,
+                    max: None,
+                    step: None,
+                    value: dec,
+                },
+            ],
+        })
+    }
+
+    #[test]
+    fn test_slew() {
+        let mut telescope = Telescope::new(&SimulatorConfig::default());
+        let start = Utc::now();
+        telescope.tick(start);
+        telescope.update(&goto(23.5, -8.0), start);
+        assert_eq!(
+            telescope.properties.state("EQUATORIAL_EOD_COORD"),
+            PropertyState::Busy
+        );
+
+        // 6.088 hours west is 91.3 degrees, so about 30 seconds at 3 degrees a second.
+        let mut now = start;
+        for _ in 0..29 {
+            now += chrono::Duration::seconds(1);
+            telescope.tick(now);
+        }
+        assert_eq!(
+            telescope.properties.state("EQUATORIAL_EOD_COORD"),
+            PropertyState::Busy
+        );
+        assert_ne!(telescope.position(), (23.5, -8.0));
+
+        telescope.tick(now + chrono::Duration::seconds(2));
+        assert_eq!(telescope.position(), (23.5, -8.0));
+        assert_eq!(
+            telescope.properties.state("EQUATORIAL_EOD_COORD"),
+            PropertyState::Ok
+        );
+        assert_eq!(
+            telescope.properties.number("EQUATORIAL_EOD_COORD", "RA"),
+            23.5
+        );
+    }
+
+    #[test]
+    fn test_drift() {
+        let mut telescope = Telescope::new(&SimulatorConfig::default());
+        let start = Utc::now();
+        telescope.tick(start);
+        assert!(telescope
+            .tick(start + chrono::Duration::seconds(10))
+            .is_empty());
+
+        telescope.update(
+            &Command::NewSwitchVector(NewSwitchVector {
+                device: "Telescope Simulator".to_string(),
+                name: "TELESCOPE_TRACK_STATE".to_string(),
+                timestamp: None,
+                switches: vec![OneSwitch {
+                    name: "TRACK_OFF".to_string(),
+                    value: SwitchState::On,
+                }],
+            }),
+            start,
+        );
+        let updates = telescope.tick(start + chrono::Duration::seconds(3610));
+        assert_eq!(updates.len(), 1);
+        let (ra, _) = telescope.position();
+        assert!((ra - (5.588 + 1.0027379)).abs() < 1e-6, "{}", ra);
+    }
+}
@@ -1,4 +1,5 @@
 use indi::DeError;
+use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
@@ -12,27 +13,18 @@ pub enum ConnectionStatus {
     Connected,
 }
 
-struct DropFn {
-    func: Box<dyn Fn() -> ()>,
-}
-
-impl Drop for DropFn {
-    fn drop(&mut self) {
-        (self.func)();
-    }
-}
-
 pub struct Backend {
-    client: Arc<Mutex<indi::Client>>,
-    connection: Arc<Mutex<Option<indi::Connection>>>,
+    aggregator: Arc<Mutex<indi::Aggregator>>,
+    events: Option<mpsc::Receiver<indi::ServerEvent>>,
     connection_status: Arc<Mutex<ConnectionStatus>>,
 }
 
 impl Default for Backend {
     fn default() -> Self {
+        let (aggregator, events) = indi::Aggregator::new();
         Self {
-            client: Arc::new(Mutex::new(indi::Client::new())),
-            connection: Arc::new(Mutex::new(None)),
+            aggregator: Arc::new(Mutex::new(aggregator)),
+            events: Some(events),
             connection_status: Arc::new(Mutex::new(ConnectionStatus::Disconnected)),
         }
     }
@@ -40,52 +32,68 @@ impl Default for Backend {
 
 impl Drop for Backend {
     fn drop(&mut self) {
-        let mut locked_connection = self.connection.lock().unwrap();
-        if let Some(connection) = &mut *locked_connection {
-            _ = connection.disconnect();
-        }
+        let mut aggregator = self.aggregator.lock().unwrap();
+        aggregator.clear();
     }
 }
 impl Backend {
-    pub fn send_command(&self, command: &indi::Command) {
-        let mut locked_connection = self.connection.lock().unwrap();
-        if let Some(connection) = &mut *locked_connection {
-            connection.send(command);
+    pub fn send_command(&self, server: &str, command: &indi::Command) {
+        let mut aggregator = self.aggregator.lock().unwrap();
+        if let Err(e) = aggregator.write(server, command) {
+            event!(Level::ERROR, "Error sending command to {}: {}", server, e);
         }
     }
 
+    /// Connects to every server in the comma separated list of addresses.
     #[instrument(skip(self, ctx))]
     pub fn connect(&mut self, ctx: egui::Context, address: String) -> Result<(), indi::DeError> {
-        let runtime_client = Arc::clone(&self.client);
-        let runtime_connection = Arc::clone(&self.connection);
-        let runtime_connection_status = Arc::clone(&self.connection_status);
         self.disconnect()?;
 
-        thread::spawn(move || -> Result<(), DeError> {
-            let guard_client = Arc::clone(&runtime_client);
-            let guard_ctx = ctx.clone();
-            let guard_status = Arc::clone(&runtime_connection_status);
-            let _guard = DropFn {
-                func: Box::new(move || {
-                    {
-                        let mut l = guard_status.lock().unwrap();
-                        *l = ConnectionStatus::Disconnected;
-                        event!(Level::INFO, "Done with connection");
-                    }
-                    {
-                        let mut l = guard_client.lock().unwrap();
-                        l.clear();
+        if let Some(events) = self.events.take() {
+            let runtime_aggregator = Arc::clone(&self.aggregator);
+            let runtime_connection_status = Arc::clone(&self.connection_status);
+            let runtime_ctx = ctx.clone();
+            thread::spawn(move || {
+                for server_event in events {
+                    let mut aggregator = runtime_aggregator.lock().unwrap();
+                    match server_event {
+                        indi::ServerEvent::Command {
+                            server,
+                            connection,
+                            command: Ok(command),
+                        } => {
+                            if let Err(e) = aggregator.update(&server, connection, command) {
+                                println!("error: {}", e)
+                            }
+                        }
+                        indi::ServerEvent::Command {
+                            server,
+                            command: Err(e),
+                            ..
+                        } => {
+                            println!("error from {}: {}", server, e)
+                        }
+                        indi::ServerEvent::Disconnected { server, connection } => {
+                            event!(Level::INFO, "Done with connection to {}", server);
+                            _ = aggregator.disconnect(&server, connection);
+                            if aggregator.server_names().is_empty() {
+                                let mut l = runtime_connection_status.lock().unwrap();
+                                *l = ConnectionStatus::Disconnected;
+                            }
+                        }
                     }
-                    guard_ctx.request_repaint();
-                }),
-            };
-
-            let iter = {
-                let mut locked_connection = runtime_connection.lock().unwrap();
-                if let Some(connection) = &mut *locked_connection {
-                    _ = connection.disconnect();
+                    runtime_ctx.request_repaint();
                 }
+            });
+        }
 
+        for address in address.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+            let address = address.to_string();
+            let runtime_aggregator = Arc::clone(&self.aggregator);
+            let runtime_connection_status = Arc::clone(&self.connection_status);
+            let ctx = ctx.clone();
+
+            thread::spawn(move || -> Result<(), DeError> {
                 event!(Level::INFO, "Connecting to {}", address);
                 {
                     let mut l = runtime_connection_status.lock().unwrap();
@@ -93,7 +101,16 @@ impl Backend {
                     ctx.request_repaint();
                 }
 
-                let mut connection = indi::Connection::new(&address)?;
+                let connection = match indi::Connection::new(&address) {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        event!(Level::ERROR, "Error connecting to {}: {}", address, e);
+                        let mut l = runtime_connection_status.lock().unwrap();
+                        *l = ConnectionStatus::Disconnected;
+                        ctx.request_repaint();
+                        return Err(e.into());
+                    }
+                };
                 event!(Level::INFO, "Connected, requesting properties");
                 {
                     let mut l = runtime_connection_status.lock().unwrap();
@@ -101,41 +118,19 @@ impl Backend {
                     ctx.request_repaint();
                 }
 
-                connection.send(&indi::GetProperties {
-                    version: indi::INDI_PROTOCOL_VERSION.to_string(),
-                    device: None,
-                    name: None,
-                })?;
-                
+                runtime_aggregator
+                    .lock()
+                    .unwrap()
+                    .add_connection(&address, connection)?;
 
                 {
                     let mut l = runtime_connection_status.lock().unwrap();
                     *l = ConnectionStatus::Connected;
                     ctx.request_repaint();
                 }
-                let iter = connection.command_iter()?;
-                *locked_connection = Some(connection);
-                iter
-            };
-
-            for command in iter {
-                // event!(Level::INFO, "Command: {:?}", command);
-                match command {
-                    Ok(command) => {
-                        let mut client = runtime_client.lock().unwrap();
-                        if let Err(e) = client.update(command) {
-                            println!("error: {:?}", e)
-                        }
-                        ctx.request_repaint();
-                    }
-                    Err(e) => match e {
-                        e => println!("error: {:?}", e),
-                    },
-                }
-            }
-
-            Ok(())
-        });
+                Ok(())
+            });
+        }
 
         Ok(())
     }
@@ -145,24 +140,23 @@ impl Backend {
         l.clone()
     }
 
-    pub fn get_client(&self) -> Arc<Mutex<indi::Client>> {
-        self.client.clone()
+    pub fn get_aggregator(&self) -> Arc<Mutex<indi::Aggregator>> {
+        self.aggregator.clone()
     }
 
     #[instrument(skip(self))]
     pub fn disconnect(&mut self) -> Result<(), DeError> {
-        let connection = self.connection.lock();
-        match connection {
-            Ok(mut connection) => {
-                if let Some(connection) = &mut *connection {
-                    connection.disconnect()?;
-                }
-                *connection = None;
+        let aggregator = self.aggregator.lock();
+        match aggregator {
+            Ok(mut aggregator) => {
+                aggregator.clear();
             }
             Err(e) => {
                 event!(Level::ERROR, "Error disconnecting: {:?}", e);
             }
         }
+        let mut l = self.connection_status.lock().unwrap();
+        *l = ConnectionStatus::Disconnected;
 
         Ok(())
     }
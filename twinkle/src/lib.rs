@@ -6,14 +6,14 @@ mod fits_viewer;
 
 use tracing::{event, Level};
 
-use indi::Parameter;
+use indi::{DeviceId, Parameter};
 
 pub struct TwinkleApp {
     backend: Backend,
 
     address: String,
 
-    selected_device: Option<String>,
+    selected_device: Option<DeviceId>,
     selected_group: Option<String>,
     fits_viewer: Option<fits_viewer::Custom3d>,
 }
@@ -53,9 +53,10 @@ impl eframe::App for TwinkleApp {
             fits_viewer,
         } = self;
 
-        let client_lock = backend.get_client(); //.lock().unwrap();
-        let client = client_lock.lock().unwrap();
-        let devices = client.get_devices();
+        let aggregator_lock = backend.get_aggregator();
+        let aggregator = aggregator_lock.lock().unwrap();
+        let devices = aggregator.get_devices();
+        let mut commands: Vec<(String, indi::Command)> = vec![];
 
         egui::SidePanel::left("side_panel").show(ctx, |ui| {
             ui.horizontal(|ui| {
@@ -89,12 +90,15 @@ impl eframe::App for TwinkleApp {
             ui.separator();
 
             {
-                for (name, device) in devices {
+                let mut ids: Vec<&DeviceId> = devices.keys().collect();
+                ids.sort();
+                for id in ids {
+                    let device = devices[id];
                     if ui
-                        .selectable_value(&mut Some(name), selected_device.as_ref(), name)
+                        .selectable_label(selected_device.as_ref() == Some(id), id.to_string())
                         .clicked()
                     {
-                        *selected_device = Some(name.to_string());
+                        *selected_device = Some(id.clone());
                         if device.parameter_groups().len() > 0 {
                             *selected_group = device.parameter_groups()[0].clone();
                         } else {
@@ -118,9 +122,9 @@ impl eframe::App for TwinkleApp {
             egui::ScrollArea::vertical()
                 .auto_shrink([false; 2])
                 .show_viewport(ui, |ui, viewport| {
-                    if let Some(device_name) = selected_device {
-                        if let Some(device) = devices.get(device_name) {
-                            ui.heading(device_name.clone());
+                    if let Some(device_id) = selected_device {
+                        if let Some(device) = devices.get(device_id) {
+                            ui.heading(device_id.to_string());
                             ui.separator();
                             ui.horizontal(|ui| {
                                 for group in device.parameter_groups() {
@@ -172,10 +176,11 @@ impl eframe::App for TwinkleApp {
                                                     ))
                                                     .clicked()
                                                 {
-                                                    backend.send_command(
-                                                        &indi::Command::NewSwitchVector(
+                                                    commands.push((
+                                                        device_id.server.clone(),
+                                                        indi::Command::NewSwitchVector(
                                                             indi::NewSwitchVector {
-                                                                device: device_name.to_string(),
+                                                                device: device_id.device.clone(),
                                                                 name: name.to_string(),
                                                                 timestamp: None,
                                                                 switches: vec![indi::OneSwitch {
@@ -184,25 +189,36 @@ impl eframe::App for TwinkleApp {
                                                                 }],
                                                             },
                                                         ),
-                                                    );
+                                                    ));
                                                 }
                                             }
                                         });
                                     }
+                                    Parameter::LightVector(lv) => {
+                                        egui::Grid::new(format!("{}", name)).num_columns(2).show(
+                                            ui,
+                                            |ui| {
+                                                for (light_name, light) in &lv.values {
+                                                    ui.colored_label(light_color(light.get_value()), "⏺");
+                                                    ui.label(light.get_label().clone().unwrap_or(light_name.clone()));
+                                                    ui.end_row();
+                                                }
+                                            },
+                                        );
+                                    }
                                     Parameter::BlobVector(bv) => {
                                         for (name, blob) in &bv.values {
                                             ui.label(format!("BLOB {}", name));
                                             if ui.button("Images").clicked() {
                                                 let enable_blob = indi::EnableBlob {
-                                                    device: device_name.clone(),
+                                                    device: device_id.device.clone(),
                                                     name: None,
                                                     enabled: indi::BlobEnable::Also,
                                                 };
-                                                backend.send_command(&indi::Command::EnableBlob(enable_blob));
+                                                commands.push((device_id.server.clone(), indi::Command::EnableBlob(enable_blob)));
                                             }
                                         }
                                     }
-                                    _ => {}
                                 }
 
                                 ui.end_row();
@@ -211,6 +227,21 @@ impl eframe::App for TwinkleApp {
                     }
                 });
         });
+
+        drop(devices);
+        drop(aggregator);
+        for (server, command) in commands {
+            backend.send_command(&server, &command);
+        }
         // self.backend.tick();
     }
 }
+
+fn light_color(state: &indi::PropertyState) -> egui::Color32 {
+    match state {
+        indi::PropertyState::Idle => egui::Color32::GRAY,
+        indi::PropertyState::Ok => egui::Color32::GREEN,
+        indi::PropertyState::Busy => egui::Color32::YELLOW,
+        indi::PropertyState::Alert => egui::Color32::RED,
+    }
+}